@@ -0,0 +1,226 @@
+// Static analysis of loaded images
+//
+// `lc3 analyze` builds a control flow graph of the loaded program, starting
+// at the entry point and following branches, jumps and subroutine calls, and
+// warns about:
+//
+//   unreachable code       words that are never reached and do not look like
+//                          data (zero, or a character in the low byte)
+//   branches to non-code   a BR or JSR whose target is outside the loaded
+//                          images or holds data
+//   stray returns          a RET that can be reached without passing a JSR
+//   stores into code       an ST or STI whose target is a reachable
+//                          instruction
+//
+// Targets through registers (JMP, JSRR, LDR and STR) are not followed; a word
+// reached only that way shows up as unreachable. The checks are heuristics on
+// a program that may mix code and data freely, so they are warnings, not
+// errors.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    defs::{OP, TRAP},
+    instr::sign_extend,
+    state::Memory,
+};
+
+const RET: u16 = 0xC1C0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub address: u16,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x{:04X}: {}", self.address, self.message)
+    }
+}
+
+struct Program<'a> {
+    mem: &'a Memory,
+    loaded: &'a [(u16, usize)],
+}
+
+impl Program<'_> {
+    fn contains(&self, address: u16) -> bool {
+        self.loaded
+            .iter()
+            .any(|&(origin, length)| (address.wrapping_sub(origin) as usize) < length)
+    }
+
+    fn is_data(&self, address: u16) -> bool {
+        self.mem.peek(address) <= 0x00FF
+    }
+}
+
+// Where control can go after one instruction
+#[derive(Default)]
+struct Flow {
+    next: Option<u16>,   /* falling through */
+    branch: Option<u16>, /* a BR target */
+    call: Option<u16>,   /* a JSR target, which returns to next */
+}
+
+fn flow(address: u16, instr: u16) -> Flow {
+    let next = address.wrapping_add(1);
+    let offset = |bits: i32| next.wrapping_add(sign_extend(instr & ((1 << bits) - 1), bits));
+    let fall = Flow {
+        next: Some(next),
+        ..Flow::default()
+    };
+    match OP::try_from(instr >> 12).expect("opcode out of range") {
+        OP::BR => match (instr >> 9) & 0x7 {
+            0 => fall,
+            cond => Flow {
+                next: (cond != 0x7).then_some(next),
+                branch: Some(offset(9)),
+                call: None,
+            },
+        },
+        OP::JMP | OP::RTI | OP::RES => Flow::default(),
+        OP::JSR if instr & 0x800 != 0 => Flow {
+            call: Some(offset(11)),
+            ..fall
+        },
+        OP::TRAP => match TRAP::try_from(instr & 0xFF) {
+            Ok(TRAP::HALT | TRAP::EXIT) => Flow::default(),
+            _ => fall,
+        },
+        _ => fall,
+    }
+}
+
+pub fn analyze(mem: &Memory, loaded: &[(u16, usize)], entry: u16) -> Vec<Warning> {
+    let program = Program { mem, loaded };
+    let mut warnings = Vec::new();
+    let mut warn = |address: u16, message: String| warnings.push(Warning { address, message });
+
+    // walk the graph, keeping apart the paths inside a subroutine
+    let mut reached = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![(entry, false)];
+    while let Some((address, in_call)) = pending.pop() {
+        if !program.contains(address) || !visited.insert((address, in_call)) {
+            continue;
+        }
+        reached.insert(address);
+        let instr = mem.peek(address);
+        if instr == RET && !in_call {
+            warn(address, String::from("RET reachable without a JSR"));
+        }
+        if instr >> 12 == OP::RES as u16 {
+            warn(address, format!("illegal instruction x{:04X}", instr));
+        }
+
+        let flow = flow(address, instr);
+        for target in flow.branch.into_iter().chain(flow.call) {
+            if !program.contains(target) {
+                warn(
+                    address,
+                    format!("branch to x{:04X}, outside the program", target),
+                );
+            } else if program.is_data(target) {
+                warn(
+                    address,
+                    format!("branch to x{:04X}, which holds data", target),
+                );
+            }
+        }
+        pending.extend(
+            flow.next
+                .into_iter()
+                .chain(flow.branch)
+                .map(|a| (a, in_call)),
+        );
+        pending.extend(flow.call.map(|a| (a, true)));
+    }
+
+    // stores whose target is known statically
+    for &address in &reached {
+        let instr = mem.peek(address);
+        let next = address.wrapping_add(1);
+        let target = next.wrapping_add(sign_extend(instr & 0x1FF, 9));
+        let target = match OP::try_from(instr >> 12) {
+            Ok(OP::ST) => Some(target),
+            Ok(OP::STI) if program.contains(target) => Some(mem.peek(target)),
+            _ => None,
+        };
+        if let Some(target) = target.filter(|t| reached.contains(t) && !program.is_data(*t)) {
+            warn(address, format!("store into code at x{:04X}", target));
+        }
+    }
+
+    // runs of words never reached that look like code
+    for &(origin, length) in loaded {
+        let mut run: Option<(u16, u16)> = None;
+        for i in 0..=length {
+            let address = origin.wrapping_add(i as u16);
+            let code = i < length && !reached.contains(&address) && !program.is_data(address);
+            match (code, run) {
+                (true, None) => run = Some((address, address)),
+                (true, Some((start, _))) => run = Some((start, address)),
+                (false, Some((start, end))) => {
+                    let message = match start == end {
+                        true => String::from("unreachable code"),
+                        false => format!("unreachable code up to x{:04X}", end),
+                    };
+                    warn(start, message);
+                    run = None;
+                }
+                (false, None) => {}
+            }
+        }
+    }
+
+    /* a word reached both inside and outside a call is checked twice */
+    warnings.sort_by(|a, b| (a.address, &a.message).cmp(&(b.address, &b.message)));
+    warnings.dedup();
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analyze::analyze, state::Memory};
+
+    fn messages(words: &[u16]) -> Vec<String> {
+        let mut mem = Memory::default();
+        mem.write_slice(0x3000, words);
+        analyze(&mem, &[(0x3000, words.len())], 0x3000)
+            .iter()
+            .map(|w| w.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn clean_programs_have_no_warnings() {
+        // JSR SUB; HALT; "A"; SUB: ADD R1, R1, #1; RET
+        let words = [0x4802, 0xF025, 0x0041, 0x1261, 0xC1C0];
+        assert!(messages(&words).is_empty());
+    }
+
+    #[test]
+    fn problems_are_found() {
+        let words = [
+            0x0E03, // BRnzp x3004
+            0x1261, // ADD R1, R1, #1
+            0x1262, // ADD R1, R1, #2
+            0x0000, // data
+            0x3FFE, // ST R7, x3003
+            0x3FFA, // ST R7, x3000
+            0x0BFC, // BRnp x3003
+            0xC1C0, // RET
+        ];
+        assert_eq!(
+            vec![
+                "x3001: unreachable code up to x3002",
+                "x3005: store into code at x3000",
+                "x3006: branch to x3003, which holds data",
+                "x3007: RET reachable without a JSR",
+            ],
+            messages(&words)
+        );
+    }
+}
@@ -0,0 +1,832 @@
+// Assembler
+//
+// `encode` turns one line of LC-3 assembly into an instruction word. A
+// PC-relative operand is an offset (`#-3`), the absolute target address
+// (`x3005`), which is made relative to `address`, the location the
+// instruction will be stored at, or a label from `symbols`. Without symbols
+// this accepts what the disassembler prints.
+//
+// Numbers are decimal, optionally after `#`, hex after `x` or `0x`, binary
+// after `b` or `0b`, or an ASCII character literal such as 'A' or '\n', in
+// instructions and .FILL alike. A value too big for its field is an error
+// that gives the field's range.
+//
+// `assemble` turns a source file into an image in two passes, the first
+// assigning addresses to labels. Output depends only on the source: symbols
+// are kept sorted and nothing like a timestamp is recorded, so identical
+// input always gives byte-identical object, listing and symbol files.
+//
+// Unless pseudo-instructions are turned off, for courses that want only the
+// real instruction set, `assemble` also expands
+//
+//   ZERO R2       AND R2, R2, #0
+//   COPY R1, R2   ADD R1, R2, #0
+//   PUSH R1       ADD R6, R6, #-1 and STR R1, R6, #0
+//   POP R1        LDR R1, R6, #0 and ADD R6, R6, #1
+//
+// and the listing shows what each one expanded to. A name among these
+// followed by another instruction is still a label, as in `PUSH ADD ...`.
+//
+// A program that assembles can still carry mistakes, so `Program::warnings`
+// lists labels nothing refers to (except one at the origin) and instructions
+// right after an unconditional BR, JMP, RET or RTI that no label lets a
+// branch reach. A branch too far for its offset is an error which suggests
+// jumping through a register instead, as is a .FILL value that truncates.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    defs::{OP, TRAP},
+    meta::Metadata,
+};
+
+pub type Symbols = BTreeMap<String, u16>;
+
+pub fn encode(line: &str, address: u16) -> Result<u16, String> {
+    encode_with(line, address, &Symbols::new())
+}
+
+pub fn encode_with(line: &str, address: u16, symbols: &Symbols) -> Result<u16, String> {
+    let line = strip_comment(line).trim();
+    let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let mnemonic = mnemonic.to_uppercase();
+    let operands = split_operands(rest);
+
+    let count = |n: usize| {
+        if operands.len() == n {
+            Ok(())
+        } else {
+            Err(format!("{} expects {} operand(s)", mnemonic, n))
+        }
+    };
+    let op = |op: OP| (op as u16) << 12;
+
+    let word = match mnemonic.as_str() {
+        "ADD" | "AND" => {
+            count(3)?;
+            let base = if mnemonic == "ADD" {
+                op(OP::ADD)
+            } else {
+                op(OP::AND)
+            };
+            let (dr, sr1) = (register(operands[0])?, register(operands[1])?);
+            let last = match register(operands[2]) {
+                Ok(sr2) => sr2,
+                Err(_) => 1 << 5 | signed(number(operands[2])?, 5)?,
+            };
+            base | dr << 9 | sr1 << 6 | last
+        }
+        "NOT" => {
+            count(2)?;
+            op(OP::NOT) | register(operands[0])? << 9 | register(operands[1])? << 6 | 0x3F
+        }
+        "NOP" => {
+            count(0)?;
+            op(OP::BR)
+        }
+        br if br.starts_with("BR") => {
+            let flags = &br[2..];
+            if !flags.chars().all(|c| "NZP".contains(c)) {
+                return Err(format!("unknown instruction {}", mnemonic));
+            }
+            count(1)?;
+            let cond = match flags {
+                "" => 0x7,
+                _ => flags.chars().fold(0, |cond, c| match c {
+                    'N' => cond | 0x4,
+                    'Z' => cond | 0x2,
+                    _ => cond | 0x1,
+                }),
+            };
+            let offset = signed(pc_offset(operands[0], address, symbols)?, 9)
+                .map_err(|e| format!("{}; load the target with LD and use JMP", e))?;
+            op(OP::BR) | cond << 9 | offset
+        }
+        "JMP" => {
+            count(1)?;
+            op(OP::JMP) | register(operands[0])? << 6
+        }
+        "RET" => {
+            count(0)?;
+            op(OP::JMP) | 7 << 6
+        }
+        "JSR" => {
+            count(1)?;
+            let offset = signed(pc_offset(operands[0], address, symbols)?, 11)
+                .map_err(|e| format!("{}; load the target with LD and use JSRR", e))?;
+            op(OP::JSR) | 1 << 11 | offset
+        }
+        "JSRR" => {
+            count(1)?;
+            op(OP::JSR) | register(operands[0])? << 6
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            count(2)?;
+            let base = match mnemonic.as_str() {
+                "LD" => op(OP::LD),
+                "LDI" => op(OP::LDI),
+                "LEA" => op(OP::LEA),
+                "ST" => op(OP::ST),
+                _ => op(OP::STI),
+            };
+            let offset = signed(pc_offset(operands[1], address, symbols)?, 9)?;
+            base | register(operands[0])? << 9 | offset
+        }
+        "LDR" | "STR" => {
+            count(3)?;
+            let base = if mnemonic == "LDR" {
+                op(OP::LDR)
+            } else {
+                op(OP::STR)
+            };
+            base | register(operands[0])? << 9
+                | register(operands[1])? << 6
+                | signed(number(operands[2])?, 6)?
+        }
+        "TRAP" => {
+            count(1)?;
+            let vector = number(operands[0])?;
+            if !(0..=0xFF).contains(&vector) {
+                return Err(format!(
+                    "trap vector {} does not fit in 8 bits",
+                    operands[0]
+                ));
+            }
+            op(OP::TRAP) | vector as u16
+        }
+        "RTI" => {
+            count(0)?;
+            op(OP::RTI)
+        }
+        ".FILL" => {
+            count(1)?;
+            let value = match symbols.get(operands[0]) {
+                Some(&address) => address as i32,
+                None => number(operands[0])?,
+            };
+            if !(-0x8000..=0xFFFF).contains(&value) {
+                return Err(format!(
+                    "{} does not fit in 16 bits, -32768 to 65535",
+                    operands[0]
+                ));
+            }
+            value as u16
+        }
+        name => match (0x20..=0xFF).find(|&v| TRAP::try_from(v).is_ok_and(|t| t.name() == name)) {
+            Some(vector) => {
+                count(0)?;
+                op(OP::TRAP) | vector
+            }
+            None => return Err(format!("unknown instruction {}", mnemonic)),
+        },
+    };
+    Ok(word)
+}
+
+pub(crate) fn register(operand: &str) -> Result<u16, String> {
+    match operand.as_bytes() {
+        [b'R' | b'r', n @ b'0'..=b'7'] => Ok((n - b'0') as u16),
+        _ => Err(format!("expected a register, got {}", operand)),
+    }
+}
+
+fn number(operand: &str) -> Result<i32, String> {
+    if operand.starts_with('\'') {
+        let text = unquote(operand, '\'')?;
+        let mut chars = text.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c as i32),
+            _ => Err(format!("expected one character, got {}", operand)),
+        };
+    }
+    let text = operand.strip_prefix('#').unwrap_or(operand);
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let radix = |prefixes: [&str; 3]| prefixes.iter().find_map(|p| text.strip_prefix(p));
+    let value = if let Some(digits) = radix(["0x", "x", "X"]) {
+        i32::from_str_radix(digits, 16)
+    } else if let Some(digits) = radix(["0b", "b", "B"]) {
+        i32::from_str_radix(digits, 2)
+    } else {
+        text.parse()
+    }
+    .map_err(|_| format!("expected a number, got {}", operand))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn signed(value: i32, bits: u32) -> Result<u16, String> {
+    let limit = 1 << (bits - 1);
+    if !(-limit..limit).contains(&value) {
+        return Err(format!(
+            "{} does not fit in {} signed bits, {} to {}",
+            value,
+            bits,
+            -limit,
+            limit - 1
+        ));
+    }
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+// `#n` is an offset, anything else a label or the absolute address of the
+// target. The caller checks the offset fits its field.
+fn pc_offset(operand: &str, address: u16, symbols: &Symbols) -> Result<i32, String> {
+    if operand.starts_with('#') {
+        return number(operand);
+    }
+    let target = match symbols.get(operand) {
+        Some(&target) => target as i32,
+        None => number(operand)
+            .map_err(|_| format!("expected a label or an address, got {}", operand))?,
+    };
+    Ok(target - (address as i32 + 1))
+}
+
+pub struct Program {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: Symbols,
+    lines: Vec<(u16, usize, String)>, /* address, line number and text of each statement */
+    expanded: BTreeMap<u16, String>,  /* the instruction a pseudo-instruction put at an address */
+    pub warnings: Vec<String>,        /* "line N: ..." for each lint finding */
+}
+
+struct Statement<'a> {
+    address: u16,
+    line_no: usize,
+    mnemonic: String,
+    operands: String,
+    expansion: Option<Vec<String>>,
+    labeled: bool, /* a label names this statement's address */
+    raw: &'a str,
+}
+
+impl Program {
+    // The object image: the origin followed by the words, big-endian,
+    // optionally followed by a metadata block.
+    pub fn object(&self, metadata: Option<&Metadata>) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .chain(metadata.map(Metadata::to_words).unwrap_or_default())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    pub fn metadata(&self, source: &str) -> Metadata {
+        Metadata {
+            version: format!("lc3vm {}", env!("CARGO_PKG_VERSION")),
+            source_hash: hash(source.as_bytes()),
+            symbols: self.symbols.clone(),
+            lines: self
+                .lines
+                .iter()
+                .map(|&(address, line_no, _)| (address, line_no as u16))
+                .collect(),
+        }
+    }
+
+    // One line per word: address, word, source line number and text. Words
+    // after the first of a .BLKW or .STRINGZ have no source column.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        let mut statements = self.lines.iter().peekable();
+        for (i, word) in self.words.iter().enumerate() {
+            let address = self.origin.wrapping_add(i as u16);
+            let expanded = match self.expanded.get(&address) {
+                Some(text) => format!("  ; -> {}", text),
+                None => String::new(),
+            };
+            match statements.next_if(|(start, _, _)| *start == address) {
+                Some((_, line_no, text)) => listing.push_str(&format!(
+                    "x{:04X}  {:04X}  {:>4}  {}{}\n",
+                    address, word, line_no, text, expanded
+                )),
+                None if expanded.is_empty() => {
+                    listing.push_str(&format!("x{:04X}  {:04X}\n", address, word))
+                }
+                None => listing.push_str(&format!(
+                    "x{:04X}  {:04X}      {}\n",
+                    address,
+                    word,
+                    expanded.trim_start()
+                )),
+            }
+        }
+        listing
+    }
+
+    pub fn symbol_table(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, address)| format!("{:<20} x{:04X}\n", name, address))
+            .collect()
+    }
+}
+
+const DIRECTIVES: [&str; 5] = [".ORIG", ".FILL", ".BLKW", ".STRINGZ", ".END"];
+const PSEUDO_OPS: [&str; 4] = ["ZERO", "COPY", "PUSH", "POP"];
+
+pub fn assemble(source: &str) -> Result<Program, String> {
+    assemble_with(source, true)
+}
+
+pub fn assemble_with(source: &str, pseudo_ops: bool) -> Result<Program, String> {
+    /* first pass: lay out the statements and collect the labels */
+    let mut origin = None;
+    let mut symbols = Symbols::new();
+    let mut statements = Vec::new();
+    let mut label_lines = BTreeMap::new();
+    let mut labeled = false;
+    let mut address = 0u32;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line_no = index + 1;
+        let at = |e: String| format!("line {}: {}", line_no, e);
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (first, rest) = split_word(line);
+        let pseudo = is_pseudo_op(first, rest);
+        if pseudo && !pseudo_ops {
+            return Err(at(format!(
+                "{} is a pseudo-instruction and they are turned off",
+                first.to_uppercase()
+            )));
+        }
+        let (label, statement) = if is_mnemonic(first) || pseudo {
+            (None, line)
+        } else {
+            (Some(first.trim_end_matches(':')), rest)
+        };
+        let (mnemonic, operands) = split_word(statement);
+        let mnemonic = mnemonic.to_uppercase();
+        let expansion = match pseudo_ops && PSEUDO_OPS.contains(&mnemonic.as_str()) {
+            true => Some(expand(&mnemonic, operands).map_err(at)?),
+            false => None,
+        };
+
+        if mnemonic == ".ORIG" {
+            if origin.is_some() {
+                return Err(at(String::from("only one .ORIG is supported")));
+            }
+            let start = number(operands).map_err(at)?;
+            if !(0..=0xFFFF).contains(&start) {
+                return Err(at(format!("origin {} is out of range", operands)));
+            }
+            origin = Some(start as u16);
+            address = start as u32;
+            continue;
+        }
+        if origin.is_none() {
+            return Err(at(String::from(
+                "expected .ORIG before the first statement",
+            )));
+        }
+        if mnemonic == ".END" {
+            break;
+        }
+
+        if let Some(label) = label {
+            if !label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                return Err(at(format!("invalid label {}", label)));
+            }
+            if symbols.insert(label.to_string(), address as u16).is_some() {
+                return Err(at(format!("label {} is defined twice", label)));
+            }
+            label_lines.insert(label.to_string(), line_no);
+            labeled = true;
+        }
+        if mnemonic.is_empty() {
+            continue;
+        }
+
+        let size = match mnemonic.as_str() {
+            ".BLKW" => {
+                let n = number(operands).map_err(at)?;
+                u32::try_from(n).map_err(|_| at(format!(".BLKW {} is negative", n)))?
+            }
+            ".STRINGZ" => string(operands).map_err(at)?.len() as u32 + 1,
+            _ => expansion.as_ref().map_or(1, |lines| lines.len() as u32),
+        };
+        statements.push(Statement {
+            address: address as u16,
+            line_no,
+            mnemonic,
+            operands: operands.to_string(),
+            expansion,
+            labeled: std::mem::take(&mut labeled),
+            raw,
+        });
+        address += size;
+        if address > 0x10000 {
+            return Err(at(String::from("program runs past xFFFF")));
+        }
+    }
+
+    /* second pass: encode now that every label has an address */
+    let origin = origin.ok_or("no .ORIG found")?;
+    let warnings = lint(&statements, &symbols, &label_lines, origin);
+    let mut words = Vec::new();
+    let mut lines = Vec::new();
+    let mut expanded = BTreeMap::new();
+    for statement in statements {
+        let Statement {
+            address,
+            line_no,
+            mnemonic,
+            operands,
+            expansion,
+            raw,
+            ..
+        } = statement;
+        let at = |e: String| format!("line {}: {}", line_no, e);
+        lines.push((address, line_no, raw.trim().to_string()));
+        if let Some(expansion) = expansion {
+            for (i, text) in expansion.into_iter().enumerate() {
+                let address = address.wrapping_add(i as u16);
+                words.push(encode_with(&text, address, &symbols).map_err(at)?);
+                expanded.insert(address, text);
+            }
+            continue;
+        }
+        match mnemonic.as_str() {
+            ".BLKW" => words.resize(words.len() + number(&operands).map_err(at)? as usize, 0),
+            ".STRINGZ" => {
+                words.extend(string(&operands).map_err(at)?.bytes().map(u16::from));
+                words.push(0);
+            }
+            _ => {
+                let text = format!("{} {}", mnemonic, operands);
+                words.push(encode_with(&text, address, &symbols).map_err(at)?);
+            }
+        }
+    }
+
+    Ok(Program {
+        origin,
+        words,
+        symbols,
+        lines,
+        expanded,
+        warnings,
+    })
+}
+
+// The lint findings, in source order.
+fn lint(
+    statements: &[Statement],
+    symbols: &Symbols,
+    label_lines: &BTreeMap<String, usize>,
+    origin: u16,
+) -> Vec<String> {
+    let mut findings = Vec::new();
+    let referenced: Vec<&str> = (statements.iter())
+        .flat_map(|s| split_operands(&s.operands))
+        .collect();
+    for (name, &address) in symbols {
+        if address != origin && !referenced.contains(&name.as_str()) {
+            findings.push((label_lines[name], format!("label {} is never used", name)));
+        }
+    }
+    for pair in statements.windows(2) {
+        let (before, after) = (&pair[0], &pair[1]);
+        let unconditional = match before.mnemonic.strip_prefix("BR") {
+            Some(flags) => flags.is_empty() || "NZP".chars().all(|c| flags.contains(c)),
+            None => ["JMP", "RET", "RTI"].contains(&before.mnemonic.as_str()),
+        };
+        if unconditional && !after.labeled && !after.mnemonic.starts_with('.') {
+            let message = format!(
+                "unreachable code after {} on line {}",
+                before.mnemonic, before.line_no
+            );
+            findings.push((after.line_no, message));
+        }
+    }
+    findings.sort();
+    (findings.into_iter())
+        .map(|(line_no, message)| format!("line {}: {}", line_no, message))
+        .collect()
+}
+
+// Whether a line starting with `word` is a pseudo-instruction rather than a
+// label of that name.
+pub(crate) fn is_pseudo_op(word: &str, rest: &str) -> bool {
+    PSEUDO_OPS.contains(&word.to_uppercase().as_str())
+        && !rest.is_empty()
+        && !is_mnemonic(split_word(rest).0)
+}
+
+// The instructions pseudo-instruction `mnemonic` stands for.
+fn expand(mnemonic: &str, operands: &str) -> Result<Vec<String>, String> {
+    let operands = split_operands(operands);
+    let registers = |n: usize| {
+        if operands.len() != n {
+            return Err(format!("{} expects {} operand(s)", mnemonic, n));
+        }
+        operands
+            .iter()
+            .map(|o| register(o))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(match mnemonic {
+        "ZERO" => {
+            let r = registers(1)?[0];
+            vec![format!("AND R{}, R{}, #0", r, r)]
+        }
+        "COPY" => {
+            let r = registers(2)?;
+            vec![format!("ADD R{}, R{}, #0", r[0], r[1])]
+        }
+        "PUSH" => {
+            let r = registers(1)?[0];
+            vec![
+                String::from("ADD R6, R6, #-1"),
+                format!("STR R{}, R6, #0", r),
+            ]
+        }
+        _ => {
+            let r = registers(1)?[0];
+            vec![
+                format!("LDR R{}, R6, #0", r),
+                String::from("ADD R6, R6, #1"),
+            ]
+        }
+    })
+}
+
+// 64-bit FNV-1a, a stable content hash for comparing outputs.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub(crate) fn is_mnemonic(word: &str) -> bool {
+    let word = word.to_uppercase();
+    DIRECTIVES.contains(&word.as_str())
+        || (word.starts_with("BR") && word[2..].chars().all(|c| "NZP".contains(c)))
+        || ["NOP", "RET", "JSRR", "RTI"].contains(&word.as_str())
+        || (0..16).any(|op| OP::try_from(op).is_ok_and(|op| op.name() == word))
+        || (0x20..=0xFF).any(|v| TRAP::try_from(v).is_ok_and(|t| t.name() == word))
+}
+
+// Splits operands at the commas outside quotes, so ',' is one operand.
+pub(crate) fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let (mut start, mut quote) = (0, None);
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote.is_some() => escaped = true,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if quote == Some(c) => quote = None,
+            ',' if quote.is_none() => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim());
+    operands.retain(|o| !o.is_empty());
+    operands
+}
+
+pub(crate) fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+// Removes a `;` comment, ignoring semicolons inside string and character
+// literals.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote.is_some() => escaped = true,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if quote == Some(c) => quote = None,
+            ';' if quote.is_none() => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn string(operand: &str) -> Result<String, String> {
+    unquote(operand, '"')
+}
+
+// The text between `quote`s, with its escapes replaced.
+fn unquote(operand: &str, quote: char) -> Result<String, String> {
+    let body = operand
+        .strip_prefix(quote)
+        .and_then(|rest| rest.strip_suffix(quote))
+        .filter(|_| operand.len() > 1)
+        .ok_or(format!("expected a quoted string, got {}", operand))?;
+
+    let mut text = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        text.push(match (c, c == '\\') {
+            (_, false) => c,
+            (_, true) => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(e @ ('"' | '\'' | '\\')) => e,
+                _ => return Err(String::from("invalid escape in string")),
+            },
+        });
+    }
+    if !text.is_ascii() {
+        return Err(String::from("strings and characters must be ASCII"));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::{assemble, assemble_with, encode, hash},
+        disasm::disassemble,
+    };
+
+    #[test]
+    fn encode_instructions() {
+        assert_eq!(Ok(0x12A3), encode("ADD R1, R2, #3", 0x3000));
+        assert_eq!(Ok(0x5642), encode("and r3, r1, r2", 0x3000));
+        assert_eq!(Ok(0x0FFA), encode("BR #-6", 0x3000));
+        assert_eq!(Ok(0x07FD), encode("BRzp x3000", 0x3002));
+        assert_eq!(Ok(0xF025), encode("HALT ; done", 0x3000));
+        assert_eq!(Ok(0x6042), encode("LDR R0, R1, #2", 0x3000));
+        assert_eq!(Ok(0xFFFD), encode(".FILL #-3", 0x3000));
+    }
+
+    #[test]
+    fn encode_literals_in_every_base() {
+        assert_eq!(Ok(0x000A), encode(".FILL b1010", 0x3000));
+        assert_eq!(Ok(0x001F), encode(".FILL x1F", 0x3000));
+        assert_eq!(Ok(0x0041), encode(".FILL 'A'", 0x3000));
+        assert_eq!(Ok(0x002C), encode(".FILL ','", 0x3000));
+        assert_eq!(Ok(0x003B), encode(".FILL ';' ; a semicolon", 0x3000));
+        assert_eq!(Ok(0x000A), encode(".FILL '\\n'", 0x3000));
+        assert_eq!(Ok(0x1025), encode("ADD R0, R0, b0101", 0x3000));
+        assert_eq!(Ok(0x103D), encode("ADD R0, R0, #-3", 0x3000));
+        assert_eq!(Ok(0x5020 | 0x0F), encode("AND R0, R0, xF", 0x3000));
+
+        assert_eq!(
+            Err(String::from("65 does not fit in 5 signed bits, -16 to 15")),
+            encode("ADD R0, R0, 'A'", 0x3000)
+        );
+        assert!(encode(".FILL 'AB'", 0x3000).is_err());
+        assert!(encode(".FILL b102", 0x3000).is_err());
+    }
+
+    #[test]
+    fn encode_rejects_bad_operands() {
+        assert!(encode("ADD R1, R2, #16", 0x3000).is_err());
+        assert!(encode("ADD R1, R8, R2", 0x3000).is_err());
+        assert!(encode("LD R0", 0x3000).is_err());
+        assert!(encode("BRx #1", 0x3000).is_err());
+        assert!(encode("MOV R0, R1", 0x3000).is_err());
+    }
+
+    #[test]
+    fn encode_accepts_disassembly() {
+        for word in [
+            0x12BD, 0x907F, 0x03FD, 0x4080, 0x4BFF, 0xC1C0, 0xE1FE, 0xB00A, 0xF021,
+        ] {
+            let text = disassemble(0x3005, word);
+            assert_eq!(Ok(word), encode(&text, 0x3005), "{}", text);
+        }
+    }
+
+    #[test]
+    fn assemble_program_with_labels() {
+        let source = "
+            ; print a greeting
+                    .ORIG x3000
+            START   LEA R0, MSG
+                    PUTS
+                    BRnzp DONE
+            MSG     .STRINGZ \"hi; there\\n\"
+            BUF:    .BLKW 2
+            DONE    HALT
+                    .FILL START
+                    .END
+            ";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(0x3000, program.origin);
+        assert_eq!(0xE002, program.words[0]); // LEA R0, x3003
+        assert_eq!(0x0E0D, program.words[2]); // BRnzp x3010
+        assert_eq!(b'h' as u16, program.words[3]);
+        assert_eq!(0x0A, program.words[12]);
+        assert_eq!(0, program.words[13]);
+        assert_eq!(0xF025, program.words[16]);
+        assert_eq!(0x3000, program.words[17]);
+
+        /* symbols come out sorted, whatever the source order */
+        assert_eq!(
+            "BUF                  x300E\nDONE                 x3010\nMSG                  x3003\nSTART                x3000\n",
+            program.symbol_table()
+        );
+        assert!(program
+            .listing()
+            .starts_with("x3000  E002     4  START   LEA R0, MSG\n"));
+    }
+
+    #[test]
+    fn assemble_is_reproducible() {
+        let source = ".ORIG x3000\nB .FILL A\nA .FILL B\n.END\n";
+        let first = assemble(source).unwrap();
+        let second = assemble(source).unwrap();
+        assert_eq!(first.object(None), second.object(None));
+        assert_eq!(first.listing(), second.listing());
+        assert_eq!(
+            first.object(Some(&first.metadata(source))),
+            second.object(Some(&second.metadata(source)))
+        );
+        assert_ne!(hash(&[0x30, 0x00]), hash(&[0x30, 0x01]));
+    }
+
+    #[test]
+    fn assemble_expands_pseudo_instructions() {
+        let source = "
+            .ORIG x3000
+            ZERO R2
+            COPY R1, R2
+            LOOP PUSH R1
+            POP R3
+            JSR PUSH
+            PUSH ADD R6, R6, #-1
+            .END
+            ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            vec![0x54A0, 0x12A0, 0x1DBF, 0x7380, 0x6780, 0x1DA1, 0x4800, 0x1DBF],
+            program.words
+        );
+        assert_eq!(Some(&0x3002), program.symbols.get("LOOP"));
+        assert_eq!(Some(&0x3007), program.symbols.get("PUSH"));
+        assert!(program.listing().contains(
+            "x3002  1DBF     5  LOOP PUSH R1  ; -> ADD R6, R6, #-1\n\
+             x3003  7380      ; -> STR R1, R6, #0\n"
+        ));
+
+        assert_eq!(
+            Err(String::from(
+                "line 2: ZERO is a pseudo-instruction and they are turned off"
+            )),
+            assemble_with(".ORIG x3000\nZERO R2\n", false).map(|_| ())
+        );
+        assert!(assemble(".ORIG x3000\nPUSH R9\n").is_err());
+    }
+
+    #[test]
+    fn assemble_reports_line_numbers() {
+        assert_eq!(
+            Err(String::from(
+                "line 2: expected a label or an address, got NOWHERE"
+            )),
+            assemble(".ORIG x3000\nBR NOWHERE\n").map(|_| ())
+        );
+        assert!(assemble("HALT\n").is_err());
+        assert!(assemble(".ORIG x3000\nA HALT\nA HALT\n").is_err());
+    }
+
+    #[test]
+    fn lint_warns_about_unused_labels_and_dead_code() {
+        let source = ".ORIG x3000\nSTART LD R0, DATA\nBRnzp DONE\nADD R0, R0, #1\n\
+                      SPARE\nADD R0, R0, #2\nDONE RET\nHALT\nDATA .FILL 5\n.END\n";
+        assert_eq!(
+            vec![
+                String::from("line 4: unreachable code after BRNZP on line 3"),
+                String::from("line 5: label SPARE is never used"),
+                String::from("line 8: unreachable code after RET on line 7"),
+            ],
+            assemble(source).unwrap().warnings
+        );
+
+        let far = ".ORIG x3000\nBR FAR\n.BLKW 300\nFAR HALT\n";
+        assert_eq!(
+            Err(String::from(
+                "line 2: 300 does not fit in 9 signed bits, -256 to 255; \
+                 load the target with LD and use JMP"
+            )),
+            assemble(far).map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,157 @@
+// Assembly formatter
+//
+// Lays out LC-3 assembly in columns for `lc3 fmt`, reading each line with
+// the assembler's own tokenizer so a label, a mnemonic and its operands are
+// told apart the same way `assemble` tells them apart:
+//
+//   LOOP    ADD     R1, R1, #1              ; one more
+//
+// Labels start in column 0, and mnemonics, operands and comments start at
+// the columns of the Style. A field that runs past its column is followed
+// by one space. Mnemonics and register names are upper-cased, but for the
+// conditions of branches, which are lower-cased as in BRnz. Operands are
+// separated by ", ", and trailing whitespace goes. A comment on a line of
+// its own stays in column 0 when it was there, and moves to the mnemonic
+// column otherwise. Formatting never changes what the source assembles to.
+
+use crate::asm;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    pub opcode_column: usize,
+    pub operand_column: usize,
+    pub comment_column: usize,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            opcode_column: 8,
+            operand_column: 16,
+            comment_column: 40,
+        }
+    }
+}
+
+pub fn format(source: &str, style: &Style) -> String {
+    let mut out = String::new();
+    for raw in source.lines() {
+        out.push_str(&format_line(raw, style));
+        out.push('\n');
+    }
+    out
+}
+
+fn format_line(raw: &str, style: &Style) -> String {
+    let code = asm::strip_comment(raw);
+    let comment = raw[code.len()..].trim_end();
+    let code = code.trim();
+    if code.is_empty() {
+        return match comment.is_empty() || raw.starts_with(';') {
+            true => comment.to_string(),
+            false => column(String::new(), style.opcode_column, comment),
+        };
+    }
+
+    let (first, rest) = asm::split_word(code);
+    let (label, statement) = match asm::is_mnemonic(first) || asm::is_pseudo_op(first, rest) {
+        true => ("", code),
+        false => (first, rest),
+    };
+    let (mnemonic, operands) = asm::split_word(statement);
+    let operands: Vec<String> = asm::split_operands(operands)
+        .into_iter()
+        .map(|operand| match asm::register(operand) {
+            Ok(_) => operand.to_uppercase(),
+            Err(_) => operand.to_string(),
+        })
+        .collect();
+
+    let mut line = label.to_string();
+    if !mnemonic.is_empty() {
+        line = column(line, style.opcode_column, &case(mnemonic));
+    }
+    if !operands.is_empty() {
+        line = column(line, style.operand_column, &operands.join(", "));
+    }
+    if !comment.is_empty() {
+        line = column(line, style.comment_column, comment);
+    }
+    line
+}
+
+// `mnemonic` upper-cased, except for the condition of a branch, as in BRnz.
+fn case(mnemonic: &str) -> String {
+    let upper = mnemonic.to_uppercase();
+    match upper.strip_prefix("BR") {
+        Some(condition) => format!("BR{}", condition.to_lowercase()),
+        None => upper,
+    }
+}
+
+// `line` with `field` added at column `at`, or one space after the line if
+// it already reaches that far.
+fn column(mut line: String, at: usize, field: &str) -> String {
+    let width = line.chars().count();
+    let pad = match width < at {
+        true => at - width,
+        false if width == 0 => 0,
+        false => 1,
+    };
+    line.extend(std::iter::repeat_n(' ', pad));
+    line.push_str(field);
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm,
+        asmfmt::{format, Style},
+    };
+
+    const SOURCE: &str = "\
+; doubles R1
+  .orig x3000
+main: and r1,r1,#0   ; clear
+LOOP add R1, R1,R1
+   ; comment in the code
+          brp LOOP
+PUSH R1
+FAR     .STRINGZ \"a, b;\" ;text
+     halt
+.END
+";
+
+    #[test]
+    fn columns_are_aligned() {
+        let expected = "\
+; doubles R1
+        .ORIG   x3000
+main:   AND     R1, R1, #0              ; clear
+LOOP    ADD     R1, R1, R1
+        ; comment in the code
+        BRp     LOOP
+        PUSH    R1
+FAR     .STRINGZ \"a, b;\"                ;text
+        HALT
+        .END
+";
+        let style = Style::default();
+        assert_eq!(expected, format(SOURCE, &style));
+        assert_eq!(expected, format(expected, &style));
+
+        let words = |source: &str| asm::assemble(source).unwrap().words;
+        assert_eq!(words(SOURCE), words(expected));
+
+        let narrow = Style {
+            opcode_column: 4,
+            operand_column: 10,
+            comment_column: 0,
+        };
+        assert_eq!(
+            "LOOP ADD  R1, R1, R1 ; one\n",
+            format("LOOP ADD R1,R1,R1 ; one", &narrow)
+        );
+    }
+}
@@ -0,0 +1,71 @@
+// Branch prediction statistics
+//
+// Records the outcome of every conditional branch (BR with some but not all of
+// n, z and p set) and scores three textbook predictors against them:
+// always-taken, a 1-bit last-outcome predictor and a 2-bit saturating counter.
+// The history tables have one entry per branch address, with no aliasing, and
+// start out predicting taken.
+
+use std::collections::BTreeMap;
+
+pub const PREDICTORS: [&str; 3] = ["always-taken", "1-bit", "2-bit"];
+
+#[derive(Clone, Debug, Default)]
+pub struct BranchStats {
+    pub branches: u64,
+    pub taken: u64,
+    pub mispredicted: [u64; 3], /* per predictor, in PREDICTORS order */
+    history: BTreeMap<u16, (bool, u8)>, /* last outcome and 2-bit counter */
+}
+
+impl BranchStats {
+    // Returns whether `instr` is a conditional branch.
+    pub fn is_conditional(instr: u16) -> bool {
+        let cond = (instr >> 9) & 0x7;
+        instr >> 12 == 0 && cond != 0 && cond != 0x7
+    }
+
+    pub fn record(&mut self, address: u16, taken: bool) {
+        let (last, counter) = self.history.entry(address).or_insert((true, 2));
+        let predictions = [true, *last, *counter >= 2];
+        for (count, prediction) in self.mispredicted.iter_mut().zip(predictions) {
+            if prediction != taken {
+                *count += 1;
+            }
+        }
+
+        *last = taken;
+        *counter = if taken {
+            (*counter + 1).min(3)
+        } else {
+            counter.saturating_sub(1)
+        };
+        self.branches += 1;
+        self.taken += u64::from(taken);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::branch::BranchStats;
+
+    #[test]
+    fn predictors_are_scored_per_branch() {
+        let mut stats = BranchStats::default();
+        // a loop branch taken three times, then falling through, twice over
+        for taken in [true, true, true, false, true, true, true, false] {
+            stats.record(0x3004, taken);
+        }
+
+        assert_eq!((8, 6), (stats.branches, stats.taken));
+        assert_eq!([2, 3, 2], stats.mispredicted);
+    }
+
+    #[test]
+    fn only_conditional_branches_count() {
+        assert!(BranchStats::is_conditional(0x0BFD)); // BRnp
+        assert!(!BranchStats::is_conditional(0x0FFD)); // BRnzp
+        assert!(!BranchStats::is_conditional(0x0000)); // NOP
+        assert!(!BranchStats::is_conditional(0x1BFD)); // ADD
+    }
+}
@@ -0,0 +1,271 @@
+// Breakpoints and watchpoints
+//
+// Breakpoints match on the address of an instruction or on the decoded
+// instruction itself, so a breakpoint on STI or on TRAP IN fires wherever the
+// instruction appears. A hit stops the machine before the instruction
+// executes. Watchpoints stop it after an instruction has read or written a
+// word, not counting instruction fetches.
+//
+// Both are added to a State, which hands out a BreakpointId for each one. The
+// command line and any frontend embedding the library go through the same
+// calls (see State::add_breakpoint and State::step).
+//
+// A range breakpoint fires when PC enters the range from outside it, so it
+// stops once per visit rather than on every instruction. Ranges are given as
+// two addresses or as a Span naming a routine, which runs from its label to
+// the first RET. The same spans limit tracing to part of a program.
+
+use std::fmt;
+
+use crate::{
+    asm::Symbols,
+    defs::{OP, TRAP},
+    playground,
+    state::Memory,
+};
+
+const RET: u16 = 0xC1C0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BreakpointId(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    Address(u16),    /* the instruction at this address */
+    Opcode(u16),     /* any instruction with this opcode */
+    Trap(u16),       /* any TRAP to this vector */
+    Range(u16, u16), /* entering start..=end */
+}
+
+impl Breakpoint {
+    // Whether the breakpoint fires on `instr`, about to execute at `pc` after
+    // the instruction at `previous`, if any.
+    pub fn matches(&self, pc: u16, instr: u16, previous: Option<u16>) -> bool {
+        match *self {
+            Breakpoint::Address(address) => pc == address,
+            Breakpoint::Range(start, end) => {
+                let inside = |a: u16| (start..=end).contains(&a);
+                inside(pc) && !previous.is_some_and(inside)
+            }
+            Breakpoint::Opcode(op) => instr >> 12 == op,
+            Breakpoint::Trap(vector) => instr >> 12 == OP::TRAP as u16 && instr & 0xFF == vector,
+        }
+    }
+
+    // Opcodes are named by mnemonic, e.g. STI.
+    pub fn opcode(name: &str) -> Option<Self> {
+        (0..16)
+            .find(|&op| OP::try_from(op).is_ok_and(|op| op.name().eq_ignore_ascii_case(name)))
+            .map(Breakpoint::Opcode)
+    }
+
+    // Traps are named by routine, e.g. IN, or by vector, e.g. x23.
+    pub fn trap(name: &str) -> Option<Self> {
+        let vector = match name.strip_prefix('x').or_else(|| name.strip_prefix("0x")) {
+            Some(hex) => u16::from_str_radix(hex, 16).ok().filter(|&v| v <= 0xFF),
+            None => (0x20..=0x28).find(|&v| {
+                TRAP::try_from(v).is_ok_and(|trap| trap.name().eq_ignore_ascii_case(name))
+            }),
+        };
+        vector.map(Breakpoint::Trap)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Breakpoint::Address(address) => write!(f, "x{:04X}", address),
+            Breakpoint::Range(start, end) => write!(f, "x{:04X}-x{:04X}", start, end),
+            Breakpoint::Opcode(op) => write!(f, "{}", OP::try_from(op).unwrap().name()),
+            Breakpoint::Trap(vector) => match TRAP::try_from(vector) {
+                Ok(trap) => write!(f, "TRAP {}", trap.name()),
+                Err(_) => write!(f, "TRAP x{:02X}", vector),
+            },
+        }
+    }
+}
+
+// Addresses given by the user, resolved against memory once it is loaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Span {
+    Range(u16, u16),
+    Routine(u16), /* from this address to the first RET */
+}
+
+impl Span {
+    // Parses START:END or a label, which names a routine. Addresses are
+    // numbers or labels.
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        let address = |text: &str| match symbols.get(text) {
+            Some(&address) => Ok(address),
+            None => playground::parse(text),
+        };
+        match text.split_once(':') {
+            Some((start, end)) => Ok(Span::Range(address(start)?, address(end)?)),
+            None => match symbols.get(text) {
+                Some(&start) => Ok(Span::Routine(start)),
+                None => Err(format!("{} is neither START:END nor a label", text)),
+            },
+        }
+    }
+
+    // The first and last address of the span.
+    pub fn resolve(self, mem: &Memory) -> (u16, u16) {
+        match self {
+            Span::Range(start, end) => (start.min(end), start.max(end)),
+            Span::Routine(start) => {
+                let ret = (start..=u16::MAX).find(|&a| mem.peek(a) == RET);
+                (start, ret.unwrap_or(u16::MAX))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+impl Access {
+    // Whether a watchpoint on this kind of access fires on `access`.
+    pub fn covers(self, access: Access) -> bool {
+        self == Access::Any || self == access
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    // Parses ADDR, ADDR:r, ADDR:w or ADDR:rw. A bare address watches writes.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (address, access) = text.split_once(':').unwrap_or((text, "w"));
+        let access = match access {
+            "r" => Access::Read,
+            "w" => Access::Write,
+            "rw" => Access::Any,
+            _ => return None,
+        };
+        let address = match address
+            .strip_prefix('x')
+            .or_else(|| address.strip_prefix("0x"))
+        {
+            Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+            None => address.parse().ok()?,
+        };
+        Some(Self { address, access })
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.access {
+            Access::Read => "reads of",
+            Access::Write => "writes to",
+            Access::Any => "accesses to",
+        };
+        write!(f, "{} x{:04X}", access, self.address)
+    }
+}
+
+// What stopped the machine at a breakpoint or watchpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hit {
+    Breakpoint {
+        id: BreakpointId,
+        address: u16, /* of the instruction that did not run yet */
+        breakpoint: Breakpoint,
+    },
+    Watchpoint {
+        id: BreakpointId,
+        pc: u16, /* of the instruction that made the access */
+        watchpoint: Watchpoint,
+    },
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hit::Breakpoint {
+                address,
+                breakpoint,
+                ..
+            } => write!(f, "break on {} at x{:04X}", breakpoint, address),
+            Hit::Watchpoint { pc, watchpoint, .. } => {
+                write!(f, "watch on {} at x{:04X}", watchpoint, pc)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::Symbols,
+        breakpoints::{Access, Breakpoint, Span, Watchpoint},
+        state::Memory,
+    };
+
+    #[test]
+    fn parse_and_match_breakpoints() {
+        let sti = Breakpoint::opcode("sti").unwrap();
+        assert!(sti.matches(0x3000, 0xB000, None));
+        assert!(!sti.matches(0x3000, 0xA000, None));
+        assert!(Breakpoint::opcode("MOV").is_none());
+
+        let input = Breakpoint::trap("IN").unwrap();
+        assert_eq!(Some(input), Breakpoint::trap("x23"));
+        assert!(input.matches(0x3000, 0xF023, None));
+        assert!(!input.matches(0x3000, 0xF020, None));
+        assert_eq!("TRAP IN", input.to_string());
+        assert!(Breakpoint::trap("x100").is_none());
+
+        assert!(Breakpoint::Address(0x3004).matches(0x3004, 0x0000, None));
+        assert!(!Breakpoint::Address(0x3004).matches(0x3005, 0x0000, None));
+    }
+
+    #[test]
+    fn ranges_fire_on_entry() {
+        let range = Breakpoint::Range(0x3010, 0x301F);
+        assert!(range.matches(0x3010, 0, Some(0x3004)));
+        assert!(range.matches(0x3015, 0, None));
+        assert!(!range.matches(0x3011, 0, Some(0x3010)));
+        assert!(!range.matches(0x3020, 0, Some(0x301F)));
+    }
+
+    #[test]
+    fn spans_are_parsed_and_resolved() {
+        let symbols = Symbols::from([(String::from("SUBR"), 0x3010)]);
+        assert_eq!(
+            Ok(Span::Range(0x3000, 0x30FF)),
+            Span::parse("x3000:x30FF", &symbols)
+        );
+        assert_eq!(
+            Ok(Span::Range(0x3010, 0x3020)),
+            Span::parse("SUBR:x3020", &symbols)
+        );
+        assert_eq!(Ok(Span::Routine(0x3010)), Span::parse("SUBR", &symbols));
+        assert!(Span::parse("LOOP", &symbols).is_err());
+
+        let mut mem = Memory::default();
+        mem.write_slice(0x3010, &[0x1261, 0x0000, 0xC1C0]);
+        assert_eq!((0x3010, 0x3012), Span::Routine(0x3010).resolve(&mem));
+        assert_eq!((0x3000, 0x30FF), Span::Range(0x30FF, 0x3000).resolve(&mem));
+    }
+
+    #[test]
+    fn parse_watchpoints() {
+        let watch = |address, access| Some(Watchpoint { address, access });
+        assert_eq!(watch(0x4000, Access::Write), Watchpoint::parse("x4000"));
+        assert_eq!(watch(0x4000, Access::Read), Watchpoint::parse("0x4000:r"));
+        assert_eq!(watch(16, Access::Any), Watchpoint::parse("16:rw"));
+        assert_eq!(None, Watchpoint::parse("x4000:x"));
+        assert!(Access::Any.covers(Access::Read));
+        assert!(!Access::Write.covers(Access::Read));
+    }
+}
@@ -0,0 +1,120 @@
+// Program bundles
+//
+// A bundle packs several object images into one file, so an OS image and the
+// user program it runs can be handed out together. It is a sequence of
+// big-endian words:
+//
+//   BUNDLE_MAGIC (two words)  start PC  image count
+//   for each image: its length in words, then the words of the image file
+//
+// The list doubles as the manifest: images are loaded in the order they are
+// stored, so a later image overwrites an earlier one where they overlap. Each
+// image keeps its own origin, checksum record and metadata block, which is
+// where symbols travel.
+
+use std::io::{self, ErrorKind};
+
+use crate::loader::Image;
+
+pub const BUNDLE_MAGIC: [u16; 2] = [0x4C43, 0x3342]; /* "LC3B" */
+
+#[derive(Debug, PartialEq)]
+pub struct Bundle {
+    pub start: u16,
+    pub images: Vec<Vec<u8>>, /* object files, in load order */
+}
+
+impl Bundle {
+    pub fn is_bundle(bytes: &[u8]) -> bool {
+        bytes.len() >= 4 && words(&bytes[..4]).eq(BUNDLE_MAGIC)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if !Self::is_bundle(bytes) || !bytes.len().is_multiple_of(2) {
+            return Err(invalid("not a bundle"));
+        }
+        let words: Vec<u16> = words(&bytes[4..]).collect();
+        let (&[start, count], mut rest) = words
+            .split_first_chunk::<2>()
+            .ok_or_else(|| invalid("bundle header is truncated"))?;
+
+        let mut images = Vec::new();
+        for i in 0..count {
+            let (&length, after) = rest
+                .split_first()
+                .ok_or_else(|| invalid(&format!("bundle ends before image {}", i)))?;
+            let (image, after) = after
+                .split_at_checked(length as usize)
+                .ok_or_else(|| invalid(&format!("image {} runs past the end of the bundle", i)))?;
+            images.push(image.iter().flat_map(|w| w.to_be_bytes()).collect());
+            rest = after;
+        }
+        if !rest.is_empty() {
+            return Err(invalid(&format!(
+                "{} words follow the last image",
+                rest.len()
+            )));
+        }
+        Ok(Self { start, images })
+    }
+
+    // Checks that every image parses before packing them.
+    pub fn new(start: u16, images: Vec<Vec<u8>>) -> io::Result<Self> {
+        for (i, image) in images.iter().enumerate() {
+            Image::parse(image, false).map_err(|e| invalid(&format!("image {}: {}", i, e)))?;
+            if image.len() / 2 > u16::MAX as usize {
+                return Err(invalid(&format!("image {} is too large to bundle", i)));
+            }
+        }
+        if images.len() > u16::MAX as usize {
+            return Err(invalid("too many images for one bundle"));
+        }
+        Ok(Self { start, images })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = BUNDLE_MAGIC.to_vec();
+        words.extend([self.start, self.images.len() as u16]);
+        for image in &self.images {
+            words.push((image.len() / 2) as u16);
+            words.extend(self::words(image));
+        }
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::Bundle;
+
+    #[test]
+    fn bundle_round_trip() {
+        let os = vec![0x02, 0x00, 0xF0, 0x25];
+        let user = vec![0x30, 0x00, 0x12, 0x61, 0xF0, 0x25];
+        let bundle = Bundle::new(0x0200, vec![os, user]).unwrap();
+
+        let bytes = bundle.to_bytes();
+        assert!(Bundle::is_bundle(&bytes));
+        assert_eq!(bundle, Bundle::parse(&bytes).unwrap());
+    }
+
+    #[test]
+    fn truncated_bundle_is_rejected() {
+        let bundle = Bundle::new(0x3000, vec![vec![0x30, 0x00, 0xF0, 0x25]]).unwrap();
+        let bytes = bundle.to_bytes();
+
+        assert!(Bundle::parse(&bytes[..bytes.len() - 2]).is_err());
+        assert!(!Bundle::is_bundle(&[0x30, 0x00, 0xF0, 0x25]));
+        assert!(Bundle::new(0x3000, vec![vec![0x30]]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+// Cache simulation
+//
+// An optional model of an instruction cache and a data cache for architecture
+// coursework. It only counts hits and misses: memory contents and timing are
+// unaffected. Each cache is set-associative with LRU replacement, and sizes are
+// given in words since that is the unit LC-3 memory is addressed in.
+//
+// Accesses are also attributed to the subroutine that made them. The current
+// subroutine is followed through JSR/JSRR and RET, starting at the first
+// instruction fetched.
+
+use std::collections::BTreeMap;
+
+use crate::defs::OP;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheConfig {
+    pub size: usize, /* words */
+    pub ways: usize,
+    pub line: usize, /* words per line */
+}
+
+impl CacheConfig {
+    // Parses SIZE:WAYS:LINE, e.g. 256:2:4.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let fields: Vec<usize> = text
+            .split(':')
+            .map(|field| {
+                field
+                    .parse()
+                    .map_err(|_| format!("cache geometry {} is not SIZE:WAYS:LINE", text))
+            })
+            .collect::<Result<_, _>>()?;
+        let &[size, ways, line] = fields.as_slice() else {
+            return Err(format!("cache geometry {} is not SIZE:WAYS:LINE", text));
+        };
+        if ways == 0 || line == 0 || size == 0 || !size.is_multiple_of(ways * line) {
+            return Err(format!(
+                "a cache of {} words cannot hold {}-way sets of {}-word lines",
+                size, ways, line
+            ));
+        }
+        Ok(Self { size, ways, line })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Counts {
+    fn count(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cache {
+    pub config: CacheConfig,
+    sets: Vec<Vec<(usize, u64)>>, /* tag and last use of each resident line */
+    uses: u64,
+    pub counts: Counts,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            sets: vec![Vec::new(); config.size / (config.ways * config.line)],
+            uses: 0,
+            counts: Counts::default(),
+        }
+    }
+
+    // Looks `address` up, filling its line on a miss. Returns whether it hit.
+    pub fn access(&mut self, address: u16) -> bool {
+        self.uses += 1;
+        let block = address as usize / self.config.line;
+        let (index, tag) = (block % self.sets.len(), block / self.sets.len());
+        let set = &mut self.sets[index];
+
+        let hit = match set.iter_mut().find(|(t, _)| *t == tag) {
+            Some(line) => {
+                line.1 = self.uses;
+                true
+            }
+            None => {
+                if set.len() == self.config.ways {
+                    let lru = (0..set.len()).min_by_key(|&i| set[i].1).unwrap();
+                    set.swap_remove(lru);
+                }
+                set.push((tag, self.uses));
+                false
+            }
+        };
+        self.counts.count(hit);
+        hit
+    }
+}
+
+// Hit and miss counts of one subroutine
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoutineCounts {
+    pub icache: Counts,
+    pub dcache: Counts,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CacheSim {
+    pub icache: Option<Cache>,
+    pub dcache: Option<Cache>,
+    calls: Vec<u16>, /* entry addresses of the active subroutines */
+    pub routines: BTreeMap<u16, RoutineCounts>,
+}
+
+impl CacheSim {
+    pub fn new(icache: Option<CacheConfig>, dcache: Option<CacheConfig>) -> Self {
+        Self {
+            icache: icache.map(Cache::new),
+            dcache: dcache.map(Cache::new),
+            ..Self::default()
+        }
+    }
+
+    pub fn fetch(&mut self, address: u16) {
+        if self.calls.is_empty() {
+            self.calls.push(address);
+        }
+        if let Some(cache) = &mut self.icache {
+            let hit = cache.access(address);
+            self.routine().icache.count(hit);
+        }
+    }
+
+    pub fn data(&mut self, address: u16) {
+        if let Some(cache) = &mut self.dcache {
+            let hit = cache.access(address);
+            self.routine().dcache.count(hit);
+        }
+    }
+
+    // Follows calls and returns after `instr` has executed and left PC at `pc`.
+    pub fn follow(&mut self, instr: u16, pc: u16) {
+        let base = (instr >> 6) & 0x7;
+        match OP::try_from(instr >> 12) {
+            Ok(OP::JSR) => self.calls.push(pc),
+            Ok(OP::JMP) if base == 7 && self.calls.len() > 1 => {
+                self.calls.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn routine(&mut self) -> &mut RoutineCounts {
+        let entry = self.calls.last().copied().unwrap_or_default();
+        self.routines.entry(entry).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{Cache, CacheConfig, CacheSim};
+
+    #[test]
+    fn parse_geometry() {
+        let config = CacheConfig::parse("256:2:4").unwrap();
+        assert_eq!((256, 2, 4), (config.size, config.ways, config.line));
+        assert!(CacheConfig::parse("256:2").is_err());
+        assert!(CacheConfig::parse("10:4:4").is_err());
+    }
+
+    #[test]
+    fn lines_are_filled_and_evicted_lru() {
+        // two sets of two 4-word lines
+        let mut cache = Cache::new(CacheConfig::parse("16:2:4").unwrap());
+        assert!(!cache.access(0x3000));
+        assert!(cache.access(0x3003)); /* same line */
+        assert!(!cache.access(0x3008)); /* same set, second way */
+        assert!(cache.access(0x3000)); /* 0x3008 is now least recently used */
+        assert!(!cache.access(0x3010)); /* evicts 0x3008 */
+        assert!(cache.access(0x3000));
+        assert!(!cache.access(0x3008));
+        assert_eq!((3, 4), (cache.counts.hits, cache.counts.misses));
+    }
+
+    #[test]
+    fn accesses_are_attributed_to_subroutines() {
+        let mut sim = CacheSim::new(CacheConfig::parse("16:1:4").ok(), None);
+        sim.fetch(0x3000);
+        sim.follow(0x4810, 0x3011); // JSR to x3011
+        sim.fetch(0x3011);
+        sim.follow(0xC1C0, 0x3001); // RET
+        sim.fetch(0x3001);
+
+        assert_eq!(
+            2,
+            sim.routines[&0x3000].icache.hits + sim.routines[&0x3000].icache.misses
+        );
+        assert_eq!(1, sim.routines[&0x3011].icache.misses);
+    }
+}
@@ -0,0 +1,211 @@
+// Shadow call stack
+//
+// The machine has no call stack of its own: JSR and JSRR leave the return
+// address in R7 and RET jumps back through it. State follows every
+// instruction with a CallStack, which pushes a Frame on a call and pops it
+// when a RET lands on its return address. A RET that matches no frame, as
+// from a trap routine, leaves the stack alone, and one that matches an outer
+// frame drops everything above it, as after a longjmp.
+//
+// The arguments kept with a frame are R0 to R3 as they were at the call.
+//
+// The stack also catches the classic bug of a subroutine that calls another
+// one, or a trap, without saving R7 first. Once a routine has copied R7
+// anywhere, to memory or to another register, it is taken to have saved it;
+// until then, overwriting R7 while it holds the return address loses the
+// way back, and follow reports the address RET will now jump to. It also
+// reports a routine that returns with R6, the stack pointer by convention,
+// somewhere other than where the call left it: a push without a pop, or the
+// other way round.
+
+use crate::{
+    defs::{OP, R},
+    state::Registers,
+};
+
+const MAX_DEPTH: usize = 1024;
+const RET: u16 = 0xC1C0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub call_site: u16, /* address of the JSR or JSRR */
+    pub routine: u16,
+    pub return_to: u16,
+    pub args: [u16; 4],
+    pub settled: bool, /* R7 was saved, or its loss reported */
+    pub sp: u16,       /* R6 at the call */
+}
+
+// Something follow noticed about the program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Clobbered(u16), /* an unsaved R7 overwritten, with its new value */
+    Unbalanced { frame: Frame, sp: u16 }, /* returned from `frame` with R6 at sp */
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>, /* the innermost call last */
+}
+
+impl CallStack {
+    // Takes note of `instr`, which ran at `pc` with `r7` in R7 and left the
+    // registers as `reg`.
+    pub fn follow(&mut self, pc: u16, instr: u16, r7: u16, reg: &Registers) -> Option<Event> {
+        let mut event = None;
+        if let Some(frame) = self.frames.last_mut().filter(|f| f.return_to == r7) {
+            if reads_r7(instr) {
+                frame.settled = true;
+            } else if !frame.settled && reg[R::R7] != r7 {
+                /* only the first loss is worth reporting */
+                event = Some(Event::Clobbered(reg[R::R7]));
+                frame.settled = true;
+            }
+        }
+
+        if instr >> 12 == OP::JSR as u16 {
+            if self.frames.len() == MAX_DEPTH {
+                /* runaway recursion, forget the outermost call */
+                self.frames.remove(0);
+            }
+            self.frames.push(Frame {
+                call_site: pc,
+                routine: reg.pc(),
+                return_to: pc.wrapping_add(1),
+                args: [R::R0, R::R1, R::R2, R::R3].map(|r| reg[r]),
+                settled: false,
+                sp: reg[R::R6],
+            });
+        } else if instr == RET {
+            let pc = reg.pc();
+            if let Some(i) = self.frames.iter().rposition(|f| f.return_to == pc) {
+                let frame = self.frames[i];
+                /* unwinding several frames at once has no balance to keep */
+                if i + 1 == self.frames.len() && reg[R::R6] != frame.sp {
+                    event = Some(Event::Unbalanced {
+                        frame,
+                        sp: reg[R::R6],
+                    });
+                }
+                self.frames.truncate(i);
+            }
+        }
+        event
+    }
+
+    // The frames, innermost first.
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev()
+    }
+
+    // The frame `n` calls out from the innermost one.
+    pub fn frame(&self, n: usize) -> Option<&Frame> {
+        self.frames.iter().rev().nth(n)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+// Whether `instr` reads R7 as a source, other than to jump through it.
+fn reads_r7(instr: u16) -> bool {
+    let field = |shift: u16| (instr >> shift) & 0x7 == 7;
+    match OP::try_from(instr >> 12) {
+        Ok(OP::ST | OP::STI | OP::STR) => field(9),
+        Ok(OP::NOT) => field(6),
+        Ok(OP::ADD | OP::AND) => field(6) || (instr & 0x20 == 0 && field(0)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        callstack::{CallStack, Event, Frame},
+        defs::R,
+        state::Registers,
+    };
+
+    fn call(stack: &mut CallStack, reg: &mut Registers, pc: u16, routine: u16) -> Option<Event> {
+        let r7 = reg[R::R7];
+        reg.set(R::R7, pc.wrapping_add(1));
+        reg.set_pc(routine);
+        stack.follow(pc, 0x4800, r7, reg)
+    }
+
+    fn ret(stack: &mut CallStack, reg: &mut Registers) -> Option<Event> {
+        reg.set_pc(reg[R::R7]);
+        stack.follow(0, 0xC1C0, reg[R::R7], reg)
+    }
+
+    #[test]
+    fn calls_push_and_returns_pop() {
+        let mut stack = CallStack::default();
+        let mut reg = Registers::new(0x3000);
+        reg.set(R::R1, 7);
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        call(&mut stack, &mut reg, 0x3012, 0x3020);
+        assert_eq!(2, stack.depth());
+        assert_eq!(
+            Some(&Frame {
+                call_site: 0x3000,
+                routine: 0x3010,
+                return_to: 0x3001,
+                args: [0, 7, 0, 0],
+                settled: true, /* the nested call overwrote R7 */
+                sp: 0,
+            }),
+            stack.frame(1)
+        );
+
+        ret(&mut stack, &mut reg);
+        assert_eq!(0x3000, stack.frame(0).unwrap().call_site);
+        // a RET nothing called, as from a trap routine
+        reg.set(R::R7, 0x4000);
+        ret(&mut stack, &mut reg);
+        assert_eq!(1, stack.depth());
+    }
+
+    #[test]
+    fn unsaved_return_addresses_are_reported() {
+        let mut stack = CallStack::default();
+        let mut reg = Registers::new(0x3000);
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        let clobbered = call(&mut stack, &mut reg, 0x3012, 0x3020);
+        assert_eq!(Some(Event::Clobbered(0x3013)), clobbered);
+
+        // ST R7 saves it, so the nested call is fine
+        let mut stack = CallStack::default();
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        stack.follow(0x3010, 0x3E05, 0x3001, &reg);
+        assert_eq!(None, call(&mut stack, &mut reg, 0x3012, 0x3020));
+    }
+
+    #[test]
+    fn returning_past_frames_unwinds_them() {
+        let mut stack = CallStack::default();
+        let mut reg = Registers::new(0x3000);
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        call(&mut stack, &mut reg, 0x3012, 0x3020);
+        reg.set(R::R7, 0x3001);
+        ret(&mut stack, &mut reg);
+        assert_eq!(0, stack.depth());
+    }
+
+    #[test]
+    fn returns_with_a_moved_stack_pointer_are_reported() {
+        let mut stack = CallStack::default();
+        let mut reg = Registers::new(0x3000);
+        reg.set(R::R6, 0xFE00);
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        reg.set(R::R6, 0xFDFF); // a push with no pop
+        let Some(Event::Unbalanced { frame, sp }) = ret(&mut stack, &mut reg) else {
+            panic!("no event");
+        };
+        assert_eq!((0x3000, 0xFE00, 0xFDFF), (frame.call_site, frame.sp, sp));
+
+        call(&mut stack, &mut reg, 0x3000, 0x3010);
+        assert_eq!(None, ret(&mut stack, &mut reg));
+    }
+}
@@ -0,0 +1,92 @@
+// Control-flow integrity checks
+//
+// With --cfi every JMP must land on an address the program has good reason
+// to go to, or the machine stops with RuntimeError::WildJump before the jump
+// is taken. The return addresses handed out by JSR, JSRR and any TRAP that
+// runs a service routine are kept on a stack of their own:
+//
+//   RET        must return to one of them, which drops it and any above it
+//   JMP BaseR  may also go to a label from the symbol table
+//
+// A RET that returns to a return address already used, or to one that was
+// never handed out, almost always comes from an R7 clobbered by a nested
+// call or a trap.
+
+use crate::{asm::Symbols, defs::OP};
+
+const MAX_DEPTH: usize = 1024;
+const RET: u16 = 0xC1C0;
+
+#[derive(Clone, Debug, Default)]
+pub struct FlowCheck {
+    labels: Vec<u16>,
+    returns: Vec<u16>, /* return addresses not yet returned to, the latest last */
+}
+
+impl FlowCheck {
+    pub fn new(symbols: &Symbols) -> Self {
+        Self {
+            labels: symbols.values().copied().collect(),
+            returns: Vec::new(),
+        }
+    }
+
+    // Checks `instr`, about to execute, where a JMP would go to `target`.
+    // Returns false for a wild jump.
+    pub fn check(&mut self, instr: u16, target: u16) -> bool {
+        if instr >> 12 != OP::JMP as u16 {
+            return true;
+        }
+        match self.returns.iter().rposition(|&r| r == target) {
+            Some(i) => {
+                self.returns.truncate(i);
+                true
+            }
+            None => instr != RET && self.labels.contains(&target),
+        }
+    }
+
+    // Takes note of `instr`, which ran at `pc` and left PC at `next`.
+    pub fn follow(&mut self, pc: u16, instr: u16, next: u16) {
+        let returns = pc.wrapping_add(1);
+        let call = match OP::try_from(instr >> 12) {
+            Ok(OP::JSR) => true,
+            Ok(OP::TRAP) => next != returns, /* not serviced by the machine */
+            _ => false,
+        };
+        if call {
+            if self.returns.len() == MAX_DEPTH {
+                self.returns.remove(0);
+            }
+            self.returns.push(returns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{asm::Symbols, cfi::FlowCheck};
+
+    #[test]
+    fn returns_need_a_live_return_address() {
+        let mut check = FlowCheck::default();
+        assert!(!check.check(0xC1C0, 0x3001));
+        check.follow(0x3000, 0x4801, 0x3002); // JSR A
+        check.follow(0x3002, 0x4801, 0x3004); // JSR B
+        assert!(check.check(0xC1C0, 0x3003));
+        // R7 still holds the return address into A
+        assert!(!check.check(0xC1C0, 0x3003));
+        assert!(check.check(0xC1C0, 0x3001));
+    }
+
+    #[test]
+    fn jumps_may_go_to_labels() {
+        let symbols = Symbols::from([(String::from("TABLE"), 0x3100)]);
+        let mut check = FlowCheck::new(&symbols);
+        assert!(check.check(0xC080, 0x3100)); // JMP R2
+        assert!(!check.check(0xC080, 0x3101));
+        assert!(!check.check(0xC1C0, 0x3100));
+        check.follow(0x3000, 0xF025, 0x3001); // HALT, done by the machine
+        assert!(!check.check(0xC1C0, 0x3001));
+    }
+}
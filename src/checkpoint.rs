@@ -0,0 +1,90 @@
+// Checkpoints
+//
+// A ring of machine snapshots taken every `interval` instructions. Only the
+// newest `capacity` are kept, which gives coarse time travel back from a
+// failure without recording a full trace.
+
+use std::collections::VecDeque;
+
+use crate::state::State;
+
+#[derive(Debug)]
+pub struct Checkpoints {
+    interval: u64,
+    capacity: usize,
+    ring: VecDeque<State>, /* oldest first */
+}
+
+impl Checkpoints {
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            interval,
+            capacity,
+            ring: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // Snapshots `state` if it has reached the next multiple of the interval.
+    pub fn record(&mut self, state: &State) {
+        let count = state.stats.instructions;
+        if self.capacity == 0 || !count.is_multiple_of(self.interval) {
+            return;
+        }
+        if self
+            .ring
+            .back()
+            .is_some_and(|s| s.stats.instructions == count)
+        {
+            return;
+        }
+        if self.ring.len() == self.capacity {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(state.clone());
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    // Returns the k-th newest checkpoint, 1 being the most recent.
+    pub fn rollback(&self, k: usize) -> Option<&State> {
+        let index = self.ring.len().checked_sub(k).filter(|_| k > 0)?;
+        self.ring.get(index)
+    }
+
+    // Drops the checkpoints newer than the k-th newest, which a machine
+    // restored to it has not reached yet, and returns that one.
+    pub fn rewind(&mut self, k: usize) -> Option<&State> {
+        let index = self.ring.len().checked_sub(k).filter(|_| k > 0)?;
+        self.ring.truncate(index + 1);
+        self.ring.back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{checkpoint::Checkpoints, defs::R, state::State};
+
+    #[test]
+    fn ring_keeps_the_newest_checkpoints() {
+        let mut state = State::new();
+        let mut checkpoints = Checkpoints::new(2, 2);
+
+        for _ in 0..7 {
+            state.execute_word(0x1021); // ADD R0, R0, #1
+            checkpoints.record(&state);
+        }
+
+        /* taken after instructions 2, 4 and 6, the first was dropped */
+        assert_eq!(2, checkpoints.len());
+        assert_eq!(6, checkpoints.rollback(1).unwrap().reg[R::R0]);
+        assert_eq!(4, checkpoints.rollback(2).unwrap().reg[R::R0]);
+        assert!(checkpoints.rollback(3).is_none());
+        assert!(checkpoints.rollback(0).is_none());
+    }
+}
@@ -0,0 +1,959 @@
+// Command line parsing
+
+use crate::{
+    asmfmt::Style,
+    breakpoints::{Breakpoint, Watchpoint},
+    cache::CacheConfig,
+    clock::ClockMode,
+    color::ColorChoice,
+    config::Config,
+    console::{Encoding, Enter, EofPolicy},
+    defs::TRAP,
+    diag::{Check, Severity},
+    instr::UnknownTrap,
+    loader::Arg,
+    policy,
+    state::{MEMORY_MAX, PC_START},
+};
+
+pub const USAGE: &str = "lc3 [--config FILE] [--verify] [--utf8 | --wide-chars] [--special-keys]
+    [--enter lf|cr] [--echo] [--crlf] [--on-eof halt|error|VALUE]
+    [--stdin-fd N | --console-pipe PATH] [--trap-unknown ignore|error]
+    [--trap-base ADDR] [--trap-routine NAME:ADDR]
+    [--break-opcode OP] [--break-trap NAME|VECTOR] [--break-at ADDR]
+    [--break-range START:END|LABEL] [--watch ADDR[:r|:w|:rw]] [--trace]
+    [--trace-traps] [--trace-only START:END|LABEL] [--color auto|always|never]
+    [--stats] [--opcode-stats FILE] [--detect-loops] [--no-pc-checks] [--cfi]
+    [--history N] [--require NAME,...] [--forbid NAME,...] [--policy FILE]
+    [--warn CHECK,...] [--error CHECK,...] [--no-warn CHECK,...]
+    [--print-state-on-halt] [--checkpoint-interval N [--rollback K]]
+    [--args-at ADDR [--arg-string TEXT] [--arg-word VALUE] [--pack-args]]
+    [--max-instructions N] [--max-output-bytes N] [--max-mem-writes N]
+    [--env-block [--seed N]] [--start-all [--quantum N]] [--exit-code]
+    [--clock uptime|realtime] [--deterministic] [--counters [--mask-counters]]
+    [--snapshot FILE] [--assert-state FILE [--regs-only]] [--record FILE]
+    [--perfetto FILE] [--vectors FILE [--strict-vectors]]
+    [--icache SIZE:WAYS:LINE] [--dcache SIZE:WAYS:LINE]
+    [--pipeline FIRST[:COUNT] [--pipeline-csv FILE]] [--memory-size WORDS]
+    [--fill-pattern VALUE] [--guard-images] [--map] [image-file1] ...
+lc3 debug [--init FILE] [--source FILE] ... [image-file1] ...
+lc3 dap [--config FILE]
+lc3 analyze [image-file1] ...
+lc3 explore [--depth N] [--steps N] [--inputs CHARS] [image-file1] ...
+lc3 dump [--start ADDR] [--length N] [--stride N] [image-file1] ...
+lc3 isa [MNEMONIC]
+lc3 encode [--at ADDR] INSTRUCTION
+lc3 decode [--at ADDR] WORD
+lc3 flags [VALUE] ...
+lc3 asm [--output FILE] [--listing FILE] [--symbols FILE] [--hash]
+    [--metadata] [--strict] SOURCE
+lc3 fmt [--check] [--columns OPCODE:OPERANDS:COMMENT] SOURCE ...
+lc3 lsp [--stdio]
+lc3 bundle [--start ADDR] [--output FILE] image-file1 ...
+lc3 diff-run [--input TEXT | --input-file FILE] [--steps N] IMAGE-A IMAGE-B
+lc3 snapshot-diff SNAPSHOT-A SNAPSHOT-B
+lc3 trace-view TRACE
+lc3 selftest
+lc3 grade --spec FILE [--report FILE] SOURCE
+lc3 help";
+
+// Shown by lc3 help after the usage, for behaviour the flags do not explain
+pub const NOTES: &str =
+    "--start-all runs each image as a process and switches to the next one every
+--quantum instructions. The emulator makes the switch, not a timer interrupt:
+the machine has no interrupt model, so no guest handler runs and a program
+can neither see nor mask the switch. TRAP SLEEP ends a turn early.";
+
+pub enum Command {
+    Run,
+    Debug(DebugOptions), /* commands are read from stdin, see debugger.rs */
+    Dap,                 /* a debug adapter on stdin and stdout, see dap.rs */
+    Analyze,
+    Explore(ExploreOptions),
+    Dump(DumpOptions),
+    Isa(Option<String>), /* show the reference for one instruction, or list them all */
+    Encode(CodecOptions),
+    Decode(CodecOptions),
+    Flags(Vec<String>), /* values to describe, read from stdin if empty */
+    Asm(AsmOptions),
+    Fmt(FmtOptions),
+    Lsp, /* a language server on stdin and stdout, see lsp.rs */
+    Bundle(BundleOptions),
+    DiffRun(DiffOptions),
+    SnapshotDiff, /* the two snapshots are taken from the image list */
+    TraceView,    /* the trace is taken from the image list, see tracefile.rs */
+    Selftest,
+    Grade(GradeOptions), /* see grade.rs */
+    Help,
+}
+
+#[derive(Default)]
+pub struct GradeOptions {
+    pub source: Option<String>,
+    pub spec: Option<String>,
+    pub report: Option<String>, /* Markdown, or HTML for an .html file; stdout if unset */
+}
+
+#[derive(Default)]
+pub struct AsmOptions {
+    pub source: Option<String>,
+    pub output: Option<String>, /* defaults to the source with an .obj extension */
+    pub listing: Option<String>,
+    pub symbols: Option<String>,
+    pub hash: bool,     /* print a content hash of each output */
+    pub metadata: bool, /* append version, source hash, symbols and lines to the object */
+    pub strict: bool,   /* no pseudo-instructions, see asm.rs */
+}
+
+#[derive(Default)]
+pub struct FmtOptions {
+    pub check: bool, /* report the files that would change instead of rewriting them */
+    pub style: Style,
+}
+
+pub struct BundleOptions {
+    pub output: Option<String>, /* defaults to bundle.lc3 */
+    pub start: u16,             /* PC the bundle starts at */
+}
+
+impl Default for BundleOptions {
+    fn default() -> Self {
+        Self {
+            output: None,
+            start: PC_START,
+        }
+    }
+}
+
+pub struct DiffOptions {
+    pub input: Vec<u8>,             /* fed to both machines as keyboard input */
+    pub input_file: Option<String>, /* read as input instead of --input */
+    pub steps: u64,                 /* instructions compared before giving up */
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            input: Vec::new(),
+            input_file: None,
+            steps: 1_000_000,
+        }
+    }
+}
+
+pub struct CodecOptions {
+    pub input: Option<String>, /* the instruction or word to convert */
+    pub at: u16,               /* address PC-relative operands refer from */
+}
+
+impl Default for CodecOptions {
+    fn default() -> Self {
+        Self {
+            input: None,
+            at: PC_START,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DebugOptions {
+    pub init: Option<String>, /* script run before reading commands, ~/.lc3init by default */
+    pub sources: Vec<String>, /* assembly sources of the images, besides NAME.asm next to each */
+}
+
+pub struct ExploreOptions {
+    pub depth: usize,    /* maximum number of forks along a single path */
+    pub steps: usize,    /* instructions executed per path before giving up */
+    pub inputs: Vec<u8>, /* candidate characters returned by GETC/IN */
+}
+
+impl Default for ExploreOptions {
+    fn default() -> Self {
+        Self {
+            depth: 8,
+            steps: 100_000,
+            inputs: b"yn01".to_vec(),
+        }
+    }
+}
+
+pub struct DumpOptions {
+    pub start: Option<u16>, /* first address shown, defaults to each image's origin */
+    pub length: Option<usize>, /* words shown, defaults to the image length */
+    pub stride: usize,      /* words per row */
+}
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        Self {
+            start: None,
+            length: None,
+            stride: 8,
+        }
+    }
+}
+
+// An alternative to stdin for guest keyboard input
+pub enum InputSource {
+    Fd(i32),      /* an already open descriptor inherited from the parent */
+    Pipe(String), /* a path to open for reading, typically a FIFO */
+}
+
+pub struct Options {
+    pub command: Command,
+    pub images: Vec<String>,
+    pub config: Option<String>,
+    pub verify: bool, /* require a valid checksum record in every image */
+    pub encoding: Option<Encoding>, /* overrides the configured console encoding */
+    pub enter: Option<Enter>,
+    pub echo: bool,
+    pub crlf: bool,
+    pub special_keys: bool, /* arrow and function keys as single codes, see console.rs */
+    pub on_eof: Option<EofPolicy>,
+    pub input: Option<InputSource>, /* where keystrokes come from, stdin if unset */
+    pub unknown_trap: Option<UnknownTrap>,
+    pub trap_base: Option<u16>,          /* moves the trap vector table */
+    pub trap_routines: Vec<(u16, TRAP)>, /* addresses of built-in routines */
+    pub stats: bool,                     /* print execution statistics at exit */
+    pub opcode_stats: Option<String>,    /* CSV of instruction counts, see opstats.rs */
+    pub detect_loops: bool,              /* stop a program stuck in a loop, see loopcheck.rs */
+    pub pc_checks: bool,                 /* stop when PC strays into tables or devices */
+    pub cfi: bool,                       /* stop on wild jumps, see cfi.rs */
+    pub history: Option<usize>,          /* instructions listed after an error */
+    pub required: Vec<&'static str>,     /* instructions the program must run, see policy.rs */
+    pub forbidden: Vec<&'static str>,
+    pub policy: Option<String>, /* a policy file, read once the options are parsed */
+    pub checks: Vec<(Check, Severity)>, /* severities from --warn and the like, see diag.rs */
+    pub trace: bool,            /* log every instruction */
+    pub color: ColorChoice,     /* colour for the trace, disassembly and debugger, see color.rs */
+    pub trace_traps: bool,      /* log every TRAP with its arguments */
+    pub trace_only: Vec<String>, /* spans traced, resolved once the images are loaded */
+    pub print_state: bool,      /* print registers and the next instruction at exit */
+    pub breakpoints: Vec<Breakpoint>,
+    pub break_ranges: Vec<String>, /* spans, like trace_only */
+    pub watchpoints: Vec<Watchpoint>,
+    pub checkpoint_interval: Option<u64>, /* instructions between checkpoints */
+    pub rollback: usize, /* checkpoint restored after a runtime error, 1 being the newest */
+    pub args_at: Option<u16>, /* where guest arguments are written */
+    pub args: Vec<Arg>,
+    pub pack_args: bool, /* two characters per word instead of one */
+    pub max_instructions: Option<u64>,
+    pub max_output: Option<u64>, /* console bytes, see config.rs */
+    pub max_writes: Option<u64>,
+    pub env_block: bool, /* describe the run in the environment block */
+    pub seed: Option<u16>,
+    pub start_all: bool, /* run every image as a process, round-robin */
+    pub quantum: u64,    /* instructions per turn with --start-all */
+    pub exit_code: bool, /* exit with the status passed to TRAP EXIT */
+    pub clock_mode: Option<ClockMode>,
+    pub deterministic: bool,      /* derive time from the instruction count */
+    pub counters: bool,           /* map the performance counters */
+    pub mask_counters: bool,      /* and have them read zero */
+    pub snapshot: Option<String>, /* written once the machine stops */
+    pub assert_state: Option<String>, /* a snapshot to match once the machine stops */
+    pub regs_only: bool,          /* compare only the registers with it */
+    pub record: Option<String>,   /* a trace of the run, see tracefile.rs */
+    pub perfetto: Option<String>, /* a timeline of calls and traps, see timeline.rs */
+    pub vectors: Option<String>,  /* image loaded into the vector tables first */
+    pub strict_vectors: bool,     /* every unserviced TRAP needs a vector */
+    pub icache: Option<CacheConfig>,
+    pub dcache: Option<CacheConfig>,
+    pub pipeline: Option<(u64, usize)>, /* first instruction and count to diagram */
+    pub pipeline_csv: Option<String>,
+    pub memory_size: Option<usize>, /* words, the rest of the address space faults */
+    pub fill: Option<u16>,          /* initial memory contents */
+    pub guard_images: bool,         /* stop on the words around each image */
+    pub map: bool,                  /* draw the memory map after loading */
+}
+
+impl Options {
+    // Applies the machine and console flags on top of the loaded config.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(encoding) = self.encoding {
+            config.console.encoding = encoding;
+        }
+        if self.enter.is_some() {
+            config.console.enter = self.enter;
+        }
+        config.console.echo |= self.echo;
+        config.console.crlf |= self.crlf;
+        config.console.special_keys |= self.special_keys;
+        if let Some(on_eof) = self.on_eof {
+            config.console.on_eof = on_eof;
+        }
+        if let Some(base) = self.trap_base {
+            config.traps.base = base;
+        }
+        config
+            .traps
+            .routines
+            .extend(self.trap_routines.iter().copied());
+        if let Some(unknown_trap) = self.unknown_trap {
+            config.unknown_trap = unknown_trap;
+        }
+        if self.max_instructions.is_some() {
+            config.max_instructions = self.max_instructions;
+        }
+        if self.max_output.is_some() {
+            config.console.max_output = self.max_output;
+        }
+        if self.max_writes.is_some() {
+            config.max_writes = self.max_writes;
+        }
+        if self.clock_mode.is_some() {
+            config.clock_mode = self.clock_mode;
+        }
+        config.deterministic |= self.deterministic;
+        config.counters_mapped |= self.counters;
+        config.mask_counters |= self.mask_counters;
+        if self.icache.is_some() {
+            config.icache = self.icache;
+        }
+        if self.dcache.is_some() {
+            config.dcache = self.dcache;
+        }
+        if let Some(words) = self.memory_size {
+            config.memory_size = words;
+        }
+        if let Some(fill) = self.fill {
+            config.fill = fill;
+        }
+    }
+}
+
+// Parses the arguments following the program name.
+// Flag values may be given as the next argument or as `--flag=value`.
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let args: Vec<String> = args
+        .iter()
+        .flat_map(|a| match a.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                vec![flag.to_string(), value.to_string()]
+            }
+            _ => vec![a.clone()],
+        })
+        .collect();
+    let mut args = args.iter().peekable();
+
+    let command = match args.peek().map(|a| a.as_str()) {
+        Some("debug") => Command::Debug(DebugOptions::default()),
+        Some("dap") => Command::Dap,
+        Some("analyze") => Command::Analyze,
+        Some("explore") => Command::Explore(ExploreOptions::default()),
+        Some("dump") => Command::Dump(DumpOptions::default()),
+        Some("isa") => Command::Isa(None),
+        Some("encode") => Command::Encode(CodecOptions::default()),
+        Some("decode") => Command::Decode(CodecOptions::default()),
+        Some("flags") => Command::Flags(Vec::new()),
+        Some("asm") => Command::Asm(AsmOptions::default()),
+        Some("fmt") => Command::Fmt(FmtOptions::default()),
+        Some("lsp") => Command::Lsp,
+        Some("bundle") => Command::Bundle(BundleOptions::default()),
+        Some("diff-run") => Command::DiffRun(DiffOptions::default()),
+        Some("snapshot-diff") => Command::SnapshotDiff,
+        Some("trace-view") => Command::TraceView,
+        Some("selftest") => Command::Selftest,
+        Some("grade") => Command::Grade(GradeOptions::default()),
+        Some("help") => Command::Help,
+        _ => Command::Run,
+    };
+    if !matches!(command, Command::Run) {
+        args.next();
+    }
+
+    let mut options = Options {
+        command,
+        images: Vec::new(),
+        config: None,
+        verify: false,
+        encoding: None,
+        enter: None,
+        echo: false,
+        crlf: false,
+        special_keys: false,
+        on_eof: None,
+        input: None,
+        unknown_trap: None,
+        trap_base: None,
+        trap_routines: Vec::new(),
+        stats: false,
+        opcode_stats: None,
+        detect_loops: false,
+        pc_checks: true,
+        cfi: false,
+        history: None,
+        required: Vec::new(),
+        forbidden: Vec::new(),
+        policy: None,
+        checks: Vec::new(),
+        trace: false,
+        color: ColorChoice::Auto,
+        trace_traps: false,
+        trace_only: Vec::new(),
+        print_state: false,
+        breakpoints: Vec::new(),
+        break_ranges: Vec::new(),
+        watchpoints: Vec::new(),
+        checkpoint_interval: None,
+        rollback: 1,
+        args_at: None,
+        args: Vec::new(),
+        pack_args: false,
+        max_instructions: None,
+        max_output: None,
+        max_writes: None,
+        env_block: false,
+        seed: None,
+        start_all: false,
+        quantum: 1000,
+        exit_code: false,
+        clock_mode: None,
+        deterministic: false,
+        counters: false,
+        mask_counters: false,
+        snapshot: None,
+        assert_state: None,
+        regs_only: false,
+        record: None,
+        perfetto: None,
+        vectors: None,
+        strict_vectors: false,
+        icache: None,
+        dcache: None,
+        pipeline: None,
+        pipeline_csv: None,
+        memory_size: None,
+        fill: None,
+        guard_images: false,
+        map: false,
+    };
+
+    while let Some(a) = args.next() {
+        match (a.as_str(), &mut options.command) {
+            /* negative decimals look like flags */
+            (value, Command::Flags(values)) => values.push(value.to_string()),
+            ("--init", Command::Debug(opts)) => {
+                let path = args.next().ok_or(format!("{} expects a file", a))?;
+                opts.init = Some(path.clone());
+            }
+            ("--source", Command::Debug(opts)) => {
+                let path = args.next().ok_or(format!("{} expects a file", a))?;
+                opts.sources.push(path.clone());
+            }
+            ("--depth", Command::Explore(opts)) => opts.depth = parse_number(a, args.next())?,
+            ("--steps", Command::Explore(opts)) => opts.steps = parse_number(a, args.next())?,
+            ("--inputs", Command::Explore(opts)) => {
+                let value = args.next().ok_or(format!("{} expects a value", a))?;
+                if value.is_empty() {
+                    return Err(format!("{} expects at least one character", a));
+                }
+                opts.inputs = value.as_bytes().to_vec();
+            }
+            ("--start", Command::Dump(opts)) => opts.start = Some(parse_address(a, args.next())?),
+            ("--length", Command::Dump(opts)) => opts.length = Some(parse_number(a, args.next())?),
+            ("--stride", Command::Dump(opts)) => {
+                opts.stride = parse_number(a, args.next())?;
+                if opts.stride == 0 {
+                    return Err(format!("{} must be at least 1", a));
+                }
+            }
+            ("--at", Command::Encode(opts) | Command::Decode(opts)) => {
+                opts.at = parse_address(a, args.next())?
+            }
+            ("--output" | "-o", Command::Asm(opts)) => {
+                opts.output = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--listing", Command::Asm(opts)) => {
+                opts.listing = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--symbols", Command::Asm(opts)) => {
+                opts.symbols = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--hash", Command::Asm(opts)) => opts.hash = true,
+            ("--spec", Command::Grade(opts)) => {
+                opts.spec = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--report", Command::Grade(opts)) => {
+                opts.report = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--metadata", Command::Asm(opts)) => opts.metadata = true,
+            ("--strict", Command::Asm(opts)) => opts.strict = true,
+            ("--check", Command::Fmt(opts)) => opts.check = true,
+            ("--stdio", Command::Lsp) => {} /* the only transport, named by most clients */
+            ("--columns", Command::Fmt(opts)) => {
+                let text = args.next().ok_or(format!("{} expects three columns", a))?;
+                let columns: Vec<usize> = text
+                    .split(':')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("{} expects OPCODE:OPERANDS:COMMENT, got {}", a, text))?;
+                let [opcode_column, operand_column, comment_column] = columns[..] else {
+                    return Err(format!(
+                        "{} expects OPCODE:OPERANDS:COMMENT, got {}",
+                        a, text
+                    ));
+                };
+                opts.style = Style {
+                    opcode_column,
+                    operand_column,
+                    comment_column,
+                };
+            }
+            ("--start", Command::Bundle(opts)) => opts.start = parse_address(a, args.next())?,
+            ("--output" | "-o", Command::Bundle(opts)) => {
+                opts.output = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--input", Command::DiffRun(opts)) => {
+                opts.input = args
+                    .next()
+                    .ok_or(format!("{} expects text", a))?
+                    .clone()
+                    .into()
+            }
+            ("--input-file", Command::DiffRun(opts)) => {
+                opts.input_file = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--steps", Command::DiffRun(opts)) => {
+                opts.steps = parse_number(a, args.next())? as u64
+            }
+            ("--config", _) => {
+                options.config = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--verify", _) => options.verify = true,
+            ("--utf8", _) => options.encoding = Some(Encoding::Utf8),
+            ("--wide-chars", _) => options.encoding = Some(Encoding::Wide),
+            ("--enter", _) => {
+                options.enter = match args.next().map(|v| v.as_str()) {
+                    Some("lf") => Some(Enter::Lf),
+                    Some("cr") => Some(Enter::Cr),
+                    _ => return Err(format!("{} expects lf or cr", a)),
+                }
+            }
+            ("--trap-unknown", _) => {
+                options.unknown_trap = match args.next().map(|v| v.as_str()) {
+                    Some("ignore") => Some(UnknownTrap::Ignore),
+                    Some("error") => Some(UnknownTrap::Error),
+                    _ => return Err(format!("{} expects ignore or error", a)),
+                }
+            }
+            ("--trap-base", _) => options.trap_base = Some(parse_address(a, args.next())?),
+            ("--trap-routine", _) => {
+                let value = args.next().ok_or(format!("{} expects NAME:ADDR", a))?;
+                let (name, address) = value
+                    .split_once(':')
+                    .ok_or(format!("{} expects NAME:ADDR, got {}", a, value))?;
+                let trap = TRAP::from_name(name).ok_or(format!("{}: unknown trap {}", a, name))?;
+                let address = parse_address(a, Some(&address.to_string()))?;
+                options.trap_routines.push((address, trap));
+            }
+            ("--echo", _) => options.echo = true,
+            ("--crlf", _) => options.crlf = true,
+            ("--special-keys", _) => options.special_keys = true,
+            ("--stats", _) => options.stats = true,
+            ("--opcode-stats", _) => {
+                let path = args.next().ok_or(format!("{} expects a file", a))?;
+                options.opcode_stats = Some(path.to_string());
+            }
+            ("--detect-loops", _) => options.detect_loops = true,
+            ("--no-pc-checks", _) => options.pc_checks = false,
+            ("--cfi", _) => options.cfi = true,
+            ("--require" | "--forbid", _) => {
+                let text = args
+                    .next()
+                    .ok_or(format!("{} expects instruction names", a))?;
+                let names = policy::parse_names(text).map_err(|e| format!("{}: {}", a, e))?;
+                match a.as_str() {
+                    "--require" => options.required.extend(names),
+                    _ => options.forbidden.extend(names),
+                }
+            }
+            ("--policy", _) => {
+                options.policy = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--warn" | "--error" | "--no-warn", _) => {
+                let text = args.next().ok_or(format!("{} expects check names", a))?;
+                let severity = match a.as_str() {
+                    "--warn" => Severity::Warning,
+                    "--error" => Severity::Error,
+                    _ => Severity::Off,
+                };
+                for name in text.split(',') {
+                    let checks = Check::parse(name.trim()).map_err(|e| format!("{}: {}", a, e))?;
+                    options
+                        .checks
+                        .extend(checks.into_iter().map(|c| (c, severity)));
+                }
+            }
+            ("--history", _) => options.history = Some(parse_number(a, args.next())?),
+            ("--trace", _) => options.trace = true,
+            ("--color", _) => {
+                let text = args.next().map_or("", |s| s.as_str());
+                options.color = ColorChoice::parse(text)
+                    .ok_or(format!("{} expects auto, always or never", a))?;
+            }
+            ("--trace-traps", _) => options.trace_traps = true,
+            ("--trace-only", _) => {
+                let span = args
+                    .next()
+                    .ok_or(format!("{} expects START:END or a label", a))?;
+                options.trace_only.push(span.to_string());
+            }
+            ("--print-state-on-halt", _) => options.print_state = true,
+            ("--checkpoint-interval", _) => {
+                let interval = parse_number(a, args.next())?;
+                if interval == 0 {
+                    return Err(format!("{} must be at least 1", a));
+                }
+                options.checkpoint_interval = Some(interval as u64);
+            }
+            ("--rollback", _) => {
+                options.rollback = parse_number(a, args.next())?;
+                if options.rollback == 0 {
+                    return Err(format!("{} must be at least 1", a));
+                }
+            }
+            ("--args-at", _) => options.args_at = Some(parse_address(a, args.next())?),
+            ("--arg-string", _) => {
+                let text = args.next().ok_or(format!("{} expects a value", a))?;
+                options.args.push(Arg::Str(text.clone()));
+            }
+            ("--arg-word", _) => options.args.push(Arg::Word(parse_address(a, args.next())?)),
+            ("--pack-args", _) => options.pack_args = true,
+            ("--max-instructions", _) => {
+                options.max_instructions = Some(parse_number(a, args.next())? as u64)
+            }
+            ("--max-output-bytes", _) => {
+                options.max_output = Some(parse_number(a, args.next())? as u64)
+            }
+            ("--max-mem-writes", _) => {
+                options.max_writes = Some(parse_number(a, args.next())? as u64)
+            }
+            ("--env-block", _) => options.env_block = true,
+            ("--start-all", _) => options.start_all = true,
+            ("--exit-code", _) => options.exit_code = true,
+            ("--clock", _) => {
+                options.clock_mode = match args.next().map(|v| v.as_str()) {
+                    Some("uptime") => Some(ClockMode::Uptime),
+                    Some("realtime") => Some(ClockMode::RealTime),
+                    _ => return Err(format!("{} expects uptime or realtime", a)),
+                }
+            }
+            ("--deterministic", _) => options.deterministic = true,
+            ("--counters", _) => options.counters = true,
+            ("--mask-counters", _) => options.mask_counters = true,
+            ("--vectors", _) => {
+                options.vectors = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--strict-vectors", _) => options.strict_vectors = true,
+            ("--icache", _) => {
+                let geometry = args.next().ok_or(format!("{} expects SIZE:WAYS:LINE", a))?;
+                options.icache = Some(CacheConfig::parse(geometry)?);
+            }
+            ("--pipeline", _) => {
+                let window = args.next().ok_or(format!("{} expects FIRST[:COUNT]", a))?;
+                let (first, count) = window.split_once(':').unwrap_or((window, "16"));
+                let first = parse_number(a, Some(&first.to_string()))?.max(1) as u64;
+                options.pipeline = Some((first, parse_number(a, Some(&count.to_string()))?));
+            }
+            ("--memory-size", _) => {
+                /* x10000 does not fit an address, so hex is parsed here */
+                let value = args.next().ok_or(format!("{} expects a size", a))?;
+                let words = match value.strip_prefix('x').or_else(|| value.strip_prefix("0x")) {
+                    Some(digits) => usize::from_str_radix(digits, 16),
+                    None => value.parse(),
+                }
+                .map_err(|_| format!("{} expects a number of words, got {}", a, value))?;
+                if !(1..=MEMORY_MAX).contains(&words) {
+                    return Err(format!("{} must be between 1 and {}", a, MEMORY_MAX));
+                }
+                options.memory_size = Some(words);
+            }
+            ("--guard-images", _) => options.guard_images = true,
+            ("--map", _) => options.map = true,
+            ("--fill-pattern", _) => options.fill = Some(parse_address(a, args.next())?),
+            ("--pipeline-csv", _) => {
+                options.pipeline_csv =
+                    Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--dcache", _) => {
+                let geometry = args.next().ok_or(format!("{} expects SIZE:WAYS:LINE", a))?;
+                options.dcache = Some(CacheConfig::parse(geometry)?);
+            }
+            ("--snapshot", _) => {
+                options.snapshot = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--assert-state", _) => {
+                options.assert_state =
+                    Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--regs-only", _) => options.regs_only = true,
+            ("--record", _) => {
+                options.record = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--perfetto", _) => {
+                options.perfetto = Some(args.next().ok_or(format!("{} expects a file", a))?.clone())
+            }
+            ("--quantum", _) => {
+                options.quantum = parse_number(a, args.next())? as u64;
+                if options.quantum == 0 {
+                    return Err(format!("{} must be at least 1", a));
+                }
+            }
+            ("--seed", _) => options.seed = Some(parse_address(a, args.next())?),
+            ("--break-opcode", _) => {
+                let name = args.next().ok_or(format!("{} expects an opcode", a))?;
+                let breakpoint =
+                    Breakpoint::opcode(name).ok_or(format!("{}: unknown opcode {}", a, name))?;
+                options.breakpoints.push(breakpoint);
+            }
+            ("--break-trap", _) => {
+                let name = args.next().ok_or(format!("{} expects a trap", a))?;
+                let breakpoint =
+                    Breakpoint::trap(name).ok_or(format!("{}: unknown trap {}", a, name))?;
+                options.breakpoints.push(breakpoint);
+            }
+            ("--break-at", _) => {
+                let address = parse_address(a, args.next())?;
+                options.breakpoints.push(Breakpoint::Address(address));
+            }
+            ("--break-range", _) => {
+                let span = args
+                    .next()
+                    .ok_or(format!("{} expects START:END or a label", a))?;
+                options.break_ranges.push(span.to_string());
+            }
+            ("--watch", _) => {
+                let text = args.next().ok_or(format!("{} expects an address", a))?;
+                let watchpoint = Watchpoint::parse(text)
+                    .ok_or(format!("{} expects ADDR[:r|:w|:rw], got {}", a, text))?;
+                options.watchpoints.push(watchpoint);
+            }
+            ("--stdin-fd", _) => {
+                let fd = parse_number(a, args.next())?;
+                let fd = i32::try_from(fd).map_err(|_| format!("{} {} is out of range", a, fd))?;
+                options.input = Some(InputSource::Fd(fd));
+            }
+            ("--console-pipe", _) => {
+                let path = args.next().ok_or(format!("{} expects a path", a))?;
+                options.input = Some(InputSource::Pipe(path.clone()));
+            }
+            ("--on-eof", _) => {
+                options.on_eof = match args.peek().map(|v| v.as_str()) {
+                    Some("halt") => Some(EofPolicy::Halt),
+                    Some("error") => Some(EofPolicy::Error),
+                    _ => Some(EofPolicy::Sentinel(parse_address(a, args.peek().copied())?)),
+                };
+                args.next();
+            }
+            (flag, _) if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            (mnemonic, Command::Isa(wanted)) if wanted.is_none() => {
+                *wanted = Some(mnemonic.to_string())
+            }
+            (input, Command::Encode(opts) | Command::Decode(opts)) if opts.input.is_none() => {
+                opts.input = Some(input.to_string())
+            }
+            (source, Command::Asm(opts)) if opts.source.is_none() => {
+                opts.source = Some(source.to_string())
+            }
+            (source, Command::Grade(opts)) if opts.source.is_none() => {
+                opts.source = Some(source.to_string())
+            }
+            (image, _) => options.images.push(image.to_string()),
+        }
+    }
+
+    match &options.command {
+        Command::Encode(opts) | Command::Decode(opts) if opts.input.is_none() => {
+            return Err(String::from("nothing to convert"))
+        }
+        Command::Asm(opts) if opts.source.is_none() => {
+            return Err(String::from("no source file given"))
+        }
+        Command::Grade(opts) if opts.source.is_none() => {
+            return Err(String::from("no source file given"))
+        }
+        Command::Grade(opts) if opts.spec.is_none() => {
+            return Err(String::from("grade needs --spec"))
+        }
+        Command::Run
+        | Command::Debug(_)
+        | Command::Analyze
+        | Command::Explore(_)
+        | Command::Dump(_)
+        | Command::Bundle(_)
+            if options.images.is_empty() =>
+        {
+            return Err(String::from("no image files given"))
+        }
+        Command::Fmt(_) if options.images.is_empty() => {
+            return Err(String::from("no source files given"))
+        }
+        Command::Dap if !options.images.is_empty() => {
+            return Err(String::from(
+                "dap takes the program from the launch request",
+            ))
+        }
+        Command::Lsp if !options.images.is_empty() => {
+            return Err(String::from(
+                "lsp reads documents from the client, not files",
+            ))
+        }
+        Command::DiffRun(_) if options.images.len() != 2 => {
+            return Err(String::from("diff-run compares exactly two images"))
+        }
+        Command::TraceView if options.images.len() != 1 => {
+            return Err(String::from("trace-view reads exactly one trace"))
+        }
+        Command::SnapshotDiff if options.images.len() != 2 => {
+            return Err(String::from("snapshot-diff compares exactly two snapshots"))
+        }
+        _ => {}
+    }
+    if !options.args.is_empty() && options.args_at.is_none() {
+        return Err(String::from("guest arguments need --args-at"));
+    }
+    if options.pipeline_csv.is_some() && options.pipeline.is_none() {
+        return Err(String::from("--pipeline-csv needs --pipeline"));
+    }
+    if options.mask_counters && !options.counters {
+        return Err(String::from("--mask-counters needs --counters"));
+    }
+    if options.regs_only && options.assert_state.is_none() {
+        return Err(String::from("--regs-only needs --assert-state"));
+    }
+    if options.strict_vectors && options.vectors.is_none() {
+        return Err(String::from("--strict-vectors needs --vectors"));
+    }
+
+    Ok(options)
+}
+
+fn parse_number(flag: &str, value: Option<&String>) -> Result<usize, String> {
+    let value = value.ok_or(format!("{} expects a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+// Addresses are written the LC-3 way (x3000), C-style (0x3000) or in decimal.
+fn parse_address(flag: &str, value: Option<&String>) -> Result<u16, String> {
+    let value = value.ok_or(format!("{} expects an address", flag))?;
+    let hex = value
+        .strip_prefix('x')
+        .or_else(|| value.strip_prefix("0x"))
+        .or_else(|| value.strip_prefix('X'));
+    let parsed = match hex {
+        Some(digits) => u16::from_str_radix(digits, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("{} expects an address, got {}", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse, Command, Options};
+
+    fn parse_line(line: &str) -> Result<Options, String> {
+        parse(
+            &line
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn error(line: &str) -> String {
+        parse_line(line).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn values_follow_the_flag_or_an_equals_sign() {
+        let options = parse_line("--max-instructions=500 --trap-base x0100 prog.obj").unwrap();
+        assert_eq!(Some(500), options.max_instructions);
+        assert_eq!(Some(0x0100), options.trap_base);
+        assert_eq!(vec![String::from("prog.obj")], options.images);
+
+        let options = parse_line("asm --strict --listing=out=1.lst prog.asm").unwrap();
+        let Command::Asm(opts) = options.command else {
+            panic!("not asm");
+        };
+        assert!(opts.strict);
+        assert_eq!(Some("out=1.lst"), opts.listing.as_deref());
+        assert_eq!(Some("prog.asm"), opts.source.as_deref());
+    }
+
+    #[test]
+    fn bad_flags_and_values_are_errors() {
+        assert_eq!(
+            "unknown option --frobnicate",
+            error("--frobnicate prog.obj")
+        );
+        assert_eq!(
+            "unknown option --strict",
+            error("--strict prog.obj"),
+            "asm's flags belong to asm"
+        );
+        assert_eq!(
+            "--max-instructions expects a value",
+            error("prog.obj --max-instructions")
+        );
+        assert_eq!(
+            "--max-instructions expects a number, got lots",
+            error("--max-instructions=lots prog.obj")
+        );
+        assert_eq!(
+            "--trap-base expects an address",
+            error("prog.obj --trap-base")
+        );
+        assert_eq!(
+            "--trap-base expects an address, got x10000",
+            error("--trap-base x10000 prog.obj")
+        );
+        assert_eq!(
+            "--clock expects uptime or realtime",
+            error("--clock=wall prog.obj")
+        );
+        assert_eq!(
+            "--color expects auto, always or never",
+            error("--color=on prog.obj")
+        );
+        assert_eq!(
+            "--columns expects OPCODE:OPERANDS:COMMENT, got 8:16",
+            error("fmt --columns 8:16 prog.asm")
+        );
+    }
+
+    #[test]
+    fn flags_that_need_others_say_so() {
+        for (line, message) in [
+            ("--arg-string hi prog.obj", "guest arguments need --args-at"),
+            (
+                "--pipeline-csv p.csv prog.obj",
+                "--pipeline-csv needs --pipeline",
+            ),
+            (
+                "--mask-counters prog.obj",
+                "--mask-counters needs --counters",
+            ),
+            ("--regs-only prog.obj", "--regs-only needs --assert-state"),
+            (
+                "--strict-vectors prog.obj",
+                "--strict-vectors needs --vectors",
+            ),
+            ("grade prog.asm", "grade needs --spec"),
+            ("asm", "no source file given"),
+            ("fmt --check", "no source files given"),
+            (
+                "lsp a.asm",
+                "lsp reads documents from the client, not files",
+            ),
+            ("--max-instructions 5", "no image files given"),
+            ("diff-run a.obj", "diff-run compares exactly two images"),
+        ] {
+            assert_eq!(message, error(line), "{}", line);
+        }
+        assert!(parse_line("--args-at x4000 --arg-string hi prog.obj").is_ok());
+        assert!(parse_line("--counters --mask-counters prog.obj").is_ok());
+        assert!(parse_line("lsp --stdio").is_ok());
+    }
+}
@@ -0,0 +1,176 @@
+// Clocks and the clock device
+//
+// Everything the machine does in time goes through a Clock: the clock
+// device reads it, TRAP SLEEP waits on it and each instruction ticks it.
+// RealClock follows the host, VirtualClock counts instructions instead, so
+// deterministic runs are reproducible and tests can move time on by hand.
+// An embedder running the machine from its own scheduler can put a Clock of
+// its own in State::mem.clock.
+//
+// The device is a 32-bit millisecond counter mapped at two consecutive
+// addresses: the high word at the device address and the low word after it.
+// Reading the high word latches the low word, so reading high then low gives
+// a consistent value. It counts from the start of the run, or gives the time
+// of day (UTC, since midnight) in real-time mode.
+
+use std::{
+    fmt,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const INSTRUCTIONS_PER_MS: u64 = 1000; /* virtual speed in deterministic mode */
+
+const MS_PER_DAY: u64 = 86_400_000;
+
+pub trait Clock: fmt::Debug + Send {
+    // Milliseconds since the run started.
+    fn uptime(&self) -> u64;
+
+    // Milliseconds since midnight UTC.
+    fn time_of_day(&self) -> u64;
+
+    // Counts one executed instruction.
+    fn tick(&mut self) {}
+
+    // Lets `millis` milliseconds go by.
+    fn sleep(&mut self, millis: u64);
+
+    fn boxed(&self) -> Box<dyn Clock>;
+}
+
+impl Clone for Box<dyn Clock> {
+    fn clone(&self) -> Self {
+        self.boxed()
+    }
+}
+
+// The host's clock
+#[derive(Clone, Debug)]
+pub struct RealClock {
+    start: Instant,
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for RealClock {
+    fn uptime(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn time_of_day(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_millis() as u64 % MS_PER_DAY
+    }
+
+    fn sleep(&mut self, millis: u64) {
+        std::thread::sleep(Duration::from_millis(millis));
+    }
+
+    fn boxed(&self) -> Box<dyn Clock> {
+        Box::new(self.clone())
+    }
+}
+
+// Time derived from the number of instructions executed, starting at
+// midnight. Sleeping moves it on without waiting.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    pub instructions: u64,
+}
+
+impl Clock for VirtualClock {
+    fn uptime(&self) -> u64 {
+        self.instructions / INSTRUCTIONS_PER_MS
+    }
+
+    fn time_of_day(&self) -> u64 {
+        self.uptime() % MS_PER_DAY
+    }
+
+    fn tick(&mut self) {
+        self.instructions += 1;
+    }
+
+    fn sleep(&mut self, millis: u64) {
+        self.instructions += millis * INSTRUCTIONS_PER_MS;
+    }
+
+    fn boxed(&self) -> Box<dyn Clock> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockMode {
+    Uptime,   /* milliseconds since the run started */
+    RealTime, /* milliseconds since midnight UTC */
+}
+
+#[derive(Clone, Debug)]
+pub struct ClockDevice {
+    mode: ClockMode,
+    latched_low: u16,
+}
+
+impl ClockDevice {
+    pub fn new(mode: ClockMode) -> Self {
+        Self {
+            mode,
+            latched_low: 0,
+        }
+    }
+
+    pub fn millis(&self, clock: &dyn Clock) -> u32 {
+        match self.mode {
+            ClockMode::Uptime => clock.uptime() as u32,
+            ClockMode::RealTime => clock.time_of_day() as u32,
+        }
+    }
+
+    pub fn read_high(&mut self, clock: &dyn Clock) -> u16 {
+        let millis = self.millis(clock);
+        self.latched_low = millis as u16;
+        (millis >> 16) as u16
+    }
+
+    pub fn read_low(&self) -> u16 {
+        self.latched_low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{Clock, ClockDevice, ClockMode, VirtualClock, INSTRUCTIONS_PER_MS};
+
+    #[test]
+    fn virtual_clock_follows_instruction_count() {
+        let mut clock = VirtualClock {
+            instructions: INSTRUCTIONS_PER_MS * 70_000 - 1,
+        };
+        clock.tick();
+
+        /* 70000 ms = x0001_1170 */
+        let mut device = ClockDevice::new(ClockMode::Uptime);
+        assert_eq!(0x0001, device.read_high(&clock));
+        assert_eq!(0x1170, device.read_low());
+    }
+
+    #[test]
+    fn virtual_sleep_does_not_wait() {
+        let mut clock = VirtualClock::default();
+        clock.sleep(86_400_000 + 5);
+        assert_eq!((86_400_005, 5), (clock.uptime(), clock.time_of_day()));
+
+        let boxed: Box<dyn Clock> = clock.boxed();
+        let device = ClockDevice::new(ClockMode::RealTime);
+        assert_eq!(5, device.millis(boxed.clone().as_ref()));
+    }
+}
@@ -0,0 +1,117 @@
+// Terminal colour
+//
+// Output read by people can be coloured with ANSI escapes, set with
+// --color auto|always|never. auto colours only a terminal, and only when the
+// NO_COLOR environment variable is unset or empty (see no-color.org);
+// always and never override NO_COLOR, as it asks of command line flags.
+//
+// The disassembly, the trace and the debugger colour instruction text with
+// `instruction`: the mnemonic, registers, immediates and addresses each in
+// their own colour. Text is coloured after it is laid out, so the plain and
+// coloured forms line up the same.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+const OPCODE: &str = "36"; /* cyan */
+const REGISTER: &str = "32"; /* green */
+const IMMEDIATE: &str = "35"; /* magenta */
+const ADDRESS: &str = "33"; /* yellow */
+
+impl ColorChoice {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // Whether to colour output going to a terminal if `terminal` is set.
+    pub fn enabled(self, terminal: bool) -> bool {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        self.resolve(terminal, no_color)
+    }
+
+    fn resolve(self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+fn paint(code: &str, text: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+pub fn address(text: &str) -> String {
+    paint(ADDRESS, text)
+}
+
+pub fn register(text: &str) -> String {
+    paint(REGISTER, text)
+}
+
+// Colours disassembly such as "LDR R4, R2, #5" a word at a time. The first
+// word is the mnemonic.
+pub fn instruction(text: &str) -> String {
+    let mut out = String::new();
+    let mut first = true;
+    for word in text.split_inclusive([' ', ',']) {
+        let (word, separator) = word.split_at(word.trim_end_matches([' ', ',']).len());
+        let code = match word.as_bytes() {
+            [] => None,
+            _ if first => Some(OPCODE),
+            [b'R', b'0'..=b'7'] => Some(REGISTER),
+            [b'#', ..] => Some(IMMEDIATE),
+            [b'x', rest @ ..] if !rest.is_empty() && rest.iter().all(u8::is_ascii_hexdigit) => {
+                Some(ADDRESS)
+            }
+            _ => None,
+        };
+        match code {
+            Some(code) => out.push_str(&paint(code, word)),
+            None => out.push_str(word),
+        }
+        out.push_str(separator);
+        first &= word.is_empty();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{instruction, ColorChoice};
+
+    #[test]
+    fn instructions_are_coloured_a_word_at_a_time() {
+        assert_eq!(
+            "\x1b[36mLDR\x1b[0m \x1b[32mR4\x1b[0m, \x1b[32mR2\x1b[0m, \x1b[35m#5\x1b[0m",
+            instruction("LDR R4, R2, #5")
+        );
+        assert_eq!(
+            "\x1b[36mBRz\x1b[0m \x1b[33mx3005\x1b[0m",
+            instruction("BRz x3005")
+        );
+        assert_eq!("\x1b[36mRET\x1b[0m", instruction("RET"));
+    }
+
+    #[test]
+    fn no_color_only_turns_auto_off() {
+        assert!(ColorChoice::Auto.resolve(true, false));
+        assert!(!ColorChoice::Auto.resolve(true, true));
+        assert!(!ColorChoice::Auto.resolve(false, false));
+        assert!(ColorChoice::Always.resolve(false, true));
+        assert!(!ColorChoice::Never.resolve(true, false));
+        assert_eq!(Some(ColorChoice::Never), ColorChoice::parse("never"));
+        assert_eq!(None, ColorChoice::parse("sometimes"));
+    }
+}
@@ -0,0 +1,669 @@
+// Machine configuration
+//
+// Loaded from a small TOML subset: `[section]` headers, `key = value` pairs,
+// integers (decimal, 0x, 0o, 0b), strings, booleans and inline arrays.
+// Strings take TOML's escapes: \" \\ \n \t \r \b \f \uXXXX and \UXXXXXXXX.
+//
+// [machine]
+// pc_start = 0x3000
+// unknown_trap = "error" # or "ignore", for vectors without a set table entry
+// trap_base = 0x0000     # where the trap vector table starts
+// max_instructions = 1_000_000
+// max_mem_writes = 1_000_000  # stop with an error past this many, unlimited unless set
+// clock = "uptime"       # or "realtime", off unless set
+// deterministic = false  # derive the clock from the instruction count
+// counters = false       # map the performance counters, see counters.rs
+// mask_counters = false  # and have them read zero
+//
+// [devices]
+// kbsr = 0xFE00
+// kbdr = 0xFE02
+// dsr = 0xFE04
+// ddr = 0xFE06
+// mcr = 0xFFFE
+// clock = 0xFE08
+// counters = 0xFE0A
+//
+// [traps]
+// getc = 0x0400  # run the built-in GETC when PC gets here, see instr.rs
+//
+// [memory]
+// read_only = [[0x0000, 0x2FFF]]
+// size = 0x8000                   # words, accesses past the end are errors
+// fill = 0xDEAD                   # initial contents, zero unless set
+// mirrors = [[0xC000, 0xFDFF, 0x4000]] # start, end and the address start aliases
+//
+// [console]
+// encoding = "latin1" # or "utf8", "wide"
+// enter = "lf"         # or "cr"
+// echo = false
+// crlf = false
+// on_eof = "halt"      # or "error", or a sentinel value such as 0x04
+// max_output_bytes = 65536 # stop with an error past this much, unlimited unless set
+// keymap = [[[0x1B, 0x5B, 0x41], 0x80]] # host key bytes, or a string, and a guest code
+// charmap = [[0x80, "^"]]     # a guest code and the host text, or bytes, it prints
+// special_keys = false     # arrow, function and editing keys as single codes
+//
+// [cache]
+// icache = "256:2:4"   # SIZE:WAYS:LINE in words, off unless set
+// dcache = "256:2:4"
+
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    cache::CacheConfig,
+    clock::ClockMode,
+    console::{ConsoleOptions, Encoding, Enter, EofPolicy},
+    defs::{MR, TRAP},
+    instr::{TrapTable, UnknownTrap},
+    state::{MEMORY_MAX, PC_START},
+};
+
+#[derive(Clone)]
+pub struct Config {
+    pub pc_start: u16,
+    pub kbsr: u16,
+    pub kbdr: u16,
+    pub dsr: u16,
+    pub ddr: u16,
+    pub mcr: u16,
+    pub clock: u16,                 /* high word of the clock, the low word follows */
+    pub counters: u16,              /* the first of the performance counters */
+    pub read_only: Vec<(u16, u16)>, /* inclusive address ranges */
+    pub memory_size: usize,         /* words, device registers are always present */
+    pub mirrors: Vec<(u16, u16, u16)>,
+    pub fill: u16, /* initial value of every word but the device registers */
+    pub console: ConsoleOptions,
+    pub unknown_trap: UnknownTrap,
+    pub traps: TrapTable,
+    pub max_instructions: Option<u64>, /* stop with an error after this many */
+    pub max_writes: Option<u64>,       /* the same for memory writes */
+    pub clock_mode: Option<ClockMode>, /* None leaves the clock unmapped */
+    pub deterministic: bool,
+    pub counters_mapped: bool,
+    pub mask_counters: bool,
+    pub icache: Option<CacheConfig>, /* simulated caches, off unless set */
+    pub dcache: Option<CacheConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pc_start: PC_START,
+            kbsr: MR::KBSR as u16,
+            kbdr: MR::KBDR as u16,
+            dsr: MR::DSR as u16,
+            ddr: MR::DDR as u16,
+            mcr: MR::MCR as u16,
+            clock: MR::CLK as u16,
+            counters: MR::CNT as u16,
+            read_only: Vec::new(),
+            memory_size: MEMORY_MAX,
+            mirrors: Vec::new(),
+            fill: 0,
+            console: ConsoleOptions::default(),
+            unknown_trap: UnknownTrap::default(),
+            traps: TrapTable::default(),
+            max_instructions: None,
+            max_writes: None,
+            clock_mode: None,
+            deterministic: false,
+            counters_mapped: false,
+            mask_counters: false,
+            icache: None,
+            dcache: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for entry in parse_document(text)? {
+            config
+                .set(&entry.section, &entry.key, entry.value)
+                .map_err(|e| format!("line {}: {}", entry.line, e))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<(), String> {
+        match (section, key) {
+            ("machine", "pc_start") => self.pc_start = address(&value)?,
+            ("machine", "unknown_trap") => self.unknown_trap = unknown_trap(&value)?,
+            ("machine", "trap_base") => self.traps.base = address(&value)?,
+            ("traps", name) => {
+                let trap = TRAP::from_name(name).ok_or(format!("unknown trap {}", name))?;
+                self.traps.routines.push((address(&value)?, trap));
+            }
+            ("machine", "max_instructions") => self.max_instructions = Some(count(&value)?),
+            ("machine", "max_mem_writes") => self.max_writes = Some(count(&value)?),
+            ("machine", "clock") => self.clock_mode = Some(clock_mode(&value)?),
+            ("machine", "deterministic") => self.deterministic = boolean(&value)?,
+            ("machine", "counters") => self.counters_mapped = boolean(&value)?,
+            ("machine", "mask_counters") => self.mask_counters = boolean(&value)?,
+            ("devices", "kbsr") => self.kbsr = address(&value)?,
+            ("devices", "kbdr") => self.kbdr = address(&value)?,
+            ("devices", "dsr") => self.dsr = address(&value)?,
+            ("devices", "ddr") => self.ddr = address(&value)?,
+            ("devices", "mcr") => self.mcr = address(&value)?,
+            ("devices", "clock") => self.clock = address(&value)?,
+            ("devices", "counters") => self.counters = address(&value)?,
+            ("memory", "read_only") => self.read_only = ranges(&value)?,
+            ("memory", "size") => self.memory_size = memory_size(&value)?,
+            ("memory", "mirrors") => self.mirrors = mirrors(&value)?,
+            ("memory", "fill") => self.fill = address(&value)?,
+            ("console", "encoding") => self.console.encoding = encoding(&value)?,
+            ("console", "enter") => self.console.enter = Some(enter(&value)?),
+            ("console", "echo") => self.console.echo = boolean(&value)?,
+            ("console", "crlf") => self.console.crlf = boolean(&value)?,
+            ("console", "on_eof") => self.console.on_eof = eof_policy(&value)?,
+            ("console", "max_output_bytes") => self.console.max_output = Some(count(&value)?),
+            ("console", "keymap") => self.console.keymap = keymap(&value)?,
+            ("console", "charmap") => self.console.charmap = charmap(&value)?,
+            ("console", "special_keys") => self.console.special_keys = boolean(&value)?,
+            ("cache", "icache") => self.icache = Some(cache(&value)?),
+            ("cache", "dcache") => self.dcache = Some(cache(&value)?),
+            _ => return Err(format!("unknown setting {}.{}", section, key)),
+        }
+        Ok(())
+    }
+}
+
+// One `key = value` pair of a document in the TOML subset
+pub(crate) struct Entry {
+    pub line: usize,
+    pub section: String, /* the latest [section] header, empty before any */
+    pub key: String,
+    pub value: Value,
+}
+
+// Reads the pairs of a document, also used for the specs of grade.rs.
+pub(crate) fn parse_document(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line_no = index + 1;
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+
+        /* arrays may span several lines */
+        while !brackets_balanced(&line) {
+            match lines.next() {
+                Some((_, next)) => {
+                    line.push(' ');
+                    line.push_str(strip_comment(next).trim());
+                }
+                None => return Err(format!("line {}: unterminated array", line_no)),
+            }
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(format!("line {}: expected `key = value`", line_no))?;
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", line_no, e))?;
+        entries.push(Entry {
+            line: line_no,
+            section: section.clone(),
+            key: key.trim().to_string(),
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn brackets_balanced(line: &str) -> bool {
+    let mut depth = 0i32;
+    let (mut in_string, mut escaped) = (false, false);
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let (value, rest) = parse_prefix(text)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected `{}` after value", rest.trim()));
+    }
+    Ok(value)
+}
+
+// Parses one value from the start of `text` and returns the remainder.
+fn parse_prefix(text: &str) -> Result<(Value, &str), String> {
+    let text = text.trim_start();
+
+    if let Some(rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        let mut rest = rest.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_prefix(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err(String::from("expected `,` or `]` in array"));
+            }
+        }
+    }
+
+    if let Some(rest) = text.strip_prefix('"') {
+        let (text, rest) = parse_string(rest)?;
+        return Ok((Value::Str(text), rest));
+    }
+
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (token, rest) = text.split_at(end);
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::Int(parse_int(token)?),
+    };
+    Ok((value, rest))
+}
+
+// Reads a string up to its closing quote, which `text` starts after, and
+// returns it with the remainder.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut string = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &text[i + 1..])),
+            '\\' => {
+                let Some((_, escape)) = chars.next() else {
+                    break;
+                };
+                string.push(match escape {
+                    '"' => '"',
+                    '\\' => '\\',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\x08',
+                    'f' => '\x0C',
+                    'u' | 'U' => {
+                        let digits = if escape == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                        (u32::from_str_radix(&hex, 16).ok())
+                            .filter(|_| hex.len() == digits)
+                            .and_then(char::from_u32)
+                            .ok_or(format!("invalid escape \\{}{}", escape, hex))?
+                    }
+                    c => return Err(format!("invalid escape \\{}", c)),
+                });
+            }
+            c => string.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+fn parse_int(token: &str) -> Result<i64, String> {
+    let digits = token.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(d) => (true, d.to_string()),
+        None => (false, digits.trim_start_matches('+').to_string()),
+    };
+
+    let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(oct) = digits.strip_prefix("0o") {
+        i64::from_str_radix(oct, 8)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        digits.parse()
+    };
+
+    let value = parsed.map_err(|_| format!("invalid value `{}`", token))?;
+    Ok(if negative { -value } else { value })
+}
+
+pub(crate) fn address(value: &Value) -> Result<u16, String> {
+    match value {
+        Value::Int(n) => u16::try_from(*n).map_err(|_| format!("address {} out of range", n)),
+        _ => Err(String::from("expected an address")),
+    }
+}
+
+pub(crate) fn count(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Int(n) => u64::try_from(*n).map_err(|_| format!("count {} is negative", n)),
+        _ => Err(String::from("expected a number")),
+    }
+}
+
+fn encoding(value: &Value) -> Result<Encoding, String> {
+    match value {
+        Value::Str(name) if name == "latin1" => Ok(Encoding::Latin1),
+        Value::Str(name) if name == "utf8" => Ok(Encoding::Utf8),
+        Value::Str(name) if name == "wide" => Ok(Encoding::Wide),
+        _ => Err(String::from("expected \"latin1\", \"utf8\" or \"wide\"")),
+    }
+}
+
+fn enter(value: &Value) -> Result<Enter, String> {
+    match value {
+        Value::Str(name) if name == "lf" => Ok(Enter::Lf),
+        Value::Str(name) if name == "cr" => Ok(Enter::Cr),
+        _ => Err(String::from("expected \"lf\" or \"cr\"")),
+    }
+}
+
+fn eof_policy(value: &Value) -> Result<EofPolicy, String> {
+    match value {
+        Value::Str(name) if name == "halt" => Ok(EofPolicy::Halt),
+        Value::Str(name) if name == "error" => Ok(EofPolicy::Error),
+        Value::Int(_) => Ok(EofPolicy::Sentinel(address(value)?)),
+        _ => Err(String::from(
+            "expected \"halt\", \"error\" or a sentinel value",
+        )),
+    }
+}
+
+fn unknown_trap(value: &Value) -> Result<UnknownTrap, String> {
+    match value {
+        Value::Str(name) if name == "ignore" => Ok(UnknownTrap::Ignore),
+        Value::Str(name) if name == "error" => Ok(UnknownTrap::Error),
+        _ => Err(String::from("expected \"ignore\" or \"error\"")),
+    }
+}
+
+fn clock_mode(value: &Value) -> Result<ClockMode, String> {
+    match value {
+        Value::Str(name) if name == "uptime" => Ok(ClockMode::Uptime),
+        Value::Str(name) if name == "realtime" => Ok(ClockMode::RealTime),
+        _ => Err(String::from("expected \"uptime\" or \"realtime\"")),
+    }
+}
+
+fn cache(value: &Value) -> Result<CacheConfig, String> {
+    match value {
+        Value::Str(geometry) => CacheConfig::parse(geometry),
+        _ => Err(String::from("expected a \"SIZE:WAYS:LINE\" string")),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        _ => Err(String::from("expected true or false")),
+    }
+}
+
+fn memory_size(value: &Value) -> Result<usize, String> {
+    match value {
+        Value::Int(n) if (1..=MEMORY_MAX as i64).contains(n) => Ok(*n as usize),
+        Value::Int(n) => Err(format!(
+            "memory size {} is not between 1 and 65536 words",
+            n
+        )),
+        _ => Err(String::from("expected a number of words")),
+    }
+}
+
+fn mirrors(value: &Value) -> Result<Vec<(u16, u16, u16)>, String> {
+    let Value::Array(items) = value else {
+        return Err(String::from(
+            "expected an array of [start, end, target] triples",
+        ));
+    };
+
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Array(triple) if triple.len() == 3 => {
+                let (start, end) = (address(&triple[0])?, address(&triple[1])?);
+                let target = address(&triple[2])?;
+                if start > end {
+                    return Err(format!("range x{:04X}-x{:04X} is reversed", start, end));
+                }
+                if target as usize + (end - start) as usize >= MEMORY_MAX {
+                    return Err(format!(
+                        "mirror of x{:04X}-x{:04X} at x{:04X} runs past xFFFF",
+                        start, end, target
+                    ));
+                }
+                Ok((start, end, target))
+            }
+            _ => Err(String::from("expected a [start, end, target] triple")),
+        })
+        .collect()
+}
+
+fn keymap(value: &Value) -> Result<Vec<(Vec<u8>, u8)>, String> {
+    let Value::Array(items) = value else {
+        return Err(String::from("expected an array of [keys, code] pairs"));
+    };
+
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Array(pair) if pair.len() == 2 => {
+                let keys = host_bytes(&pair[0])?;
+                if keys.is_empty() {
+                    return Err(String::from("an empty key sequence matches nothing"));
+                }
+                let code = u8::try_from(address(&pair[1])?)
+                    .map_err(|_| String::from("guest key codes are bytes"))?;
+                Ok((keys, code))
+            }
+            _ => Err(String::from("expected a [keys, code] pair")),
+        })
+        .collect()
+}
+
+fn charmap(value: &Value) -> Result<BTreeMap<u16, String>, String> {
+    let Value::Array(items) = value else {
+        return Err(String::from("expected an array of [code, text] pairs"));
+    };
+
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Array(pair) if pair.len() == 2 => {
+                let text = String::from_utf8(host_bytes(&pair[1])?)
+                    .map_err(|_| String::from("host text is not valid UTF-8"))?;
+                Ok((address(&pair[0])?, text))
+            }
+            _ => Err(String::from("expected a [code, text] pair")),
+        })
+        .collect()
+}
+
+// A host byte sequence, written as a string or an array of bytes so the
+// control characters of escape sequences can be spelled out.
+fn host_bytes(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::Str(text) => Ok(text.as_bytes().to_vec()),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Int(n) => u8::try_from(*n).map_err(|_| format!("byte {} out of range", n)),
+                _ => Err(String::from("expected a byte")),
+            })
+            .collect(),
+        _ => Err(String::from("expected a string or an array of bytes")),
+    }
+}
+
+fn ranges(value: &Value) -> Result<Vec<(u16, u16)>, String> {
+    let Value::Array(items) = value else {
+        return Err(String::from("expected an array of [start, end] pairs"));
+    };
+
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Array(pair) if pair.len() == 2 => {
+                let (start, end) = (address(&pair[0])?, address(&pair[1])?);
+                if start > end {
+                    return Err(format!("range x{:04X}-x{:04X} is reversed", start, end));
+                }
+                Ok((start, end))
+            }
+            _ => Err(String::from("expected a [start, end] pair")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        console::{Encoding, Enter},
+        defs::TRAP,
+    };
+
+    #[test]
+    fn parse_full_config() {
+        let config = Config::parse(
+            "
+            # course variant B
+            [machine]
+            pc_start = 0x4000
+
+            trap_base = 0x0100
+
+            [traps]
+            puts = 0x0450
+            HALT = 0x0520
+
+            [devices]
+            kbsr = 0xFE10 # moved keyboard
+            kbdr = 65042
+
+            [memory]
+            read_only = [
+                [0x0000, 0x00FF],
+                [0x0200, 0x2FFF],
+            ]
+            size = 0x8000
+            mirrors = [[0xC000, 0xFDFF, 0x4000]]
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(0x4000, config.pc_start);
+        assert_eq!(0x0122, config.traps.entry(0x22));
+        assert_eq!(Some(TRAP::HALT), config.traps.routine_at(0x0520));
+        assert_eq!(0xFE10, config.kbsr);
+        assert_eq!(0xFE12, config.kbdr);
+        assert_eq!(vec![(0x0000, 0x00FF), (0x0200, 0x2FFF)], config.read_only);
+        assert_eq!(0x8000, config.memory_size);
+        assert_eq!(vec![(0xC000, 0xFDFF, 0x4000)], config.mirrors);
+    }
+
+    #[test]
+    fn parse_console_options() {
+        let config = Config::parse(
+            "[console]\nencoding = \"utf8\"\nenter = \"cr\"\necho = true\ncrlf = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(Encoding::Utf8, config.console.encoding);
+        assert_eq!(Some(Enter::Cr), config.console.enter);
+        assert!(config.console.echo);
+        assert!(config.console.crlf);
+    }
+
+    #[test]
+    fn parse_key_and_character_maps() {
+        let config = Config::parse(
+            "[console]\nkeymap = [[[0x1B, 0x5B, 0x41], 0x80], [\"w\", 0x80]]\n\
+             charmap = [[0x80, \"^\"], [0x0C, [0x1B, 0x63]]]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![(vec![0x1B, 0x5B, 0x41], 0x80), (b"w".to_vec(), 0x80)],
+            config.console.keymap
+        );
+        assert_eq!(
+            Some("\x1bc"),
+            config.console.charmap.get(&0x0C).map(|s| &s[..])
+        );
+        assert!(Config::parse("[console]\nkeymap = [[\"w\", 0x100]]\n").is_err());
+    }
+
+    #[test]
+    fn strings_take_escapes() {
+        let config = Config::parse(
+            r#"[console]
+            charmap = [[0x80, "\"q\" \\ # [x]\n\t\u00e9\U0001F600"]]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some("\"q\" \\ # [x]\n\t\u{e9}\u{1F600}"),
+            config.console.charmap.get(&0x80).map(|s| &s[..])
+        );
+
+        for bad in [r#""\q""#, r#""\u12""#, r#""\uD800""#, r#""open\""#] {
+            let text = format!("[console]\ncharmap = [[0x80, {}]]\n", bad);
+            assert!(Config::parse(&text).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys_and_bad_addresses() {
+        let unknown = Config::parse("[machine]\nspeed = 3\n");
+        assert_eq!(
+            Err(String::from("line 2: unknown setting machine.speed")),
+            unknown.map(|_| ())
+        );
+
+        let too_big = Config::parse("[machine]\npc_start = 0x10000\n");
+        assert!(too_big.is_err());
+    }
+}
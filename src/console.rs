@@ -0,0 +1,624 @@
+// Guest console
+//
+// Keystrokes are drained from the host into a queue whenever the console is
+// polled, so GETC, IN and the keyboard registers all consume the same input
+// stream in order and nothing typed between polls is dropped. An embedder can
+// queue keys of its own, which are read before any more host input.
+//
+// Output words are turned into host text according to the configured encoding.
+// A terminal is polled so a program spinning on KBSR keeps running while no key
+// is typed. Redirected input is read in blocking mode instead: the next key is
+// already in the file or on its way down the pipe, so waiting for it keeps runs
+// reproducible and avoids polling descriptors that cannot be polled.
+//
+// Enter can be translated on the way in, input consumed by GETC or KBDR can be
+// echoed and newlines can be expanded to CRLF for terminals in raw mode.
+//
+// A keymap turns host key sequences into single guest codes, so an arrow key
+// arriving as ESC [ A can reach a game as one code of the assignment's
+// choosing, and a charmap turns guest codes into host text on the way out.
+// A sequence split across two reads is put back together as long as the
+// first read filled the buffer; otherwise its bytes are delivered as typed.
+//
+// With special_keys, or --special-keys, the escape sequences terminals send
+// for the keys below come through as one code each, unless the keymap maps
+// the same sequence, and DEL comes through as backspace:
+//
+//   x08 backspace    x80 up     x84 home    x88 page up
+//                    x81 down   x85 end     x89 page down
+//                    x82 right  x86 insert  x91-x9C F1-F12
+//                    x83 left   x87 delete
+//
+// Output can also be recorded as events, each one the text a single
+// instruction wrote one way, so a harness can tell a PUTS from the same text
+// written with OUT, and the echo of a key from output. Or it can be drawn on
+// a virtual screen, see screen.rs, for tests of programs that move the
+// cursor around.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    os::unix::io::{AsFd, BorrowedFd},
+    sync::Arc,
+};
+
+use crate::{screen::Screen, terminal::check_key};
+
+// The codes of the special keys and the sequences terminals send for them,
+// in normal and application cursor mode
+const SPECIAL_KEYS: &[(&[u8], u8)] = &[
+    (b"\x7f", 0x08),
+    (b"\x1b[A", 0x80),
+    (b"\x1bOA", 0x80),
+    (b"\x1b[B", 0x81),
+    (b"\x1bOB", 0x81),
+    (b"\x1b[C", 0x82),
+    (b"\x1bOC", 0x82),
+    (b"\x1b[D", 0x83),
+    (b"\x1bOD", 0x83),
+    (b"\x1b[H", 0x84),
+    (b"\x1bOH", 0x84),
+    (b"\x1b[1~", 0x84),
+    (b"\x1b[F", 0x85),
+    (b"\x1bOF", 0x85),
+    (b"\x1b[4~", 0x85),
+    (b"\x1b[2~", 0x86),
+    (b"\x1b[3~", 0x87),
+    (b"\x1b[5~", 0x88),
+    (b"\x1b[6~", 0x89),
+    (b"\x1bOP", 0x91),
+    (b"\x1bOQ", 0x92),
+    (b"\x1bOR", 0x93),
+    (b"\x1bOS", 0x94),
+    (b"\x1b[11~", 0x91),
+    (b"\x1b[12~", 0x92),
+    (b"\x1b[13~", 0x93),
+    (b"\x1b[14~", 0x94),
+    (b"\x1b[15~", 0x95),
+    (b"\x1b[17~", 0x96),
+    (b"\x1b[18~", 0x97),
+    (b"\x1b[19~", 0x98),
+    (b"\x1b[20~", 0x99),
+    (b"\x1b[21~", 0x9A),
+    (b"\x1b[23~", 0x9B),
+    (b"\x1b[24~", 0x9C),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Latin1, /* the low byte of each word is one character */
+    Utf8, /* low bytes are collected and decoded as UTF-8 */
+    Wide, /* each word is a Unicode scalar value */
+}
+
+// What the Enter key is delivered to the guest as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Enter {
+    Lf, /* \r becomes \n */
+    Cr, /* \n becomes \r */
+}
+
+// What keyboard reads do once the host input is closed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EofPolicy {
+    #[default]
+    Halt, /* stop the machine as if HALT had run */
+    Error,         /* stop the machine with a runtime error */
+    Sentinel(u16), /* keep returning this value as the key */
+}
+
+// What wrote a piece of output
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputKind {
+    Out,
+    Puts,
+    Putsp,
+    Echo,    /* a key echoed by IN, or by --echo */
+    Message, /* text of the machine's own, the IN prompt and the HALT message */
+    #[default]
+    Display, /* a write to DDR */
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputEvent {
+    pub kind: OutputKind,
+    pub instruction: u64, /* the index in the run of the instruction that wrote it */
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsoleOptions {
+    pub encoding: Encoding,
+    pub enter: Option<Enter>, /* None passes keys through unchanged */
+    pub echo: bool,           /* echo keys consumed by GETC and KBDR */
+    pub crlf: bool,           /* expand output \n to \r\n */
+    pub on_eof: EofPolicy,
+    pub max_output: Option<u64>, /* bytes written before output is cut off */
+    pub keymap: Vec<(Vec<u8>, u8)>, /* host key sequences and the guest codes they become */
+    pub charmap: BTreeMap<u16, String>, /* guest codes and the host text they print */
+    pub special_keys: bool,      /* deliver arrow and function keys as single codes */
+}
+
+// Where keystrokes come from
+#[derive(Clone, Debug, Default)]
+enum Source {
+    #[default]
+    Stdin,
+    File(Arc<File>), /* a pre-opened descriptor or a FIFO */
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Console {
+    source: Source,
+    interactive: bool, /* the source is a terminal */
+    input: VecDeque<u8>,
+    unmatched: Vec<u8>, /* the start of a keymap sequence the last read ended in */
+    events: Option<Vec<OutputEvent>>, /* output since the last take_events, if recorded */
+    options: ConsoleOptions,
+    pending: Vec<u8>,              /* incomplete UTF-8 sequence */
+    closed: bool,                  /* the host input reached EOF */
+    eof_hit: bool,                 /* a read found the input closed since the last take_eof */
+    captured: Option<String>,      /* output collected instead of printed */
+    screen: Option<Screen>,        /* the output as a terminal would show it, if tracked */
+    written: u64,                  /* bytes of output so far */
+    overflowed: bool,              /* output went past max_output since the last take_overflow */
+    io: Option<(Vec<u8>, String)>, /* input consumed and output since start_io */
+    pub kind: OutputKind,          /* what is writing the output */
+    pub instruction: u64,          /* the index of the instruction running */
+}
+
+impl Console {
+    pub fn new(mut options: ConsoleOptions) -> Self {
+        if options.special_keys {
+            /* first, so keymap entries win ties */
+            let special = SPECIAL_KEYS
+                .iter()
+                .map(|&(keys, code)| (keys.to_vec(), code));
+            options.keymap.splice(0..0, special);
+        }
+        Self {
+            interactive: io::stdin().is_terminal(),
+            options,
+            ..Self::default()
+        }
+    }
+
+    // Reads keystrokes from `file` instead of stdin.
+    pub fn attach(&mut self, file: File) {
+        self.interactive = file.is_terminal();
+        self.source = Source::File(Arc::new(file));
+    }
+
+    // Replaces host input with `input`. Once it is consumed the input is closed.
+    pub fn feed(&mut self, input: &[u8]) {
+        self.interactive = false;
+        self.closed = true;
+        self.input.extend(input);
+    }
+
+    // Queues `keys` after the input already taken from the host, as if they
+    // had been typed. They are read first, whatever the input is attached to.
+    pub fn queue(&mut self, keys: &[u8]) {
+        self.input.extend(keys);
+    }
+
+    // Records the output as events as well, see take_events.
+    pub fn record_events(&mut self) {
+        self.events.get_or_insert_with(Vec::new);
+    }
+
+    // Returns the output events since the last call, if they are recorded.
+    pub fn take_events(&mut self) -> Vec<OutputEvent> {
+        self.events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Collects output instead of printing it.
+    pub fn capture(&mut self) {
+        self.captured = Some(String::new());
+    }
+
+    pub fn captured(&self) -> Option<&str> {
+        self.captured.as_deref()
+    }
+
+    // Draws the output from now on on a virtual screen as well.
+    pub fn track_screen(&mut self) {
+        self.screen.get_or_insert_with(Screen::default);
+    }
+
+    // What the virtual screen shows, if it is tracked.
+    pub fn screen_text(&self) -> Option<String> {
+        self.screen.as_ref().map(Screen::text)
+    }
+
+    // Calls `f` with the descriptor keystrokes are read from.
+    pub fn with_input_fd<T>(&self, f: impl FnOnce(BorrowedFd) -> T) -> T {
+        match &self.source {
+            Source::Stdin => f(io::stdin().as_fd()),
+            Source::File(file) => f(file.as_fd()),
+        }
+    }
+
+    // Moves everything the host has typed so far into the queue, without
+    // blocking on a terminal. Redirected input is read as soon as the queue runs dry.
+    pub fn poll(&mut self) {
+        if !self.interactive {
+            if self.input.is_empty() {
+                self.fill();
+            }
+        } else if self.with_input_fd(check_key).unwrap_or(false) {
+            self.fill();
+        }
+    }
+
+    // Returns the next queued key if one is available, without blocking.
+    pub fn try_read_key(&mut self) -> Option<u8> {
+        if self.input.is_empty() {
+            self.poll();
+        }
+        self.take_key()
+    }
+
+    // Blocks until a key is available. Returns None once the host input is closed.
+    pub fn read_key(&mut self) -> Option<u8> {
+        if self.input.is_empty() {
+            self.fill();
+        }
+        self.take_key()
+    }
+
+    fn take_key(&mut self) -> Option<u8> {
+        let key = self.input.pop_front()?;
+        if let Some((keys, _)) = &mut self.io {
+            keys.push(key);
+        }
+        Some(key)
+    }
+
+    // Keeps the keys consumed and the text output until take_io.
+    pub fn start_io(&mut self) {
+        self.io = Some(Default::default());
+    }
+
+    // Returns the keys consumed and the text output since start_io, and
+    // stops keeping them.
+    pub fn take_io(&mut self) -> (Vec<u8>, String) {
+        self.io.take().unwrap_or_default()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed && self.input.is_empty()
+    }
+
+    // Applies the EOF policy to a read that found no input left.
+    // Returns the sentinel to deliver as the key, if there is one.
+    pub fn end_of_input(&mut self) -> Option<u16> {
+        match self.options.on_eof {
+            EofPolicy::Sentinel(value) => Some(value),
+            EofPolicy::Halt | EofPolicy::Error => {
+                self.eof_hit = true;
+                None
+            }
+        }
+    }
+
+    // Reports whether a read hit the end of input since the last call.
+    pub fn take_eof(&mut self) -> Option<EofPolicy> {
+        if std::mem::take(&mut self.eof_hit) {
+            Some(self.options.on_eof)
+        } else {
+            None
+        }
+    }
+
+    // Reads one host buffer's worth of input, blocking if none is pending.
+    // Input that cannot be read is treated as closed.
+    fn fill(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        let mut buffer = [0u8; 4096];
+        let read = loop {
+            let read = match &self.source {
+                Source::Stdin => {
+                    let mut stdin = io::stdin().lock();
+                    let read = stdin.fill_buf().map(|available| {
+                        let n = available.len();
+                        buffer[..n].copy_from_slice(available);
+                        n
+                    });
+                    if let Ok(n) = read {
+                        stdin.consume(n);
+                    }
+                    read
+                }
+                Source::File(file) => (&**file).read(&mut buffer),
+            };
+            match read {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read,
+            }
+        };
+        let n = read.unwrap_or(0);
+        if n == 0 {
+            self.closed = true;
+        }
+        self.translate(&buffer[..n], n == buffer.len());
+    }
+
+    // Queues host `bytes` as guest keys, through the keymap and the Enter
+    // translation. With `more` to come, a trailing part of a keymap sequence
+    // is held back for the next read.
+    fn translate(&mut self, bytes: &[u8], more: bool) {
+        let mut raw = std::mem::take(&mut self.unmatched);
+        raw.extend_from_slice(bytes);
+
+        let mut i = 0;
+        while i < raw.len() {
+            let rest = &raw[i..];
+            let mapped = (self.options.keymap.iter())
+                .filter(|(from, _)| !from.is_empty() && rest.starts_with(from))
+                .max_by_key(|(from, _)| from.len());
+            if let Some((from, code)) = mapped {
+                self.input.push_back(*code);
+                i += from.len();
+                continue;
+            }
+            let partial = (self.options.keymap.iter())
+                .any(|(from, _)| from.len() > rest.len() && from.starts_with(rest));
+            if more && partial {
+                self.unmatched = rest.to_vec();
+                return;
+            }
+            self.input.push_back(match (self.options.enter, raw[i]) {
+                (Some(Enter::Lf), b'\r') => b'\n',
+                (Some(Enter::Cr), b'\n') => b'\r',
+                (_, c) => c,
+            });
+            i += 1;
+        }
+    }
+
+    // Echoes a key the guest consumed without echo of its own, if enabled.
+    pub fn echo(&mut self, key: u8) {
+        if self.options.echo {
+            let kind = std::mem::replace(&mut self.kind, OutputKind::Echo);
+            self.put_byte(key);
+            self.flush();
+            self.kind = kind;
+        }
+    }
+
+    // Outputs one character word, as written by OUT, PUTS or the display.
+    pub fn put_word(&mut self, word: u16) {
+        if self.put_mapped(word) {
+            return;
+        }
+        match self.options.encoding {
+            Encoding::Latin1 => self.put_char(word as u8 as char),
+            Encoding::Utf8 => self.put_utf8(word as u8),
+            Encoding::Wide => {
+                self.put_char(char::from_u32(word as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+            }
+        }
+    }
+
+    // Outputs one byte of a packed string, as written by PUTSP.
+    pub fn put_byte(&mut self, byte: u8) {
+        if self.put_mapped(byte as u16) {
+            return;
+        }
+        match self.options.encoding {
+            Encoding::Utf8 => self.put_utf8(byte),
+            Encoding::Latin1 | Encoding::Wide => self.put_char(byte as char),
+        }
+    }
+
+    // Outputs host text such as prompts, bypassing the guest encoding.
+    pub fn put_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.put_char(c);
+        }
+    }
+
+    // Prints the charmap text for `code`, if it has one.
+    fn put_mapped(&mut self, code: u16) -> bool {
+        match self.options.charmap.get(&code) {
+            Some(text) => {
+                let text = text.clone();
+                self.put_str(&text);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns the output limit if output went past it since the last call.
+    // Output past the limit is dropped.
+    pub fn take_overflow(&mut self) -> Option<u64> {
+        std::mem::take(&mut self.overflowed)
+            .then_some(self.options.max_output)
+            .flatten()
+    }
+
+    pub fn flush(&mut self) {
+        io::stdout().flush().unwrap();
+    }
+
+    fn put_char(&mut self, c: char) {
+        self.written += c.len_utf8() as u64;
+        if self
+            .options
+            .max_output
+            .is_some_and(|max| self.written > max)
+        {
+            self.overflowed = true;
+            return;
+        }
+        if let Some((_, text)) = &mut self.io {
+            text.push(c);
+        }
+        if let Some(screen) = &mut self.screen {
+            screen.put(c);
+        }
+        if let Some(events) = &mut self.events {
+            match events.last_mut() {
+                Some(last) if (last.kind, last.instruction) == (self.kind, self.instruction) => {
+                    last.text.push(c)
+                }
+                _ => events.push(OutputEvent {
+                    kind: self.kind,
+                    instruction: self.instruction,
+                    text: String::from(c),
+                }),
+            }
+        }
+        if let Some(captured) = &mut self.captured {
+            captured.push(c);
+            return;
+        }
+        if c == '\n' && self.options.crlf {
+            print!("\r");
+        }
+        print!("{}", c);
+    }
+
+    fn put_utf8(&mut self, byte: u8) {
+        self.pending.push(byte);
+        for c in decode_utf8(&mut self.pending) {
+            self.put_char(c);
+        }
+    }
+}
+
+// Removes and returns every character that can be decoded from the front of
+// `pending`, replacing invalid sequences and leaving an incomplete tail.
+fn decode_utf8(pending: &mut Vec<u8>) -> Vec<char> {
+    let mut decoded = Vec::new();
+    loop {
+        match std::str::from_utf8(pending) {
+            Ok(text) => {
+                decoded.extend(text.chars());
+                pending.clear();
+                return decoded;
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                decoded.extend(std::str::from_utf8(&pending[..valid]).unwrap().chars());
+                match e.error_len() {
+                    Some(len) => {
+                        decoded.push(char::REPLACEMENT_CHARACTER);
+                        pending.drain(..valid + len);
+                    }
+                    None => {
+                        pending.drain(..valid);
+                        return decoded;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use crate::console::{decode_utf8, Console, ConsoleOptions, Enter};
+
+    #[test]
+    fn decode_utf8_waits_for_complete_sequences() {
+        let mut pending = vec![b'h', 0xC3];
+        assert_eq!(vec!['h'], decode_utf8(&mut pending));
+        assert_eq!(vec![0xC3], pending);
+
+        pending.push(0xA9);
+        assert_eq!(vec!['é'], decode_utf8(&mut pending));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn decode_utf8_replaces_invalid_bytes() {
+        let mut pending = vec![0xFF, b'a'];
+        assert_eq!(
+            vec![char::REPLACEMENT_CHARACTER, 'a'],
+            decode_utf8(&mut pending)
+        );
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn fed_input_then_closed() {
+        let mut console = Console::new(ConsoleOptions::default());
+        console.feed(b"y");
+        console.capture();
+        console.put_str("ok");
+
+        assert_eq!(Some(b'y'), console.read_key());
+        assert_eq!(None, console.read_key());
+        assert!(console.is_closed());
+        assert_eq!(Some("ok"), console.captured());
+        assert_eq!((vec![], String::new()), console.take_io()); /* not kept */
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] /* needs the host filesystem */
+    fn redirected_input_is_read_without_polling() {
+        let path = std::env::temp_dir().join(format!("lc3-console-{}", std::process::id()));
+        fs::write(&path, "ok").unwrap();
+
+        let mut console = Console::new(ConsoleOptions::default());
+        console.attach(File::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(Some(b'o'), console.try_read_key());
+        assert_eq!(Some(b'k'), console.try_read_key());
+        assert_eq!(None, console.try_read_key());
+        assert!(console.is_closed());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] /* needs the host filesystem */
+    fn unreadable_input_is_closed() {
+        let mut console = Console::new(ConsoleOptions::default());
+        console.attach(File::open(std::env::temp_dir()).unwrap());
+        assert_eq!(None, console.read_key());
+        assert!(console.is_closed());
+    }
+
+    #[test]
+    fn keymap_and_charmap_translate_codes() {
+        let mut console = Console::new(ConsoleOptions {
+            enter: Some(Enter::Lf),
+            keymap: vec![(b"\x1b[A".to_vec(), 0x80), (b"\x1b[B".to_vec(), 0x81)],
+            charmap: [(0x80, String::from("^")), (0x0C, String::from("\x1b[2J"))].into(),
+            ..ConsoleOptions::default()
+        });
+        console.translate(b"\x1b[Aq\x1b[", true);
+        console.translate(b"B\r\x1b[", false);
+        let keys: Vec<u8> = console.input.iter().copied().collect();
+        assert_eq!(vec![0x80, b'q', 0x81, b'\n', 0x1B, b'['], keys);
+
+        console.capture();
+        for word in [0x80, 0x0C, 0x41] {
+            console.put_word(word);
+        }
+        console.put_byte(0x80);
+        assert_eq!(Some("^\x1b[2JA^"), console.captured());
+    }
+
+    #[test]
+    fn special_keys_become_single_codes() {
+        let mut console = Console::new(ConsoleOptions {
+            special_keys: true,
+            keymap: vec![(b"\x1b[A".to_vec(), b'w')],
+            ..ConsoleOptions::default()
+        });
+        console.translate(b"\x1b[A\x1bOB\x1b[3~\x7f\x1b[24~\x1bx", false);
+        let keys: Vec<u8> = console.input.iter().copied().collect();
+        assert_eq!(vec![b'w', 0x81, 0x87, 0x08, 0x9C, 0x1B, b'x'], keys);
+    }
+}
@@ -0,0 +1,116 @@
+// Performance counters
+//
+// With `counters = true` in [machine], or --counters, three 32-bit counters
+// are mapped from the counters address on (xFE0A unless [devices] says
+// otherwise), each as a high word then a low word:
+//
+//   +0, +1  instructions executed
+//   +2, +3  cycles, one per instruction and one per memory access, fetches
+//           included, a rough model of the LC-3's memory-bound datapath
+//   +4, +5  memory reads, instruction fetches aside
+//
+// Reading a high word latches the low one, as with the clock, so a program
+// reads a consistent value high word first. The counters are read-only:
+// writes to them are dropped. With `mask_counters = true` or
+// --mask-counters they all read zero, so a graded run cannot depend on how
+// fast the program was.
+
+pub const COUNTER_WORDS: u16 = 6;
+
+// The memory accesses so far
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub reads: u64, /* fetches included */
+    pub writes: u64,
+    pub fetches: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Counters {
+    base: u16,
+    masked: bool,
+    instructions: u64,
+    latched_low: u16,
+}
+
+impl Counters {
+    pub fn new(base: u16, masked: bool) -> Self {
+        Self {
+            base,
+            masked,
+            instructions: 0,
+            latched_low: 0,
+        }
+    }
+
+    // Counts one executed instruction.
+    pub fn tick(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..COUNTER_WORDS).map(|i| self.base.wrapping_add(i))
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        address.wrapping_sub(self.base) < COUNTER_WORDS
+    }
+
+    // The word at `address` without latching. None if it is not a counter.
+    pub fn peek(&self, address: u16, counts: &Counts) -> Option<u16> {
+        let offset = address.wrapping_sub(self.base);
+        match offset {
+            _ if offset >= COUNTER_WORDS => None,
+            _ if offset.is_multiple_of(2) => Some((self.value(offset / 2, counts) >> 16) as u16),
+            _ => Some(self.latched_low),
+        }
+    }
+
+    // A read by the program, which latches the low word along with a high one.
+    pub fn read(&mut self, address: u16, counts: &Counts) -> Option<u16> {
+        let offset = address.wrapping_sub(self.base);
+        if offset < COUNTER_WORDS && offset.is_multiple_of(2) {
+            self.latched_low = self.value(offset / 2, counts) as u16;
+        }
+        self.peek(address, counts)
+    }
+
+    fn value(&self, counter: u16, counts: &Counts) -> u32 {
+        if self.masked {
+            return 0;
+        }
+        let value = match counter {
+            0 => self.instructions,
+            1 => self.instructions + counts.reads + counts.writes,
+            _ => counts.reads - counts.fetches,
+        };
+        value as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::counters::{Counters, Counts};
+
+    #[test]
+    fn counters_latch_their_low_words() {
+        let counts = Counts {
+            reads: 0x1_0009,
+            writes: 1,
+            fetches: 0x1_0002,
+        };
+        let mut counters = Counters::new(0xFE0A, false);
+        counters.instructions = 0x1_0002;
+        assert_eq!(Some(1), counters.read(0xFE0A, &counts));
+        assert_eq!(Some(2), counters.read(0xFE0B, &counts));
+        assert_eq!(Some(2), counters.read(0xFE0C, &counts));
+        assert_eq!(Some(0x000C), counters.read(0xFE0D, &counts));
+        assert_eq!(Some(0), counters.read(0xFE0E, &counts));
+        assert_eq!(Some(7), counters.peek(0xFE0F, &counts));
+        assert_eq!(None, counters.peek(0xFE10, &counts));
+
+        let mut masked = Counters::new(0xFE0A, true);
+        masked.read(0xFE0A, &counts);
+        assert_eq!(Some(0), masked.read(0xFE0B, &counts));
+    }
+}
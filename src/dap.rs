@@ -0,0 +1,859 @@
+// Debug adapter
+//
+// `lc3 dap` speaks the Debug Adapter Protocol on stdin and stdout, so an
+// editor's debug UI can drive a DebuggerCore. A launch configuration names
+// the image, and may ask to stop on entry and give keyboard input:
+//
+//   {"type": "lc3", "request": "launch", "program": "prog.obj",
+//    "stopOnEntry": true, "input": "42\n", "sources": ["src/prog.asm"]}
+//
+// The input is all the program reads; once it runs out, reads see the end
+// of input as --on-eof says. The program's output is sent as output
+// events, since stdout carries the protocol.
+//
+// The machine is a single thread. Breakpoints are set on source lines,
+// which needs an image built with lc3 asm --metadata and its source, found
+// as lc3 debug finds it (see srcmap.rs). Stepping goes by source statement
+// when there is source and by instruction otherwise: next steps over a JSR,
+// step in follows it and step out runs until the routine returns. The
+// stack shows PC and the call site of each frame, and the registers are
+// the only scope. Memory is read a word as two bytes, high byte first, from
+// a word address such as x3000. The debug console runs lc3 debug commands.
+//
+// The machine runs in slices, so a pause or any other request is answered
+// while it runs.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    sync::mpsc::{self, TryRecvError},
+    thread,
+};
+
+use crate::{
+    breakpoints::{Breakpoint, BreakpointId, Hit},
+    config::Config,
+    debugger::{DebugCommand, DebugResponse, DebuggerCore},
+    defs::R,
+    json::{self, Json},
+    loader::read_image_file,
+    meta::Metadata,
+    state::{State, StepResult},
+};
+
+const SLICE: u64 = 10_000; /* instructions run between looks at the input */
+const STEPS: usize = 1_000; /* steps taken towards a step goal between looks */
+const THREAD: u64 = 1;
+const REGISTERS: u64 = 1; /* the variables reference of the register scope */
+
+// What the machine runs towards while it runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Goal {
+    Continue,
+    Step {
+        lines: bool,          /* by source statement, or by instruction */
+        depth: Option<usize>, /* until the call depth is at most this */
+    },
+}
+
+pub struct Adapter {
+    config: Config,
+    core: Option<DebuggerCore>,
+    seq: u64,
+    breakpoints: BTreeMap<String, Vec<BreakpointId>>, /* set in each source */
+    goal: Option<Goal>,                               /* None while the machine is stopped */
+    stop_on_entry: bool,
+    configured: bool, /* configurationDone was received */
+    printed: usize,   /* bytes of the program's output already sent */
+    pub done: bool,   /* the session is over */
+}
+
+impl Adapter {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            core: None,
+            seq: 0,
+            breakpoints: BTreeMap::new(),
+            goal: None,
+            stop_on_entry: false,
+            configured: false,
+            printed: 0,
+            done: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    // The messages to send in answer to `message`: its response, then any
+    // events it caused.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let command = message.get("command").and_then(Json::as_str).unwrap_or("");
+        let arguments = message.get("arguments").unwrap_or(&Json::Null);
+        let mut events = Vec::new();
+        let body = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(arguments).map(|()| {
+                /* breakpoints from the client follow, now there are sources */
+                events.push(event("initialized", Json::Null));
+                if self.configured {
+                    self.start(&mut events);
+                }
+                Json::Null
+            }),
+            "configurationDone" => {
+                self.configured = true;
+                if self.core.is_some() {
+                    self.start(&mut events);
+                }
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::Array(vec![Json::object([
+                    ("id", THREAD.into()),
+                    ("name", "LC-3".into()),
+                ])]),
+            )])),
+            "stackTrace" => self.core().map(stack_trace),
+            "scopes" => Ok(Json::object([(
+                "scopes",
+                Json::Array(vec![Json::object([
+                    ("name", "Registers".into()),
+                    ("presentationHint", "registers".into()),
+                    ("variablesReference", REGISTERS.into()),
+                    ("expensive", false.into()),
+                ])]),
+            )])),
+            "variables" => self.core().map(registers),
+            "readMemory" => self.core().and_then(|core| read_memory(core, arguments)),
+            "evaluate" => self.evaluate(arguments, &mut events),
+            "continue" => self
+                .go(Goal::Continue)
+                .map(|()| Json::object([("allThreadsContinued", true.into())])),
+            "next" | "stepIn" | "stepOut" => self
+                .core()
+                .map(|core| {
+                    let depth = core.state.calls.depth();
+                    Goal::Step {
+                        lines: !core.sources.is_empty(),
+                        depth: match command {
+                            "next" => Some(depth),
+                            "stepIn" => None,
+                            _ => Some(depth.saturating_sub(1)),
+                        },
+                    }
+                })
+                .and_then(|goal| self.go(goal))
+                .map(|()| Json::Null),
+            "pause" => {
+                if self.goal.take().is_some() {
+                    events.push(stopped("pause", None, None));
+                }
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("unknown request {}", command)),
+        };
+
+        let mut replies = vec![response(message, command, body)];
+        replies.append(&mut events);
+        self.numbered(replies)
+    }
+
+    // Runs the machine a slice towards its goal, answering with the events
+    // of what happened.
+    pub fn resume(&mut self) -> Vec<Json> {
+        let mut events = Vec::new();
+        let (Some(goal), Some(core)) = (self.goal, self.core.as_mut()) else {
+            return events;
+        };
+        let stop = match goal {
+            Goal::Continue => Some(core.execute(DebugCommand::Step(SLICE))),
+            Goal::Step { lines, depth } => (0..STEPS).find_map(|_| {
+                let response = core.execute(match lines {
+                    true => DebugCommand::StepLine(1),
+                    false => DebugCommand::Step(1),
+                });
+                let arrived = depth.is_none_or(|depth| core.state.calls.depth() <= depth);
+                match response {
+                    DebugResponse::Stopped {
+                        result: StepResult::Running,
+                        ..
+                    } if !arrived => None,
+                    response => Some(response),
+                }
+            }),
+        };
+        if let Some(response) = stop {
+            self.outcome(response, "step", &mut events);
+        }
+        self.output(&mut events);
+        self.numbered(events)
+    }
+
+    fn core(&self) -> Result<&DebuggerCore, String> {
+        self.core
+            .as_ref()
+            .ok_or(String::from("no program has been launched"))
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<(), String> {
+        let program = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs a program")?;
+        /* the client names sources by absolute path */
+        let program = fs::canonicalize(program)
+            .map_err(|e| format!("failed to load image {}: {}", program, e))?;
+        let program = program.display().to_string();
+
+        let mut state = State::with_config(&self.config);
+        let (images, start) = read_image_file(&program, &mut state, false)
+            .map_err(|e| format!("failed to load image {}: {}", program, e))?;
+        if let Some(start) = start {
+            state.reg[R::PC] = start;
+        }
+        let input = arguments.get("input").and_then(Json::as_str).unwrap_or("");
+        state.mem.console.feed(input.as_bytes());
+        state.mem.console.capture();
+
+        let candidates: Vec<String> = (arguments.get("sources").and_then(Json::as_array))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Json::as_str)
+            .map(|path| match fs::canonicalize(path) {
+                Ok(path) => path.display().to_string(),
+                Err(_) => path.to_string(),
+            })
+            .collect();
+        let mut core = DebuggerCore::new(state);
+        for metadata in images.iter().filter_map(|image| image.metadata.as_ref()) {
+            core.state.symbols.extend(metadata.symbols.clone());
+            if !metadata.lines.is_empty() {
+                core.sources.find(&program, metadata, &candidates);
+            }
+        }
+        self.stop_on_entry =
+            (arguments.get("stopOnEntry").and_then(Json::as_bool)).unwrap_or(false);
+        self.core = Some(core);
+        Ok(())
+    }
+
+    fn start(&mut self, events: &mut Vec<Json>) {
+        match self.stop_on_entry {
+            true => events.push(stopped("entry", None, None)),
+            false => self.goal = Some(Goal::Continue),
+        }
+    }
+
+    fn go(&mut self, goal: Goal) -> Result<(), String> {
+        self.core()?;
+        self.goal = Some(goal);
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .at(&["source", "path"])
+            .and_then(Json::as_str)
+            .ok_or("setBreakpoints needs a source path")?
+            .to_string();
+        let lines: Vec<usize> = (arguments.get("breakpoints").and_then(Json::as_array))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line")?.as_u64())
+            .map(|line| line as usize)
+            .collect();
+        let core = self
+            .core
+            .as_mut()
+            .ok_or(String::from("no program has been launched"))?;
+
+        for id in self.breakpoints.remove(&path).unwrap_or_default() {
+            core.execute(DebugCommand::Delete(id));
+        }
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in lines {
+            let added = core.sources.address_of(&path, line).and_then(|address| {
+                match core.execute(DebugCommand::Break(Breakpoint::Address(address))) {
+                    DebugResponse::Added(id) => Ok((id, address)),
+                    DebugResponse::Error(e) => Err(e),
+                    _ => unreachable!("break answers with the breakpoint added"),
+                }
+            });
+            breakpoints.push(match added {
+                Ok((id, address)) => {
+                    ids.push(id);
+                    let line = core.sources.statement_at(address).map_or(line, |s| s.line);
+                    Json::object([
+                        ("id", u64::from(id.0).into()),
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                    ])
+                }
+                Err(e) => Json::object([("verified", false.into()), ("message", e.into())]),
+            });
+        }
+        self.breakpoints.insert(path, ids);
+        Ok(Json::object([("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    fn evaluate(&mut self, arguments: &Json, events: &mut Vec<Json>) -> Result<Json, String> {
+        let expression = arguments
+            .get("expression")
+            .and_then(Json::as_str)
+            .ok_or("evaluate needs an expression")?;
+        let core = self
+            .core
+            .as_mut()
+            .ok_or(String::from("no program has been launched"))?;
+        let responses = core.execute_line(expression);
+        let result: Vec<String> = responses.iter().map(|r| r.to_string()).collect();
+        for response in responses {
+            match response {
+                DebugResponse::Quit => self.done = true,
+                response @ DebugResponse::Stopped { .. } => {
+                    self.goal = None;
+                    self.outcome(response, "step", events);
+                }
+                DebugResponse::RolledBack { .. } => {
+                    self.goal = None;
+                    events.push(stopped("goto", None, None));
+                }
+                _ => {}
+            }
+        }
+        self.output(events);
+        Ok(Json::object([
+            ("result", result.join("\n").into()),
+            ("variablesReference", 0u64.into()),
+        ]))
+    }
+
+    // Reports where `response` left the machine, `reason` being why it
+    // stopped if it stopped where it was meant to.
+    fn outcome(&mut self, response: DebugResponse, reason: &str, events: &mut Vec<Json>) {
+        self.output(events);
+        let DebugResponse::Stopped { result, error, .. } = response else {
+            if let DebugResponse::Error(e) = response {
+                events.push(event(
+                    "output",
+                    Json::object([("category", "stderr".into()), ("output", (e + "\n").into())]),
+                ));
+            }
+            self.terminate(events);
+            return;
+        };
+        match (result, error) {
+            (StepResult::Running, _) if self.goal == Some(Goal::Continue) => return,
+            (StepResult::Running, _) => events.push(stopped(reason, None, None)),
+            (StepResult::BreakpointHit(hit), _) => {
+                let (reason, id) = match hit {
+                    Hit::Breakpoint { id, .. } => ("breakpoint", id),
+                    Hit::Watchpoint { id, .. } => ("data breakpoint", id),
+                };
+                events.push(stopped(reason, Some(hit.to_string()), Some(id)));
+            }
+            (StepResult::Stopped, Some(error)) => {
+                events.push(stopped("exception", Some(error.to_string()), None))
+            }
+            (StepResult::Stopped, None) => self.terminate(events),
+        }
+        self.goal = None;
+    }
+
+    fn terminate(&mut self, events: &mut Vec<Json>) {
+        self.goal = None;
+        let status = (self.core.as_ref())
+            .and_then(|core| core.state.exit_status)
+            .unwrap_or(0);
+        events.push(event("exited", Json::object([("exitCode", status.into())])));
+        events.push(event("terminated", Json::Null));
+    }
+
+    // Sends what the program printed since the last time.
+    fn output(&mut self, events: &mut Vec<Json>) {
+        let Some(captured) =
+            (self.core.as_ref()).and_then(|core| core.state.mem.console.captured())
+        else {
+            return;
+        };
+        if captured.len() > self.printed {
+            let text = captured[self.printed..].to_string();
+            self.printed = captured.len();
+            events.push(event(
+                "output",
+                Json::object([("category", "stdout".into()), ("output", text.into())]),
+            ));
+        }
+    }
+
+    // Numbers messages in the order they are sent.
+    fn numbered(&mut self, messages: Vec<Json>) -> Vec<Json> {
+        (messages.into_iter())
+            .map(|message| match message {
+                Json::Object(mut members) => {
+                    self.seq += 1;
+                    members.insert(0, (String::from("seq"), self.seq.into()));
+                    Json::Object(members)
+                }
+                message => message,
+            })
+            .collect()
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+fn stopped(reason: &str, text: Option<String>, hit: Option<BreakpointId>) -> Json {
+    let mut body = vec![
+        (String::from("reason"), Json::from(reason)),
+        (String::from("threadId"), THREAD.into()),
+        (String::from("allThreadsStopped"), true.into()),
+    ];
+    if let Some(text) = text {
+        body.push((String::from("text"), text.into()));
+    }
+    if let Some(id) = hit {
+        body.push((
+            String::from("hitBreakpointIds"),
+            Json::Array(vec![u64::from(id.0).into()]),
+        ));
+    }
+    event("stopped", Json::Object(body))
+}
+
+fn event(event: &str, body: Json) -> Json {
+    let mut message = vec![
+        (String::from("type"), "event".into()),
+        (String::from("event"), event.into()),
+    ];
+    if body != Json::Null {
+        message.push((String::from("body"), body));
+    }
+    Json::Object(message)
+}
+
+fn response(request: &Json, command: &str, body: Result<Json, String>) -> Json {
+    let mut message = vec![
+        (String::from("type"), "response".into()),
+        (
+            String::from("request_seq"),
+            request.get("seq").cloned().unwrap_or(Json::Null),
+        ),
+        (String::from("success"), body.is_ok().into()),
+        (String::from("command"), command.into()),
+    ];
+    match body {
+        Ok(Json::Null) => {}
+        Ok(body) => message.push((String::from("body"), body)),
+        Err(e) => message.push((String::from("message"), e.into())),
+    }
+    Json::Object(message)
+}
+
+// PC in the innermost frame, then the call site in each frame out from it.
+fn stack_trace(core: &DebuggerCore) -> Json {
+    let state = &core.state;
+    let calls = (0..state.calls.depth()).filter_map(|n| state.calls.frame(n));
+    let addresses: Vec<u16> = std::iter::once(state.reg.pc())
+        .chain(calls.map(|frame| frame.call_site))
+        .collect();
+    let frames: Vec<Json> = (addresses.iter().enumerate())
+        .map(|(id, &address)| {
+            let name = Metadata::symbolize(&state.symbols, address)
+                .unwrap_or_else(|| format!("x{:04X}", address));
+            let mut frame = vec![
+                (String::from("id"), id.into()),
+                (String::from("name"), name.into()),
+                (
+                    String::from("instructionPointerReference"),
+                    format!("x{:04X}", address).into(),
+                ),
+            ];
+            match core.sources.statement_at(address) {
+                Some(location) => frame.extend([
+                    (String::from("line"), location.line.into()),
+                    (String::from("column"), 1u64.into()),
+                    (
+                        String::from("source"),
+                        Json::object([
+                            ("name", file_name(location.path).into()),
+                            ("path", location.path.into()),
+                        ]),
+                    ),
+                ]),
+                None => frame.extend([
+                    (String::from("line"), 0u64.into()),
+                    (String::from("column"), 0u64.into()),
+                ]),
+            }
+            Json::Object(frame)
+        })
+        .collect();
+    Json::object([
+        ("totalFrames", frames.len().into()),
+        ("stackFrames", Json::Array(frames)),
+    ])
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+fn registers(core: &DebuggerCore) -> Json {
+    let reg = &core.state.reg;
+    let variables = R::ALL
+        .iter()
+        .map(|&r| {
+            let value = match r {
+                R::COND => reg.cond().to_string(),
+                r => core.format.show(reg.get(r)),
+            };
+            Json::object([
+                ("name", r.name().into()),
+                ("value", value.into()),
+                ("variablesReference", 0u64.into()),
+            ])
+        })
+        .collect();
+    Json::object([("variables", Json::Array(variables))])
+}
+
+fn read_memory(core: &DebuggerCore, arguments: &Json) -> Result<Json, String> {
+    let reference = arguments
+        .get("memoryReference")
+        .and_then(Json::as_str)
+        .ok_or("readMemory needs a memory reference")?;
+    let start = parse_address(reference).ok_or(format!("no address {}", reference))?;
+    let offset = match arguments.get("offset") {
+        Some(Json::Number(n)) => *n as i64,
+        _ => 0,
+    };
+    let count = (arguments.get("count").and_then(Json::as_u64)).unwrap_or(0) as usize;
+    /* a byte offset, rounded down to a word */
+    let start = (start as i64 + offset.div_euclid(2)) as u16;
+    let bytes: Vec<u8> = (0..count.div_ceil(2))
+        .flat_map(|i| {
+            core.state
+                .mem
+                .peek(start.wrapping_add(i as u16))
+                .to_be_bytes()
+        })
+        .take(count)
+        .collect();
+    Ok(Json::object([
+        ("address", format!("x{:04X}", start).into()),
+        ("data", base64(&bytes).into()),
+    ]))
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let hex = (text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix('x'))
+        .map(|digits| u16::from_str_radix(digits, 16));
+    hex.unwrap_or_else(|| text.parse()).ok()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            text.push(match i <= chunk.len() {
+                true => DIGITS[(n >> (18 - 6 * i) & 0x3F) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    text
+}
+
+// Serves the client until it disconnects or closes the input. Requests are
+// read on a thread of their own, so the machine runs between them.
+pub fn serve(
+    input: impl BufRead + Send + 'static,
+    output: &mut impl Write,
+    config: Config,
+) -> io::Result<()> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        loop {
+            let message = json::read_message(&mut input).transpose();
+            let last = !matches!(message, Some(Ok(_)));
+            if let Some(message) = message {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+            if last {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter::new(config);
+    while !adapter.done {
+        let message = match adapter.is_running() {
+            true => match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            },
+            false => match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            },
+        };
+        let replies = match message {
+            Some(message) => adapter.handle(&message?),
+            None => adapter.resume(),
+        };
+        for reply in replies {
+            json::write_message(output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm,
+        config::Config,
+        dap::{base64, Adapter},
+        json::Json,
+    };
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        JSR TWICE
+        HALT
+TWICE   ADD R1, R1, #2
+        RET
+HELLO   .STRINGZ \"hi\"
+        .END
+";
+
+    // An adapter with `SOURCE` built with its line table in a directory of
+    // its own, and the path of the source.
+    fn launched(name: &str, stop_on_entry: bool) -> (Adapter, String) {
+        let dir = std::env::temp_dir().join(format!("lc3-dap-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = asm::assemble(SOURCE).unwrap();
+        let image = dir.join("prog.obj");
+        std::fs::write(&image, program.object(Some(&program.metadata(SOURCE)))).unwrap();
+        std::fs::write(dir.join("prog.asm"), SOURCE).unwrap();
+        let source = std::fs::canonicalize(dir.join("prog.asm")).unwrap();
+
+        let mut adapter = Adapter::new(Config::default());
+        request(&mut adapter, "initialize", "{}");
+        let replies = request(
+            &mut adapter,
+            "launch",
+            &format!(
+                r#"{{"program":{},"stopOnEntry":{}}}"#,
+                Json::from(image.display().to_string()),
+                stop_on_entry
+            ),
+        );
+        assert_eq!(Some("initialized"), field(&replies[1], "event"));
+        let seq: Vec<u64> = (replies.iter())
+            .filter_map(|reply| reply.get("seq")?.as_u64())
+            .collect();
+        assert_eq!(vec![2, 3], seq, "messages are numbered as they are sent");
+        (adapter, source.display().to_string())
+    }
+
+    fn request(adapter: &mut Adapter, command: &str, arguments: &str) -> Vec<Json> {
+        let message = Json::parse(&format!(
+            r#"{{"seq":1,"type":"request","command":"{}","arguments":{}}}"#,
+            command, arguments
+        ))
+        .unwrap();
+        let replies = adapter.handle(&message);
+        assert_eq!(
+            Some(&Json::Bool(true)),
+            replies[0].get("success"),
+            "{}",
+            replies[0]
+        );
+        replies
+    }
+
+    fn field<'a>(message: &'a Json, key: &str) -> Option<&'a str> {
+        message.get(key).and_then(Json::as_str)
+    }
+
+    // The events up to the next stop, by name, with any output joined up.
+    fn run(adapter: &mut Adapter) -> (Vec<String>, String) {
+        let (mut names, mut output) = (Vec::new(), String::new());
+        while adapter.is_running() {
+            for event in adapter.resume() {
+                match field(&event, "event") {
+                    Some("output") => {
+                        output += event.at(&["body", "output"]).unwrap().as_str().unwrap()
+                    }
+                    Some("stopped") => names.push(format!(
+                        "stopped {}",
+                        event.at(&["body", "reason"]).unwrap().as_str().unwrap()
+                    )),
+                    Some(name) => names.push(name.to_string()),
+                    None => {}
+                }
+            }
+        }
+        (names, output)
+    }
+
+    #[test]
+    fn breakpoints_stop_on_source_lines() {
+        let (mut adapter, source) = launched("break", false);
+        let replies = request(
+            &mut adapter,
+            "setBreakpoints",
+            &format!(
+                r#"{{"source":{{"path":{}}},"breakpoints":[{{"line":5}},{{"line":9}}]}}"#,
+                Json::from(source.as_str())
+            ),
+        );
+        let body = replies[0].get("body").unwrap().to_string();
+        assert!(
+            body.starts_with(
+                r#"{"breakpoints":[{"id":1,"verified":true,"line":5},{"verified":false,"message":"no code at or after"#
+            ),
+            "{}",
+            body
+        );
+
+        request(&mut adapter, "configurationDone", "{}");
+        assert_eq!(
+            (vec![String::from("stopped breakpoint")], String::from("hi")),
+            run(&mut adapter)
+        );
+
+        let replies = request(&mut adapter, "stackTrace", r#"{"threadId":1}"#);
+        let top = &replies[0]
+            .at(&["body", "stackFrames"])
+            .unwrap()
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(Some("x3003"), field(top, "instructionPointerReference"));
+        assert_eq!(Some(5), top.get("line").and_then(Json::as_u64));
+        assert_eq!(
+            Some(source.as_str()),
+            top.at(&["source", "path"]).and_then(Json::as_str)
+        );
+
+        request(&mut adapter, "continue", r#"{"threadId":1}"#);
+        assert_eq!(
+            (
+                vec![String::from("exited"), String::from("terminated")],
+                String::from("HALT\n")
+            ),
+            run(&mut adapter)
+        );
+    }
+
+    #[test]
+    fn steps_go_over_into_and_out_of_calls() {
+        let (mut adapter, _) = launched("step", true);
+        let replies = request(&mut adapter, "configurationDone", "{}");
+        assert_eq!(Some("stopped"), field(&replies[1], "event"));
+
+        let line = |adapter: &mut Adapter| {
+            let replies = request(adapter, "stackTrace", r#"{"threadId":1}"#);
+            let frames = replies[0]
+                .at(&["body", "stackFrames"])
+                .unwrap()
+                .as_array()
+                .unwrap();
+            let names: Vec<&str> = frames.iter().filter_map(|f| field(f, "name")).collect();
+            (
+                frames[0].get("line").and_then(Json::as_u64).unwrap(),
+                names.join(" "),
+            )
+        };
+        let step = |adapter: &mut Adapter, command| {
+            request(adapter, command, r#"{"threadId":1}"#);
+            assert_eq!(vec![String::from("stopped step")], run(adapter).0);
+        };
+        step(&mut adapter, "next");
+        step(&mut adapter, "next");
+        assert_eq!((4, String::from("x3002")), line(&mut adapter));
+        step(&mut adapter, "stepIn");
+        assert_eq!((6, String::from("TWICE x3002")), line(&mut adapter));
+        step(&mut adapter, "stepOut");
+        assert_eq!((5, String::from("x3003")), line(&mut adapter));
+
+        let replies = request(&mut adapter, "variables", r#"{"variablesReference":1}"#);
+        let r1 = &replies[0]
+            .at(&["body", "variables"])
+            .unwrap()
+            .as_array()
+            .unwrap()[1];
+        assert_eq!(Some("x0002"), field(r1, "value"));
+
+        let replies = request(
+            &mut adapter,
+            "evaluate",
+            r#"{"expression":"set R1 7; regs /d"}"#,
+        );
+        let result = replies[0]
+            .at(&["body", "result"])
+            .and_then(Json::as_str)
+            .unwrap();
+        assert!(result.contains("\nR1 7\n"), "{}", result);
+
+        let replies = request(
+            &mut adapter,
+            "readMemory",
+            r#"{"memoryReference":"x3006","count":4}"#,
+        );
+        assert_eq!(
+            Some("AGgAaQ=="),
+            replies[0].at(&["body", "data"]).and_then(Json::as_str)
+        );
+
+        request(&mut adapter, "continue", r#"{"threadId":1}"#);
+        request(&mut adapter, "pause", r#"{"threadId":1}"#);
+        assert!(!adapter.is_running());
+        request(&mut adapter, "disconnect", "{}");
+        assert!(adapter.done);
+    }
+
+    #[test]
+    fn bytes_are_encoded_in_base64() {
+        assert_eq!("", base64(b""));
+        assert_eq!("TQ==", base64(b"M"));
+        assert_eq!("TWE=", base64(b"Ma"));
+        assert_eq!("TWFu", base64(b"Man"));
+        assert_eq!("aGVsbG8gd29ybGQ=", base64(b"hello world"));
+    }
+}
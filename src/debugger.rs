@@ -0,0 +1,1521 @@
+// Debugger core
+//
+// The debugger logic is kept apart from any frontend. A DebuggerCore owns the
+// machine, takes one DebugCommand at a time and answers with a DebugResponse,
+// which a text frontend prints through Display and other frontends can take
+// apart. DebugCommand::parse reads the command language of `lc3 debug`:
+//
+//   step [N]                 s    run N instructions, 1 by default
+//   step-line [N]            sl   run to the start of the N-th source
+//                                 statement, 1 by default
+//   skip                          move PC past the next instruction unrun
+//   rollback [K]                  go back to the K-th newest checkpoint, taken
+//                                 every 1000 instructions unless
+//                                 --checkpoint-interval says otherwise
+//   continue                 c    run until a breakpoint, watchpoint or halt
+//   break ADDR               b    break at an address
+//   break FILE:LINE               break at the first statement at or after
+//                                 a source line, e.g. break prog.asm:42
+//   break op NAME                 break on an opcode, e.g. break op STI
+//   break trap NAME|VECTOR        break on a trap, e.g. break trap IN
+//   break range START END         break on entering a range of addresses
+//   break range LABEL             break on entering the routine at LABEL
+//   watch ADDR[:r|:w|:rw]    w    stop after the program accesses a word
+//   delete ID                d    remove a breakpoint or watchpoint
+//   breaks                        list breakpoints and watchpoints
+//   frame [N]                f    show call frame N, 0 being the innermost
+//   up / down                     show the caller / callee of the frame
+//   regs [/F]                r    show the registers
+//   status                        show registers, flags and the next
+//                                 instruction as --print-state-on-halt does
+//   mem [/F] ADDR [N]        x    show N words of memory, 8 by default
+//   x/[S] ADDR [N]                dump N words, 64 by default, as hex words,
+//                                 bytes and ASCII, S words to a row (8)
+//   find VALUE|"TEXT"             list where memory holds a word, or a string
+//   find bytes B1 B2 ...          stored a character to a word, or a run of
+//                                 bytes, taking words as two bytes high first
+//   dis [ADDR] [N]                disassemble N words, from PC by default
+//   set REG VALUE                 write a register, e.g. set R1 x10
+//   poke ADDR VALUE               write a memory word
+//   fill START END [VALUE]        overwrite a range with VALUE, by default
+//                                 the --fill-pattern word, so it reads as
+//                                 never written again
+//   assemble-at ADDR "INSTR"      assemble one instruction into memory,
+//                                 e.g. assemble-at x3004 "ADD R0,R0,#1"
+//   trace on|off                  log every instruction as it runs
+//   trace --only START:END|LABEL  trace only within a range or routine, and
+//                                 turn tracing on; trace --all drops ranges
+//   assert-state FILE [--regs-only]
+//                                 check the machine against a snapshot, or
+//                                 only its registers, showing what differs
+//   format hex|signed|unsigned|char
+//                                 how regs and mem show values from now on
+//   help                     h    list the commands
+//   quit                     q    leave the debugger
+//
+// When the image was built with its line table and the debugger has the
+// source, see srcmap.rs, every stop shows the source statement at PC too.
+//
+// When step ends on a load or store, the response shows the effective
+// address and the word moved, from the StepInfo the machine returns:
+//
+//   x3000: 6885  LDR R4, R2, #5 ; EA=x4005 -> x0042
+//
+// Addresses and values are x/0x hex, b/0b binary or decimal, or labels from
+// the symbol table. The /F of regs and mem shows values one way for that
+// command only: /x hex, /d signed, /u unsigned or /c characters.
+//
+// Lines may hold several commands separated by semicolons, and two more
+// commands name sequences of them. They are usually kept in the init script:
+//
+//   alias NAME TEXT          NAME stands for TEXT, with any arguments after it
+//   define NAME ... end      NAME runs the commands up to end, e.g.
+//                            define dumploop; mem x3000 16; regs; end
+//
+// Lines are taken apart by DebuggerCore::execute_line, so every frontend
+// shares the definitions.
+
+use std::{collections::BTreeMap, fmt, fs};
+
+use crate::{
+    asm::{self, Symbols},
+    breakpoints::{Breakpoint, BreakpointId, Span, Watchpoint},
+    callstack::Frame,
+    checkpoint::Checkpoints,
+    color,
+    defs::{OP, R},
+    disasm::disassemble,
+    dump,
+    error::RuntimeError,
+    playground,
+    snapshot::{self, Snapshot},
+    srcmap::SourceMap,
+    state::{Registers, State, StepInfo, StepResult, MEMORY_MAX},
+    status,
+};
+
+pub const HELP: &str = "step [N]  step-line [N]  skip  continue  rollback [K]  status
+break ADDR|FILE:LINE|op NAME|trap NAME|range START END|range LABEL  watch ADDR[:r|:w|:rw]
+delete ID  breaks  frame [N]  up  down  regs [/F]  mem [/F] ADDR [N]  dis [ADDR] [N]
+x/[S] ADDR [N]  find VALUE|\"TEXT\"|bytes B1 B2 ...  fill START END [VALUE]
+set REG VALUE  poke ADDR VALUE  assemble-at ADDR \"INSTR\"  trace on|off|--only SPAN|--all
+assert-state FILE [--regs-only]  format hex|signed|unsigned|char (/x /d /u /c)
+alias NAME TEXT  define NAME ... end  help  quit";
+
+// Command names, for completion
+pub const COMMANDS: [&str; 30] = [
+    "step",
+    "step-line",
+    "skip",
+    "rollback",
+    "continue",
+    "break",
+    "watch",
+    "delete",
+    "breaks",
+    "frame",
+    "up",
+    "down",
+    "regs",
+    "status",
+    "mem",
+    "x/",
+    "find",
+    "dis",
+    "set",
+    "poke",
+    "fill",
+    "assemble-at",
+    "trace",
+    "assert-state",
+    "format",
+    "alias",
+    "define",
+    "end",
+    "help",
+    "quit",
+];
+
+// Checkpoints kept for rollback unless --checkpoint-interval is given
+pub const CHECKPOINT_INTERVAL: u64 = 1000;
+pub const CHECKPOINTS: usize = 16;
+
+const EXPANSION_DEPTH: usize = 16;
+
+const MEM_WORDS: usize = 8;
+const DUMP_WORDS: usize = 64;
+const DUMP_STRIDE: usize = 8;
+const DIS_WORDS: usize = 8;
+
+// How regs and mem show the values of words
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Hex,
+    Signed,
+    Unsigned,
+    Char, /* printable ASCII quoted, anything else in hex */
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "hex" | "/x" => Ok(Format::Hex),
+            "signed" | "/d" => Ok(Format::Signed),
+            "unsigned" | "/u" => Ok(Format::Unsigned),
+            "char" | "/c" => Ok(Format::Char),
+            _ => Err(format!(
+                "unknown format {}, try hex, signed, unsigned or char",
+                name
+            )),
+        }
+    }
+
+    pub fn show(self, word: u16) -> String {
+        match self {
+            Format::Hex => format!("x{:04X}", word),
+            Format::Signed => (word as i16).to_string(),
+            Format::Unsigned => word.to_string(),
+            Format::Char => match u8::try_from(word) {
+                Ok(c) if c.is_ascii_graphic() || c == b' ' => format!("'{}'", c as char),
+                _ => format!("x{:04X}", word),
+            },
+        }
+    }
+}
+
+// What find looks for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    Words(Vec<u16>), /* a value, or a string a character to a word */
+    Bytes(Vec<u8>),  /* the bytes of consecutive words, high byte first */
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugCommand {
+    Step(u64),
+    StepLine(u64),
+    Skip,
+    Rollback(usize), /* 1 being the newest checkpoint */
+    Continue,
+    Break(Breakpoint),
+    BreakRange(Span),         /* resolved against memory when added */
+    BreakLine(String, usize), /* a source file and line, resolved when added */
+    Watch(Watchpoint),
+    Delete(BreakpointId),
+    Breaks,
+    Frame(Option<usize>), /* the selected frame if None */
+    Up,
+    Down,
+    Registers(Option<Format>), /* the session's format if None */
+    Status,
+    Memory(u16, usize, Option<Format>), /* start, number of words and format */
+    Dump(u16, usize, usize),            /* start, number of words and words to a row */
+    Find(Pattern),
+    Disassemble(Option<u16>, usize), /* start, PC if None, and number of words */
+    Set(R, u16),
+    Poke(u16, u16),
+    Fill(u16, u16, Option<u16>), /* start, end and value, the fill pattern if None */
+    Assemble(u16, u16),          /* address and the encoded instruction */
+    Trace(bool),
+    TraceOnly(Option<Span>),   /* None traces everywhere again */
+    AssertState(String, bool), /* snapshot file, and whether to compare only registers */
+    Format(Format),            /* the session's format from now on */
+    Help,
+    Quit,
+}
+
+impl DebugCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        Self::parse_with(line, &Symbols::new())
+    }
+
+    pub fn parse_with(line: &str, symbols: &Symbols) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Err(String::from("no command given"));
+        };
+        let (format, args) = match (name, args.split_first()) {
+            ("regs" | "r" | "mem" | "x", Some((first, rest))) if first.starts_with('/') => {
+                (Some(Format::parse(first)?), rest)
+            }
+            _ => (None, args),
+        };
+        let value = |i: usize| -> Result<u16, String> {
+            args.get(i)
+                .ok_or(format!("{} expects more arguments", name))
+                .and_then(|text| match symbols.get(*text) {
+                    Some(&address) => Ok(address),
+                    None => playground::parse(text),
+                })
+        };
+        let count = |i: usize, default: usize| -> Result<usize, String> {
+            args.get(i).map_or(Ok(default), |text| {
+                text.parse().map_err(|_| format!("{} is not a count", text))
+            })
+        };
+
+        let command = match name {
+            "step" | "s" => DebugCommand::Step(count(0, 1)? as u64),
+            "step-line" | "sl" => DebugCommand::StepLine(count(0, 1)? as u64),
+            "skip" => DebugCommand::Skip,
+            "rollback" => DebugCommand::Rollback(count(0, 1)?),
+            "continue" | "c" => DebugCommand::Continue,
+            "break" | "b" => match args {
+                ["range", start, end] => {
+                    DebugCommand::BreakRange(Span::parse(&format!("{}:{}", start, end), symbols)?)
+                }
+                ["range", label] => DebugCommand::BreakRange(Span::parse(label, symbols)?),
+                [location] if location.contains(':') => {
+                    let (file, line) = location.rsplit_once(':').unwrap_or_default();
+                    let line = line
+                        .parse()
+                        .map_err(|_| format!("break expects FILE:LINE, got {}", location))?;
+                    DebugCommand::BreakLine(file.to_string(), line)
+                }
+                _ => DebugCommand::Break(match args {
+                    ["op", op] => Breakpoint::opcode(op).ok_or(format!("unknown opcode {}", op))?,
+                    ["trap", trap] => {
+                        Breakpoint::trap(trap).ok_or(format!("unknown trap {}", trap))?
+                    }
+                    [_] => Breakpoint::Address(value(0)?),
+                    _ => {
+                        return Err(String::from(
+                            "break expects ADDR, op NAME, trap NAME or range",
+                        ))
+                    }
+                }),
+            },
+            "watch" | "w" => {
+                let text = args.first().ok_or("watch expects an address")?;
+                DebugCommand::Watch(
+                    Watchpoint::parse(text)
+                        .ok_or(format!("watch expects ADDR[:r|:w|:rw], got {}", text))?,
+                )
+            }
+            "delete" | "d" => {
+                let text = args.first().ok_or("delete expects an id")?;
+                let id = text.parse().map_err(|_| format!("{} is not an id", text))?;
+                DebugCommand::Delete(BreakpointId(id))
+            }
+            "breaks" => DebugCommand::Breaks,
+            "frame" | "f" => match args {
+                [] => DebugCommand::Frame(None),
+                _ => DebugCommand::Frame(Some(count(0, 0)?)),
+            },
+            "up" => DebugCommand::Up,
+            "down" => DebugCommand::Down,
+            "regs" | "r" => DebugCommand::Registers(format),
+            "status" => DebugCommand::Status,
+            "mem" | "x" => DebugCommand::Memory(value(0)?, count(1, MEM_WORDS)?, format),
+            dump if dump.starts_with("x/") => {
+                let stride = match &dump[2..] {
+                    "" => DUMP_STRIDE,
+                    text => (text.parse().ok())
+                        .filter(|&stride| stride > 0)
+                        .ok_or(format!("{} is not a count", text))?,
+                };
+                DebugCommand::Dump(value(0)?, count(1, DUMP_WORDS)?, stride)
+            }
+            "find" => {
+                let text = after_words(line, 1).trim();
+                let pattern = match (text.strip_prefix('"'), args) {
+                    (Some(text), _) => {
+                        let text = (text.strip_suffix('"'))
+                            .filter(|text| !text.is_empty())
+                            .ok_or("find expects a string in quotes")?;
+                        Pattern::Words(text.bytes().map(u16::from).collect())
+                    }
+                    (None, ["bytes", bytes @ ..]) if !bytes.is_empty() => Pattern::Bytes(
+                        (bytes.iter())
+                            .map(|text| {
+                                playground::parse(text).and_then(|b| {
+                                    u8::try_from(b).map_err(|_| format!("{} is not a byte", text))
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    (None, [_]) => Pattern::Words(vec![value(0)?]),
+                    _ => {
+                        return Err(String::from(
+                            "find expects VALUE, \"TEXT\" or bytes B1 B2 ...",
+                        ))
+                    }
+                };
+                DebugCommand::Find(pattern)
+            }
+            "dis" => match args {
+                [] => DebugCommand::Disassemble(None, DIS_WORDS),
+                _ => DebugCommand::Disassemble(Some(value(0)?), count(1, DIS_WORDS)?),
+            },
+            "set" => {
+                let text = args.first().ok_or("set expects a register")?;
+                let r = R::ALL
+                    .into_iter()
+                    .find(|r| r.name().eq_ignore_ascii_case(text))
+                    .ok_or(format!("no register {}", text))?;
+                DebugCommand::Set(r, value(1)?)
+            }
+            "poke" => DebugCommand::Poke(value(0)?, value(1)?),
+            "fill" => {
+                let (start, end) = (value(0)?, value(1)?);
+                if end < start {
+                    return Err(String::from("fill expects START before END"));
+                }
+                DebugCommand::Fill(start, end, args.get(2).map(|_| value(2)).transpose()?)
+            }
+            "assemble-at" => {
+                let address = value(0)?;
+                let text = after_words(line, 2).trim();
+                let text = text
+                    .strip_prefix('"')
+                    .and_then(|t| t.strip_suffix('"'))
+                    .unwrap_or(text);
+                if text.is_empty() {
+                    return Err(String::from("assemble-at expects an instruction"));
+                }
+                DebugCommand::Assemble(address, asm::encode_with(text, address, symbols)?)
+            }
+            "trace" => match args {
+                ["on"] => DebugCommand::Trace(true),
+                ["off"] => DebugCommand::Trace(false),
+                ["--only", span] => DebugCommand::TraceOnly(Some(Span::parse(span, symbols)?)),
+                ["--all"] => DebugCommand::TraceOnly(None),
+                _ => return Err(String::from("trace expects on, off, --only SPAN or --all")),
+            },
+            "assert-state" => match args {
+                [path] => DebugCommand::AssertState(path.to_string(), false),
+                [path, "--regs-only"] | ["--regs-only", path] => {
+                    DebugCommand::AssertState(path.to_string(), true)
+                }
+                _ => return Err(String::from("assert-state expects a snapshot file")),
+            },
+            "format" => match args {
+                [name] => DebugCommand::Format(Format::parse(name)?),
+                _ => return Err(String::from("format expects hex, signed, unsigned or char")),
+            },
+            "help" | "h" => DebugCommand::Help,
+            "quit" | "q" => DebugCommand::Quit,
+            _ => return Err(format!("unknown command {}, try help", name)),
+        };
+        Ok(command)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugResponse {
+    Stopped {
+        result: StepResult,
+        error: Option<RuntimeError>,
+        pc: u16,
+        instr: u16,             /* the word at PC, which runs next */
+        last: Option<StepInfo>, /* the last instruction run, when stepping */
+        source: Option<String>, /* the source statement at PC, if it is known */
+    },
+    RolledBack {
+        instructions: u64, /* run when the checkpoint was taken */
+        pc: u16,
+        instr: u16,
+    },
+    Added(BreakpointId),
+    Deleted(BreakpointId),
+    Breaks {
+        breakpoints: Vec<(BreakpointId, Breakpoint)>,
+        watchpoints: Vec<(BreakpointId, Watchpoint)>,
+    },
+    Frame {
+        index: usize,
+        frame: Frame,
+        routine: Option<String>, /* the label of the routine called */
+    },
+    Registers(Registers, Format),
+    Status(String),                /* the --print-state-on-halt layout */
+    Memory(u16, Vec<u16>, Format), /* start and contents */
+    Dump(u16, Vec<u16>, usize),    /* start, contents and words to a row */
+    Found(Vec<u16>),               /* the addresses where each match starts */
+    Disassembly(u16, Vec<u16>),
+    Matches(String), /* the snapshot asserted */
+    Done,
+    Help,
+    Quit,
+    Error(String),
+}
+
+// Display shows responses as plain text, and the alternate form {:#} colours
+// instructions, addresses and registers, see color.rs.
+impl fmt::Display for DebugResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let color = f.alternate();
+        match self {
+            DebugResponse::Stopped {
+                result,
+                error,
+                pc,
+                instr,
+                last,
+                source,
+            } => {
+                if let Some((pc, instr, ann)) = last.as_ref().and_then(annotation) {
+                    writeln!(f, "{} ; {}", listing(pc, instr, color), ann)?;
+                }
+                match (result, error) {
+                    (StepResult::BreakpointHit(hit), _) => writeln!(f, "{}", hit)?,
+                    (StepResult::Stopped, Some(error)) => writeln!(f, "{}", error)?,
+                    (StepResult::Stopped, None) => writeln!(f, "halted")?,
+                    (StepResult::Running, _) => {}
+                }
+                write!(f, "{}", listing(*pc, *instr, color))?;
+                match source {
+                    Some(source) => write!(f, "\n{}", source),
+                    None => Ok(()),
+                }
+            }
+            DebugResponse::RolledBack {
+                instructions,
+                pc,
+                instr,
+            } => {
+                writeln!(
+                    f,
+                    "rolled back to the checkpoint at instruction {}",
+                    instructions
+                )?;
+                write!(f, "{}", listing(*pc, *instr, color))
+            }
+            DebugResponse::Added(id) => write!(f, "added {}", id.0),
+            DebugResponse::Deleted(id) => write!(f, "deleted {}", id.0),
+            DebugResponse::Breaks {
+                breakpoints,
+                watchpoints,
+            } => {
+                if breakpoints.is_empty() && watchpoints.is_empty() {
+                    return write!(f, "no breakpoints or watchpoints");
+                }
+                let mut lines: Vec<(BreakpointId, String)> = breakpoints
+                    .iter()
+                    .map(|(id, b)| (*id, format!("break on {}", b)))
+                    .chain(
+                        watchpoints
+                            .iter()
+                            .map(|(id, w)| (*id, format!("watch {}", w))),
+                    )
+                    .collect();
+                lines.sort();
+                let lines: Vec<String> = lines
+                    .into_iter()
+                    .map(|(id, line)| format!("{:>3}  {}", id.0, line))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            DebugResponse::Frame {
+                index,
+                frame,
+                routine,
+            } => {
+                write!(f, "#{}  x{:04X}", index, frame.routine)?;
+                if let Some(routine) = routine {
+                    write!(f, " {}", routine)?;
+                }
+                writeln!(
+                    f,
+                    ", called from x{:04X}, returns to x{:04X}",
+                    frame.call_site, frame.return_to
+                )?;
+                let args: Vec<String> = (0..)
+                    .zip(frame.args)
+                    .map(|(i, word)| format!("R{} x{:04X}", i, word))
+                    .collect();
+                write!(f, "{}", args.join("  "))
+            }
+            DebugResponse::Registers(reg, format) => {
+                let name = |r: R| match color {
+                    true => color::register(r.name()),
+                    false => r.name().to_string(),
+                };
+                let mut lines: Vec<String> = R::ALL[..8]
+                    .iter()
+                    .map(|&r| match format {
+                        Format::Hex => format!("{} x{:04X} {:>6}", name(r), reg[r], reg[r] as i16),
+                        _ => format!("{} {}", name(r), format.show(reg[r])),
+                    })
+                    .collect();
+                lines.push(format!("PC x{:04X}", reg.pc()));
+                lines.push(format!("CC {}", reg.cond()));
+                write!(f, "{}", lines.join("\n"))
+            }
+            DebugResponse::Status(text) => write!(f, "{}", text),
+            DebugResponse::Memory(start, words, format) => {
+                let lines: Vec<String> = (*start..)
+                    .zip(words)
+                    .map(|(address, &word)| {
+                        let address = format!("x{:04X}", address);
+                        let address = match color {
+                            true => color::address(&address),
+                            false => address,
+                        };
+                        match format {
+                            Format::Hex => format!("{}: {:04X}", address, word),
+                            _ => format!("{}: {}", address, format.show(word)),
+                        }
+                    })
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            DebugResponse::Dump(start, words, stride) => {
+                write!(f, "{}", dump::rows(*start, words, *stride).join("\n"))
+            }
+            DebugResponse::Found(addresses) => {
+                match addresses.len() {
+                    0 => return write!(f, "not found"),
+                    1 => write!(f, "1 match")?,
+                    n => write!(f, "{} matches", n)?,
+                }
+                for row in addresses.chunks(8) {
+                    let row: Vec<String> = row.iter().map(|a| format!("x{:04X}", a)).collect();
+                    write!(f, "\n{}", row.join(" "))?;
+                }
+                Ok(())
+            }
+            DebugResponse::Disassembly(start, words) => {
+                let lines: Vec<String> = (*start..)
+                    .zip(words)
+                    .map(|(address, &word)| listing(address, word, color))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            DebugResponse::Matches(path) => write!(f, "state matches {}", path),
+            DebugResponse::Done => Ok(()),
+            DebugResponse::Help => write!(f, "{}", HELP),
+            DebugResponse::Quit => Ok(()),
+            DebugResponse::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// The line showing `word` at `address` disassembled, e.g.
+// "x3000: 1261  ADD R1, R1, #1", coloured if `color` is set.
+fn listing(address: u16, word: u16, color: bool) -> String {
+    let text = disassemble(address, word);
+    match color {
+        true => format!(
+            "{}: {:04X}  {}",
+            color::address(&format!("x{:04X}", address)),
+            word,
+            color::instruction(&text)
+        ),
+        false => format!("x{:04X}: {:04X}  {}", address, word, text),
+    }
+}
+
+// The load or store `info` ran, with its effective address and the word it
+// moved, e.g. "EA=x3FFB -> x0042". None for other instructions.
+fn annotation(info: &StepInfo) -> Option<(u16, u16, String)> {
+    let (pc, instr) = info.executed?;
+    let text = match OP::try_from(instr >> 12) {
+        Ok(OP::LD | OP::LDR | OP::LDI) => info
+            .loads
+            .last()
+            .map(|&(address, value)| format!("EA=x{:04X} -> x{:04X}", address, value)),
+        Ok(OP::ST | OP::STR | OP::STI) => info
+            .stores
+            .last()
+            .map(|&(address, value)| format!("EA=x{:04X} <- x{:04X}", address, value)),
+        _ => None,
+    };
+    Some((pc, instr, text?))
+}
+
+// What is left of `line` after its first `n` words.
+fn after_words(line: &str, n: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest.trim_start());
+    }
+    rest
+}
+
+// Aliases and macros, and the macro being defined, if any
+#[derive(Clone, Debug, Default)]
+pub struct Macros {
+    aliases: BTreeMap<String, String>,
+    macros: BTreeMap<String, Vec<String>>,
+    defining: Option<(String, Vec<String>)>,
+}
+
+impl Macros {
+    // Splits a line into the commands it runs, taking in any definitions.
+    pub fn expand(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let mut commands = Vec::new();
+        for statement in line.split(';').map(str::trim) {
+            if let Some((name, body)) = &mut self.defining {
+                match statement {
+                    "end" => {
+                        let name = std::mem::take(name);
+                        let body = std::mem::take(body);
+                        self.defining = None;
+                        self.macros.insert(name, body);
+                    }
+                    "" => {}
+                    _ => body.push(statement.to_string()),
+                }
+                continue;
+            }
+            let (word, rest) = statement
+                .split_once(char::is_whitespace)
+                .map_or((statement, ""), |(word, rest)| (word, rest.trim()));
+            match word {
+                "" => {}
+                "alias" => {
+                    let (name, text) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or("alias expects a name and a command")?;
+                    self.check_name(name)?;
+                    self.aliases
+                        .insert(name.to_string(), text.trim().to_string());
+                }
+                "define" => {
+                    if rest.is_empty() || rest.contains(char::is_whitespace) {
+                        return Err(String::from("define expects a name"));
+                    }
+                    self.check_name(rest)?;
+                    self.defining = Some((rest.to_string(), Vec::new()));
+                }
+                "end" => return Err(String::from("end without define")),
+                _ => self.expand_statement(statement, 0, &mut commands)?,
+            }
+        }
+        Ok(commands)
+    }
+
+    fn expand_statement(
+        &self,
+        statement: &str,
+        depth: usize,
+        commands: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if depth > EXPANSION_DEPTH {
+            return Err(format!("{} expands too deeply", statement));
+        }
+        let (word, rest) = statement
+            .split_once(char::is_whitespace)
+            .unwrap_or((statement, ""));
+        if let Some(body) = self.macros.get(word) {
+            for line in body {
+                self.expand_statement(line, depth + 1, commands)?;
+            }
+        } else if let Some(text) = self.aliases.get(word) {
+            let expanded = format!("{} {}", text, rest);
+            for statement in expanded.split(';') {
+                self.expand_statement(statement.trim(), depth + 1, commands)?;
+            }
+        } else {
+            commands.push(statement.to_string());
+        }
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), String> {
+        let builtin = COMMANDS.contains(&name)
+            || !matches!(DebugCommand::parse(name), Err(e) if e.starts_with("unknown command"));
+        if builtin {
+            return Err(format!("{} is already a command", name));
+        }
+        Ok(())
+    }
+
+    // Whether a define is waiting for its end.
+    pub fn is_defining(&self) -> bool {
+        self.defining.is_some()
+    }
+
+    // The alias and macro names, for completion.
+    pub fn names(&self) -> Vec<String> {
+        self.aliases
+            .keys()
+            .chain(self.macros.keys())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct DebuggerCore {
+    pub state: State,
+    pub macros: Macros,
+    pub format: Format,           /* how regs and mem show values without a /F */
+    pub checkpoints: Checkpoints, /* taken as the machine runs, for rollback */
+    pub sources: SourceMap,       /* source lines of the images, when they are known */
+    frame: usize,                 /* selected by frame, up and down; 0 once the machine runs */
+}
+
+impl DebuggerCore {
+    pub fn new(state: State) -> Self {
+        Self {
+            state,
+            macros: Macros::default(),
+            format: Format::default(),
+            checkpoints: Checkpoints::new(CHECKPOINT_INTERVAL, CHECKPOINTS),
+            sources: SourceMap::default(),
+            frame: 0,
+        }
+    }
+
+    // Runs every command on a line, after expanding aliases and macros. A
+    // failing command or quit skips the rest.
+    pub fn execute_line(&mut self, line: &str) -> Vec<DebugResponse> {
+        let commands = match self.macros.expand(line) {
+            Ok(commands) => commands,
+            Err(e) => return vec![DebugResponse::Error(e)],
+        };
+        let mut responses = Vec::new();
+        for command in commands {
+            let response = match DebugCommand::parse_with(&command, &self.state.symbols) {
+                Ok(command) => self.execute(command),
+                Err(e) => DebugResponse::Error(e),
+            };
+            let last = matches!(response, DebugResponse::Error(_) | DebugResponse::Quit);
+            responses.push(response);
+            if last {
+                break;
+            }
+        }
+        responses
+    }
+
+    pub fn execute(&mut self, command: DebugCommand) -> DebugResponse {
+        match command {
+            DebugCommand::Step(count) => self.run(Some(count)),
+            DebugCommand::Skip => {
+                self.frame = 0;
+                let state = &mut self.state;
+                if !state.running && state.hit.is_none() {
+                    return DebugResponse::Error(String::from("the program is not running"));
+                }
+                /* the instruction is never run, so a pending hit is dropped */
+                state.hit = None;
+                state.running = true;
+                let pc = state.reg.pc().wrapping_add(1);
+                state.reg.set_pc(pc);
+                DebugResponse::Stopped {
+                    result: StepResult::Running,
+                    error: None,
+                    pc,
+                    instr: state.mem.peek(pc),
+                    last: None,
+                    source: self.sources.statement_at(pc).map(|s| s.to_string()),
+                }
+            }
+            DebugCommand::Rollback(k) => {
+                let Some(checkpoint) = self.checkpoints.rewind(k) else {
+                    return DebugResponse::Error(match self.checkpoints.len() {
+                        0 => String::from("no checkpoint has been taken"),
+                        n => format!("only {} checkpoints are kept", n),
+                    });
+                };
+                self.frame = 0;
+                self.state.restore(checkpoint);
+                let pc = self.state.reg.pc();
+                DebugResponse::RolledBack {
+                    instructions: self.state.stats.instructions,
+                    pc,
+                    instr: self.state.mem.peek(pc),
+                }
+            }
+            DebugCommand::StepLine(count) => self.step_lines(count),
+            DebugCommand::Continue => self.run(None),
+            DebugCommand::Break(breakpoint) => {
+                DebugResponse::Added(self.state.add_breakpoint(breakpoint))
+            }
+            DebugCommand::BreakLine(file, line) => match self.sources.address_of(&file, line) {
+                Ok(address) => {
+                    DebugResponse::Added(self.state.add_breakpoint(Breakpoint::Address(address)))
+                }
+                Err(e) => DebugResponse::Error(e),
+            },
+            DebugCommand::BreakRange(span) => {
+                let (start, end) = span.resolve(&self.state.mem);
+                DebugResponse::Added(self.state.add_breakpoint(Breakpoint::Range(start, end)))
+            }
+            DebugCommand::Watch(watchpoint) => {
+                DebugResponse::Added(self.state.add_watchpoint(watchpoint))
+            }
+            DebugCommand::Delete(id) => match self.state.remove_breakpoint(id) {
+                true => DebugResponse::Deleted(id),
+                false => DebugResponse::Error(format!("no breakpoint or watchpoint {}", id.0)),
+            },
+            DebugCommand::Breaks => DebugResponse::Breaks {
+                breakpoints: self.state.breakpoints().to_vec(),
+                watchpoints: self.state.watchpoints().to_vec(),
+            },
+            DebugCommand::Frame(n) => self.select_frame(n.unwrap_or(self.frame)),
+            DebugCommand::Up => match self.frame + 1 < self.state.calls.depth() {
+                true => self.select_frame(self.frame + 1),
+                false => DebugResponse::Error(String::from("already at the outermost frame")),
+            },
+            DebugCommand::Down => match self.frame {
+                0 => DebugResponse::Error(String::from("already at the innermost frame")),
+                n => self.select_frame(n - 1),
+            },
+            DebugCommand::Registers(format) => {
+                DebugResponse::Registers(self.state.reg.clone(), format.unwrap_or(self.format))
+            }
+            DebugCommand::Status => DebugResponse::Status(status::status(&self.state)),
+            DebugCommand::Memory(start, count, format) => {
+                let format = format.unwrap_or(self.format);
+                DebugResponse::Memory(start, self.words(start, count), format)
+            }
+            DebugCommand::Dump(start, count, stride) => {
+                DebugResponse::Dump(start, self.words(start, count), stride)
+            }
+            DebugCommand::Find(pattern) => DebugResponse::Found(self.find(&pattern)),
+            DebugCommand::Disassemble(start, count) => {
+                let start = start.unwrap_or(self.state.reg.pc());
+                DebugResponse::Disassembly(start, self.words(start, count))
+            }
+            DebugCommand::Set(r, value) => {
+                self.state.reg.set(r, value);
+                DebugResponse::Done
+            }
+            DebugCommand::Poke(address, value) => {
+                self.state.mem.poke(address, value);
+                DebugResponse::Done
+            }
+            DebugCommand::Fill(start, end, value) => {
+                let value = value.unwrap_or(self.state.mem.fill_pattern());
+                self.state.mem.fill(start, end, value);
+                DebugResponse::Done
+            }
+            DebugCommand::Assemble(address, word) => {
+                self.state.mem.poke(address, word);
+                DebugResponse::Disassembly(address, vec![word])
+            }
+            DebugCommand::Trace(on) => {
+                self.state.trace = on;
+                DebugResponse::Done
+            }
+            DebugCommand::TraceOnly(span) => {
+                match span {
+                    Some(span) => {
+                        self.state.trace_only.push(span.resolve(&self.state.mem));
+                        self.state.trace = true;
+                    }
+                    None => self.state.trace_only.clear(),
+                }
+                DebugResponse::Done
+            }
+            DebugCommand::AssertState(path, registers_only) => {
+                let checked = fs::read(&path)
+                    .and_then(|bytes| Snapshot::parse(&bytes))
+                    .map_err(|e| format!("{}: {}", path, e))
+                    .and_then(|expected| {
+                        snapshot::assert_state(&expected, &self.state, registers_only)
+                    });
+                match checked {
+                    Ok(()) => DebugResponse::Matches(path),
+                    Err(e) => DebugResponse::Error(e),
+                }
+            }
+            DebugCommand::Format(format) => {
+                self.format = format;
+                DebugResponse::Done
+            }
+            DebugCommand::Help => DebugResponse::Help,
+            DebugCommand::Quit => DebugResponse::Quit,
+        }
+    }
+
+    // Runs `count` instructions, or until the machine stops if None.
+    fn run(&mut self, count: Option<u64>) -> DebugResponse {
+        self.frame = 0;
+        let state = &mut self.state;
+        if !state.running && state.hit.is_none() {
+            return DebugResponse::Error(String::from("the program is not running"));
+        }
+        if state.hit.is_some() {
+            state.resume();
+        }
+        let mut result = StepResult::Running;
+        let mut last = None;
+        let mut steps = 0;
+        self.checkpoints.record(state);
+        while result == StepResult::Running && count.is_none_or(|count| steps < count) {
+            if count.is_none() {
+                result = state.step_once();
+                self.checkpoints.record(state);
+                continue;
+            }
+            let info = state.step();
+            self.checkpoints.record(state);
+            result = info.result;
+            if info.executed.is_some() {
+                last = Some(info);
+            }
+            steps += 1;
+        }
+
+        let pc = state.reg.pc();
+        DebugResponse::Stopped {
+            result,
+            error: state.error.clone(),
+            pc,
+            instr: state.mem.peek(pc),
+            last,
+            source: self.sources.statement_at(pc).map(|s| s.to_string()),
+        }
+    }
+
+    // Steps until PC reaches the start of a source statement `count` times,
+    // running through any code without source on the way.
+    fn step_lines(&mut self, count: u64) -> DebugResponse {
+        if self.sources.is_empty() {
+            return DebugResponse::Error(String::from(
+                "no source is loaded, build the image with lc3 asm --metadata",
+            ));
+        }
+        let mut lines = 0;
+        loop {
+            let response = self.run(Some(1));
+            let DebugResponse::Stopped {
+                result: StepResult::Running,
+                source: Some(_),
+                ..
+            } = response
+            else {
+                match response {
+                    DebugResponse::Stopped {
+                        result: StepResult::Running,
+                        ..
+                    } => continue,
+                    response => return response,
+                }
+            };
+            lines += 1;
+            if lines >= count {
+                return response;
+            }
+        }
+    }
+
+    fn select_frame(&mut self, n: usize) -> DebugResponse {
+        let Some(&frame) = self.state.calls.frame(n) else {
+            return DebugResponse::Error(match self.state.calls.depth() {
+                0 => String::from("not in a subroutine"),
+                depth => format!("no frame {}, the frames are 0 to {}", n, depth - 1),
+            });
+        };
+        self.frame = n;
+        let routine = self
+            .state
+            .symbols
+            .iter()
+            .find(|&(_, &address)| address == frame.routine)
+            .map(|(label, _)| label.clone());
+        DebugResponse::Frame {
+            index: n,
+            frame,
+            routine,
+        }
+    }
+
+    // The addresses of the words where `pattern` starts, a byte pattern
+    // starting in the low byte of a word giving that word.
+    fn find(&self, pattern: &Pattern) -> Vec<u16> {
+        let memory = self.words(0, MEMORY_MAX);
+        let mut found: Vec<u16> = match pattern {
+            Pattern::Words(words) => (memory.windows(words.len()).zip(0..=u16::MAX))
+                .filter(|(window, _)| window == words)
+                .map(|(_, address)| address)
+                .collect(),
+            Pattern::Bytes(bytes) => {
+                let memory: Vec<u8> = memory.iter().flat_map(|w| w.to_be_bytes()).collect();
+                (memory.windows(bytes.len()).enumerate())
+                    .filter(|(_, window)| window == bytes)
+                    .map(|(i, _)| (i / 2) as u16)
+                    .collect()
+            }
+        };
+        found.dedup();
+        found
+    }
+
+    fn words(&self, start: u16, count: usize) -> Vec<u16> {
+        (start..=u16::MAX)
+            .take(count)
+            .map(|address| self.state.mem.peek(address))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::{self, Symbols},
+        breakpoints::{Breakpoint, BreakpointId, Hit, Span},
+        checkpoint::Checkpoints,
+        color,
+        config::Config,
+        debugger::{DebugCommand, DebugResponse, DebuggerCore, Format},
+        defs::R,
+        snapshot::Snapshot,
+        state::{State, StepResult},
+        status,
+    };
+
+    fn core() -> DebuggerCore {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.poke(0x3000, 0x1261); // ADD R1, R1, #1
+        state.mem.poke(0x3001, 0x0FFE); // BRnzp x3000
+        DebuggerCore::new(state)
+    }
+
+    fn run(core: &mut DebuggerCore, line: &str) -> DebugResponse {
+        core.execute(DebugCommand::parse(line).unwrap())
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(DebugCommand::Step(1)), DebugCommand::parse("s"));
+        assert_eq!(Ok(DebugCommand::Step(5)), DebugCommand::parse("step 5"));
+        assert_eq!(
+            Ok(DebugCommand::Break(Breakpoint::Address(0x3001))),
+            DebugCommand::parse("b x3001")
+        );
+        assert_eq!(
+            Ok(DebugCommand::Break(Breakpoint::Trap(0x23))),
+            DebugCommand::parse("break trap IN")
+        );
+        assert_eq!(
+            Ok(DebugCommand::Memory(0x4000, 8, None)),
+            DebugCommand::parse("x 0x4000")
+        );
+        assert_eq!(
+            Ok(DebugCommand::Set(R::R2, 16)),
+            DebugCommand::parse("set r2 x10")
+        );
+        let symbols = Symbols::from([(String::from("LOOP"), 0x3004)]);
+        assert_eq!(
+            Ok(DebugCommand::Break(Breakpoint::Address(0x3004))),
+            DebugCommand::parse_with("b LOOP", &symbols)
+        );
+        assert!(DebugCommand::parse("").is_err());
+        assert!(DebugCommand::parse("break op FOO").is_err());
+        assert_eq!(
+            Ok(DebugCommand::BreakRange(Span::Range(0x3000, 0x30FF))),
+            DebugCommand::parse("break range x3000 x30FF")
+        );
+        let symbols = Symbols::from([(String::from("SUBR"), 0x3010)]);
+        assert_eq!(
+            Ok(DebugCommand::TraceOnly(Some(Span::Routine(0x3010)))),
+            DebugCommand::parse_with("trace --only SUBR", &symbols)
+        );
+        assert!(DebugCommand::parse("trace maybe").is_err());
+        assert!(DebugCommand::parse("jump").is_err());
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints_and_steps_past_them() {
+        let mut core = core();
+        assert_eq!(
+            DebugResponse::Added(BreakpointId(1)),
+            run(&mut core, "b x3001")
+        );
+
+        let response = run(&mut core, "c");
+        let hit = Hit::Breakpoint {
+            id: BreakpointId(1),
+            address: 0x3001,
+            breakpoint: Breakpoint::Address(0x3001),
+        };
+        assert_eq!(
+            DebugResponse::Stopped {
+                result: StepResult::BreakpointHit(hit),
+                error: None,
+                pc: 0x3001,
+                instr: 0x0FFE,
+                last: None,
+                source: None,
+            },
+            response
+        );
+        assert_eq!(
+            "break on x3001 at x3001\nx3001: 0FFE  BRnzp x3000",
+            response.to_string()
+        );
+
+        run(&mut core, "step 2");
+        assert_eq!(2, core.state.reg[R::R1]);
+        assert_eq!(0x3001, core.state.reg.pc());
+
+        assert_eq!(
+            DebugResponse::Deleted(BreakpointId(1)),
+            run(&mut core, "d 1")
+        );
+        assert!(matches!(run(&mut core, "d 1"), DebugResponse::Error(_)));
+    }
+
+    #[test]
+    fn frames_follow_the_calls() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.symbols.insert(String::from("INNER"), 0x3020);
+        // x3000 JSR x3010, x3010 JSR x3020, x3020 HALT
+        state.mem.poke(0x3000, 0x480F);
+        state.mem.poke(0x3010, 0x480F);
+        state.mem.poke(0x3020, 0xF025);
+        let mut core = DebuggerCore::new(state);
+        assert!(matches!(run(&mut core, "f"), DebugResponse::Error(_)));
+
+        run(&mut core, "step 2");
+        let DebugResponse::Frame {
+            index,
+            frame,
+            routine,
+        } = run(&mut core, "frame")
+        else {
+            panic!("no frame");
+        };
+        assert_eq!(
+            (0, 0x3010, Some("INNER")),
+            (index, frame.call_site, routine.as_deref())
+        );
+        assert_eq!(
+            "#1  x3010, called from x3000, returns to x3001\nR0 x0000  R1 x0000  R2 x0000  R3 x0000",
+            run(&mut core, "up").to_string()
+        );
+        assert!(matches!(run(&mut core, "up"), DebugResponse::Error(_)));
+        assert!(matches!(
+            run(&mut core, "down"),
+            DebugResponse::Frame { index: 0, .. }
+        ));
+        assert!(matches!(run(&mut core, "frame 2"), DebugResponse::Error(_)));
+    }
+
+    #[test]
+    fn skip_and_patch_instructions() {
+        let mut core = core();
+        run(&mut core, "skip");
+        assert_eq!((0, 0x3001), (core.state.reg[R::R1], core.state.reg.pc()));
+
+        let symbols = Symbols::from([(String::from("TOP"), 0x3000)]);
+        assert_eq!(
+            Ok(DebugCommand::Assemble(0x3001, 0x0FFE)),
+            DebugCommand::parse_with("assemble-at x3001 \"BRnzp TOP\"", &symbols)
+        );
+        assert_eq!(
+            DebugResponse::Disassembly(0x3000, vec![0x1262]),
+            run(&mut core, "assemble-at x3000   \"ADD R1,R1,#2\"")
+        );
+        run(&mut core, "step 2");
+        assert_eq!(2, core.state.reg[R::R1]);
+        assert!(DebugCommand::parse("assemble-at x3000").is_err());
+        assert!(DebugCommand::parse("assemble-at x3000 \"FOO R1\"").is_err());
+    }
+
+    #[test]
+    fn aliases_and_macros_expand() {
+        let mut core = core();
+        let responses = core.execute_line("alias peek mem x3000; define twice; step; step; end");
+        assert!(responses.is_empty());
+        assert!(!core.macros.is_defining());
+
+        let responses = core.execute_line("twice; peek 2");
+        assert_eq!((1, 0x3000), (core.state.reg[R::R1], core.state.reg.pc()));
+        assert_eq!(
+            Some(&DebugResponse::Memory(
+                0x3000,
+                vec![0x1261, 0x0FFE],
+                Format::Hex
+            )),
+            responses.last()
+        );
+
+        core.execute_line("define loop");
+        assert!(core.macros.is_defining());
+        core.execute_line("loop");
+        core.execute_line("end");
+        assert!(matches!(
+            core.execute_line("loop").as_slice(),
+            [DebugResponse::Error(_)]
+        ));
+        assert!(matches!(
+            core.execute_line("alias regs mem x0").as_slice(),
+            [DebugResponse::Error(_)]
+        ));
+        assert!(matches!(
+            core.execute_line("end").as_slice(),
+            [DebugResponse::Error(_)]
+        ));
+        assert_eq!(vec!["peek", "loop", "twice"], core.macros.names());
+    }
+
+    #[test]
+    fn memory_and_registers_are_shown_and_set() {
+        let mut core = core();
+        run(&mut core, "poke x4000 xBEEF");
+        run(&mut core, "set R3 -1");
+        assert_eq!(
+            DebugResponse::Memory(0x4000, vec![0xBEEF, 0], Format::Hex),
+            run(&mut core, "mem x4000 2")
+        );
+        assert_eq!(0xFFFF, core.state.reg[R::R3]);
+        assert_eq!(
+            "x3000: 1261  ADD R1, R1, #1",
+            run(&mut core, "dis x3000 1").to_string()
+        );
+    }
+
+    #[test]
+    fn find_values_strings_and_bytes() {
+        let mut core = core();
+        core.state.mem.write_slice(0x4000, &[0x48, 0x69, 0]);
+        core.state.mem.write_slice(0x4010, &[0x4869, 0x00EF]);
+        core.state.mem.write_slice(0x4020, &[0xBEEF]);
+        assert_eq!(
+            DebugResponse::Found(vec![0x4000]),
+            run(&mut core, "find \"Hi\"")
+        );
+        assert_eq!(
+            DebugResponse::Found(vec![0x4001, 0x4010]),
+            run(&mut core, "find bytes x69 0")
+        );
+        assert_eq!(
+            DebugResponse::Found(vec![0x4010]),
+            run(&mut core, "find bytes x48 x69")
+        );
+        assert_eq!("1 match\nx4020", run(&mut core, "find xBEEF").to_string());
+        assert_eq!("not found", run(&mut core, "find xCAFE").to_string());
+        assert!(DebugCommand::parse("find bytes x100").is_err());
+        assert!(DebugCommand::parse("find \"open").is_err());
+    }
+
+    #[test]
+    fn dump_shows_words_bytes_and_text() {
+        let mut core = core();
+        core.state.mem.write_slice(0x4000, &[0x48, 0x69]);
+
+        assert_eq!(
+            Ok(DebugCommand::Dump(0x4000, 64, 8)),
+            DebugCommand::parse("x/ x4000")
+        );
+        assert_eq!(
+            "x4000: 0048 0069 0000 0000 | 00 48 00 69 00 00 00 00 | Hi..\n\
+             x4004: 0000 0000           | 00 00 00 00             | ..",
+            run(&mut core, "x/4 x4000 6").to_string()
+        );
+        assert!(DebugCommand::parse("x/0 x4000").is_err());
+    }
+
+    #[test]
+    fn states_are_asserted_against_snapshots() {
+        let mut core = core();
+        let path = std::env::temp_dir().join(format!("lc3-assert-{}.snap", std::process::id()));
+        std::fs::write(&path, Snapshot::take(&core.state).to_bytes()).unwrap();
+        let line = format!("assert-state {}", path.display());
+
+        assert_eq!(
+            format!("state matches {}", path.display()),
+            run(&mut core, &line).to_string()
+        );
+        run(&mut core, "poke x4000 1");
+        assert!(matches!(run(&mut core, &line), DebugResponse::Error(_)));
+        assert!(matches!(
+            run(&mut core, &format!("{} --regs-only", line)),
+            DebugResponse::Matches(_)
+        ));
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(run(&mut core, &line), DebugResponse::Error(_)));
+    }
+
+    #[test]
+    fn values_are_shown_in_the_format_asked_for() {
+        let mut core = core();
+        run(&mut core, "poke x4000 x41");
+        run(&mut core, "poke x4001 -2");
+        assert_eq!(
+            Ok(DebugCommand::Memory(0x4000, 2, Some(Format::Char))),
+            DebugCommand::parse("mem /c x4000 2")
+        );
+        assert_eq!(
+            "x4000: 'A'\nx4001: xFFFE",
+            run(&mut core, "mem /c x4000 2").to_string()
+        );
+        assert!(DebugCommand::parse("mem /q x4000").is_err());
+        assert!(DebugCommand::parse("step /d").is_err());
+
+        run(&mut core, "format signed");
+        assert_eq!("x4001: -2", run(&mut core, "mem x4001 1").to_string());
+        assert_eq!("x4001: 65534", run(&mut core, "mem /u x4001 1").to_string());
+        run(&mut core, "set R2 x41");
+        let regs = run(&mut core, "regs /c").to_string();
+        assert_eq!(Some("R2 'A'"), regs.lines().nth(2));
+        let regs = run(&mut core, "regs").to_string();
+        assert_eq!(Some("R2 65"), regs.lines().nth(2));
+    }
+
+    #[test]
+    fn steps_show_what_loads_and_stores_moved() {
+        let mut core = core();
+        core.state.mem.poke(0x3000, 0x6885); // LDR R4, R2, #5
+        core.state.mem.poke(0x3001, 0xB802); // STI R4, x3004
+        core.state.mem.poke(0x3004, 0x4100);
+        core.state.mem.poke(0x4005, 0x0042);
+        core.state.reg.set(R::R2, 0x4000);
+        assert_eq!(
+            "x3000: 6885  LDR R4, R2, #5 ; EA=x4005 -> x0042\nx3001: B802  STI R4, x3004",
+            run(&mut core, "step").to_string()
+        );
+        assert_eq!(
+            Some("x3001: B802  STI R4, x3004 ; EA=x4100 <- x0042"),
+            run(&mut core, "step").to_string().lines().next()
+        );
+        // BR touches no memory, so only the next instruction is shown
+        assert_eq!(1, run(&mut core, "step").to_string().lines().count());
+    }
+
+    #[test]
+    fn fill_restores_the_fill_pattern() {
+        let config = Config {
+            fill: 0xDEAD,
+            ..Config::default()
+        };
+        let mut core = DebuggerCore::new(State::with_config(&config));
+        core.state.mem.write_slice(0x4000, &[1, 2, 3]);
+
+        assert_eq!(DebugResponse::Done, run(&mut core, "fill x4000 x4001"));
+        assert_eq!(
+            vec![0xDEAD, 0xDEAD, 3],
+            core.state.mem.read_slice(0x4000..0x4003)
+        );
+        assert!(!core.state.mem.is_written(0x4000));
+        run(&mut core, "fill x4002 x4002 7");
+        assert_eq!(7, core.state.mem.peek(0x4002));
+
+        assert_eq!(
+            Ok(DebugCommand::Fill(0x4000, 0x40FF, Some(0))),
+            DebugCommand::parse("fill x4000 x40FF 0")
+        );
+        assert!(DebugCommand::parse("fill x4001 x4000").is_err());
+        assert!(DebugCommand::parse("fill x4000").is_err());
+    }
+
+    #[test]
+    fn status_shows_the_halt_layout() {
+        let mut core = core();
+        core.state.reg[R::R1] = 0xFFFE;
+
+        let response = run(&mut core, "status");
+        assert_eq!(DebugResponse::Status(status::status(&core.state)), response);
+        let text = response.to_string();
+        assert!(text.contains("R1 xFFFE  65534     -2"));
+        assert!(text.contains("CC N=0 Z=1 P=0"));
+        assert!(text.ends_with("PC x3000: 1261  ADD R1, R1, #1"));
+    }
+
+    #[test]
+    fn source_lines_are_shown_and_stepped() {
+        let source = "\
+        .ORIG x3000
+        JSR DOUBLE
+        HALT
+; R1 = R1 + R1
+DOUBLE  ADD R1, R1, R1
+
+        RET
+        .END
+";
+        let program = asm::assemble(source).unwrap();
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.write_slice(program.origin, &program.words);
+        let mut core = DebuggerCore::new(state);
+        assert_eq!(
+            DebugResponse::Error(String::from(
+                "no source is loaded, build the image with lc3 asm --metadata"
+            )),
+            run(&mut core, "step-line")
+        );
+        core.sources
+            .add("src/double.asm", source, &program.metadata(source))
+            .unwrap();
+
+        assert_eq!(
+            "x3002: 1241  ADD R1, R1, R1\nsrc/double.asm:5  DOUBLE  ADD R1, R1, R1",
+            run(&mut core, "step-line").to_string()
+        );
+        /* the blank line breaks on RET */
+        assert_eq!(
+            DebugResponse::Added(BreakpointId(1)),
+            run(&mut core, "break double.asm:6")
+        );
+        assert!(run(&mut core, "continue")
+            .to_string()
+            .ends_with("src/double.asm:7  RET"));
+        assert!(run(&mut core, "sl 2").to_string().contains("halted"));
+
+        assert_eq!(
+            DebugResponse::Error(String::from("no source other.asm is loaded")),
+            run(&mut core, "break other.asm:1")
+        );
+        assert_eq!(
+            Ok(DebugCommand::BreakLine(String::from("a.asm"), 3)),
+            DebugCommand::parse("break a.asm:3")
+        );
+        assert!(DebugCommand::parse("break a.asm:x").is_err());
+    }
+
+    #[test]
+    fn the_alternate_form_is_coloured() {
+        let response = DebugResponse::Disassembly(0x3000, vec![0x1261]);
+        assert_eq!("x3000: 1261  ADD R1, R1, #1", response.to_string());
+        assert_eq!(
+            format!(
+                "\x1b[33mx3000\x1b[0m: 1261  {}",
+                color::instruction("ADD R1, R1, #1")
+            ),
+            format!("{:#}", response)
+        );
+    }
+
+    #[test]
+    fn rollback_restores_registers_and_memory() {
+        let mut core = core();
+        core.checkpoints = Checkpoints::new(2, 4);
+        core.state.mem.poke(0x3001, 0x3202); // ST R1, x3004
+        core.state.mem.poke(0x3002, 0x0FFD); // BRnzp x3000
+
+        run(&mut core, "step 5");
+        assert_eq!(2, core.state.reg[R::R1]);
+        assert_eq!(2, core.state.mem.peek(0x3004));
+        assert_eq!(3, core.checkpoints.len());
+
+        let response = run(&mut core, "rollback 2");
+        assert_eq!(
+            DebugResponse::RolledBack {
+                instructions: 2,
+                pc: 0x3002,
+                instr: 0x0FFD
+            },
+            response
+        );
+        assert_eq!(1, core.state.reg[R::R1]);
+        assert_eq!(1, core.state.mem.peek(0x3004));
+        assert_eq!(2, core.state.stats.instructions);
+        /* the newer checkpoint is gone, and running again retakes it */
+        assert_eq!(2, core.checkpoints.len());
+        run(&mut core, "step 2");
+        assert_eq!(3, core.checkpoints.len());
+
+        assert_eq!(
+            DebugResponse::Error(String::from("only 3 checkpoints are kept")),
+            run(&mut core, "rollback 4")
+        );
+        core.checkpoints = Checkpoints::new(2, 4);
+        assert_eq!(
+            DebugResponse::Error(String::from("no checkpoint has been taken")),
+            run(&mut core, "rollback")
+        );
+    }
+}
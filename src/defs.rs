@@ -1,7 +1,10 @@
+#![allow(clippy::upper_case_acronyms)]
+
+use std::fmt;
+
 // Registers
 #[repr(usize)]
-#[allow(dead_code)]
-// Registers
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum R {
     R0 = 0,
     R1,
@@ -13,7 +16,40 @@ pub enum R {
     R7,
     PC, /* program counter */
     COND,
-    COUNT,
+}
+
+impl R {
+    pub const COUNT: usize = 10;
+    pub const ALL: [R; R::COUNT] = [
+        R::R0,
+        R::R1,
+        R::R2,
+        R::R3,
+        R::R4,
+        R::R5,
+        R::R6,
+        R::R7,
+        R::PC,
+        R::COND,
+    ];
+
+    // The general purpose register named by the low three bits of `bits`, as
+    // decoded from an instruction field.
+    pub(crate) fn field(bits: u16) -> Self {
+        R::ALL[(bits & 0x7) as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"][*self as usize]
+    }
+}
+
+impl TryFrom<u16> for R {
+    type Error = u16;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        R::ALL.get(value as usize).copied().ok_or(value)
+    }
 }
 
 // Opcodes
@@ -38,6 +74,29 @@ pub enum OP {
     TRAP,   /* execute trap */
 }
 
+impl OP {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OP::BR => "BR",
+            OP::ADD => "ADD",
+            OP::LD => "LD",
+            OP::ST => "ST",
+            OP::JSR => "JSR",
+            OP::AND => "AND",
+            OP::LDR => "LDR",
+            OP::STR => "STR",
+            OP::RTI => "RTI",
+            OP::NOT => "NOT",
+            OP::LDI => "LDI",
+            OP::STI => "STI",
+            OP::JMP => "JMP",
+            OP::RES => "RES",
+            OP::LEA => "LEA",
+            OP::TRAP => "TRAP",
+        }
+    }
+}
+
 impl TryFrom<u16> for OP {
     type Error = u16;
 
@@ -65,14 +124,41 @@ impl TryFrom<u16> for OP {
 }
 
 // Trap routines
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(u16)]
 pub enum TRAP {
-    GETC = 0x20,  /* get character from keyboard, not echoed onto the terminal */
-    OUT = 0x21,   /* output a character */
-    PUTS = 0x22,  /* output a word string */
-    IN = 0x23,    /* get character from keyboard, echoed onto the terminal */
-    PUTSP = 0x24, /* output a byte string */
-    HALT = 0x25,  /* halt the program */
+    GETC = 0x20,   /* get character from keyboard, not echoed onto the terminal */
+    OUT = 0x21,    /* output a character */
+    PUTS = 0x22,   /* output a word string */
+    IN = 0x23,     /* get character from keyboard, echoed onto the terminal */
+    PUTSP = 0x24,  /* output a byte string */
+    HALT = 0x25,   /* halt the program */
+    EXIT = 0x26,   /* halt with the status in R0, an emulator extension */
+    SLEEP = 0x27,  /* sleep for R0 milliseconds or yield, an emulator extension */
+    CURSOR = 0x28, /* clear the screen or move the cursor, an emulator extension */
+}
+
+impl TRAP {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TRAP::GETC => "GETC",
+            TRAP::OUT => "OUT",
+            TRAP::PUTS => "PUTS",
+            TRAP::IN => "IN",
+            TRAP::PUTSP => "PUTSP",
+            TRAP::HALT => "HALT",
+            TRAP::EXIT => "EXIT",
+            TRAP::SLEEP => "SLEEP",
+            TRAP::CURSOR => "CURSOR",
+        }
+    }
+
+    // The trap named `name`, in any case.
+    pub fn from_name(name: &str) -> Option<TRAP> {
+        (0x20..=0x28)
+            .filter_map(|vector| TRAP::try_from(vector).ok())
+            .find(|trap| trap.name().eq_ignore_ascii_case(name))
+    }
 }
 
 impl TryFrom<u16> for TRAP {
@@ -86,6 +172,9 @@ impl TryFrom<u16> for TRAP {
             0x23 => Ok(TRAP::IN),
             0x24 => Ok(TRAP::PUTSP),
             0x25 => Ok(TRAP::HALT),
+            0x26 => Ok(TRAP::EXIT),
+            0x27 => Ok(TRAP::SLEEP),
+            0x28 => Ok(TRAP::CURSOR),
             _ => Err(value),
         }
     }
@@ -99,9 +188,90 @@ pub enum FL {
     NEG = 1 << 2, /* N */
 }
 
+// The condition codes held in R::COND. Exactly one of N, Z and P is set:
+// every instruction that writes a register sets the one matching the result.
+#[derive(Clone, Copy, PartialEq)]
+pub struct CondFlags(u16);
+
+impl CondFlags {
+    pub const N: Self = Self(FL::NEG as u16);
+    pub const Z: Self = Self(FL::ZRO as u16);
+    pub const P: Self = Self(FL::POS as u16);
+
+    // The flags set by writing `value` to a register.
+    pub fn from_result(value: u16) -> Self {
+        if value == 0 {
+            Self::Z
+        } else if value >> 15 != 0 {
+            // a 1 in the left-most bit indicates negative
+            Self::N
+        } else {
+            Self::P
+        }
+    }
+
+    // Wraps the raw contents of R::COND. Anything other than exactly one of
+    // the three bits means the register was corrupted, which debug builds catch.
+    pub fn from_bits(bits: u16) -> Self {
+        debug_assert!(
+            Self::is_valid(bits),
+            "condition codes x{:04X} are not exactly one of N, Z and P",
+            bits
+        );
+        Self(bits)
+    }
+
+    pub fn is_valid(bits: u16) -> bool {
+        [Self::N, Self::Z, Self::P].contains(&Self(bits))
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn is_n(self) -> bool {
+        self.0 & FL::NEG as u16 != 0
+    }
+
+    pub fn is_z(self) -> bool {
+        self.0 & FL::ZRO as u16 != 0
+    }
+
+    pub fn is_p(self) -> bool {
+        self.0 & FL::POS as u16 != 0
+    }
+
+    // Whether a BR with the n, z and p bits `nzp` is taken.
+    pub fn matches(self, nzp: u16) -> bool {
+        self.0 & nzp != 0
+    }
+}
+
+impl fmt::Display for CondFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::N => write!(f, "N"),
+            Self::Z => write!(f, "Z"),
+            Self::P => write!(f, "P"),
+            Self(bits) => write!(f, "x{:04X}", bits),
+        }
+    }
+}
+
+impl fmt::Debug for CondFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 // Memory-mapped registers
 #[repr(u16)]
 pub enum MR {
     KBSR = 0xFE00, /* keyboard status */
     KBDR = 0xFE02, /* keyboard data */
+    DSR = 0xFE04,  /* display status */
+    DDR = 0xFE06,  /* display data */
+    CLK = 0xFE08,  /* millisecond clock, high word, low word at xFE09 */
+    CNT = 0xFE0A,  /* performance counters, six words, see counters.rs */
+    MCR = 0xFFFE,  /* machine control */
 }
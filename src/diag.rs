@@ -0,0 +1,230 @@
+// Diagnostics about the running program
+//
+// The checkers in State look for things that are legal for the machine but
+// almost always bugs in the program:
+//
+//   r7-clobber      R7 overwritten while it holds an unsaved return address
+//   uninitialized   a load from a word that was never loaded or written
+//   self-modifying  a store into a word that has already run as code
+//   stack           R6 used as a stack pointer while still x0000, or a
+//                   subroutine returning with R6 not where the call left it
+//   read-only       a store into a read-only range, which is ignored
+//
+// Each check has a severity, set with --warn NAME, --error NAME and
+// --no-warn NAME (or "all"): off, a warning, or an error that stops the
+// machine. A check is reported once per address it fires at, however often
+// it fires there, and the driver prints a summary of all of them at exit.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    R7Clobber,
+    Uninitialized,
+    SelfModifying,
+    Stack,
+    ReadOnly,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::R7Clobber,
+        Check::Uninitialized,
+        Check::SelfModifying,
+        Check::Stack,
+        Check::ReadOnly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::R7Clobber => "r7-clobber",
+            Check::Uninitialized => "uninitialized",
+            Check::SelfModifying => "self-modifying",
+            Check::Stack => "stack",
+            Check::ReadOnly => "read-only",
+        }
+    }
+
+    // The checks `name` stands for, all of them for "all".
+    pub fn parse(name: &str) -> Result<Vec<Check>, String> {
+        if name == "all" {
+            return Ok(Check::ALL.to_vec());
+        }
+        Check::ALL
+            .into_iter()
+            .find(|check| check.name() == name)
+            .map(|check| vec![check])
+            .ok_or(format!("unknown check {}", name))
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Off,
+    Warning,
+    Error, /* stops the machine */
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    severities: [Severity; Check::ALL.len()], /* in Check::ALL order */
+    counts: [u64; Check::ALL.len()],
+    seen: BTreeMap<(Check, u16), String>, /* where each check has fired, and why */
+    pub quiet: bool,                      /* keep reports without printing them */
+}
+
+impl Diagnostics {
+    pub fn set(&mut self, check: Check, severity: Severity) {
+        self.severities[check as usize] = severity;
+    }
+
+    pub fn severity(&self, check: Check) -> Severity {
+        self.severities[check as usize]
+    }
+
+    // Turns every check off, for runs whose output nobody reads.
+    pub fn silence(&mut self) {
+        self.severities = [Severity::Off; Check::ALL.len()];
+    }
+
+    // Reports that `check` fired at `pc`, keeping `message` and printing it
+    // to stderr the first time it does there. Returns the check's severity.
+    pub fn report(&mut self, check: Check, pc: u16, message: impl FnOnce() -> String) -> Severity {
+        let severity = self.severity(check);
+        if severity == Severity::Off {
+            return severity;
+        }
+        self.counts[check as usize] += 1;
+        if let Entry::Vacant(entry) = self.seen.entry((check, pc)) {
+            let message = entry.insert(message());
+            let level = match severity {
+                Severity::Error => "error",
+                _ => "warning",
+            };
+            if !self.quiet {
+                eprintln!("{}[{}]: {}", level, check, message);
+            }
+        }
+        severity
+    }
+
+    // How often `check` fired, and at how many addresses.
+    pub fn count(&self, check: Check) -> (u64, usize) {
+        let places = self.seen.keys().filter(|&&(c, _)| c == check).count();
+        (self.counts[check as usize], places)
+    }
+
+    // Each check that fired, where it did and the message it reported there,
+    // by check and then by address.
+    pub fn fired(&self) -> impl Iterator<Item = (Check, u16, &str)> {
+        self.seen
+            .iter()
+            .map(|(&(check, pc), message)| (check, pc, message.as_str()))
+    }
+
+    // One line on each check that fired, None if none did.
+    pub fn summary(&self) -> Option<String> {
+        let parts: Vec<String> = Check::ALL
+            .into_iter()
+            .filter(|&check| self.counts[check as usize] > 0)
+            .map(|check| {
+                let (count, places) = self.count(check);
+                let times = if count == 1 { "time" } else { "times" };
+                let addresses = if places == 1 { "address" } else { "addresses" };
+                format!("{} {} {} at {} {}", check, count, times, places, addresses)
+            })
+            .collect();
+        (!parts.is_empty()).then(|| format!("diagnostics: {}", parts.join(", ")))
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self {
+            severities: [Severity::Warning; Check::ALL.len()],
+            counts: [0; Check::ALL.len()],
+            seen: BTreeMap::new(),
+            quiet: false,
+        }
+    }
+}
+
+// A set of addresses, one bit each
+#[derive(Clone, Debug)]
+pub struct AddressSet {
+    bits: Vec<u64>,
+}
+
+impl AddressSet {
+    pub fn insert(&mut self, address: u16) {
+        self.bits[address as usize / 64] |= 1 << (address % 64);
+    }
+
+    pub fn remove(&mut self, address: u16) {
+        self.bits[address as usize / 64] &= !(1 << (address % 64));
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.bits[address as usize / 64] & (1 << (address % 64)) != 0
+    }
+}
+
+impl Default for AddressSet {
+    fn default() -> Self {
+        Self {
+            bits: vec![0; (1 << 16) / 64],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diag::{AddressSet, Check, Diagnostics, Severity};
+
+    #[test]
+    fn reports_are_counted_per_address() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.set(Check::Stack, Severity::Error);
+        diagnostics.set(Check::SelfModifying, Severity::Off);
+        for pc in [0x3000, 0x3000, 0x3004] {
+            diagnostics.report(Check::Uninitialized, pc, || String::from("load"));
+        }
+        let off = diagnostics.report(Check::SelfModifying, 0x3001, String::new);
+        let error = diagnostics.report(Check::Stack, 0x3002, String::new);
+        assert_eq!((Severity::Off, Severity::Error), (off, error));
+        assert_eq!((3, 2), diagnostics.count(Check::Uninitialized));
+        assert_eq!((0, 0), diagnostics.count(Check::SelfModifying));
+        let first = diagnostics.fired().next();
+        assert_eq!(Some((Check::Uninitialized, 0x3000, "load")), first);
+        assert_eq!(
+            Some(String::from(
+                "diagnostics: uninitialized 3 times at 2 addresses, stack 1 time at 1 address"
+            )),
+            diagnostics.summary()
+        );
+        diagnostics.silence();
+        assert_eq!(Severity::Off, diagnostics.severity(Check::R7Clobber));
+    }
+
+    #[test]
+    fn checks_are_named() {
+        assert_eq!(Ok(vec![Check::R7Clobber]), Check::parse("r7-clobber"));
+        assert_eq!(5, Check::parse("all").unwrap().len());
+        assert!(Check::parse("r7").is_err());
+
+        let mut set = AddressSet::default();
+        set.insert(0xFFFF);
+        set.insert(0x3000);
+        set.remove(0x3000);
+        assert!(set.contains(0xFFFF) && !set.contains(0x3000));
+    }
+}
@@ -0,0 +1,267 @@
+// Lockstep comparison of two programs
+//
+// Runs two machines on the same scripted input one instruction at a time and
+// stops after the first instruction that leaves their architectural state
+// different: a register, the condition codes, PC, a word either machine has
+// stored, the console output so far, or whether the machine is still running.
+// Memory is only compared where a program writes, since two builds of the same
+// program rarely have identical code.
+
+use crate::{
+    defs::{CondFlags, R},
+    disasm::disassemble,
+    state::State,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    Register { r: R, a: u16, b: u16 },
+    Memory { address: u16, a: u16, b: u16 },
+    Output { a: String, b: String },
+    Running { a: bool, b: bool },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Diverged {
+        step: u64,                 /* instructions run by each machine, including the last */
+        executed: [(u16, u16); 2], /* address and word of the last instruction in A and B */
+        difference: Difference,
+    },
+    Stopped {
+        steps: u64,
+    }, /* both stopped in the same state */
+    StepLimit {
+        steps: u64,
+    }, /* both still running and still in step */
+}
+
+// Both machines should already have their images loaded and their input fed.
+pub fn compare(a: &mut State, b: &mut State, steps: u64) -> Outcome {
+    a.mem.console.capture();
+    b.mem.console.capture();
+
+    for step in 1..=steps {
+        if !a.running && !b.running {
+            return Outcome::Stopped { steps: step - 1 };
+        }
+
+        let executed = [run_one(a), run_one(b)];
+        if let Some(difference) = difference(a, b) {
+            return Outcome::Diverged {
+                step,
+                executed,
+                difference,
+            };
+        }
+    }
+    if !a.running && !b.running {
+        return Outcome::Stopped { steps };
+    }
+    Outcome::StepLimit { steps }
+}
+
+// Runs one instruction if the machine is still running and returns it.
+fn run_one(state: &mut State) -> (u16, u16) {
+    let pc = state.reg[R::PC];
+    let word = state.mem.peek(pc);
+    if state.running {
+        state.step_once();
+    }
+    (pc, word)
+}
+
+fn difference(a: &mut State, b: &mut State) -> Option<Difference> {
+    if a.running != b.running {
+        return Some(Difference::Running {
+            a: a.running,
+            b: b.running,
+        });
+    }
+    /* the flags before PC, so a branch taken only in one run shows why */
+    for &r in R::ALL[..8].iter().chain(&[R::COND, R::PC]) {
+        if a.reg[r] != b.reg[r] {
+            return Some(Difference::Register {
+                r,
+                a: a.reg[r],
+                b: b.reg[r],
+            });
+        }
+    }
+
+    let written = [a.mem.take_last_write(), b.mem.take_last_write()];
+    for address in written.into_iter().flatten() {
+        let (x, y) = (a.mem.peek(address), b.mem.peek(address));
+        if x != y {
+            return Some(Difference::Memory {
+                address,
+                a: x,
+                b: y,
+            });
+        }
+    }
+
+    let (x, y) = (a.mem.console.captured(), b.mem.console.captured());
+    if x != y {
+        return Some(Difference::Output {
+            a: x.unwrap_or_default().to_string(),
+            b: y.unwrap_or_default().to_string(),
+        });
+    }
+    None
+}
+
+pub fn report(outcome: &Outcome, names: [&str; 2]) {
+    match outcome {
+        Outcome::Stopped { steps } => {
+            println!("no divergence: both stopped after {} instructions", steps)
+        }
+        Outcome::StepLimit { steps } => {
+            println!(
+                "no divergence in {} instructions, both still running",
+                steps
+            )
+        }
+        Outcome::Diverged {
+            step,
+            executed,
+            difference,
+        } => {
+            println!("diverged at instruction {}", step);
+            for (name, &(pc, word)) in names.iter().zip(executed) {
+                println!(
+                    "  {}: x{:04X}  {:04X}  {}",
+                    name,
+                    pc,
+                    word,
+                    disassemble(pc, word)
+                );
+            }
+            let [a, b] = names;
+            match difference {
+                Difference::Register {
+                    r: R::COND,
+                    a: x,
+                    b: y,
+                } => println!(
+                    "  CC is {} in {} and {} in {}",
+                    CondFlags::from_bits(*x),
+                    a,
+                    CondFlags::from_bits(*y),
+                    b
+                ),
+                Difference::Register { r, a: x, b: y } => {
+                    println!(
+                        "  {} is x{:04X} in {} and x{:04X} in {}",
+                        r.name(),
+                        x,
+                        a,
+                        y,
+                        b
+                    )
+                }
+                Difference::Memory {
+                    address,
+                    a: x,
+                    b: y,
+                } => println!(
+                    "  x{:04X} holds x{:04X} in {} and x{:04X} in {}",
+                    address, x, a, y, b
+                ),
+                Difference::Output { a: x, b: y } => {
+                    println!("  output from {}: {:?}", a, x);
+                    println!("  output from {}: {:?}", b, y);
+                }
+                Difference::Running { a: x, .. } => {
+                    let (stopped, running) = if *x { (b, a) } else { (a, b) };
+                    println!("  {} stopped while {} kept running", stopped, running)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        defs::R,
+        diffrun::{compare, Difference, Outcome},
+        state::State,
+    };
+
+    fn machine(words: &[u16]) -> State {
+        let mut state = State::new();
+        state.mem.write_slice(0x3000, words);
+        state
+    }
+
+    #[test]
+    fn identical_programs_stop_together() {
+        let program = [0x1261, 0xF025]; // ADD R1, R1, #1; HALT
+        let outcome = compare(&mut machine(&program), &mut machine(&program), 100);
+        assert_eq!(Outcome::Stopped { steps: 2 }, outcome);
+    }
+
+    #[test]
+    fn first_differing_register_is_reported() {
+        let mut a = machine(&[0x1261, 0x1261, 0xF025]); // ADD R1, R1, #1 twice
+        let mut b = machine(&[0x1261, 0x1262, 0xF025]); // then ADD R1, R1, #2
+        let outcome = compare(&mut a, &mut b, 100);
+
+        let Outcome::Diverged {
+            step,
+            executed,
+            difference,
+        } = outcome
+        else {
+            panic!("expected a divergence");
+        };
+        assert_eq!(2, step);
+        assert_eq!([(0x3001, 0x1261), (0x3001, 0x1262)], executed);
+        assert_eq!(
+            Difference::Register {
+                r: R::R1,
+                a: 2,
+                b: 3
+            },
+            difference
+        );
+        assert_eq!(0x3002, a.reg[R::PC]);
+    }
+
+    #[test]
+    fn stores_and_output_are_compared() {
+        let mut a = machine(&[0x7040, 0xF025]); // STR R0, R1, #0
+        let mut b = machine(&[0x7041, 0xF025]); // STR R0, R1, #1
+        for state in [&mut a, &mut b] {
+            state.reg[R::R0] = 7;
+            state.reg[R::R1] = 0x4000;
+        }
+        assert!(matches!(
+            compare(&mut a, &mut b, 100),
+            Outcome::Diverged {
+                step: 1,
+                difference: Difference::Memory {
+                    address: 0x4000,
+                    a: 7,
+                    b: 0
+                },
+                ..
+            }
+        ));
+
+        let mut a = machine(&[0xF021, 0xF025]); // OUT
+        let mut b = machine(&[0x0000, 0xF025]); // NOP
+        a.reg[R::R0] = b'a' as u16;
+        b.reg[R::R0] = b'a' as u16;
+        b.reg[R::R7] = 0x3001; /* where OUT leaves it in A */
+        let expected = Difference::Output {
+            a: String::from("a"),
+            b: String::new(),
+        };
+        assert!(matches!(
+            compare(&mut a, &mut b, 100),
+            Outcome::Diverged { difference, .. } if difference == expected
+        ));
+    }
+}
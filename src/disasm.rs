@@ -0,0 +1,96 @@
+// Disassembler
+//
+// Turns one instruction word back into assembler syntax. PC-relative operands
+// are resolved to the absolute address they refer to, so `address` must be
+// where the word is stored.
+
+use crate::{
+    defs::{OP, TRAP},
+    instr::sign_extend,
+};
+
+pub fn disassemble(address: u16, instr: u16) -> String {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let target = |bits: i32| {
+        let offset = sign_extend(instr & ((1 << bits) - 1), bits);
+        address.wrapping_add(1).wrapping_add(offset)
+    };
+
+    match OP::try_from(instr >> 12).expect("every opcode is defined") {
+        OP::ADD => operate("ADD", instr),
+        OP::AND => operate("AND", instr),
+        OP::NOT => format!("NOT R{}, R{}", dr, sr1),
+        OP::BR => {
+            let cond = (instr >> 9) & 0x7;
+            if cond == 0 {
+                return String::from("NOP");
+            }
+            let flags: String = [(4, 'n'), (2, 'z'), (1, 'p')]
+                .iter()
+                .filter(|&&(bit, _)| cond & bit != 0)
+                .map(|&(_, c)| c)
+                .collect();
+            format!("BR{} x{:04X}", flags, target(9))
+        }
+        OP::JMP if sr1 == 7 => String::from("RET"),
+        OP::JMP => format!("JMP R{}", sr1),
+        OP::JSR if (instr >> 11) & 1 != 0 => format!("JSR x{:04X}", target(11)),
+        OP::JSR => format!("JSRR R{}", sr1),
+        OP::LD => format!("LD R{}, x{:04X}", dr, target(9)),
+        OP::LDI => format!("LDI R{}, x{:04X}", dr, target(9)),
+        OP::LEA => format!("LEA R{}, x{:04X}", dr, target(9)),
+        OP::ST => format!("ST R{}, x{:04X}", dr, target(9)),
+        OP::STI => format!("STI R{}, x{:04X}", dr, target(9)),
+        OP::LDR => base_offset("LDR", instr),
+        OP::STR => base_offset("STR", instr),
+        OP::TRAP => match TRAP::try_from(instr & 0xFF) {
+            Ok(trap) => trap.name().to_string(),
+            Err(vector) => format!("TRAP x{:02X}", vector),
+        },
+        OP::RTI => String::from("RTI"),
+        OP::RES => format!(".FILL x{:04X}", instr),
+    }
+}
+
+// ADD and AND, in register or immediate mode
+fn operate(name: &str, instr: u16) -> String {
+    let (dr, sr1) = ((instr >> 9) & 0x7, (instr >> 6) & 0x7);
+    if (instr >> 5) & 1 != 0 {
+        let imm5 = sign_extend(instr & 0x1F, 5) as i16;
+        format!("{} R{}, R{}, #{}", name, dr, sr1, imm5)
+    } else {
+        format!("{} R{}, R{}, R{}", name, dr, sr1, instr & 0x7)
+    }
+}
+
+// LDR and STR
+fn base_offset(name: &str, instr: u16) -> String {
+    let (dr, base) = ((instr >> 9) & 0x7, (instr >> 6) & 0x7);
+    let offset6 = sign_extend(instr & 0x3F, 6) as i16;
+    format!("{} R{}, R{}, #{}", name, dr, base, offset6)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn disassemble_operate_and_memory_instructions() {
+        assert_eq!("ADD R1, R2, #-3", disassemble(0x3000, 0x12BD));
+        assert_eq!("AND R3, R3, R1", disassemble(0x3000, 0x56C1));
+        assert_eq!("NOT R0, R1", disassemble(0x3000, 0x907F));
+        assert_eq!("LDR R0, R1, #2", disassemble(0x3000, 0x6042));
+        assert_eq!("LEA R0, x2FFF", disassemble(0x3000, 0xE1FE));
+    }
+
+    #[test]
+    fn disassemble_control_instructions() {
+        assert_eq!("BRzp x3000", disassemble(0x3002, 0x07FD));
+        assert_eq!("BRnzp x3000", disassemble(0x3005, 0x0FFA));
+        assert_eq!("RET", disassemble(0x3000, 0xC1C0));
+        assert_eq!("JSRR R2", disassemble(0x3000, 0x4080));
+        assert_eq!("HALT", disassemble(0x3000, 0xF025));
+        assert_eq!("TRAP x30", disassemble(0x3000, 0xF030));
+    }
+}
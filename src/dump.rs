@@ -0,0 +1,49 @@
+// Memory dumps
+//
+// Each row shows the address, the words as hex, the same words split into
+// big-endian bytes and an ASCII column with one character per word, which is
+// how PUTS lays out strings.
+
+use crate::state::{Memory, MEMORY_MAX};
+
+pub fn dump(mem: &Memory, start: u16, length: usize, stride: usize) {
+    let end = (start as usize + length).min(MEMORY_MAX);
+    if end > start as usize {
+        let words = mem.read_slice(start..=(end - 1) as u16);
+        rows(start, &words, stride)
+            .iter()
+            .for_each(|row| println!("{}", row));
+    }
+}
+
+// The rows showing `words`, read from `start` on, `stride` words to a row.
+pub fn rows(start: u16, words: &[u16], stride: usize) -> Vec<String> {
+    (start as usize..)
+        .step_by(stride)
+        .zip(words.chunks(stride))
+        .map(|(row, words)| {
+            let hex: Vec<String> = words.iter().map(|w| format!("{:04X}", w)).collect();
+            let bytes: Vec<String> = words
+                .iter()
+                .map(|w| format!("{:02X} {:02X}", w >> 8, w & 0xFF))
+                .collect();
+            let ascii: String = words
+                .iter()
+                .map(|&w| match w {
+                    0x20..=0x7E => w as u8 as char,
+                    _ => '.',
+                })
+                .collect();
+
+            format!(
+                "x{:04X}: {:<width$} | {:<byte_width$} | {}",
+                row,
+                hex.join(" "),
+                bytes.join(" "),
+                ascii,
+                width = stride * 5 - 1,
+                byte_width = stride * 6 - 1,
+            )
+        })
+        .collect()
+}
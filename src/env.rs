@@ -0,0 +1,83 @@
+// Environment block
+//
+// With --env-block the driver describes the run in a fixed region below the
+// default user program origin, so programs can inspect their environment:
+//
+// x2F00  magic, ENV_MAGIC
+// x2F01  random seed
+// x2F02  instruction limit, high word (0 in both words means no limit)
+// x2F03  instruction limit, low word
+// x2F04  number of images loaded, N
+// x2F05  origin of each image, N words in load order
+//
+// The seed comes from --seed; without it the seed is 0 in deterministic mode
+// and time-based otherwise. Limits above 2^32 - 1 are stored as xFFFF xFFFF.
+
+use std::io;
+
+use crate::{
+    loader::{write_args, Arg},
+    state::State,
+};
+
+pub const ENV_BLOCK: u16 = 0x2F00;
+pub const ENV_MAGIC: u16 = 0x454E; /* "EN" */
+
+pub struct Environment {
+    pub seed: u16,
+    pub max_instructions: Option<u64>,
+    pub origins: Vec<u16>,
+}
+
+impl Environment {
+    pub fn words(&self) -> Vec<u16> {
+        let limit = self
+            .max_instructions
+            .map_or(0, |n| n.min(u32::MAX as u64) as u32);
+        let mut words = vec![
+            ENV_MAGIC,
+            self.seed,
+            (limit >> 16) as u16,
+            limit as u16,
+            self.origins.len() as u16,
+        ];
+        words.extend(&self.origins);
+        words
+    }
+
+    pub fn write(&self, state: &mut State) -> io::Result<usize> {
+        let words: Vec<Arg> = self.words().into_iter().map(Arg::Word).collect();
+        write_args(state, ENV_BLOCK, &words, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::{Environment, ENV_BLOCK, ENV_MAGIC},
+        state::State,
+    };
+
+    #[test]
+    fn environment_block_layout() {
+        let env = Environment {
+            seed: 42,
+            max_instructions: Some(0x1_0002),
+            origins: vec![0x3000, 0x4000],
+        };
+        assert_eq!(
+            vec![ENV_MAGIC, 42, 0x0001, 0x0002, 2, 0x3000, 0x4000],
+            env.words()
+        );
+
+        let mut state = State::new();
+        assert_eq!(7, env.write(&mut state).unwrap());
+        assert_eq!(0x4000, state.mem.peek(ENV_BLOCK + 6));
+
+        let unlimited = Environment {
+            max_instructions: None,
+            ..env
+        };
+        assert_eq!([0, 0], unlimited.words()[2..4]);
+    }
+}
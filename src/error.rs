@@ -0,0 +1,90 @@
+// Runtime errors
+//
+// These stop the machine and are reported by the driver, which then exits
+// with a failure status.
+
+use std::fmt;
+
+use crate::diag::Check;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    InputClosed { pc: u16 }, /* a keyboard read found the input closed */
+    UnknownTrap { pc: u16, vector: u16 }, /* TRAP to a vector with no routine */
+    InstructionLimit { pc: u16, limit: u64 }, /* ran the maximum number of instructions */
+    OutputLimit { pc: u16, limit: u64 }, /* wrote the maximum number of output bytes */
+    WriteLimit { pc: u16, limit: u64 }, /* made the maximum number of memory writes */
+    MemoryFault { pc: u16, address: u16 }, /* accessed an address past the end of memory */
+    GuardAccess { pc: u16, address: u16 }, /* touched the word just outside an image */
+    InfiniteLoop { pc: u16 }, /* came back to the same state with nothing else changed */
+    PcWrapped,               /* ran off the end of memory at xFFFF */
+    IllegalOpcode { pc: u16, instr: u16 }, /* the reserved opcode 1101 */
+    Forbidden { pc: u16, name: &'static str }, /* an instruction the policy forbids */
+    WildJump { pc: u16, target: u16 }, /* a JMP to no live return address or label */
+    StrayPc { pc: u16, from: Option<u16> }, /* PC left for a table or device registers */
+    Diagnostic { pc: u16, check: Check }, /* a check set to be an error fired */
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::InputClosed { pc } => {
+                write!(f, "keyboard input closed at x{:04X}", pc)
+            }
+            RuntimeError::UnknownTrap { pc, vector } => {
+                write!(f, "unknown trap vector x{:02X} at x{:04X}", vector, pc)
+            }
+            RuntimeError::InstructionLimit { pc, limit } => {
+                write!(f, "instruction limit of {} reached at x{:04X}", limit, pc)
+            }
+            RuntimeError::OutputLimit { pc, limit } => {
+                write!(f, "output limit of {} bytes reached at x{:04X}", limit, pc)
+            }
+            RuntimeError::WriteLimit { pc, limit } => {
+                write!(f, "memory write limit of {} reached at x{:04X}", limit, pc)
+            }
+            RuntimeError::MemoryFault { pc, address } => {
+                write!(
+                    f,
+                    "access to missing memory at x{:04X} from x{:04X}",
+                    address, pc
+                )
+            }
+            RuntimeError::GuardAccess { pc, address } => {
+                write!(f, "access to guard word x{:04X} from x{:04X}", address, pc)
+            }
+            RuntimeError::InfiniteLoop { pc } => {
+                write!(f, "program is in an infinite loop at x{:04X}", pc)
+            }
+            RuntimeError::PcWrapped => write!(f, "PC wrapped past xFFFF"),
+            RuntimeError::Diagnostic { pc, check } => {
+                write!(f, "{} check failed at x{:04X}", check, pc)
+            }
+            RuntimeError::WildJump { pc, target } => {
+                write!(
+                    f,
+                    "wild jump at x{:04X} to x{:04X}, not a pending return address or a label",
+                    pc, target
+                )
+            }
+            RuntimeError::Forbidden { pc, name } => {
+                write!(f, "policy forbids {} at x{:04X}", name, pc)
+            }
+            RuntimeError::IllegalOpcode { pc, instr } => {
+                write!(f, "illegal instruction x{:04X} at x{:04X}", instr, pc)
+            }
+            RuntimeError::StrayPc { pc, from } => {
+                let region = match pc {
+                    0x0000..=0x00FF => "the trap vector table",
+                    0x0100..=0x01FF => "the interrupt vector table",
+                    _ => "device register space",
+                };
+                write!(f, "PC entered {} at x{:04X}", region, pc)?;
+                match from {
+                    Some(from) => write!(f, " from x{:04X}", from),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// Bounded exploration of the inputs
+//
+// Runs the loaded program without a terminal, forking the machine state at
+// every GETC/IN (one path per candidate input character) and at every
+// conditional branch on flags that input decided (one path per outcome),
+// until the depth limit is reached. Other branches follow their concrete
+// outcome, as do all branches past the limit, and a keyboard read past it
+// abandons the path.
+//
+// Whether the flags come from input is tracked a register and a memory word
+// at a time: GETC and IN mark R0, and ADD, AND, NOT, the loads and the stores
+// carry the mark from their operands to what they write.
+//
+// Every instruction runs through State::step_once, with the checks of a run:
+// a TRAP whose table entry is set jumps through it, so a loaded OS is explored
+// too, and a stray PC stops the path. The other traps run as built in: output
+// goes to a captured console and is dropped, SLEEP does not wait, and HALT and
+// EXIT end the path.
+
+use std::collections::HashSet;
+
+use crate::{
+    cli::ExploreOptions,
+    clock::VirtualClock,
+    defs::{CondFlags, OP, R, TRAP},
+    state::{Registers, State},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ending {
+    Halt,       /* reached TRAP HALT or EXIT */
+    Stopped,    /* stopped on an unimplemented opcode or a runtime error */
+    StepLimit,  /* still running after the step limit */
+    DepthLimit, /* needed input with no forks left */
+}
+
+pub struct Outcome {
+    pub ending: Ending,
+    pub inputs: Vec<u8>,
+    pub steps: usize,
+    pub reg: Registers,
+}
+
+// Which registers, memory words and flags hold values derived from input
+#[derive(Clone, Default)]
+struct Taint {
+    reg: [bool; 8],
+    mem: HashSet<u16>,
+    flags: bool,
+}
+
+impl Taint {
+    // Carries the marks through `instr`, which ran and did `loads` and
+    // `stores`. `input` is set when it was GETC or IN.
+    fn propagate(&mut self, instr: u16, loads: &[(u16, u16)], stores: &[(u16, u16)], input: bool) {
+        let dr = ((instr >> 9) & 0x7) as usize;
+        let sr1 = ((instr >> 6) & 0x7) as usize;
+        let loaded = || loads.iter().any(|(address, _)| self.mem.contains(address));
+        let written = match OP::try_from(instr >> 12) {
+            Ok(OP::ADD | OP::AND) => {
+                Some(self.reg[sr1] || (instr & 0x20 == 0 && self.reg[(instr & 0x7) as usize]))
+            }
+            Ok(OP::NOT) => Some(self.reg[sr1]),
+            Ok(OP::LD | OP::LDI) => Some(loaded()),
+            Ok(OP::LDR) => Some(loaded() || self.reg[sr1]),
+            Ok(OP::LEA) => Some(false),
+            Ok(OP::ST | OP::STI | OP::STR) => {
+                for &(address, _) in stores {
+                    match self.reg[dr] {
+                        true => self.mem.insert(address),
+                        false => self.mem.remove(&address),
+                    };
+                }
+                None
+            }
+            Ok(OP::JSR | OP::TRAP) => {
+                self.reg[7] = false;
+                if input {
+                    self.reg[0] = true;
+                    self.flags = true;
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some(tainted) = written {
+            self.reg[dr] = tainted;
+            self.flags = tainted;
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Path {
+    state: State,
+    taint: Taint,
+    forks: usize,
+    steps: usize,
+    inputs: Vec<u8>,
+}
+
+impl Path {
+    // Runs the next instruction, with `c` typed if it reads the keyboard.
+    fn step(&mut self, input: Option<u8>) {
+        if let Some(c) = input {
+            self.state.mem.console.queue(&[c]);
+            self.inputs.push(c);
+        }
+        let ran = self.state.stats.instructions;
+        self.state.step_once();
+        if self.state.stats.instructions != ran {
+            self.steps += 1;
+            let effects = &self.state.effects;
+            self.taint.propagate(
+                effects.instr,
+                &effects.loads,
+                &effects.stores,
+                input.is_some(),
+            );
+        }
+    }
+
+    fn finish(self, ending: Ending) -> Outcome {
+        Outcome {
+            ending,
+            inputs: self.inputs,
+            steps: self.steps,
+            reg: self.state.reg,
+        }
+    }
+}
+
+pub fn explore(mut initial: State, opts: &ExploreOptions) -> Vec<Outcome> {
+    /* every path would repeat the same warnings */
+    initial.diagnostics.silence();
+    initial.mem.console.feed(b"");
+    initial.mem.console.capture();
+    initial.mem.clock = Box::new(VirtualClock::default());
+    let mut pending = vec![Path {
+        state: initial,
+        taint: Taint::default(),
+        forks: 0,
+        steps: 0,
+        inputs: Vec::new(),
+    }];
+
+    let mut outcomes = Vec::new();
+    while let Some(path) = pending.pop() {
+        outcomes.push(run_path(path, opts, &mut pending));
+    }
+    outcomes
+}
+
+fn run_path(mut path: Path, opts: &ExploreOptions, pending: &mut Vec<Path>) -> Outcome {
+    loop {
+        if !path.state.running {
+            return path.finish(Ending::Stopped);
+        }
+        if path.steps >= opts.steps {
+            return path.finish(Ending::StepLimit);
+        }
+
+        let pc = path.state.reg.pc();
+        let instr = path.state.mem.peek(pc);
+        let trap = match path.state.traps.routine_at(pc) {
+            Some(trap) => Some(trap),
+            None if matches!(OP::try_from(instr >> 12), Ok(OP::TRAP)) => {
+                builtin(&path.state, instr & 0xFF)
+            }
+            None => None,
+        };
+
+        match trap {
+            Some(TRAP::GETC | TRAP::IN) => {
+                if path.forks >= opts.depth {
+                    return path.finish(Ending::DepthLimit);
+                }
+
+                path.forks += 1;
+                for &c in opts.inputs[1..].iter().rev() {
+                    let mut fork = path.clone();
+                    fork.step(Some(c));
+                    pending.push(fork);
+                }
+                path.step(Some(opts.inputs[0]));
+            }
+            Some(TRAP::HALT | TRAP::EXIT) => {
+                path.step(None);
+                return match path.state.error {
+                    Some(_) => path.finish(Ending::Stopped),
+                    None => path.finish(Ending::Halt),
+                };
+            }
+            _ if is_conditional(instr) && path.taint.flags && path.forks < opts.depth => {
+                /* the current path follows the flags, the fork the other way */
+                path.forks += 1;
+                let mut fork = path.clone();
+                let flags = fork.state.reg.cond();
+                fork.state.reg.set_cond(other_outcome(instr, flags));
+                fork.step(None);
+                fork.state.reg.set_cond(flags);
+                pending.push(fork);
+                path.step(None);
+            }
+            _ => path.step(None),
+        }
+    }
+}
+
+// Whether `instr` is a BR that may go either way.
+fn is_conditional(instr: u16) -> bool {
+    let mask = (instr >> 9) & 0x7;
+    matches!(OP::try_from(instr >> 12), Ok(OP::BR)) && mask != 0 && mask != 0x7
+}
+
+// Flags that send the branch `instr` the other way from `flags`.
+fn other_outcome(instr: u16, flags: CondFlags) -> CondFlags {
+    let mask = (instr >> 9) & 0x7;
+    let taken = [CondFlags::N, CondFlags::Z, CondFlags::P];
+    let wanted = |f: &CondFlags| (f.bits() & mask != 0) != (flags.bits() & mask != 0);
+    *taken.iter().find(|f| wanted(f)).unwrap_or(&flags)
+}
+
+// The built-in routine TRAP `vector` runs, None if it jumps through the
+// table or has no routine.
+fn builtin(state: &State, vector: u16) -> Option<TRAP> {
+    match state.traps.handler(&state.mem, vector) {
+        Some(_) => None,
+        None => TRAP::try_from(vector).ok(),
+    }
+}
+
+pub fn report(outcomes: &[Outcome], opts: &ExploreOptions) {
+    let mut halted = 0;
+    let mut stopped = 0;
+    let mut step_limit = 0;
+    let mut depth_limit = 0;
+
+    for outcome in outcomes {
+        match outcome.ending {
+            Ending::Halt => halted += 1,
+            Ending::Stopped => stopped += 1,
+            Ending::StepLimit => step_limit += 1,
+            Ending::DepthLimit => depth_limit += 1,
+        }
+        if !matches!(outcome.ending, Ending::Halt) {
+            continue;
+        }
+
+        let inputs: String = outcome.inputs.escape_ascii().to_string();
+        println!(
+            "HALT after {} instructions, inputs \"{}\"",
+            outcome.steps, inputs
+        );
+        let registers: Vec<String> = R::ALL[..8]
+            .iter()
+            .map(|&r| format!("{} x{:04X}", r.name(), outcome.reg[r]))
+            .collect();
+        println!("  {}", registers.join(" "));
+    }
+
+    println!(
+        "explored {} paths (depth limit {}): {} halted, {} stopped, {} hit the step limit, {} ran out of forks",
+        outcomes.len(),
+        opts.depth,
+        halted,
+        stopped,
+        step_limit,
+        depth_limit
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cli::ExploreOptions,
+        defs::R,
+        explore::{explore, Ending},
+        state::State,
+    };
+
+    fn outcomes(words: &[u16], opts: &ExploreOptions) -> Vec<(Ending, Vec<u8>, usize, u16)> {
+        let mut state = State::new();
+        state.mem.write_slice(0x3000, words);
+        (explore(state, opts).into_iter())
+            .map(|o| (o.ending, o.inputs, o.steps, o.reg[R::R1]))
+            .collect()
+    }
+
+    #[test]
+    fn branches_on_input_fork_both_ways() {
+        let opts = ExploreOptions {
+            inputs: b"a".to_vec(),
+            ..ExploreOptions::default()
+        };
+        // GETC; ST R0, x3007; AND R0, R0, #0; LD R2, x3007; BRz +1;
+        // ADD R1, R1, #1; HALT
+        let program = [0xF020, 0x3005, 0x5020, 0x2403, 0x0401, 0x1261, 0xF025];
+        /* "a" falls through, the fork takes the branch */
+        assert_eq!(
+            vec![
+                (Ending::Halt, b"a".to_vec(), 7, 1),
+                (Ending::Halt, b"a".to_vec(), 6, 0)
+            ],
+            outcomes(&program, &opts)
+        );
+
+        /* with no forks left the branch follows the input */
+        let opts = ExploreOptions { depth: 1, ..opts };
+        assert_eq!(
+            vec![(Ending::Halt, b"a".to_vec(), 7, 1)],
+            outcomes(&program, &opts)
+        );
+    }
+
+    #[test]
+    fn other_branches_follow_the_flags() {
+        // BRz +1; ADD R1, R1, #1; HALT, with Z set from the start
+        let program = [0x0401, 0x1261, 0xF025];
+        assert_eq!(
+            vec![(Ending::Halt, vec![], 2, 0)],
+            outcomes(&program, &ExploreOptions::default())
+        );
+    }
+
+    #[test]
+    fn stray_pcs_stop_the_path() {
+        // JMP R1, with R1 in the device registers
+        let mut state = State::new();
+        state.mem.poke(0x3000, 0xC040);
+        state.reg[R::R1] = 0xFE02;
+        let outcomes = explore(state, &ExploreOptions::default());
+        assert_eq!(1, outcomes.len());
+        assert_eq!(
+            (Ending::Stopped, 1),
+            (outcomes[0].ending, outcomes[0].steps)
+        );
+    }
+
+    #[test]
+    fn keyboard_reads_fork_per_input() {
+        // GETC; ADD R1, R0, #0; GETC; HALT
+        let program = [0xF020, 0x1220, 0xF020, 0xF025];
+        let opts = ExploreOptions {
+            depth: 1,
+            inputs: b"ab".to_vec(),
+            ..ExploreOptions::default()
+        };
+        assert_eq!(
+            vec![
+                (Ending::DepthLimit, b"a".to_vec(), 2, u16::from(b'a')),
+                (Ending::DepthLimit, b"b".to_vec(), 2, u16::from(b'b')),
+            ],
+            outcomes(&program, &opts)
+        );
+    }
+
+    #[test]
+    fn paths_stop_at_the_step_limit() {
+        let opts = ExploreOptions {
+            steps: 10,
+            ..ExploreOptions::default()
+        };
+        // BRnzp -1
+        assert_eq!(
+            vec![(Ending::StepLimit, vec![], 10, 0)],
+            outcomes(&[0x0FFF], &opts)
+        );
+    }
+
+    #[test]
+    fn set_table_entries_are_followed() {
+        let mut state = State::new();
+        // HALT; at x4000: ADD R1, R1, #3; TRAP x26
+        state.mem.write_slice(0x3000, &[0xF025]);
+        state.mem.write_slice(0x4000, &[0x1263, 0xF026]);
+        state.mem.poke(0x0025, 0x4000);
+        let outcomes = explore(state, &ExploreOptions::default());
+
+        assert_eq!(1, outcomes.len());
+        assert_eq!((Ending::Halt, 3), (outcomes[0].ending, outcomes[0].steps));
+        assert_eq!(3, outcomes[0].reg[R::R1]);
+    }
+}
@@ -0,0 +1,467 @@
+// Report cards for student submissions
+//
+// `lc3 grade SOURCE --spec SPEC` assembles a submission, runs it against the
+// tests of a spec and writes a report card combining correctness, style and
+// performance, as Markdown or, for a --report file ending in .html, as HTML.
+// The spec is in the TOML subset of config.rs:
+//
+//   [grade]
+//   max_instructions = 100_000  # per test, 1_000_000 unless set
+//   max_output_bytes = 4096     # per test, 1 MiB unless set
+//   max_mem_writes = 10_000     # per test, unlimited unless set
+//   require = ["LDI"]           # instruction policy, see policy.rs
+//   forbid = ["JSRR"]
+//
+//   [test.greeting]
+//   input = "ab"                # keyboard input, with TOML's escapes
+//   output = "Hi\n"             # the console output expected, HALT's aside
+//   R0 = 0x0062                 # register values expected at the end
+//   points = 2                  # 1 unless set
+//
+// A test passes when the program stops without a runtime error within the
+// instruction limit and leaves what the test expects. Style covers the
+// assembler's lint warnings, those of analyze.rs, the policy and the runtime
+// checks of diag.rs, which are run on every test; performance lists what each
+// test cost.
+
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::{
+    analyze::analyze,
+    asm::assemble,
+    config::{self, Config, Value},
+    defs::R,
+    diag::Check,
+    policy::{self, Policy},
+    state::State,
+};
+
+const MAX_INSTRUCTIONS: u64 = 1_000_000;
+const MAX_OUTPUT: u64 = 1 << 20;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Test {
+    pub name: String,
+    pub input: Vec<u8>,
+    pub output: Option<String>,
+    pub registers: Vec<(&'static str, R, u16)>,
+    pub points: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Spec {
+    pub max_instructions: u64,
+    pub max_output: u64,
+    pub max_writes: Option<u64>,
+    pub policy: Policy,
+    pub tests: Vec<Test>, /* in the order of the spec */
+}
+
+impl Spec {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut spec = Spec {
+            max_instructions: MAX_INSTRUCTIONS,
+            max_output: MAX_OUTPUT,
+            max_writes: None,
+            policy: Policy::default(),
+            tests: Vec::new(),
+        };
+        for entry in config::parse_document(text)? {
+            spec.set(&entry.section, &entry.key, &entry.value)
+                .map_err(|e| format!("line {}: {}", entry.line, e))?;
+        }
+        Ok(spec)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: &Value) -> Result<(), String> {
+        if section == "grade" {
+            match key {
+                "max_instructions" => self.max_instructions = config::count(value)?,
+                "max_output_bytes" => self.max_output = config::count(value)?,
+                "max_mem_writes" => self.max_writes = Some(config::count(value)?),
+                "require" => self.policy.required.extend(names(value)?),
+                "forbid" => self.policy.forbidden.extend(names(value)?),
+                _ => return Err(format!("unknown setting grade.{}", key)),
+            }
+            return Ok(());
+        }
+        let name = section
+            .strip_prefix("test.")
+            .ok_or(format!("unknown section {}", section))?;
+        let test = match self.tests.iter().position(|t| t.name == name) {
+            Some(i) => &mut self.tests[i],
+            None => {
+                self.tests.push(Test {
+                    name: name.to_string(),
+                    points: 1,
+                    ..Test::default()
+                });
+                self.tests.last_mut().expect("a test was just pushed")
+            }
+        };
+        match (key, value) {
+            ("input", Value::Str(text)) => test.input = text.clone().into_bytes(),
+            ("output", Value::Str(text)) => test.output = Some(text.clone()),
+            ("points", _) => {
+                let points = config::count(value)?;
+                test.points = u32::try_from(points).map_err(|_| "too many points")?;
+            }
+            ("input" | "output", _) => return Err(String::from("expected a string")),
+            _ => {
+                let &r = R::ALL[..8]
+                    .iter()
+                    .find(|r| r.name() == key)
+                    .ok_or(format!("unknown setting {}.{}", section, key))?;
+                test.registers.push((r.name(), r, config::address(value)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn names(value: &Value) -> Result<Vec<&'static str>, String> {
+    let Value::Array(items) = value else {
+        return Err(String::from("expected an array of instruction names"));
+    };
+    let mut names = Vec::new();
+    for item in items {
+        match item {
+            Value::Str(name) => names.extend(policy::parse_names(name)?),
+            _ => return Err(String::from("expected an instruction name")),
+        }
+    }
+    Ok(names)
+}
+
+// How one test went
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub name: String,
+    pub points: u32,
+    pub failures: Vec<String>, /* empty when the test passed */
+    pub instructions: u64,
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl Outcome {
+    pub fn earned(&self) -> u32 {
+        match self.failures.is_empty() {
+            true => self.points,
+            false => 0,
+        }
+    }
+
+    // "pass", or what went wrong.
+    pub fn result(&self) -> String {
+        match self.failures.is_empty() {
+            true => String::from("pass"),
+            false => format!("FAIL: {}", self.failures.join("; ")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Card {
+    pub submission: String,
+    pub words: usize, /* size of the assembled program */
+    pub outcomes: Vec<Outcome>,
+    pub style: Vec<String>,
+}
+
+impl Card {
+    // Points earned and points possible.
+    pub fn score(&self) -> (u32, u32) {
+        let earned = self.outcomes.iter().map(Outcome::earned).sum();
+        let total = self.outcomes.iter().map(|o| o.points).sum();
+        (earned, total)
+    }
+
+    pub fn markdown(&self) -> String {
+        let (earned, total) = self.score();
+        let mut out = format!("# Report card: {}\n\n", self.submission);
+        let _ = writeln!(out, "**Score: {} of {} points**\n", earned, total);
+
+        out.push_str("## Correctness\n\n| Test | Points | Result |\n|---|---|---|\n");
+        for outcome in &self.outcomes {
+            let _ = writeln!(
+                out,
+                "| {} | {}/{} | {} |",
+                outcome.name,
+                outcome.earned(),
+                outcome.points,
+                outcome.result().replace('|', "\\|")
+            );
+        }
+
+        out.push_str("\n## Style\n\n");
+        if self.style.is_empty() {
+            out.push_str("No problems found.\n");
+        }
+        for line in &self.style {
+            let _ = writeln!(out, "- {}", line);
+        }
+
+        let _ = writeln!(
+            out,
+            "\n## Performance\n\nProgram size: {} words\n",
+            self.words
+        );
+        out.push_str("| Test | Instructions | Memory reads | Memory writes |\n|---|---|---|---|\n");
+        for outcome in &self.outcomes {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                outcome.name, outcome.instructions, outcome.reads, outcome.writes
+            );
+        }
+        out
+    }
+
+    pub fn html(&self) -> String {
+        let (earned, total) = self.score();
+        let title = format!("Report card: {}", escape(&self.submission));
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+            title
+        );
+        let _ = writeln!(
+            out,
+            "<p><strong>Score: {} of {} points</strong></p>",
+            earned, total
+        );
+
+        out.push_str(
+            "<h2>Correctness</h2>\n<table>\n<tr><th>Test</th><th>Points</th><th>Result</th></tr>\n",
+        );
+        for outcome in &self.outcomes {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}/{}</td><td>{}</td></tr>",
+                escape(&outcome.name),
+                outcome.earned(),
+                outcome.points,
+                escape(&outcome.result())
+            );
+        }
+        out.push_str("</table>\n<h2>Style</h2>\n");
+        if self.style.is_empty() {
+            out.push_str("<p>No problems found.</p>\n");
+        } else {
+            out.push_str("<ul>\n");
+            for line in &self.style {
+                let _ = writeln!(out, "<li>{}</li>", escape(line));
+            }
+            out.push_str("</ul>\n");
+        }
+
+        let _ = writeln!(
+            out,
+            "<h2>Performance</h2>\n<p>Program size: {} words</p>",
+            self.words
+        );
+        out.push_str("<table>\n<tr><th>Test</th><th>Instructions</th><th>Memory reads</th><th>Memory writes</th></tr>\n");
+        for outcome in &self.outcomes {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&outcome.name),
+                outcome.instructions,
+                outcome.reads,
+                outcome.writes
+            );
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Grades `source`, the text of the submission called `submission`. A
+// submission that does not assemble fails every test.
+pub fn grade(submission: &str, source: &str, spec: &Spec) -> Card {
+    let program = match assemble(source) {
+        Ok(program) => program,
+        Err(e) => {
+            let failure = format!("the program does not assemble: {}", e);
+            return Card {
+                submission: submission.to_string(),
+                words: 0,
+                outcomes: spec
+                    .tests
+                    .iter()
+                    .map(|test| Outcome {
+                        name: test.name.clone(),
+                        points: test.points,
+                        failures: vec![failure.clone()],
+                        instructions: 0,
+                        reads: 0,
+                        writes: 0,
+                    })
+                    .collect(),
+                style: Vec::new(),
+            };
+        }
+    };
+
+    let mut config = Config::default();
+    config.console.max_output = Some(spec.max_output);
+    config.max_writes = spec.max_writes;
+    config.max_instructions = Some(spec.max_instructions);
+    let mut initial = State::with_config(&config);
+    initial.mem.write_slice(program.origin, &program.words);
+    initial.symbols = program.symbols.clone();
+    initial.reg[R::PC] = program.origin;
+    initial.diagnostics.quiet = true;
+
+    let loaded = [(program.origin, program.words.len())];
+    let mut style: Vec<String> = (program.warnings.iter())
+        .map(|w| format!("asm: {}", w))
+        .collect();
+    style.extend(
+        analyze(&initial.mem, &loaded, program.origin)
+            .iter()
+            .map(|w| w.to_string()),
+    );
+    let words = (program.origin..).zip(program.words.iter().copied());
+    style.extend(
+        spec.policy
+            .check_image(words)
+            .into_iter()
+            .map(|v| format!("policy: {}", v)),
+    );
+
+    let mut fired = BTreeSet::new();
+    let mut outcomes = Vec::new();
+    for test in &spec.tests {
+        let mut state = initial.clone();
+        state.policy = (!spec.policy.is_empty()).then(|| spec.policy.clone());
+        state.mem.console.feed(&test.input);
+        state.mem.console.capture();
+        while state.running {
+            state.step_once();
+        }
+        outcomes.push(outcome(test, &state));
+        for (check, pc, message) in state.diagnostics.fired() {
+            fired.insert((check, pc, message.to_string()));
+        }
+    }
+    style.extend(
+        fired
+            .into_iter()
+            .map(|(check, _, message): (Check, u16, String)| format!("{}: {}", check, message)),
+    );
+
+    Card {
+        submission: submission.to_string(),
+        words: program.words.len(),
+        outcomes,
+        style,
+    }
+}
+
+fn outcome(test: &Test, state: &State) -> Outcome {
+    let mut failures = Vec::new();
+    if let Some(error) = &state.error {
+        failures.push(error.to_string());
+    }
+    let output = state.mem.console.captured().unwrap_or_default();
+    let output = output.strip_suffix("HALT\n").unwrap_or(output);
+    if let Some(expected) = test.output.as_ref().filter(|e| *e != output) {
+        failures.push(format!("output {:?}, expected {:?}", output, expected));
+    }
+    for &(name, r, expected) in &test.registers {
+        if state.reg[r] != expected {
+            failures.push(format!(
+                "{} is x{:04X}, expected x{:04X}",
+                name, state.reg[r], expected
+            ));
+        }
+    }
+    if let Some(policy) = &state.policy {
+        for name in policy.missing() {
+            failures.push(format!("required {} was never executed", name));
+        }
+    }
+    Outcome {
+        name: test.name.clone(),
+        points: test.points,
+        failures,
+        instructions: state.stats.instructions,
+        reads: state.mem.reads(),
+        writes: state.mem.writes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grade::{grade, Spec};
+
+    const SPEC: &str = r#"
+[grade]
+forbid = ["JSRR"]
+max_output_bytes = 64
+
+[test.echo]
+input = "a"
+output = "a"
+R0 = 0x61
+points = 2
+
+[test.shout]
+input = "b"
+output = "B"
+"#;
+
+    const SOURCE: &str = "
+        .ORIG x3000
+        GETC
+        OUT
+        HALT
+        .END
+    ";
+
+    #[test]
+    fn specs_are_parsed() {
+        let spec = Spec::parse(SPEC).unwrap();
+        assert_eq!(vec!["JSRR"], spec.policy.forbidden);
+        assert_eq!((64, None), (spec.max_output, spec.max_writes));
+        assert_eq!(2, spec.tests.len());
+        assert_eq!(
+            (b"a".to_vec(), 2),
+            (spec.tests[0].input.clone(), spec.tests[0].points)
+        );
+        assert_eq!(Some(String::from("B")), spec.tests[1].output);
+        assert!(Spec::parse("[test.x]\nR9 = 1").is_err());
+    }
+
+    #[test]
+    fn cards_combine_the_results() {
+        let card = grade("echo.asm", SOURCE, &Spec::parse(SPEC).unwrap());
+        assert_eq!((2, 3), card.score());
+        assert_eq!(3, card.words);
+        assert!(card.outcomes[1].failures[0].starts_with("output \"b\", expected \"B\""));
+
+        let markdown = card.markdown();
+        assert!(markdown.starts_with("# Report card: echo.asm\n\n**Score: 2 of 3 points**"));
+        assert!(markdown.contains("| echo | 2/2 | pass |"));
+        assert!(card
+            .html()
+            .contains("<h2>Style</h2>\n<p>No problems found.</p>"));
+
+        let unused = SOURCE.replace("OUT", "OUT\n        SPARE");
+        let card = grade("echo.asm", &unused, &Spec::parse(SPEC).unwrap());
+        assert_eq!(
+            vec![String::from("asm: line 5: label SPARE is never used")],
+            card.style
+        );
+
+        let broken = grade("x.asm", "FOO", &Spec::parse(SPEC).unwrap());
+        assert_eq!((0, 3), broken.score());
+    }
+}
@@ -0,0 +1,84 @@
+// Recently executed instructions
+//
+// State keeps the last instructions it ran in a ring buffer, HISTORY_LEN of
+// them unless --history says otherwise, so that the driver can show what led
+// up to a runtime error or a panic in the simulator, like a flight recorder.
+// The disassembly is made from the word as it was run, so code that has
+// since been overwritten is listed as it was.
+
+use std::collections::VecDeque;
+
+use crate::disasm::disassemble;
+
+const HISTORY_LEN: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct History {
+    entries: VecDeque<(u16, u16)>, /* address and word, the oldest first */
+    capacity: usize,
+}
+
+impl History {
+    // Keeps up to `capacity` instructions, none at all if it is 0.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, pc: u16, instr: u16) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((pc, instr));
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &(u16, u16)> {
+        self.entries.iter()
+    }
+
+    // One line per instruction, the oldest first.
+    pub fn listing(&self) -> String {
+        let lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|&(pc, instr)| format!("x{:04X}  {:04X}  {}", pc, instr, disassemble(pc, instr)))
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(HISTORY_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::History;
+
+    #[test]
+    fn only_the_latest_are_kept() {
+        let mut history = History::new(8);
+        for pc in 0x3000..0x3010 {
+            history.push(pc, 0x1261);
+        }
+        let first = history.entries().next();
+        assert_eq!(Some(&(0x3008, 0x1261)), first);
+        assert_eq!(8, history.entries().count());
+        assert!(history.listing().ends_with("x300F  1261  ADD R1, R1, #1"));
+
+        let mut none = History::new(0);
+        none.push(0x3000, 0x1261);
+        assert!(none.is_empty());
+    }
+}
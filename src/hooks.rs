@@ -0,0 +1,105 @@
+// Run-completion hooks for embedders
+//
+// An application running the machine itself, a GUI or a grader, can ask to
+// be told when the run ends instead of watching the console for "HALT":
+//
+//   state.on_halt(|halt, state| ...)        the machine stopped, and why
+//   state.on_exception(|error, state| ...)  it stopped with a RuntimeError
+//
+// Both are called from State::step, with the machine as it stopped. A
+// breakpoint or watchpoint calls neither, since the run can resume.
+
+use std::{fmt, sync::Arc};
+
+use crate::{error::RuntimeError, state::State};
+
+type HaltHook = dyn Fn(&Halt, &State) + Send + Sync;
+type ExceptionHook = dyn Fn(&RuntimeError, &State) + Send + Sync;
+
+// Why the machine stopped without an error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Halt {
+    Halted,       /* TRAP HALT, RTI, or input ending under --on-eof halt */
+    Exited(u16),  /* TRAP EXIT, with R0 */
+    ClockStopped, /* the program cleared the clock enable bit of the MCR */
+}
+
+#[derive(Clone, Default)]
+pub struct Hooks {
+    halt: Vec<Arc<HaltHook>>,
+    exception: Vec<Arc<ExceptionHook>>,
+}
+
+impl Hooks {
+    pub fn on_halt(&mut self, hook: impl Fn(&Halt, &State) + Send + Sync + 'static) {
+        self.halt.push(Arc::new(hook));
+    }
+
+    pub fn on_exception(&mut self, hook: impl Fn(&RuntimeError, &State) + Send + Sync + 'static) {
+        self.exception.push(Arc::new(hook));
+    }
+
+    // Calls the hooks for `state`, which has just stopped.
+    pub fn stopped(&self, state: &State) {
+        if state.hit.is_some() {
+            return;
+        }
+        match &state.error {
+            Some(error) => self.exception.iter().for_each(|hook| hook(error, state)),
+            None => {
+                let halt = match state.exit_status {
+                    Some(status) => Halt::Exited(status),
+                    None if !state.mem.clock_enabled() => Halt::ClockStopped,
+                    None => Halt::Halted,
+                };
+                self.halt.iter().for_each(|hook| hook(&halt, state));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("halt", &self.halt.len())
+            .field("exception", &self.exception.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{error::RuntimeError, hooks::Halt, state::State};
+
+    fn run(words: &[u16]) -> (Vec<Halt>, Vec<RuntimeError>) {
+        let halts = Arc::new(Mutex::new(Vec::new()));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let mut state = State::new();
+        state.mem.console.capture();
+        state.mem.write_slice(0x3000, words);
+        let seen = halts.clone();
+        state.on_halt(move |&halt, _| seen.lock().unwrap().push(halt));
+        let seen = errors.clone();
+        state.on_exception(move |error, _| seen.lock().unwrap().push(error.clone()));
+        while state.running {
+            state.step_once();
+        }
+        let halts = halts.lock().unwrap().clone();
+        let errors = errors.lock().unwrap().clone();
+        (halts, errors)
+    }
+
+    #[test]
+    fn hooks_hear_how_the_run_ended() {
+        // AND R0, R0, #0; ADD R0, R0, #2; TRAP x26
+        let exit = run(&[0x5020, 0x1022, 0xF026]);
+        assert_eq!((vec![Halt::Exited(2)], vec![]), exit);
+        assert_eq!((vec![Halt::Halted], vec![]), run(&[0xF025]));
+
+        let (halts, errors) = run(&[0xF030]); // TRAP x30
+        assert!(halts.is_empty());
+        assert!(matches!(errors[..], [RuntimeError::UnknownTrap { .. }]));
+    }
+}
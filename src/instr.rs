@@ -1,10 +1,82 @@
 use crate::{
-    defs::{R, TRAP},
-    state::State,
+    console::OutputKind,
+    defs::{OP, R, TRAP},
+    error::RuntimeError,
+    state::{Memory, State},
 };
-use std::io::{Read, Write};
 
-fn sign_extend(mut x: u16, bit_count: i32) -> u16 {
+// What TRAP does with a vector that has no built-in routine
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnknownTrap {
+    Ignore, /* continue with the next instruction */
+    #[default]
+    Error, /* stop the machine with a runtime error */
+}
+
+// Where the trap vector table is, and where the machine's own service
+// routines sit. A jump to one of the routine addresses runs the built-in
+// routine and returns to R7, so an OS image whose table points at them
+// still gets the machine's services.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrapTable {
+    pub base: u16,                  /* address of the entry for vector x00 */
+    pub routines: Vec<(u16, TRAP)>, /* addresses that run a built-in routine */
+}
+
+impl TrapTable {
+    // The address of the table entry for `vector`.
+    pub fn entry(&self, vector: u16) -> u16 {
+        self.base.wrapping_add(vector)
+    }
+
+    pub fn routine_at(&self, address: u16) -> Option<TRAP> {
+        (self.routines.iter())
+            .find(|&&(a, _)| a == address)
+            .map(|&(_, trap)| trap)
+    }
+
+    // Where TRAP `vector` jumps to, if its table entry was set other than
+    // to the built-in routine the machine would run anyway. The fill
+    // pattern never counts as set.
+    pub fn handler(&self, mem: &Memory, vector: u16) -> Option<u16> {
+        let entry = self.entry(vector);
+        let handler = mem.peek(entry);
+        let native = TRAP::try_from(vector).ok();
+        let set = handler != 0
+            && mem.is_written(entry)
+            && (native.is_none() || self.routine_at(handler) != native);
+        set.then_some(handler)
+    }
+}
+
+// Decodes `instr` and executes it against `state`.
+// The PC is expected to already point past the instruction.
+pub fn execute(instr: u16, state: &mut State) {
+    let op = instr >> 12;
+    match OP::try_from(op).expect("unknown opcode") {
+        OP::BR => do_br(instr, state),
+        OP::ADD => do_add(instr, state),
+        OP::LD => do_ld(instr, state),
+        OP::ST => do_st(instr, state),
+        OP::JSR => do_jsr(instr, state),
+        OP::AND => do_and(instr, state),
+        OP::LDR => do_ldr(instr, state),
+        OP::STR => do_str(instr, state),
+        OP::RTI => state.running = false, // not simulated // TODO
+        OP::NOT => do_not(instr, state),
+        OP::LDI => do_ldi(instr, state),
+        OP::STI => do_sti(instr, state),
+        OP::JMP => do_jmp(instr, state),
+        OP::RES => {
+            let pc = state.reg[R::PC].wrapping_sub(1);
+            state.fail(RuntimeError::IllegalOpcode { pc, instr });
+        }
+        OP::LEA => do_lea(instr, state),
+        OP::TRAP => do_trap(instr, state),
+    }
+}
+
+pub fn sign_extend(mut x: u16, bit_count: i32) -> u16 {
     if (x >> (bit_count - 1)) & 1 != 0 {
         x |= 0xFFFF << bit_count;
     }
@@ -13,7 +85,7 @@ fn sign_extend(mut x: u16, bit_count: i32) -> u16 {
 
 // # Assembler formats
 //
-// ADD DR, SR1, SR2,
+// ADD DR, SR1, SR2
 // ADD DR, SR1, imm5
 //
 // # Examples
@@ -31,15 +103,15 @@ fn sign_extend(mut x: u16, bit_count: i32) -> u16 {
 // 0001 xxx xxx 1 xxxxx
 // ADD  DR  SR1   imm5
 pub fn do_add(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7; // destination register (DR)
-    let r1: u16 = (instr >> 6) & 0x7; // first operand (SR1)
+    let r0 = R::field(instr >> 9); // destination register (DR)
+    let r1 = R::field(instr >> 6); // first operand (SR1)
     let imm_flag: u16 = (instr >> 5) & 1; // whether we are in immediate mode
 
     if imm_flag != 0 {
         let imm5: u16 = sign_extend(instr & 0x1F, 5);
         state.reg[r0] = state.reg[r1].wrapping_add(imm5);
     } else {
-        let r2: u16 = instr & 0x7;
+        let r2 = R::field(instr);
         state.reg[r0] = state.reg[r1].wrapping_add(state.reg[r2]);
     }
 
@@ -59,18 +131,18 @@ pub fn do_add(instr: u16, state: &mut State) {
 // 1010 xxx xxxxxxxxx
 //      DR  PCoffset9
 pub fn do_ldi(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 4) & 0x7; // destination register (DR)
+    let r0 = R::field(instr >> 9); // destination register (DR)
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
     // add pc_offset to the current PC, look at that memory location to get the final address
-    let pc = state.mem.read(state.reg[R::PC]);
-    state.reg[r0] = state.mem.read(pc.wrapping_add(pc_offset));
+    let address = state.mem.read(state.reg[R::PC].wrapping_add(pc_offset));
+    state.reg[r0] = state.mem.read(address);
     state.reg.update_flags(r0);
 }
 
 // # Assembler formats
 //
-// AND DR, SR1, SR2,
+// AND DR, SR1, SR2
 // AND DR, SR1, imm5
 //
 // # Examples
@@ -81,22 +153,22 @@ pub fn do_ldi(instr: u16, state: &mut State) {
 // # Encodings
 //
 // Register mode:
-// 0001 xxx xxx 0 00 xxx
+// 0101 xxx xxx 0 00 xxx
 // AND  DR  SR1      SR2
 //
 // Immediate mode:
-// 0001 xxx xxx 1 xxxxx
+// 0101 xxx xxx 1 xxxxx
 // AND  DR  SR1   imm5
 pub fn do_and(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7; // destination register (DR)
-    let r1: u16 = (instr >> 6) & 0x7; // first operand (SR1)
+    let r0 = R::field(instr >> 9); // destination register (DR)
+    let r1 = R::field(instr >> 6); // first operand (SR1)
     let imm_flag: u16 = (instr >> 5) & 1; // whether we are in immediate mode
 
     if imm_flag != 0 {
         let imm5: u16 = sign_extend(instr & 0x1F, 5);
         state.reg[r0] = state.reg[r1] & imm5;
     } else {
-        let r2: u16 = instr & 0x7;
+        let r2 = R::field(instr);
         state.reg[r0] = state.reg[r1] & state.reg[r2];
     }
 
@@ -116,8 +188,8 @@ pub fn do_and(instr: u16, state: &mut State) {
 // 1001 xxx xxx 1 11111
 // NOT  DR  SR1
 pub fn do_not(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7; // destination register (DR)
-    let r1: u16 = (instr >> 6) & 0x7; // first operand (SR1)
+    let r0 = R::field(instr >> 9); // destination register (DR)
+    let r1 = R::field(instr >> 6); // first operand (SR1)
 
     state.reg[r0] = !state.reg[r1];
 
@@ -130,7 +202,7 @@ pub fn do_not(instr: u16, state: &mut State) {
 // BRz LABEL
 // BRp LABEL
 // BRzp LABEL
-// BPnp LABEL
+// BRnp LABEL
 // BRnz LABEL
 // BRnzp LABEL ; same as `BR LABEL`
 // BR LABEL    ; same as `BRnzp LABEL`
@@ -148,7 +220,7 @@ pub fn do_br(instr: u16, state: &mut State) {
     let cond_flag: u16 = (instr >> 9) & 0x7;
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
-    if cond_flag & state.reg[R::COND] != 0 {
+    if state.reg.cond().matches(cond_flag) {
         state.reg[R::PC] = state.reg[R::PC].wrapping_add(pc_offset);
     }
 }
@@ -170,7 +242,7 @@ pub fn do_br(instr: u16, state: &mut State) {
 //
 // RET: 1100 000 111   000000
 pub fn do_jmp(instr: u16, state: &mut State) {
-    let r1 = (instr >> 6) & 0x7;
+    let r1 = R::field(instr >> 6);
     state.reg[R::PC] = state.reg[r1];
 }
 
@@ -195,17 +267,18 @@ pub fn do_jmp(instr: u16, state: &mut State) {
 //                 BaseR
 pub fn do_jsr(instr: u16, state: &mut State) {
     let long_flag: u16 = (instr >> 11) & 1;
-    state.reg[R::R7] = state.reg[R::PC];
+    let link = state.reg[R::PC];
 
     if long_flag != 0 {
         /* JSR */
         let long_pc_offset = sign_extend(instr & 0x7FF, 11); // PCoffset11
         state.reg[R::PC] = state.reg[R::PC].wrapping_add(long_pc_offset);
     } else {
-        /* JSRR */
-        let r1: u16 = (instr >> 6) & 0x7;
+        /* JSRR, reading BaseR before R7 is written so JSRR R7 works */
+        let r1 = R::field(instr >> 6);
         state.reg[R::PC] = state.reg[r1];
     }
+    state.reg[R::R7] = link;
 }
 
 // # Assembler formats
@@ -221,7 +294,7 @@ pub fn do_jsr(instr: u16, state: &mut State) {
 // 0010 xxx xxxxxxxxx
 //      DR  PCoffset9
 pub fn do_ld(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7;
+    let r0 = R::field(instr >> 9);
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
     state.reg[r0] = state.mem.read(state.reg[R::PC].wrapping_add(pc_offset));
@@ -241,8 +314,8 @@ pub fn do_ld(instr: u16, state: &mut State) {
 // 0110 xxx xxx   xxxxxx
 //      DR  BaseR offset6
 pub fn do_ldr(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7; // DR
-    let r1: u16 = (instr >> 6) & 0x7; // BaseR
+    let r0 = R::field(instr >> 9); // DR
+    let r1 = R::field(instr >> 6); // BaseR
     let offset6 = sign_extend(instr & 0x3F, 6);
 
     state.reg[r0] = state.mem.read(state.reg[r1].wrapping_add(offset6));
@@ -262,7 +335,7 @@ pub fn do_ldr(instr: u16, state: &mut State) {
 // 1110 xxx xxxxxxxxx
 //      DR  PCoffset9
 pub fn do_lea(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7;
+    let r0 = R::field(instr >> 9);
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
     state.reg[r0] = state.reg[R::PC].wrapping_add(pc_offset);
@@ -282,10 +355,10 @@ pub fn do_lea(instr: u16, state: &mut State) {
 // 0011 xxx xxxxxxxxx
 //      SR  PCoffset9
 pub fn do_st(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7;
+    let r0 = R::field(instr >> 9);
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
-    let address = (R::PC as u16).wrapping_add(pc_offset);
+    let address = state.reg[R::PC].wrapping_add(pc_offset);
     let value = state.reg[r0];
     state.mem.write(address, value);
 }
@@ -303,10 +376,10 @@ pub fn do_st(instr: u16, state: &mut State) {
 // 1011 xxx xxxxxxxxx
 //      SR  PCoffset9
 pub fn do_sti(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7;
+    let r0 = R::field(instr >> 9);
     let pc_offset = sign_extend(instr & 0x1FF, 9); // PCoffset9
 
-    let address = state.mem.read((R::PC as u16).wrapping_add(pc_offset));
+    let address = state.mem.read(state.reg[R::PC].wrapping_add(pc_offset));
     let value = state.reg[r0];
     state.mem.write(address, value);
 }
@@ -324,8 +397,8 @@ pub fn do_sti(instr: u16, state: &mut State) {
 // 0111 xxx xxx   xxxxxx
 //      SR  BaseR offset6
 pub fn do_str(instr: u16, state: &mut State) {
-    let r0: u16 = (instr >> 9) & 0x7;
-    let r1: u16 = (instr >> 6) & 0x7;
+    let r0 = R::field(instr >> 9);
+    let r1 = R::field(instr >> 6);
     let offset = sign_extend(instr & 0x3F, 6); // offset6
 
     let address = state.reg[r1].wrapping_add(offset);
@@ -347,71 +420,156 @@ pub fn do_str(instr: u16, state: &mut State) {
 //
 // 1111 0000 xxxxxxxx
 //           trapvect8
+//
+// A table entry that was set, by an OS image or by the program, wins: the
+// TRAP jumps through it as on the hardware. Otherwise the machine services
+// the trap itself, as it does when the entry points at the built-in routine.
+//
+// TRAP x28, CURSOR, drives the host terminal for text games, with the
+// command in R0:
+//
+//   0  clear the screen and move the cursor to the top left
+//   1  move the cursor to row R1, column R2, both counted from 0
+//   2  clear the rest of the line
+//   3  hide the cursor
+//   4  show it again
+//
+// Other commands do nothing. Each is written as its ANSI escape sequence.
 pub fn do_trap(instr: u16, state: &mut State) {
-    state.reg[R::R7] = state.reg[R::PC];
+    let vector = instr & 0xFF;
+    if state.traps.handler(&state.mem, vector).is_some() {
+        state.reg[R::R7] = state.reg[R::PC];
+        state.reg[R::PC] = state.mem.read(state.traps.entry(vector));
+        return;
+    }
+    let Ok(trap_vector) = TRAP::try_from(vector) else {
+        return do_unknown_trap(vector, state);
+    };
 
-    let trap_vector = TRAP::try_from(instr & 0xFF).expect("unknown trap routine");
+    state.reg[R::R7] = state.reg[R::PC];
+    state.mem.console.kind = match trap_vector {
+        TRAP::OUT => OutputKind::Out,
+        TRAP::PUTS => OutputKind::Puts,
+        TRAP::PUTSP => OutputKind::Putsp,
+        _ => OutputKind::Message,
+    };
     match trap_vector {
         TRAP::GETC => {
-            state.reg[R::R0] = std::io::stdin()
-                .bytes()
-                .next()
-                .and_then(|result| result.ok())
-                .map(|byte| byte as u16)
-                .unwrap();
-            state.reg.update_flags(R::R0 as u16);
+            let input = match state.mem.console.read_key() {
+                Some(c) => {
+                    state.mem.console.echo(c);
+                    Some(c as u16)
+                }
+                None => state.mem.console.end_of_input(),
+            };
+
+            if let Some(c) = input {
+                state.reg[R::R0] = c;
+                state.reg.update_flags(R::R0);
+            }
         }
         TRAP::OUT => {
-            let c = state.reg[R::R0] as u8 as char;
-            print!("{}", c);
-            std::io::stdout().flush().unwrap();
+            state.mem.console.put_word(state.reg[R::R0]);
+            state.mem.console.flush();
         }
         TRAP::PUTS => {
             let mut address = state.reg[R::R0];
             loop {
-                let c = state.mem.read(address) as u8;
+                let c = state.mem.read(address);
                 if c == 0 {
                     break;
                 }
-                print!("{}", c as char);
+                state.mem.console.put_word(c);
                 address = address.wrapping_add(1);
             }
+            state.mem.console.flush();
         }
         TRAP::IN => {
-            print!("Enter a character: ");
-            std::io::stdout().flush().unwrap();
-
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
+            state.mem.console.put_str("Enter a character: ");
+            state.mem.console.flush();
+
+            /* one key, as typed, without waiting for Enter */
+            let input = match state.mem.console.read_key() {
+                Some(c) => {
+                    state.mem.console.kind = OutputKind::Echo;
+                    state.mem.console.put_byte(c);
+                    state.mem.console.kind = OutputKind::Message;
+                    state.mem.console.put_str("\n");
+                    state.mem.console.flush();
+                    Some(c as u16)
+                }
+                None => state.mem.console.end_of_input(),
+            };
 
-            if let Some(c) = input.chars().next() {
-                print!("{}", c);
-                std::io::stdout().flush().unwrap();
-                state.reg[R::R0] = c as u16;
-                state.reg.update_flags(R::R0 as u16);
+            if let Some(c) = input {
+                state.reg[R::R0] = c;
+                state.reg.update_flags(R::R0);
             }
         }
         TRAP::PUTSP => {
-            /* one char per byte (two bytes per word)
-            here we need to swap back to
-            big endian format */
-            let mut c = state.reg[R::R0];
-            while state.mem.read(c) != 0 {
-                let char1 = (state.mem.read(c) & 0xFF) as u8 as char;
-                print!("{}", char1);
-
-                let char2 = (state.mem.read(c) >> 8) as u8 as char;
-                print!("{}", char2);
-
-                c = c.wrapping_add(1);
+            /* two characters per word, the low byte first; a zero byte in
+            either half ends the string, so an odd-length one ends with the
+            high byte of its last word */
+            let mut address = state.reg[R::R0];
+            'string: loop {
+                let word = state.mem.read(address);
+                for byte in [word as u8, (word >> 8) as u8] {
+                    if byte == 0 {
+                        break 'string;
+                    }
+                    state.mem.console.put_byte(byte);
+                }
+                address = address.wrapping_add(1);
             }
 
-            std::io::stdout().flush().unwrap();
+            state.mem.console.flush();
         }
         TRAP::HALT => {
-            println!("HALT");
-            std::io::stdout().flush().unwrap();
+            state.mem.console.put_str("HALT\n");
+            state.mem.console.flush();
             state.running = false;
         }
+        TRAP::EXIT => {
+            state.exit_status = Some(state.reg[R::R0]);
+            state.running = false;
+        }
+        TRAP::CURSOR => {
+            let (row, column) = (state.reg[R::R1], state.reg[R::R2]);
+            if let Some(sequence) = cursor_sequence(state.reg[R::R0], row, column) {
+                state.mem.console.put_str(&sequence);
+                state.mem.console.flush();
+            }
+        }
+        TRAP::SLEEP => {
+            /* under the scheduler the rest of the turn goes to the next process */
+            let millis = state.reg[R::R0] as u64;
+            state.yielded = true;
+            if state.deterministic || !state.scheduled {
+                state.mem.console.flush();
+                state.mem.clock.sleep(millis);
+            }
+        }
     };
+    state.mem.console.kind = OutputKind::Display;
+}
+
+// The escape sequence for CURSOR command `command`, if there is one.
+fn cursor_sequence(command: u16, row: u16, column: u16) -> Option<String> {
+    match command {
+        0 => Some(String::from("\x1b[2J\x1b[H")),
+        1 => Some(format!("\x1b[{};{}H", row as u32 + 1, column as u32 + 1)),
+        2 => Some(String::from("\x1b[K")),
+        3 => Some(String::from("\x1b[?25l")),
+        4 => Some(String::from("\x1b[?25h")),
+        _ => None,
+    }
+}
+
+// A vector with no built-in routine and an empty table entry.
+fn do_unknown_trap(vector: u16, state: &mut State) {
+    let pc = state.reg[R::PC].wrapping_sub(1);
+    match state.unknown_trap {
+        UnknownTrap::Ignore => {}
+        UnknownTrap::Error => state.fail(RuntimeError::UnknownTrap { pc, vector }),
+    }
 }
@@ -0,0 +1,120 @@
+// Instruction set reference
+//
+// The doc comments above the do_* functions in instr.rs are the reference
+// text. They are read from the source at build time, so the help printed by
+// `lc3 isa` cannot drift from the comments. Whether an instruction sets the
+// condition codes is taken from whether its function calls update_flags.
+
+use crate::defs::OP;
+
+const SOURCE: &str = include_str!("instr.rs");
+
+pub struct Entry {
+    pub mnemonic: String,
+    pub doc: String,
+    pub sets_flags: bool,
+}
+
+pub fn entries() -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut doc: Vec<&str> = Vec::new();
+    let mut lines = SOURCE.lines();
+
+    while let Some(line) = lines.next() {
+        if let Some(text) = line.strip_prefix("//") {
+            doc.push(text.strip_prefix(' ').unwrap_or(text));
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix("pub fn do_")
+            .and_then(|rest| rest.split('(').next())
+        {
+            let body: Vec<&str> = lines.by_ref().take_while(|l| *l != "}").collect();
+            entries.push(Entry {
+                mnemonic: name.to_uppercase(),
+                doc: doc.join("\n"),
+                sets_flags: body.iter().any(|l| l.contains("update_flags")),
+            });
+        }
+        doc.clear();
+    }
+    entries
+}
+
+// Finds the entry for a mnemonic, accepting the aliases assemblers use:
+// BRnzp and friends, RET, JSRR and the trap routine names.
+pub fn lookup(mnemonic: &str) -> Option<Entry> {
+    let name = mnemonic.to_uppercase();
+    let name = match name.as_str() {
+        "RET" => "JMP",
+        "JSRR" => "JSR",
+        "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" | "EXIT" | "SLEEP" | "CURSOR" => "TRAP",
+        n if n.starts_with("BR") && n[2..].chars().all(|c| "NZP".contains(c)) => "BR",
+        n => n,
+    };
+    entries().into_iter().find(|e| e.mnemonic == name)
+}
+
+pub(crate) fn flags(entry: &Entry) -> &'static str {
+    match (entry.sets_flags, entry.mnemonic.as_str()) {
+        (true, "TRAP") => "GETC and IN set N, Z, P from R0",
+        (true, _) => "sets N, Z, P from the result",
+        (false, _) => "unchanged",
+    }
+}
+
+pub fn print(mnemonic: Option<&str>) -> Result<(), String> {
+    match mnemonic {
+        Some(mnemonic) => {
+            let entry = lookup(mnemonic).ok_or(format!("unknown instruction {}", mnemonic))?;
+            println!(
+                "{}\n\n{}\n\n# Flags\n\n{}",
+                entry.mnemonic,
+                entry.doc,
+                flags(&entry)
+            );
+        }
+        None => {
+            /* in opcode order, with the first assembler format as a summary */
+            let entries = entries();
+            for op in (0..16).filter_map(|op| OP::try_from(op).ok()) {
+                match entries.iter().find(|e| e.mnemonic == op.name()) {
+                    Some(entry) => {
+                        let format = entry.doc.lines().nth(2).unwrap_or_default();
+                        println!("{:<5} {:<24} flags: {}", op.name(), format, flags(entry));
+                    }
+                    None => println!("{:<5} not simulated", op.name()),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::{entries, lookup};
+
+    #[test]
+    fn every_documented_instruction_is_listed() {
+        let names: Vec<String> = entries().into_iter().map(|e| e.mnemonic).collect();
+        for op in [
+            "ADD", "AND", "NOT", "BR", "JMP", "JSR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR",
+            "TRAP",
+        ] {
+            assert!(names.iter().any(|n| n == op), "{} is missing", op);
+        }
+    }
+
+    #[test]
+    fn lookup_accepts_aliases() {
+        let add = lookup("add").unwrap();
+        assert!(add.sets_flags);
+        assert!(add.doc.contains("ADD DR, SR1, imm5"));
+
+        assert_eq!("BR", lookup("BRzp").unwrap().mnemonic);
+        assert_eq!("JMP", lookup("RET").unwrap().mnemonic);
+        assert!(!lookup("ST").unwrap().sets_flags);
+        assert!(lookup("MOV").is_none());
+    }
+}
@@ -0,0 +1,408 @@
+// JSON and its message framing
+//
+// `lc3 lsp` and `lc3 dap` talk to editors in JSON messages, each preceded by
+// a header giving its length in bytes:
+//
+//   Content-Length: 52\r\n
+//   \r\n
+//   {"jsonrpc":"2.0","id":1,"method":"initialize",...}
+//
+// Json holds a parsed value, and Display writes one back compactly. Objects
+// keep their keys in order, so output depends only on how it was built.
+// Numbers are kept as f64, which holds every integer the protocols use.
+
+use std::{
+    fmt::{self, Write as _},
+    io::{self, BufRead, Write},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<const N: usize>(members: [(&str, Json); N]) -> Self {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Follows `keys` down through nested objects.
+    pub fn at(&self, keys: &[&str]) -> Option<&Json> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text, at: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.at == text.len() {
+            true => Ok(value),
+            false => Err(parser.error("the end of the text")),
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Self {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Self {
+        Json::String(text)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Json::Array(items)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<u16> for Json {
+    fn from(n: u16) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    at: usize, /* byte offset of the next character */
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!("expected {} at byte {}", expected, self.at)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.at..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.at..];
+        self.at += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.text[self.at..].starts_with(word);
+        if found {
+            self.at += word.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            _ if self.eat("true") => Ok(Json::Bool(true)),
+            _ if self.eat("false") => Ok(Json::Bool(false)),
+            _ if self.eat("null") => Ok(Json::Null),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.at += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("a key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(":") {
+                return Err(self.error(":"));
+            }
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(",") {
+                return Err(self.error(", or }"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.at += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error(", or ]"));
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let rest = &self.text[self.at..];
+        let length = rest
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        let n = rest[..length].parse().map_err(|_| self.error("a number"))?;
+        self.at += length;
+        Ok(Json::Number(n))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut text = String::new();
+        loop {
+            let c = self.peek().ok_or(self.error("a closing quote"))?;
+            self.at += c.len_utf8();
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let e = self.peek().ok_or(self.error("an escape"))?;
+                    self.at += e.len_utf8();
+                    text.push(match e {
+                        '"' | '\\' | '/' => e,
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("an escape")),
+                    });
+                }
+                c => text.push(c),
+            }
+        }
+    }
+
+    // The character of a \uXXXX escape, after the u, joining the two halves
+    // of a surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF if self.eat("\\u") => {
+                let low = self.hex4()?;
+                0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+            }
+            code => code,
+        };
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.at..self.at + 4)
+            .ok_or(self.error("four hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("four hex digits"))?;
+        self.at += 4;
+        Ok(code)
+    }
+}
+
+// Reads the next message, None at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let length: usize = length.ok_or_else(|| invalid(String::from("no Content-Length")))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8(body).map_err(|e| invalid(e.to_string()))?;
+    Json::parse(&text).map(Some).map_err(invalid)
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{read_message, write_message, Json};
+
+    #[test]
+    fn values_round_trip() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":"q\"\\\né😀","c":{}}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(
+            Some("q\"\\\n\u{e9}\u{1F600}"),
+            value.get("b").unwrap().as_str()
+        );
+        assert_eq!(
+            Some(1),
+            value.get("a").unwrap().as_array().unwrap()[0].as_u64()
+        );
+        assert_eq!(
+            r#"{"a":[1,-2.5,true,null],"b":"q\"\\\né😀","c":{}}"#,
+            value.to_string()
+        );
+        assert_eq!(value, Json::parse(&value.to_string()).unwrap());
+
+        assert_eq!(
+            Err(String::from("expected , or } at byte 7")),
+            Json::parse(r#"{"a":1 "b":2}"#)
+        );
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("\"open").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn messages_are_framed_by_length() {
+        let message = Json::object([("id", Json::from(1u64)), ("x", Json::from("é"))]);
+        let mut framed = Vec::new();
+        write_message(&mut framed, &message).unwrap();
+        assert_eq!(
+            b"Content-Length: 17\r\n\r\n{\"id\":1,\"x\":\"\xc3\xa9\"}".to_vec(),
+            framed
+        );
+
+        let mut input = &framed[..];
+        assert_eq!(Some(message), read_message(&mut input).unwrap());
+        assert_eq!(None, read_message(&mut input).unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+pub mod analyze;
+pub mod asm;
+pub mod asmfmt;
+pub mod branch;
+pub mod breakpoints;
+pub mod bundle;
+pub mod cache;
+pub mod callstack;
+pub mod cfi;
+pub mod checkpoint;
+pub mod cli;
+pub mod clock;
+pub mod color;
+pub mod config;
+pub mod console;
+pub mod counters;
+pub mod dap;
+pub mod debugger;
+pub mod defs;
+pub mod diag;
+pub mod diffrun;
+pub mod disasm;
+pub mod dump;
+pub mod env;
+pub mod error;
+pub mod explore;
+pub mod grade;
+pub mod history;
+pub mod hooks;
+pub mod instr;
+pub mod isa;
+pub mod json;
+pub mod lineedit;
+pub mod loader;
+pub mod loopcheck;
+pub mod lsp;
+pub mod map;
+pub mod meta;
+pub mod mmio;
+pub mod opstats;
+pub mod pipeline;
+pub mod playground;
+pub mod policy;
+pub mod sched;
+pub mod screen;
+pub mod selftest;
+pub mod snapshot;
+pub mod srcmap;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod storage;
+pub mod terminal;
+pub mod timeline;
+pub mod tracefile;
+pub mod traptrace;
@@ -0,0 +1,443 @@
+// Line editing for the debugger
+//
+// A small readline: the cursor moves with the arrow keys, Home and End (or
+// Ctrl-A and Ctrl-E), Up and Down walk the history, Ctrl-R searches it
+// backwards and Tab completes the word under the cursor. The first word of a
+// line completes from the command names and later words from the argument
+// words, which the debugger fills with register names and the symbol table.
+//
+// Editor only turns keys into edits, so it is tested without a terminal;
+// read_line puts the terminal into raw mode and drives it a key at a time.
+
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::AsFd,
+};
+
+use crate::terminal::InputBuffering;
+
+const HISTORY_MAX: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    Search,    /* Ctrl-R */
+    Interrupt, /* Ctrl-C */
+    Eof,       /* Ctrl-D */
+    Escape,
+}
+
+// Turns terminal input bytes into keys, one byte at a time.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn push(&mut self, byte: u8) -> Option<Key> {
+        self.pending.push(byte);
+        let key = match self.pending.as_slice() {
+            [0x1B] | [0x1B, b'[' | b'O'] | [0x1B, b'[', b'0'..=b'9'] => return None,
+            [0x1B, b'[' | b'O', code] => match code {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                _ => Key::Escape,
+            },
+            [0x1B, b'[', n, b'~'] => match n {
+                b'1' | b'7' => Key::Home,
+                b'3' => Key::Delete,
+                b'4' | b'8' => Key::End,
+                _ => Key::Escape,
+            },
+            [0x1B, ..] => Key::Escape,
+            [0x01] => Key::Home,
+            [0x03] => Key::Interrupt,
+            [0x04] => Key::Eof,
+            [0x05] => Key::End,
+            [0x09] => Key::Tab,
+            [b'\r' | b'\n'] => Key::Enter,
+            [0x12] => Key::Search,
+            [0x08 | 0x7F] => Key::Backspace,
+            bytes => match std::str::from_utf8(bytes) {
+                Ok(text) => match text.chars().next() {
+                    Some(c) if !c.is_control() => Key::Char(c),
+                    _ => Key::Escape,
+                },
+                Err(e) if e.error_len().is_none() && bytes.len() < 4 => return None,
+                Err(_) => Key::Escape,
+            },
+        };
+        self.pending.clear();
+        Some(key)
+    }
+}
+
+// Words to complete, by position in the line
+#[derive(Clone, Debug, Default)]
+pub struct Completer {
+    pub commands: Vec<String>,
+    pub arguments: Vec<String>,
+}
+
+impl Completer {
+    // The words starting with `prefix`, sorted and without duplicates.
+    pub fn complete(&self, prefix: &str, first: bool) -> Vec<&str> {
+        let words = if first {
+            &self.commands
+        } else {
+            &self.arguments
+        };
+        let mut found: Vec<&str> = words
+            .iter()
+            .map(String::as_str)
+            .filter(|w| w.starts_with(prefix))
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Edit,              /* keep reading keys */
+    Submit(String),    /* a finished line */
+    List(Vec<String>), /* completions to show before redrawing */
+    Cancel,            /* the line was dropped */
+    Eof,
+}
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    line: Vec<char>,
+    cursor: usize,
+    pub history: Vec<String>,
+    browsing: Option<usize>, /* history entry shown by Up and Down */
+    saved: Vec<char>,        /* the line being typed, while browsing */
+    search: Option<(String, Option<usize>)>, /* query and matching entry */
+    pub completer: Completer,
+}
+
+impl Editor {
+    pub fn new(completer: Completer) -> Self {
+        Self {
+            completer,
+            ..Self::default()
+        }
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_MAX {
+            self.history.remove(0);
+        }
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn handle(&mut self, key: Key) -> Action {
+        if self.search.is_some() {
+            return self.handle_search(key);
+        }
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Submit(self.take()),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up => self.browse(true),
+            Key::Down => self.browse(false),
+            Key::Tab => return self.complete(),
+            Key::Search => self.search = Some((String::new(), None)),
+            Key::Interrupt => {
+                self.take();
+                return Action::Cancel;
+            }
+            Key::Eof if self.line.is_empty() => return Action::Eof,
+            _ => {}
+        }
+        Action::Edit
+    }
+
+    fn handle_search(&mut self, key: Key) -> Action {
+        let Some((mut query, mut found)) = self.search.take() else {
+            return Action::Edit;
+        };
+        match key {
+            Key::Char(c) => {
+                query.push(c);
+                found = self.find(&query, self.history.len());
+            }
+            Key::Backspace => {
+                query.pop();
+                found = self.find(&query, self.history.len());
+            }
+            Key::Search => {
+                let before = found.unwrap_or(self.history.len());
+                found = self.find(&query, before).or(found);
+            }
+            Key::Interrupt => return Action::Edit,
+            key => {
+                /* any other key takes the match and acts on it */
+                if let Some(index) = found {
+                    self.line = self.history[index].chars().collect();
+                    self.cursor = self.line.len();
+                }
+                if key == Key::Escape {
+                    return Action::Edit;
+                }
+                return self.handle(key);
+            }
+        }
+        self.search = Some((query, found));
+        Action::Edit
+    }
+
+    // The newest history entry before `before` that contains `query`.
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        (0..before).rev().find(|&i| self.history[i].contains(query))
+    }
+
+    fn browse(&mut self, older: bool) {
+        let index = match (self.browsing, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.saved = std::mem::take(&mut self.line);
+                Some(self.history.len() - 1)
+            }
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+        self.line = match index {
+            Some(i) => self.history[i].chars().collect(),
+            None => std::mem::take(&mut self.saved),
+        };
+        self.browsing = index;
+        self.cursor = self.line.len();
+    }
+
+    fn complete(&mut self) -> Action {
+        let start = self.line[..self.cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let prefix: String = self.line[start..self.cursor].iter().collect();
+        let first = self.line[..start].iter().all(|c| c.is_whitespace());
+        let found = self.completer.complete(&prefix, first);
+
+        let common = match found.as_slice() {
+            [] => return Action::Edit,
+            [only] => format!("{} ", only),
+            [head, rest @ ..] => {
+                let shared = rest.iter().fold(head.len(), |len, word| {
+                    head.char_indices()
+                        .zip(word.chars())
+                        .take_while(|((i, a), b)| *i < len && a == b)
+                        .count()
+                });
+                head.chars().take(shared).collect()
+            }
+        };
+        let added: Vec<char> = common.chars().skip(prefix.chars().count()).collect();
+        if added.is_empty() {
+            return Action::List(found.into_iter().map(String::from).collect());
+        }
+        let count = added.len();
+        self.line.splice(self.cursor..self.cursor, added);
+        self.cursor += count;
+        Action::Edit
+    }
+
+    fn take(&mut self) -> String {
+        self.cursor = 0;
+        self.browsing = None;
+        self.saved.clear();
+        std::mem::take(&mut self.line).into_iter().collect()
+    }
+
+    // Redraws the line after `prompt`, leaving the terminal cursor in place.
+    pub fn render(&self, prompt: &str) -> String {
+        if let Some((query, found)) = &self.search {
+            let line = found.map_or("", |i| self.history[i].as_str());
+            return format!("\r\x1B[K(reverse-i-search)`{}': {}", query, line);
+        }
+        let line = self.line();
+        let back = self.line.len() - self.cursor;
+        let mut out = format!("\r\x1B[K{}{}", prompt, line);
+        if back > 0 {
+            out.push_str(&format!("\x1B[{}D", back));
+        }
+        out
+    }
+}
+
+// Reads one line from a terminal on stdin, echoing to stderr. Returns None
+// at end of input. Falls back to plain line reading when stdin is not a
+// terminal.
+pub fn read_line(editor: &mut Editor, prompt: &str) -> io::Result<Option<String>> {
+    let stdin = io::stdin();
+    let Some(_raw) = InputBuffering::disable(stdin.as_fd()) else {
+        eprint!("{}", prompt);
+        io::stderr().flush()?;
+        let mut line = String::new();
+        return match stdin.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line.trim_end_matches(['\r', '\n']).to_string())),
+        };
+    };
+
+    let mut stderr = io::stderr();
+    let mut decoder = Decoder::default();
+    write!(stderr, "{}", editor.render(prompt))?;
+    stderr.flush()?;
+    for byte in stdin.lock().bytes() {
+        let Some(key) = decoder.push(byte?) else {
+            continue;
+        };
+        match editor.handle(key) {
+            Action::Edit => {}
+            Action::Submit(line) => {
+                writeln!(stderr)?;
+                editor.add_history(&line);
+                return Ok(Some(line));
+            }
+            Action::List(words) => writeln!(stderr, "\r\n{}", words.join("  "))?,
+            Action::Cancel => writeln!(stderr, "^C")?,
+            Action::Eof => {
+                writeln!(stderr)?;
+                return Ok(None);
+            }
+        }
+        write!(stderr, "{}", editor.render(prompt))?;
+        stderr.flush()?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lineedit::{Action, Completer, Decoder, Editor, Key};
+
+    fn typed(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn escape_sequences_and_utf8_are_decoded() {
+        let mut decoder = Decoder::default();
+        let keys: Vec<Key> = b"\x1B[Aa\x1B[3~\x12\r\xC3\xA9"
+            .iter()
+            .filter_map(|&b| decoder.push(b))
+            .collect();
+        assert_eq!(
+            vec![
+                Key::Up,
+                Key::Char('a'),
+                Key::Delete,
+                Key::Search,
+                Key::Enter,
+                Key::Char('é')
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn editing_and_history() {
+        let mut editor = Editor::default();
+        typed(&mut editor, "mem x40");
+        editor.handle(Key::Home);
+        editor.handle(Key::Delete);
+        typed(&mut editor, "M");
+        assert_eq!(
+            Action::Submit(String::from("Mem x40")),
+            editor.handle(Key::Enter)
+        );
+
+        editor.add_history("regs");
+        editor.add_history("step 2");
+        editor.add_history("step 2");
+        typed(&mut editor, "dis");
+        editor.handle(Key::Up);
+        assert_eq!("step 2", editor.line());
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        assert_eq!("regs", editor.line());
+        editor.handle(Key::Down);
+        editor.handle(Key::Down);
+        assert_eq!("dis", editor.line());
+    }
+
+    #[test]
+    fn reverse_search_finds_older_matches() {
+        let mut editor = Editor::default();
+        for line in ["break x3000", "regs", "break op ADD"] {
+            editor.add_history(line);
+        }
+        editor.handle(Key::Search);
+        typed(&mut editor, "brea");
+        assert!(editor.render("").ends_with("`brea': break op ADD"));
+        editor.handle(Key::Search);
+        assert_eq!(
+            Action::Submit(String::from("break x3000")),
+            editor.handle(Key::Enter)
+        );
+    }
+
+    #[test]
+    fn tab_completes_commands_then_arguments() {
+        let completer = Completer {
+            commands: vec![String::from("step"), String::from("set")],
+            arguments: vec![String::from("LOOP"), String::from("LOOP_END")],
+        };
+        let mut editor = Editor::new(completer);
+        typed(&mut editor, "st");
+        editor.handle(Key::Tab);
+        assert_eq!("step ", editor.line());
+
+        editor.handle(Key::Interrupt);
+        typed(&mut editor, "s");
+        assert_eq!(
+            Action::List(vec![String::from("set"), String::from("step")]),
+            editor.handle(Key::Tab)
+        );
+
+        editor.handle(Key::Interrupt);
+        typed(&mut editor, "b LO");
+        editor.handle(Key::Tab);
+        assert_eq!("b LOOP", editor.line());
+    }
+}
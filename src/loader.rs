@@ -0,0 +1,405 @@
+// Object image loading
+//
+// An image is a big-endian origin word followed by the words to place at
+// consecutive addresses starting at the origin. It may end with a checksum
+// record: CHECKSUM_MAGIC followed by the wrapping sum of the origin and all
+// data words. A valid record is stripped before loading, as is a metadata
+// block written by the assembler (see meta.rs), which the checksum covers.
+// Files may also hold several images packed into a bundle (see bundle.rs).
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+};
+
+use crate::{
+    bundle::Bundle,
+    defs::{OP, TRAP},
+    meta::Metadata,
+    state::{State, MEMORY_MAX},
+};
+
+pub const CHECKSUM_MAGIC: u16 = 0x4353; /* "CS" */
+
+pub struct Image {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub checksummed: bool, /* ended with a valid checksum record */
+    pub metadata: Option<Metadata>,
+}
+
+impl Image {
+    // With `verify` set the image must end with a valid checksum record.
+    pub fn parse(bytes: &[u8], verify: bool) -> io::Result<Self> {
+        if bytes.len() < 2 {
+            return Err(invalid("image is missing its origin word"));
+        }
+        if !bytes.len().is_multiple_of(2) {
+            return Err(invalid(&format!(
+                "image has an odd number of bytes ({}), the last word is incomplete",
+                bytes.len()
+            )));
+        }
+
+        let mut words = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        let origin = words.next().unwrap();
+        let mut words: Vec<u16> = words.collect();
+
+        let checksummed = match words.len().checked_sub(2).map(|n| words.split_at(n)) {
+            Some((data, &[CHECKSUM_MAGIC, stored])) => {
+                let computed = checksum(origin, data);
+                if computed == stored {
+                    words.truncate(data.len());
+                    true
+                } else if verify {
+                    return Err(invalid(&format!(
+                        "checksum mismatch: record says x{:04X}, image sums to x{:04X}",
+                        stored, computed
+                    )));
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        };
+        if verify && !checksummed {
+            return Err(invalid("image has no checksum record"));
+        }
+        let metadata = Metadata::strip(&mut words);
+
+        Self {
+            origin,
+            words,
+            checksummed,
+            metadata,
+        }
+        .checked()
+    }
+
+    fn checked(self) -> io::Result<Self> {
+        if self.origin as usize + self.words.len() > MEMORY_MAX {
+            return Err(invalid(&format!(
+                "image of {} words at x{:04X} runs past xFFFF",
+                self.words.len(),
+                self.origin
+            )));
+        }
+        Ok(self)
+    }
+
+    // Writes the image into memory, unless part of it lies past the
+    // configured memory size.
+    pub fn load(&self, state: &mut State) -> io::Result<()> {
+        let addresses = (self.origin..=u16::MAX).take(self.words.len());
+        if let Some(missing) = addresses.clone().find(|&a| !state.mem.is_mapped(a)) {
+            return Err(invalid(&format!(
+                "image of {} words at x{:04X} runs past the end of memory at x{:04X}",
+                self.words.len(),
+                self.origin,
+                missing
+            )));
+        }
+        state.mem.write_slice(self.origin, &self.words);
+        Ok(())
+    }
+}
+
+pub fn checksum(origin: u16, words: &[u16]) -> u16 {
+    words.iter().fold(origin, |sum, &w| sum.wrapping_add(w))
+}
+
+// Loads the image or bundle at `path` into `state`. Returns the images in load
+// order and, for a bundle, the PC it starts at.
+pub fn read_image_file(
+    path: &str,
+    state: &mut State,
+    verify: bool,
+) -> io::Result<(Vec<Image>, Option<u16>)> {
+    let bytes = fs::read(path)?;
+    let (files, start) = if Bundle::is_bundle(&bytes) {
+        let bundle = Bundle::parse(&bytes)?;
+        (bundle.images, Some(bundle.start))
+    } else {
+        (vec![bytes], None)
+    };
+
+    let mut images = Vec::new();
+    for file in files {
+        let image = Image::parse(&file, verify)?;
+        image.load(state)?;
+        images.push(image);
+    }
+    Ok((images, start))
+}
+
+// The words just before and just after each loaded range that no range covers.
+pub fn guard_words(loaded: &[(u16, usize)]) -> Vec<u16> {
+    let covered = |address: i64| {
+        loaded.iter().any(|&(origin, length)| {
+            (origin as i64..origin as i64 + length as i64).contains(&address)
+        })
+    };
+    let mut guards: Vec<u16> = loaded
+        .iter()
+        .flat_map(|&(origin, length)| [origin as i64 - 1, origin as i64 + length as i64])
+        .filter(|&address| (0..MEMORY_MAX as i64).contains(&address) && !covered(address))
+        .map(|address| address as u16)
+        .collect();
+    guards.sort_unstable();
+    guards.dedup();
+    guards
+}
+
+// The trap vector table followed by the interrupt and exception vector table,
+// from the trap table base
+pub const VECTOR_TABLES_END: u16 = 0x01FF;
+
+// Loads an image of vectors and handlers, which must lie within the vector tables.
+pub fn read_vector_file(path: &str, state: &mut State, verify: bool) -> io::Result<Image> {
+    let image = Image::parse(&fs::read(path)?, verify)?;
+    let (start, end) = (state.traps.base, state.traps.entry(VECTOR_TABLES_END));
+    let past = image.origin as usize + image.words.len();
+    if image.origin < start || past > end as usize + 1 {
+        return Err(invalid(&format!(
+            "vector image at x{:04X} lies outside x{:04X}-x{:04X}",
+            image.origin, start, end
+        )));
+    }
+    image.load(state)?;
+    Ok(image)
+}
+
+// Finds TRAP instructions in the loaded ranges whose vector is neither
+// serviced by the machine nor set in the table. Returns each address and vector.
+pub fn unhandled_traps(state: &State, loaded: &[(u16, usize)]) -> Vec<(u16, u16)> {
+    loaded
+        .iter()
+        .flat_map(|&(origin, length)| (0..length).map(move |i| origin.wrapping_add(i as u16)))
+        .filter_map(|address| {
+            let word = state.mem.peek(address);
+            let vector = word & 0xFF;
+            let unhandled = word >> 12 == OP::TRAP as u16
+                && TRAP::try_from(vector).is_err()
+                && state.traps.handler(&state.mem, vector).is_none();
+            unhandled.then_some((address, vector))
+        })
+        .collect()
+}
+
+// A value passed to the guest on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Str(String), /* null-terminated, one character per word or packed two per word */
+    Word(u16),
+}
+
+// Encodes `args` back to back. Packed strings put the first character in the
+// low byte, the order PUTSP prints them in.
+pub fn encode_args(args: &[Arg], packed: bool) -> Vec<u16> {
+    let mut words = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Word(word) => words.push(*word),
+            Arg::Str(text) if packed => {
+                let bytes = text.as_bytes();
+                words.extend(bytes.chunks(2).map(|pair| {
+                    pair[0] as u16 | pair.get(1).map_or(0, |&high| (high as u16) << 8)
+                }));
+                if bytes.len().is_multiple_of(2) {
+                    words.push(0);
+                }
+            }
+            Arg::Str(text) => {
+                words.extend(text.bytes().map(u16::from));
+                words.push(0);
+            }
+        }
+    }
+    words
+}
+
+// Writes `args` into memory at `address` before the program starts.
+// Returns the number of words written.
+pub fn write_args(
+    state: &mut State,
+    address: u16,
+    args: &[Arg],
+    packed: bool,
+) -> io::Result<usize> {
+    let image = Image {
+        origin: address,
+        words: encode_args(args, packed),
+        checksummed: false,
+        metadata: None,
+    }
+    .checked()?;
+    image.load(state)?;
+    Ok(image.words.len())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        config::Config,
+        loader::{encode_args, guard_words, unhandled_traps, write_args, Arg, Image},
+        state::State,
+    };
+
+    #[test]
+    fn parse_big_endian_words() {
+        let image = Image::parse(&[0x30, 0x00, 0xF0, 0x25, 0x12, 0x34], false).unwrap();
+        assert_eq!(0x3000, image.origin);
+        assert_eq!(vec![0xF025, 0x1234], image.words);
+
+        let mut state = State::new();
+        image.load(&mut state).unwrap();
+        assert_eq!(0xF025, state.mem.peek(0x3000));
+        assert_eq!(0x1234, state.mem.peek(0x3001));
+    }
+
+    #[test]
+    fn images_must_fit_the_memory_size() {
+        let config = Config {
+            memory_size: 0x3001,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        let image = Image::parse(&[0x30, 0x00, 0xF0, 0x25, 0x12, 0x34], false).unwrap();
+        assert_eq!(
+            "image of 2 words at x3000 runs past the end of memory at x3001",
+            image.load(&mut state).unwrap_err().to_string()
+        );
+        assert_eq!(0, state.mem.peek(0x3000));
+
+        let image = Image::parse(&[0x30, 0x00, 0xF0, 0x25], false).unwrap();
+        assert!(image.load(&mut state).is_ok());
+    }
+
+    #[test]
+    fn parse_origin_only() {
+        let image = Image::parse(&[0x40, 0x00], false).unwrap();
+        assert_eq!(0x4000, image.origin);
+        assert!(image.words.is_empty());
+    }
+
+    #[test]
+    fn reject_truncated_images() {
+        assert!(Image::parse(&[], false).is_err());
+        assert!(Image::parse(&[0x30], false).is_err());
+
+        let odd = Image::parse(&[0x30, 0x00, 0xF0, 0x25, 0x12], false)
+            .err()
+            .unwrap();
+        assert!(odd.to_string().contains("odd number of bytes (5)"));
+    }
+
+    #[test]
+    fn reject_wraparound() {
+        /* two words at xFFFE fill memory exactly */
+        let fits = Image::parse(&[0xFF, 0xFE, 0x00, 0x01, 0x00, 0x02], false).unwrap();
+        assert_eq!(2, fits.words.len());
+
+        let wraps = Image::parse(&[0xFF, 0xFF, 0x00, 0x01, 0x00, 0x02], false)
+            .err()
+            .unwrap();
+        assert!(wraps.to_string().contains("runs past xFFFF"));
+    }
+
+    #[test]
+    fn strip_valid_checksum_record() {
+        /* x3000 + xF025 + x0001 = x2026 (wrapping) */
+        let bytes = [0x30, 0x00, 0xF0, 0x25, 0x00, 0x01, 0x43, 0x53, 0x20, 0x26];
+        let image = Image::parse(&bytes, true).unwrap();
+        assert!(image.checksummed);
+        assert_eq!(vec![0xF025, 0x0001], image.words);
+
+        let unverified = Image::parse(&bytes, false).unwrap();
+        assert!(unverified.checksummed);
+        assert_eq!(2, unverified.words.len());
+    }
+
+    #[test]
+    fn verify_rejects_missing_or_wrong_checksum() {
+        let missing = Image::parse(&[0x30, 0x00, 0xF0, 0x25], true).err().unwrap();
+        assert!(missing.to_string().contains("no checksum record"));
+
+        let bytes = [0x30, 0x00, 0xF0, 0x25, 0x43, 0x53, 0x00, 0x00];
+        let wrong = Image::parse(&bytes, true).err().unwrap();
+        assert!(wrong.to_string().contains("checksum mismatch"));
+
+        /* without --verify a bad record is just data */
+        let data = Image::parse(&bytes, false).unwrap();
+        assert!(!data.checksummed);
+        assert_eq!(3, data.words.len());
+    }
+
+    #[test]
+    fn strip_assembler_metadata() {
+        let source = ".ORIG x3000\nSTART HALT\n.END\n";
+        let program = assemble(source).unwrap();
+        let bytes = program.object(Some(&program.metadata(source)));
+
+        let image = Image::parse(&bytes, false).unwrap();
+        assert_eq!(vec![0xF025], image.words);
+        let metadata = image.metadata.unwrap();
+        assert_eq!(Some(&0x3000), metadata.symbols.get("START"));
+        assert_eq!(vec![(0x3000, 2)], metadata.lines);
+    }
+
+    #[test]
+    fn guard_words_skip_adjacent_images() {
+        let loaded = [(0x3000, 4), (0x3004, 2), (0x0000, 1)];
+        assert_eq!(vec![0x0001, 0x2FFF, 0x3006], guard_words(&loaded));
+    }
+
+    #[test]
+    fn unhandled_traps_need_a_vector() {
+        let mut state = State::new();
+        let program = [0xF030, 0xF031, 0xF025]; // TRAP x30, TRAP x31, HALT
+        state.mem.write_slice(0x3000, &program);
+        state.mem.poke(0x0030, 0x0400);
+
+        assert_eq!(
+            vec![(0x3001, 0x31)],
+            unhandled_traps(&state, &[(0x3000, 3)])
+        );
+
+        /* the fill pattern is not a vector */
+        let mut filled = State::with_config(&Config {
+            fill: 0xDEAD,
+            ..Config::default()
+        });
+        filled.mem.write_slice(0x3000, &program);
+        assert_eq!(
+            vec![(0x3000, 0x30), (0x3001, 0x31)],
+            unhandled_traps(&filled, &[(0x3000, 3)])
+        );
+    }
+
+    #[test]
+    fn encode_args_one_char_per_word_or_packed() {
+        let args = [Arg::Str(String::from("hi")), Arg::Word(0x1234)];
+        assert_eq!(vec![0x68, 0x69, 0, 0x1234], encode_args(&args, false));
+        assert_eq!(vec![0x6968, 0, 0x1234], encode_args(&args, true));
+        assert_eq!(
+            vec![0x6968, 0x0021],
+            encode_args(&[Arg::Str(String::from("hi!"))], true)
+        );
+    }
+
+    #[test]
+    fn write_args_rejects_overflow() {
+        let mut state = State::new();
+        let args = [Arg::Str(String::from("ab"))];
+        assert_eq!(3, write_args(&mut state, 0x4000, &args, false).unwrap());
+        assert_eq!(0x61, state.mem.peek(0x4000));
+        assert!(write_args(&mut state, 0xFFFE, &args, false).is_err());
+    }
+}
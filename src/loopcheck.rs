@@ -0,0 +1,77 @@
+// Infinite loop detection
+//
+// With --detect-loops the registers, PC and condition codes included, are
+// sampled every SAMPLE_INTERVAL instructions. If a sample matches an
+// earlier one and nothing outside the registers changed in between, the
+// machine is certain to go round the same way forever, and it is stopped
+// with RuntimeError::InfiniteLoop.
+//
+// Anything that could let the program out starts the history afresh: a
+// TRAP, a device access, or a write that changes a word of memory. A loop
+// polling the keyboard is therefore never flagged, since a key may still
+// arrive.
+
+use std::collections::HashSet;
+
+use crate::state::Registers;
+
+const SAMPLE_INTERVAL: u64 = 64;
+const MAX_SAMPLES: usize = 4096;
+
+#[derive(Clone, Debug, Default)]
+pub struct LoopDetector {
+    samples: HashSet<Registers>,
+    activity: u64, /* Memory::activity when the history was started */
+    countdown: u64,
+}
+
+impl LoopDetector {
+    // Follows one instruction. Returns true if the machine is in a loop.
+    pub fn observe(&mut self, reg: &Registers, activity: u64) -> bool {
+        if activity != self.activity {
+            self.activity = activity;
+            self.samples.clear();
+            self.countdown = 0;
+        }
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = SAMPLE_INTERVAL - 1;
+        if self.samples.len() == MAX_SAMPLES {
+            /* a long computation, not a short cycle so far */
+            self.samples.clear();
+        }
+        !self.samples.insert(reg.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{defs::R, loopcheck::LoopDetector, state::Registers};
+
+    #[test]
+    fn recurring_registers_are_a_loop() {
+        let mut detector = LoopDetector::default();
+        let mut reg = Registers::new(0x3000);
+        let looped = (0..1000).any(|i| {
+            reg.set_pc(0x3000 + i % 3);
+            detector.observe(&reg, 0)
+        });
+        assert!(looped);
+    }
+
+    #[test]
+    fn activity_starts_over() {
+        let mut detector = LoopDetector::default();
+        let reg = Registers::new(0x3000);
+        assert!(!(0..1000).any(|i| detector.observe(&reg, i)));
+
+        let mut reg = Registers::new(0x3000);
+        let counted = (0..1000).any(|i| {
+            reg.set(R::R1, i as u16);
+            detector.observe(&reg, 0)
+        });
+        assert!(!counted);
+    }
+}
@@ -0,0 +1,476 @@
+// Language server
+//
+// `lc3 lsp` speaks the Language Server Protocol on stdin and stdout, so an
+// editor can check LC-3 assembly as it is typed:
+//
+//   diagnostics     the error `assemble` stops at, and its warnings
+//   definition      the line that defines the label under the cursor
+//   hover           the instruction reference for a mnemonic, as lc3 isa
+//                   prints it, or the address of a label
+//   documentSymbol  every label, with its address
+//
+// Each open document is reassembled in full whenever it changes. Lines are
+// read with the assembler's tokenizer, so a label is what `assemble` takes
+// for one. Positions count characters rather than UTF-16 units, which only
+// differs for text outside the Basic Multilingual Plane.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    asm::{self, Program},
+    isa,
+    json::{self, Json},
+};
+
+const ERROR: u64 = 1;
+const WARNING: u64 = 2;
+const FUNCTION: u64 = 12; /* symbol kinds: a label on an instruction */
+const VARIABLE: u64 = 13; /* or on data */
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+#[derive(Default)]
+pub struct Server {
+    documents: BTreeMap<String, String>, /* text of each open document by URI */
+}
+
+// A label as it appears in the source
+#[derive(Debug, PartialEq)]
+struct Label<'a> {
+    name: &'a str,
+    line: usize,   /* from 0 */
+    column: usize, /* in characters */
+    data: bool,    /* it names a .FILL, .BLKW or .STRINGZ */
+}
+
+impl Server {
+    // The messages to send in answer to `message`.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .at(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .unwrap_or("");
+
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "textDocument/didOpen" => {
+                let text = params.at(&["textDocument", "text"]).and_then(Json::as_str);
+                self.documents
+                    .insert(uri.to_string(), text.unwrap_or("").to_string());
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(Json::as_array);
+                if let Some(text) = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str)
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    Json::object([("uri", uri.into()), ("diagnostics", Json::Array(vec![]))]),
+                )];
+            }
+            "textDocument/definition" => Some(self.definition(uri, params)),
+            "textDocument/hover" => Some(self.hover(uri, params)),
+            "textDocument/documentSymbol" => Some(self.symbols(uri)),
+            "shutdown" => Some(Json::Null),
+            _ => None,
+        };
+
+        /* notifications have no id and get no answer */
+        let Some(id) = message.get("id") else {
+            return vec![];
+        };
+        let answer = match result {
+            Some(result) => ("result", result),
+            None => (
+                "error",
+                Json::object([
+                    ("code", Json::Number(METHOD_NOT_FOUND)),
+                    ("message", format!("unknown method {}", method).into()),
+                ]),
+            ),
+        };
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            answer,
+        ])]
+    }
+
+    fn text(&self, uri: &str) -> &str {
+        self.documents.get(uri).map_or("", String::as_str)
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        let diagnostics = match asm::assemble(text) {
+            Ok(program) => (program.warnings.iter())
+                .map(|warning| diagnostic(text, warning, WARNING))
+                .collect(),
+            Err(e) => vec![diagnostic(text, &e, ERROR)],
+        };
+        notification(
+            "textDocument/publishDiagnostics",
+            Json::object([
+                ("uri", uri.into()),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let Some(word) = word_at(text, params) else {
+            return Json::Null;
+        };
+        match labels(text).into_iter().find(|label| label.name == word) {
+            Some(label) => Json::object([
+                ("uri", uri.into()),
+                (
+                    "range",
+                    range(
+                        label.line,
+                        label.column,
+                        label.column + word.chars().count(),
+                    ),
+                ),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let Some(word) = word_at(text, params) else {
+            return Json::Null;
+        };
+        let label = asm::assemble(text)
+            .ok()
+            .and_then(|program| program.symbols.get(word).copied());
+        let value = match (label, isa::lookup(word)) {
+            (Some(address), _) => format!("{} x{:04X}", word, address),
+            (None, Some(entry)) => format!(
+                "{}\n\n{}\n\n# Flags\n\n{}",
+                entry.mnemonic,
+                entry.doc,
+                isa::flags(&entry)
+            ),
+            (None, None) => return Json::Null,
+        };
+        Json::object([(
+            "contents",
+            Json::object([("kind", "plaintext".into()), ("value", value.into())]),
+        )])
+    }
+
+    fn symbols(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        let program = asm::assemble(text).ok();
+        let symbols = labels(text)
+            .into_iter()
+            .map(|label| {
+                let address = (program.as_ref())
+                    .and_then(|program: &Program| program.symbols.get(label.name));
+                let at = range(
+                    label.line,
+                    label.column,
+                    label.column + label.name.chars().count(),
+                );
+                Json::object([
+                    ("name", label.name.into()),
+                    (
+                        "detail",
+                        address
+                            .map_or(String::new(), |a| format!("x{:04X}", a))
+                            .into(),
+                    ),
+                    (
+                        "kind",
+                        Json::from(match label.data {
+                            true => VARIABLE,
+                            false => FUNCTION,
+                        }),
+                    ),
+                    ("range", at.clone()),
+                    ("selectionRange", at),
+                ])
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                ("textDocumentSync", Json::from(1u64)), /* the full text on each change */
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("documentSymbolProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "lc3".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(start)), ("end", position(end))])
+}
+
+// A diagnostic for an assembler message, which covers the line the message
+// starts with ("line N: ...") or the first line when it names none.
+fn diagnostic(text: &str, message: &str, severity: u64) -> Json {
+    let (line, message) = message
+        .strip_prefix("line ")
+        .and_then(|rest| rest.split_once(": "))
+        .and_then(|(n, rest)| Some((n.parse::<usize>().ok()?.checked_sub(1)?, rest)))
+        .unwrap_or((0, message));
+    let width = text.lines().nth(line).map_or(0, |l| l.chars().count());
+    Json::object([
+        ("range", range(line, 0, width)),
+        ("severity", severity.into()),
+        ("source", "lc3".into()),
+        ("message", message.into()),
+    ])
+}
+
+// The labels defined in `text`, in source order. A label alone on its line
+// names the next statement, so whether it names data is decided there.
+fn labels(text: &str) -> Vec<Label<'_>> {
+    let mut labels = Vec::new();
+    let mut pending = 0;
+    for (line, raw) in text.lines().enumerate() {
+        let code = asm::strip_comment(raw).trim();
+        if code.is_empty() {
+            continue;
+        }
+        let (first, rest) = asm::split_word(code);
+        let statement = match asm::is_mnemonic(first) || asm::is_pseudo_op(first, rest) {
+            true => code,
+            false => {
+                let name = first.trim_end_matches(':');
+                let column = raw[..raw.find(first).unwrap_or(0)].chars().count();
+                labels.push(Label {
+                    name,
+                    line,
+                    column,
+                    data: false,
+                });
+                pending += 1;
+                rest
+            }
+        };
+        let (mnemonic, _) = asm::split_word(statement);
+        if mnemonic.is_empty() {
+            continue;
+        }
+        let data = [".FILL", ".BLKW", ".STRINGZ"].contains(&mnemonic.to_uppercase().as_str());
+        let count = labels.len();
+        for label in &mut labels[count - pending..] {
+            label.data = data;
+        }
+        pending = 0;
+    }
+    labels
+}
+
+// The word under the cursor at the position in `params`.
+fn word_at<'a>(text: &'a str, params: &Json) -> Option<&'a str> {
+    let line = params.at(&["position", "line"])?.as_u64()? as usize;
+    let character = params.at(&["position", "character"])?.as_u64()? as usize;
+    let line = text.lines().nth(line)?;
+    let at = line
+        .char_indices()
+        .nth(character)
+        .map_or(line.len(), |(i, _)| i);
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let start = line[..at].rfind(|c| !is_word(c)).map_or(0, |i| i + 1);
+    let end = line[at..]
+        .find(|c| !is_word(c))
+        .map_or(line.len(), |i| at + i);
+    Some(&line[start..end]).filter(|word| !word.is_empty())
+}
+
+// Serves requests until the client sends exit or closes the input.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = json::read_message(input)? {
+        if message.get("method").and_then(Json::as_str) == Some("exit") {
+            break;
+        }
+        for reply in server.handle(&message) {
+            json::write_message(output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        json::Json,
+        lsp::{labels, Label, Server},
+    };
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        LD R2, COUNT
+        HALT
+COUNT   .FILL #5
+UNUSED
+        .BLKW 2
+        .END
+";
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        let message = Json::parse(&format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen",
+                "params":{{"textDocument":{{"uri":"file:///a.asm","text":{}}}}}}}"#,
+            Json::from(text)
+        ))
+        .unwrap();
+        server.handle(&message).remove(0)
+    }
+
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Json {
+        let message = Json::parse(&format!(
+            r#"{{"jsonrpc":"2.0","id":7,"method":"{}","params":{{
+                "textDocument":{{"uri":"file:///a.asm"}},
+                "position":{{"line":{},"character":{}}}}}}}"#,
+            method, line, character
+        ))
+        .unwrap();
+        let mut replies = server.handle(&message);
+        assert_eq!(Some(7), replies[0].get("id").and_then(Json::as_u64));
+        replies.remove(0)
+    }
+
+    #[test]
+    fn diagnostics_come_from_the_assembler() {
+        let mut server = Server::default();
+        let published = open(&mut server, SOURCE);
+        assert_eq!(
+            r#"[{"range":{"start":{"line":6,"character":0},"end":{"line":6,"character":6}},"severity":2,"source":"lc3","message":"label UNUSED is never used"}]"#,
+            published
+                .at(&["params", "diagnostics"])
+                .unwrap()
+                .to_string()
+        );
+
+        let published = open(&mut server, &SOURCE.replace("#-1", "#99"));
+        let diagnostics = published.at(&["params", "diagnostics"]).unwrap();
+        let error = &diagnostics.as_array().unwrap()[0];
+        assert_eq!(Some(1), error.get("severity").and_then(Json::as_u64));
+        assert_eq!(
+            Some(1),
+            error.at(&["range", "start", "line"]).and_then(Json::as_u64)
+        );
+        assert!(error
+            .get("message")
+            .and_then(Json::as_str)
+            .unwrap()
+            .contains("does not fit"));
+    }
+
+    #[test]
+    fn labels_are_defined_hovered_and_listed() {
+        let mut server = Server::default();
+        open(&mut server, SOURCE);
+
+        /* on COUNT in LD R2, COUNT */
+        let found = request(&mut server, "textDocument/definition", 3, 17);
+        assert_eq!(
+            r#"{"start":{"line":5,"character":0},"end":{"line":5,"character":5}}"#,
+            found.at(&["result", "range"]).unwrap().to_string()
+        );
+        assert_eq!(
+            Json::Null,
+            *request(&mut server, "textDocument/definition", 1, 13)
+                .get("result")
+                .unwrap()
+        );
+
+        let hover = |server: &mut Server, line, character| {
+            let reply = request(server, "textDocument/hover", line, character);
+            let value = reply.at(&["result", "contents", "value"]);
+            value.and_then(Json::as_str).unwrap_or("").to_string()
+        };
+        assert!(hover(&mut server, 1, 9).starts_with("ADD\n\n# Assembler formats"));
+        assert!(hover(&mut server, 2, 8).ends_with("# Flags\n\nunchanged"));
+        assert_eq!("COUNT x3004", hover(&mut server, 3, 15));
+
+        let symbols = request(&mut server, "textDocument/documentSymbol", 0, 0);
+        let listed: Vec<String> = (symbols
+            .get("result")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter())
+        .map(|s| {
+            let field = |key| s.get(key).unwrap().to_string();
+            format!("{} {} {}", field("name"), field("detail"), field("kind"))
+        })
+        .collect();
+        assert_eq!(
+            vec![
+                r#""LOOP" "x3000" 12"#,
+                r#""COUNT" "x3004" 13"#,
+                r#""UNUSED" "x3005" 13"#
+            ],
+            listed
+        );
+
+        let unknown = request(&mut server, "textDocument/rename", 0, 0);
+        assert_eq!(
+            Some(-32601.0),
+            unknown.at(&["error", "code"]).and_then(|code| match code {
+                Json::Number(n) => Some(*n),
+                _ => None,
+            })
+        );
+    }
+
+    #[test]
+    fn labels_are_found_when_assembly_fails() {
+        assert_eq!(
+            vec![Label {
+                name: "A",
+                line: 1,
+                column: 2,
+                data: true
+            }],
+            labels("  ; no origin\n  A: .FILL #1 ; one\n")
+        );
+    }
+}
@@ -1,81 +1,827 @@
+use lc3vm::{
+    analyze, asm, asmfmt,
+    branch::BranchStats,
+    breakpoints::{Breakpoint, Span},
+    bundle::Bundle,
+    cfi::FlowCheck,
+    checkpoint::Checkpoints,
+    cli::{
+        self, AsmOptions, BundleOptions, CodecOptions, Command, DebugOptions, DiffOptions,
+        FmtOptions, GradeOptions, InputSource,
+    },
+    color::{self, ColorChoice},
+    config::Config,
+    dap,
+    debugger::{DebugResponse, DebuggerCore, CHECKPOINTS, COMMANDS},
+    defs::{OP, R, TRAP},
+    diffrun, disasm, dump,
+    env::Environment,
+    explore,
+    grade::{self, Spec},
+    history::History,
+    isa,
+    lineedit::{self, Completer, Editor},
+    loader::{guard_words, read_image_file, read_vector_file, unhandled_traps, write_args},
+    loopcheck::LoopDetector,
+    lsp,
+    map::{self, Region},
+    meta::Metadata,
+    opstats::OpcodeStats,
+    pipeline::{self, Recorder},
+    playground,
+    policy::Policy,
+    sched, selftest,
+    snapshot::{self, Snapshot},
+    state::State,
+    stats, status,
+    terminal::InputBuffering,
+    timeline::Timeline,
+    tracefile::{Trace, TraceWriter, Viewer, VIEWER_COMMANDS},
+};
 use std::{
-    fs::File,
-    io::{self, Read},
+    fs::{self, File},
+    io::{self, IsTerminal},
+    os::unix::io::FromRawFd,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use defs::{OP, R};
-use state::State;
-use terminal::InputBuffering;
-
-mod defs;
-mod instr;
-mod state;
-mod terminal;
-
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
         /* show usage string */
-        println!("lc3 [image-file1] ...");
+        println!("{}", cli::USAGE);
         return;
     }
 
-    let mut state = State::new();
-    for image in args {
-        if let Err(e) = read_image_file(&image, &mut state) {
-            println!("failed to load image: {}", e);
+    let options = match cli::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let utility = match &options.command {
+        Command::Isa(mnemonic) => Some(isa::print(mnemonic.as_deref())),
+        Command::Encode(opts) => Some(encode(opts)),
+        Command::Decode(opts) => Some(decode(opts, options.color)),
+        Command::Flags(values) => Some(playground::run(values)),
+        Command::Asm(opts) => Some(assemble(opts)),
+        Command::Fmt(opts) => Some(format_sources(opts, &options.images)),
+        Command::Lsp => Some(
+            lsp::serve(&mut io::stdin().lock(), &mut io::stdout().lock())
+                .map_err(|e| e.to_string()),
+        ),
+        Command::Bundle(opts) => Some(bundle(opts, &options.images)),
+        Command::SnapshotDiff => Some(snapshot_diff(&options.images)),
+        Command::TraceView => Some(trace_view(&options.images[0])),
+        Command::Selftest => Some(selftest::run()),
+        Command::Grade(opts) => Some(grade(opts)),
+        Command::Help => {
+            println!("{}\n\n{}", cli::USAGE, cli::NOTES);
+            Some(Ok(()))
+        }
+        _ => None,
+    };
+    if let Some(result) = utility {
+        if let Err(e) = result {
+            println!("{}", e);
+            std::process::exit(1);
         }
+        return;
     }
 
-    // Disable input buffering.
+    let mut config = match &options.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("failed to load config: {}", e);
+                return;
+            }
+        },
+        None => Config::default(),
+    };
+    options.apply(&mut config);
+
+    if let Command::Dap = &options.command {
+        let mut output = io::stdout().lock();
+        if let Err(e) = dap::serve(io::BufReader::new(io::stdin()), &mut output, config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::DiffRun(opts) = &options.command {
+        match diff_run(opts, &options, &config) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut state = State::with_config(&config);
+    if let Some(path) = &options.vectors {
+        match read_vector_file(path, &mut state, options.verify) {
+            Ok(image) => eprintln!(
+                "loaded {} vector words at x{:04X} from {}",
+                image.words.len(),
+                image.origin,
+                path
+            ),
+            Err(e) => {
+                println!("failed to load vectors {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut loaded = Vec::new();
+    let mut sources = Vec::new();
+    let mut built = Vec::new(); /* metadata of each image, with the file it came from */
+    for path in &options.images {
+        let (images, start) = match read_image_file(path, &mut state, options.verify) {
+            Ok(read) => read,
+            Err(e) => {
+                println!("failed to load image {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        for (i, image) in images.iter().enumerate() {
+            let (origin, length) = (image.origin, image.words.len());
+            let source = match start {
+                Some(_) => format!("{} (image {} of {})", path, i + 1, images.len()),
+                None => path.clone(),
+            };
+            match &image.metadata {
+                Some(metadata) => {
+                    eprintln!(
+                        "loaded {} words at x{:04X} from {} (built by {})",
+                        length, origin, source, metadata.version
+                    );
+                    state.symbols.extend(metadata.symbols.clone());
+                    built.push((path.clone(), metadata.clone()));
+                }
+                None => eprintln!("loaded {} words at x{:04X} from {}", length, origin, source),
+            }
+            loaded.push((origin, length));
+            sources.push(source);
+        }
+        if let Some(start) = start {
+            state.reg[R::PC] = start;
+        }
+    }
+
+    if options.map {
+        let regions: Vec<Region> = sources
+            .iter()
+            .zip(&loaded)
+            .map(|(name, &(origin, length))| Region {
+                name,
+                origin,
+                length,
+            })
+            .collect();
+        eprint!(
+            "{}",
+            map::draw(&regions, &state.mem.devices(), state.mem.size())
+        );
+    }
+
+    if options.guard_images {
+        for address in guard_words(&loaded) {
+            state.mem.guard(address);
+        }
+    }
+
+    if options.strict_vectors {
+        let unhandled = unhandled_traps(&state, &loaded);
+        for (address, vector) in &unhandled {
+            println!("no vector for TRAP x{:02X} at x{:04X}", vector, address);
+        }
+        if !unhandled.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    let expected = options.assert_state.as_ref().map(|path| {
+        match fs::read(path).and_then(|bytes| Snapshot::parse(&bytes)) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                println!("failed to read snapshot {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let mut policy = match &options.policy {
+        Some(path) => match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Policy::parse(&text))
+        {
+            Ok(policy) => policy,
+            Err(e) => {
+                println!("failed to read policy {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Policy::default(),
+    };
+    policy.required.extend(&options.required);
+    policy.forbidden.extend(&options.forbidden);
+    if !policy.is_empty() {
+        let words =
+            loaded
+                .iter()
+                .filter(|&&(_, length)| length > 0)
+                .flat_map(|&(origin, length)| {
+                    state
+                        .mem
+                        .iter(origin..=origin.wrapping_add(length as u16 - 1))
+                });
+        let violations = policy.check_image(words);
+        for violation in &violations {
+            println!("policy: {}", violation);
+        }
+        if !violations.is_empty() {
+            std::process::exit(1);
+        }
+        state.policy = Some(policy);
+    }
+
+    if let Some(address) = options.args_at {
+        if let Err(e) = write_args(&mut state, address, &options.args, options.pack_args) {
+            println!("failed to write arguments: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if options.env_block {
+        let env = Environment {
+            seed: options
+                .seed
+                .unwrap_or_else(|| if config.deterministic { 0 } else { time_seed() }),
+            max_instructions: config.max_instructions,
+            origins: loaded.iter().map(|&(origin, _)| origin).collect(),
+        };
+        if let Err(e) = env.write(&mut state) {
+            println!("failed to write the environment block: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    match &options.command {
+        Command::Run => {}
+        Command::Debug(opts) => {
+            debug(state, opts, &options, &built);
+            return;
+        }
+        Command::Analyze => {
+            let warnings = analyze::analyze(&state.mem, &loaded, state.reg.pc());
+            for warning in &warnings {
+                println!("{}", warning);
+            }
+            if warnings.is_empty() {
+                println!("no problems found");
+            }
+            return;
+        }
+        Command::Explore(opts) => {
+            let outcomes = explore::explore(state, opts);
+            explore::report(&outcomes, opts);
+            return;
+        }
+        Command::Isa(_)
+        | Command::Encode(_)
+        | Command::Decode(_)
+        | Command::Flags(_)
+        | Command::Asm(_)
+        | Command::Fmt(_)
+        | Command::Dap
+        | Command::Lsp
+        | Command::Bundle(_)
+        | Command::DiffRun(_)
+        | Command::SnapshotDiff
+        | Command::TraceView
+        | Command::Selftest
+        | Command::Grade(_)
+        | Command::Help => {
+            unreachable!("handled before loading")
+        }
+        Command::Dump(opts) => {
+            if let Some(start) = opts.start {
+                let length = opts.length.unwrap_or(opts.stride * 16);
+                dump::dump(&state.mem, start, length, opts.stride);
+            } else {
+                for (origin, length) in loaded {
+                    dump::dump(
+                        &state.mem,
+                        origin,
+                        opts.length.unwrap_or(length),
+                        opts.stride,
+                    );
+                }
+            }
+            return;
+        }
+    }
+
+    if let Some(source) = &options.input {
+        match open_input(source) {
+            Ok(file) => state.mem.console.attach(file),
+            Err(e) => {
+                println!("failed to open console input: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Disable input buffering if the input is a terminal.
     // Restore buffering on drop.
-    let _ = InputBuffering::disable();
-
-    while state.running {
-        let instr = state.mem.read(state.reg[R::PC]);
-        state.reg[R::PC] = state.reg[R::PC].wrapping_add(1);
-
-        let op = instr >> 12;
-        match OP::try_from(op).expect("unknown opcode") {
-            OP::BR => instr::do_br(instr, &mut state),
-            OP::ADD => instr::do_add(instr, &mut state),
-            OP::LD => instr::do_ld(instr, &mut state),
-            OP::ST => instr::do_st(instr, &mut state),
-            OP::JSR => instr::do_jsr(instr, &mut state),
-            OP::AND => instr::do_and(instr, &mut state),
-            OP::LDR => instr::do_ldr(instr, &mut state),
-            OP::STR => instr::do_str(instr, &mut state),
-            OP::RTI => return, // not simulated // TODO
-            OP::NOT => instr::do_not(instr, &mut state),
-            OP::LDI => instr::do_ldi(instr, &mut state),
-            OP::STI => instr::do_sti(instr, &mut state),
-            OP::JMP => instr::do_jmp(instr, &mut state),
-            OP::RES => return,
-            OP::LEA => instr::do_lea(instr, &mut state),
-            OP::TRAP => instr::do_trap(instr, &mut state),
+    let _buffering = state.mem.console.with_input_fd(InputBuffering::disable);
+
+    for &breakpoint in &options.breakpoints {
+        state.add_breakpoint(breakpoint);
+    }
+    for &watchpoint in &options.watchpoints {
+        state.add_watchpoint(watchpoint);
+    }
+    let spans = |texts: &[String]| -> Vec<(u16, u16)> {
+        texts
+            .iter()
+            .map(|text| match Span::parse(text, &state.symbols) {
+                Ok(span) => span.resolve(&state.mem),
+                Err(e) => {
+                    println!("{}", e);
+                    std::process::exit(1);
+                }
+            })
+            .collect()
+    };
+    let ranges = spans(&options.break_ranges);
+    state.trace_only = spans(&options.trace_only);
+    for (start, end) in ranges {
+        state.add_breakpoint(Breakpoint::Range(start, end));
+    }
+    state.trace = options.trace;
+    state.color = options.color.enabled(io::stderr().is_terminal());
+    state.trace_traps = options.trace_traps;
+    if options.stats {
+        state.stats.branches = Some(BranchStats::default());
+    }
+    if options.opcode_stats.is_some() {
+        state.stats.opcodes = Some(OpcodeStats::default());
+    }
+    state.pc_checks = options.pc_checks;
+    if options.cfi {
+        state.cfi = Some(FlowCheck::new(&state.symbols));
+    }
+    for &(check, severity) in &options.checks {
+        state.diagnostics.set(check, severity);
+    }
+    if let Some(capacity) = options.history {
+        state.history = History::new(capacity);
+    }
+    if options.detect_loops {
+        state.loops = Some(LoopDetector::default());
+    }
+    if let Some((first, count)) = options.pipeline {
+        state.pipeline = Some(Recorder::new(first, count));
+    }
+    let mut checkpoints = options
+        .checkpoint_interval
+        .map(|interval| Checkpoints::new(interval, options.rollback));
+
+    let mut record = |state: &State| {
+        if let Some(checkpoints) = &mut checkpoints {
+            checkpoints.record(state);
+        }
+    };
+
+    if options.record.is_some() {
+        state.recording = Some(TraceWriter::new(&state));
+    }
+    if options.perfetto.is_some() {
+        state.timeline = Some(Timeline::new(&state.symbols));
+    }
+    let start = Instant::now();
+    if options.start_all {
+        let origins: Vec<u16> = loaded.iter().map(|&(origin, _)| origin).collect();
+        sched::run_all(&mut state, &origins, options.quantum, &mut record);
+    } else {
+        // a panic in the simulator is reported with the instructions that led
+        // to it before it goes on unwinding
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            while state.running {
+                state.step_once();
+                record(&state);
+            }
+        }));
+        if let Err(panic) = run {
+            eprintln!("recent instructions:\n{}", state.history.listing());
+            panic::resume_unwind(panic);
+        }
+    }
+
+    if let Some(hit) = state.hit {
+        eprintln!("{}", hit);
+        eprintln!("{}", status::status(&state));
+    } else if options.print_state {
+        eprintln!("{}", status::status(&state));
+    }
+    if options.stats {
+        stats::report(&state, start.elapsed());
+    }
+    if let Some(recorder) = &state.pipeline {
+        eprint!("{}", pipeline::diagram(&recorder.entries));
+        if let Some(path) = &options.pipeline_csv {
+            if let Err(e) = fs::write(path, pipeline::csv(&recorder.entries)) {
+                eprintln!("failed to write {}: {}", path, e);
+            }
+        }
+    }
+    if let (Some(path), Some(opcodes)) = (&options.opcode_stats, &state.stats.opcodes) {
+        if let Err(e) = fs::write(path, opcodes.csv()) {
+            eprintln!("failed to write {}: {}", path, e);
+        }
+    }
+    if let Some(path) = &options.snapshot {
+        if let Err(e) = fs::write(path, Snapshot::take(&state).to_bytes()) {
+            eprintln!("failed to write snapshot {}: {}", path, e);
+        }
+    }
+
+    if let (Some(path), Some(recording)) = (&options.record, &state.recording) {
+        if let Err(e) = fs::write(path, recording.to_bytes()) {
+            eprintln!("failed to write trace {}: {}", path, e);
+        }
+    }
+    if let (Some(path), Some(timeline)) = (&options.perfetto, &state.timeline) {
+        if let Err(e) = fs::write(path, timeline.json(state.stats.instructions)) {
+            eprintln!("failed to write timeline {}: {}", path, e);
+        }
+    }
+
+    if let Some(summary) = state.diagnostics.summary() {
+        eprintln!("{}", summary);
+    }
+    if let Some(policy) = &state.policy {
+        let missing = policy.missing();
+        for name in &missing {
+            eprintln!("policy: required {} was never executed", name);
+        }
+        if !missing.is_empty() && state.error.is_none() {
+            std::process::exit(1);
+        }
+    }
+    if let Some(expected) = &expected {
+        if let Err(e) = snapshot::assert_state(expected, &state, options.regs_only) {
+            eprintln!("{}", e);
+            if state.error.is_none() {
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(e) = state.error {
+        eprintln!("error: {}", e);
+        if !state.history.is_empty() {
+            eprintln!("recent instructions:\n{}", state.history.listing());
+        }
+        let rollback = checkpoints
+            .as_ref()
+            .and_then(|c| c.rollback(options.rollback));
+        if let Some(checkpoint) = rollback {
+            eprintln!(
+                "rolled back to the checkpoint at instruction {}",
+                checkpoint.stats.instructions
+            );
+            eprintln!("{}", status::status(checkpoint));
+        }
+        std::process::exit(1);
+    }
+
+    if let Some(status) = state.exit_status {
+        eprintln!("exit status {}", status);
+        if options.exit_code {
+            /* the host only keeps the low byte */
+            std::process::exit((status & 0xFF) as i32);
         }
     }
 }
 
-fn read_image_file(path: &String, state: &mut State) -> io::Result<()> {
-    let mut file = File::open(path)?;
-    let mut buffer = [0u8; std::mem::size_of::<u16>()];
+fn open_input(source: &InputSource) -> io::Result<File> {
+    match source {
+        InputSource::Fd(fd) => {
+            fs::metadata(format!("/dev/fd/{}", fd))
+                .map_err(|e| io::Error::new(e.kind(), format!("descriptor {}: {}", fd, e)))?;
+            // SAFETY: the descriptor was handed to us by the parent for this
+            // purpose and nothing else in the process uses it.
+            Ok(unsafe { File::from_raw_fd(*fd) })
+        }
+        InputSource::Pipe(path) => {
+            File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+        }
+    }
+}
 
-    /* the origin tells us where in memory to place the image */
-    file.read_exact(&mut buffer)?;
-    let origin = swap16(u16::from_ne_bytes(buffer));
+// Reads debugger commands from stdin until quit or end of input. The program
+// only gets console input when it comes from --stdin-fd or --console-pipe,
+// since stdin carries the commands.
+fn debug(
+    mut state: State,
+    opts: &DebugOptions,
+    options: &cli::Options,
+    built: &[(String, Metadata)],
+) {
+    match options.input.as_ref().map(open_input) {
+        Some(Ok(file)) => state.mem.console.attach(file),
+        Some(Err(e)) => {
+            println!("failed to open console input: {}", e);
+            std::process::exit(1);
+        }
+        None => state.mem.console.feed(b""),
+    }
+
+    let mut arguments: Vec<String> = R::ALL.iter().map(|r| r.name().to_string()).collect();
+    arguments.extend(["op", "trap", "range", "on", "off", "--only", "--all"].map(String::from));
+    arguments.extend(
+        (0..16)
+            .filter_map(|op| OP::try_from(op).ok())
+            .map(|op| op.name().to_string()),
+    );
+    arguments.extend(
+        (0x20..=0x28)
+            .filter_map(|v| TRAP::try_from(v).ok())
+            .map(|t| t.name().to_string()),
+    );
+    arguments.extend(state.symbols.keys().cloned());
+    let mut editor = Editor::new(Completer {
+        commands: COMMANDS.map(String::from).to_vec(),
+        arguments,
+    });
+
+    let color = options.color.enabled(io::stdout().is_terminal());
+    let mut core = DebuggerCore::new(state);
+    if let Some(interval) = options.checkpoint_interval {
+        core.checkpoints = Checkpoints::new(interval, options.rollback.max(CHECKPOINTS));
+    }
+    for (image, metadata) in built.iter().filter(|(_, m)| !m.lines.is_empty()) {
+        if !core.sources.find(image, metadata, &opts.sources) {
+            eprintln!("no source matches {}, stops show no source lines", image);
+        }
+    }
+    let home_init = std::env::var_os("HOME").map(|home| Path::new(&home).join(".lc3init"));
+    let init = match &opts.init {
+        Some(path) => Some(PathBuf::from(path)),
+        None => home_init.filter(|path| path.exists()),
+    };
+    if let Some(path) = init {
+        let script = match fs::read_to_string(&path) {
+            Ok(script) => script,
+            Err(e) => {
+                println!("failed to read {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        for (number, line) in script.lines().enumerate() {
+            for response in core.execute_line(line) {
+                match response {
+                    DebugResponse::Error(e) => {
+                        println!("{}:{}: {}", path.display(), number + 1, e)
+                    }
+                    response => print_response(&response, color),
+                }
+            }
+        }
+    }
+
+    loop {
+        editor.completer.commands = COMMANDS.map(String::from).to_vec();
+        editor.completer.commands.extend(core.macros.names());
+        let prompt = if core.macros.is_defining() {
+            "> "
+        } else {
+            "(lc3) "
+        };
+        let line = match lineedit::read_line(&mut editor, prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                println!("failed to read a command: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let responses = core.execute_line(&line);
+        for response in &responses {
+            print_response(response, color);
+        }
+        if responses.last() == Some(&DebugResponse::Quit) {
+            break;
+        }
+    }
+}
 
-    /* read the rest of the file */
-    let mut address = origin;
-    while let Ok(_) = file.read_exact(&mut buffer) {
-        let read = swap16(u16::from_ne_bytes(buffer));
-        state.mem.write(address, read);
-        address = address.wrapping_add(1);
+fn print_response(response: &DebugResponse, color: bool) {
+    let text = match color {
+        true => format!("{:#}", response),
+        false => response.to_string(),
+    };
+    if !text.is_empty() {
+        println!("{}", text);
     }
+}
 
+fn time_seed() -> u16 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.subsec_nanos() ^ now.as_secs() as u32) as u16
+}
+
+fn encode(opts: &CodecOptions) -> Result<(), String> {
+    let word = asm::encode(opts.input.as_deref().unwrap_or_default(), opts.at)?;
+    println!("0x{:04X}", word);
     Ok(())
 }
 
-fn swap16(x: u16) -> u16 {
-    x << 8 | x >> 8
+fn decode(opts: &CodecOptions, color: ColorChoice) -> Result<(), String> {
+    let text = opts.input.as_deref().unwrap_or_default();
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .unwrap_or(text);
+    let word = u16::from_str_radix(digits, 16)
+        .map_err(|_| format!("expected a hex word, got {}", text))?;
+    let text = disasm::disassemble(opts.at, word);
+    match color.enabled(io::stdout().is_terminal()) {
+        true => println!("{}", color::instruction(&text)),
+        false => println!("{}", text),
+    }
+    Ok(())
+}
+
+fn assemble(opts: &AsmOptions) -> Result<(), String> {
+    let source = opts.source.as_deref().unwrap_or_default();
+    let text = fs::read_to_string(source).map_err(|e| format!("{}: {}", source, e))?;
+    let program =
+        asm::assemble_with(&text, !opts.strict).map_err(|e| format!("{}: {}", source, e))?;
+    for warning in &program.warnings {
+        eprintln!("{}: warning: {}", source, warning);
+    }
+
+    let output = opts.output.clone().unwrap_or_else(|| {
+        let stem = source.strip_suffix(".asm").unwrap_or(source);
+        format!("{}.obj", stem)
+    });
+    let metadata = opts.metadata.then(|| program.metadata(&text));
+    let mut outputs = vec![(output, program.object(metadata.as_ref()))];
+    if let Some(path) = &opts.listing {
+        outputs.push((path.clone(), program.listing().into_bytes()));
+    }
+    if let Some(path) = &opts.symbols {
+        outputs.push((path.clone(), program.symbol_table().into_bytes()));
+    }
+
+    for (path, bytes) in outputs {
+        fs::write(&path, &bytes).map_err(|e| format!("{}: {}", path, e))?;
+        if opts.hash {
+            println!("{:016x}  {}", asm::hash(&bytes), path);
+        }
+    }
+    Ok(())
+}
+
+fn grade(opts: &GradeOptions) -> Result<(), String> {
+    let source = opts.source.as_deref().unwrap_or_default();
+    let text = fs::read_to_string(source).map_err(|e| format!("{}: {}", source, e))?;
+    let path = opts.spec.as_deref().unwrap_or_default();
+    let spec = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let spec = Spec::parse(&spec).map_err(|e| format!("{}: {}", path, e))?;
+
+    let card = grade::grade(source, &text, &spec);
+    match &opts.report {
+        Some(report) => {
+            let html = report.ends_with(".html");
+            let text = if html { card.html() } else { card.markdown() };
+            fs::write(report, text).map_err(|e| format!("{}: {}", report, e))?;
+            let (earned, total) = card.score();
+            println!("{}: {} of {} points", source, earned, total);
+        }
+        None => print!("{}", card.markdown()),
+    }
+    Ok(())
+}
+
+// Rewrites each source laid out in columns, or with --check only names the
+// ones that would change and fails if there are any.
+fn format_sources(opts: &FmtOptions, sources: &[String]) -> Result<(), String> {
+    let mut unformatted = 0;
+    for path in sources {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let formatted = asmfmt::format(&source, &opts.style);
+        if formatted == source {
+            continue;
+        }
+        match opts.check {
+            true => {
+                println!("{} is not formatted", path);
+                unformatted += 1;
+            }
+            false => fs::write(path, formatted).map_err(|e| format!("{}: {}", path, e))?,
+        }
+    }
+    match unformatted {
+        0 => Ok(()),
+        1 => Err(String::from("1 file would be reformatted")),
+        n => Err(format!("{} files would be reformatted", n)),
+    }
+}
+
+fn bundle(opts: &BundleOptions, images: &[String]) -> Result<(), String> {
+    let files = images
+        .iter()
+        .map(|path| fs::read(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let bundle = Bundle::new(opts.start, files).map_err(|e| e.to_string())?;
+
+    let output = opts.output.as_deref().unwrap_or("bundle.lc3");
+    fs::write(output, bundle.to_bytes()).map_err(|e| format!("{}: {}", output, e))
+}
+
+// Returns whether the two programs stayed in step.
+fn diff_run(opts: &DiffOptions, options: &cli::Options, config: &Config) -> Result<bool, String> {
+    let images = &options.images;
+    let input = match &opts.input_file {
+        Some(path) => fs::read(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => opts.input.clone(),
+    };
+
+    let mut machines = Vec::new();
+    for path in images {
+        let mut state = State::with_config(config);
+        let (_, start) = read_image_file(path, &mut state, options.verify)
+            .map_err(|e| format!("failed to load image {}: {}", path, e))?;
+        if let Some(start) = start {
+            state.reg[R::PC] = start;
+        }
+        state.mem.console.feed(&input);
+        machines.push(state);
+    }
+
+    let [a, b] = machines.as_mut_slice() else {
+        unreachable!("the command line checks for two images")
+    };
+    let outcome = diffrun::compare(a, b, opts.steps);
+    diffrun::report(&outcome, [&images[0], &images[1]]);
+    Ok(!matches!(outcome, diffrun::Outcome::Diverged { .. }))
+}
+
+fn trace_view(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let trace = Trace::parse(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+    let mut viewer = Viewer::new(trace);
+    let mut editor = Editor::new(Completer {
+        commands: VIEWER_COMMANDS.map(String::from).to_vec(),
+        arguments: Vec::new(),
+    });
+
+    println!("{}", viewer.describe());
+    loop {
+        let line = match lineedit::read_line(&mut editor, "(trace) ") {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(format!("failed to read a command: {}", e)),
+        };
+        match viewer.execute_line(&line) {
+            Some(text) if text.is_empty() => {}
+            Some(text) => println!("{}", text),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn snapshot_diff(paths: &[String]) -> Result<(), String> {
+    let snapshots = paths
+        .iter()
+        .map(|path| {
+            fs::read(path)
+                .and_then(|bytes| Snapshot::parse(&bytes))
+                .map_err(|e| format!("{}: {}", path, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for line in snapshot::diff(&snapshots[0], &snapshots[1]) {
+        println!("{}", line);
+    }
+    Ok(())
 }
@@ -0,0 +1,110 @@
+// Memory map
+//
+// Draws the 64K address space as one row per 2K block, each character covering
+// 32 words, so students can see where their images landed relative to the
+// vector tables, the device registers and each other. Images are numbered in
+// load order; a character where two images claim the same word is drawn as
+// '!', as is one where an image covers a device register.
+
+use std::fmt::Write;
+
+use crate::{loader::VECTOR_TABLES_END, state::MEMORY_MAX};
+
+const BLOCK: usize = 2048;
+const CELL: usize = 32;
+const MARKS: &[u8] = b"123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+// A loaded image: where it came from, its origin and its length in words.
+pub struct Region<'a> {
+    pub name: &'a str,
+    pub origin: u16,
+    pub length: usize,
+}
+
+// `size` is the number of words present, `devices` the device register addresses.
+pub fn draw(images: &[Region], devices: &[u16], size: usize) -> String {
+    let mut owners = vec![0u8; MEMORY_MAX]; /* image number plus one, or 0xFF if shared */
+    for (i, image) in images.iter().enumerate() {
+        let start = image.origin as usize;
+        for owner in &mut owners[start..(start + image.length).min(MEMORY_MAX)] {
+            *owner = if *owner == 0 { i as u8 + 1 } else { 0xFF };
+        }
+    }
+
+    let cell = |start: usize| -> char {
+        let words = start..start + CELL;
+        let owner = owners[words.clone()].iter().copied().find(|&o| o != 0);
+        let shared = owners[words.clone()].contains(&0xFF);
+        let device = devices.iter().any(|&d| words.contains(&(d as usize)));
+        match owner {
+            Some(_) if shared || device => '!',
+            Some(o) => MARKS.get(o as usize - 1).map_or('#', |&m| m as char),
+            None if device => 'D',
+            None if start <= VECTOR_TABLES_END as usize => 'v',
+            None if start >= size => ' ',
+            None => '.',
+        }
+    };
+
+    let mut out = String::new();
+    for block in (0..MEMORY_MAX).step_by(BLOCK) {
+        let row: String = (block..block + BLOCK).step_by(CELL).map(cell).collect();
+        let _ = writeln!(out, "x{:04X}-x{:04X} |{}|", block, block + BLOCK - 1, row);
+    }
+    for (image, mark) in images.iter().zip(MARKS) {
+        let _ = writeln!(
+            out,
+            "{} = {} x{:04X}-x{:04X} ({} words)",
+            *mark as char,
+            image.name,
+            image.origin,
+            (image.origin as usize + image.length).saturating_sub(1),
+            image.length
+        );
+    }
+    out.push_str("v = vector tables, D = device registers, . = free");
+    if size < MEMORY_MAX {
+        out.push_str(", blank = no memory");
+    }
+    if owners.contains(&0xFF) {
+        out.push_str(", ! = conflict");
+    }
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{draw, Region};
+
+    #[test]
+    fn images_vectors_and_devices_are_drawn() {
+        let images = [
+            Region {
+                name: "a.obj",
+                origin: 0x3000,
+                length: 0x40,
+            },
+            Region {
+                name: "b.obj",
+                origin: 0x3020,
+                length: 0x800,
+            },
+        ];
+        let map = draw(&images, &[0xFE00], 0x10000);
+        let rows: Vec<&str> = map.lines().collect();
+
+        assert_eq!(35, rows.len());
+        assert_eq!(
+            format!("x0000-x07FF |{}{}|", "v".repeat(16), ".".repeat(48)),
+            rows[0]
+        );
+        assert!(rows[6].starts_with("x3000-x37FF |1!2222"));
+        assert_eq!(
+            format!("xF800-xFFFF |{}D{}|", ".".repeat(48), ".".repeat(15)),
+            rows[31]
+        );
+        assert_eq!("1 = a.obj x3000-x303F (64 words)", rows[32]);
+        assert!(map.ends_with("! = conflict\n"));
+    }
+}
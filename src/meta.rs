@@ -0,0 +1,184 @@
+// Object metadata
+//
+// The assembler can append a metadata block to an image, after the data
+// words and before any checksum record:
+//
+// [tag, length, payload...]...  META_MAGIC  total length of the records
+//
+// Tags:
+// 1  assembler version, one character per word
+// 2  source hash, four words, most significant first
+// 3  symbols, repeated [address, name length, name characters...]
+// 4  line table, repeated [address, source line]
+//
+// Unknown tags are skipped. A trailer that does not parse exactly is taken
+// to be program data, so images without metadata load as before.
+
+use crate::asm::Symbols;
+
+pub const META_MAGIC: u16 = 0x4D44; /* "MD" */
+
+const VERSION: u16 = 1;
+const SOURCE_HASH: u16 = 2;
+const SYMBOLS: u16 = 3;
+const LINES: u16 = 4;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub version: String,
+    pub source_hash: u64,
+    pub symbols: Symbols,
+    pub lines: Vec<(u16, u16)>, /* address and source line of each statement */
+}
+
+impl Metadata {
+    pub fn to_words(&self) -> Vec<u16> {
+        let mut records = Vec::new();
+        let mut record = |tag: u16, payload: Vec<u16>| {
+            records.push(tag);
+            records.push(payload.len() as u16);
+            records.extend(payload);
+        };
+
+        record(VERSION, self.version.bytes().map(u16::from).collect());
+        record(
+            SOURCE_HASH,
+            (0..4)
+                .rev()
+                .map(|i| (self.source_hash >> (i * 16)) as u16)
+                .collect(),
+        );
+        let mut symbols = Vec::new();
+        for (name, &address) in &self.symbols {
+            symbols.push(address);
+            symbols.push(name.len() as u16);
+            symbols.extend(name.bytes().map(u16::from));
+        }
+        record(SYMBOLS, symbols);
+        record(
+            LINES,
+            self.lines
+                .iter()
+                .flat_map(|&(address, line)| [address, line])
+                .collect(),
+        );
+
+        let length = records.len() as u16;
+        records.push(META_MAGIC);
+        records.push(length);
+        records
+    }
+
+    // Removes a metadata block from the end of `words`, if there is one.
+    pub fn strip(words: &mut Vec<u16>) -> Option<Self> {
+        let (&length, rest) = words.split_last()?;
+        let (&magic, rest) = rest.split_last()?;
+        let start = rest.len().checked_sub(length as usize)?;
+        if magic != META_MAGIC {
+            return None;
+        }
+
+        let metadata = Self::parse(&rest[start..])?;
+        words.truncate(start);
+        Some(metadata)
+    }
+
+    fn parse(mut records: &[u16]) -> Option<Self> {
+        let mut metadata = Metadata::default();
+        while let [tag, length, rest @ ..] = records {
+            let (payload, next) = rest.split_at_checked(*length as usize)?;
+            match *tag {
+                VERSION => metadata.version = text(payload)?,
+                SOURCE_HASH if payload.len() == 4 => {
+                    metadata.source_hash = payload.iter().fold(0, |hash, &w| hash << 16 | w as u64)
+                }
+                SYMBOLS => {
+                    let mut payload = payload;
+                    while let [address, length, rest @ ..] = payload {
+                        let (name, next) = rest.split_at_checked(*length as usize)?;
+                        metadata.symbols.insert(text(name)?, *address);
+                        payload = next;
+                    }
+                    if !payload.is_empty() {
+                        return None;
+                    }
+                }
+                LINES if payload.len().is_multiple_of(2) => {
+                    metadata.lines = payload.chunks(2).map(|p| (p[0], p[1])).collect()
+                }
+                SOURCE_HASH | LINES => return None,
+                _ => {}
+            }
+            records = next;
+        }
+        records.is_empty().then_some(metadata)
+    }
+
+    // Names `address` after the nearest label at or before it, e.g. LOOP+2.
+    pub fn symbolize(symbols: &Symbols, address: u16) -> Option<String> {
+        let (name, &base) = symbols
+            .iter()
+            .filter(|(_, &a)| a <= address)
+            .max_by_key(|(_, &a)| a)?;
+        Some(match address - base {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+}
+
+fn text(words: &[u16]) -> Option<String> {
+    words
+        .iter()
+        .map(|&w| u8::try_from(w).ok().filter(u8::is_ascii).map(char::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::meta::{Metadata, META_MAGIC};
+
+    fn sample() -> Metadata {
+        Metadata {
+            version: String::from("0.1.0"),
+            source_hash: 0x0123_4567_89AB_CDEF,
+            symbols: [
+                (String::from("LOOP"), 0x3001),
+                (String::from("MSG"), 0x3005),
+            ]
+            .into_iter()
+            .collect(),
+            lines: vec![(0x3000, 2), (0x3001, 3)],
+        }
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut words = vec![0xF025];
+        words.extend(sample().to_words());
+
+        assert_eq!(Some(sample()), Metadata::strip(&mut words));
+        assert_eq!(vec![0xF025], words);
+    }
+
+    #[test]
+    fn malformed_trailer_is_program_data() {
+        let mut words = vec![0x0001, 0x0005, META_MAGIC, 2];
+        assert_eq!(None, Metadata::strip(&mut words));
+        assert_eq!(4, words.len());
+    }
+
+    #[test]
+    fn symbolize_uses_the_nearest_label() {
+        let symbols = sample().symbols;
+        assert_eq!(
+            Some(String::from("LOOP")),
+            Metadata::symbolize(&symbols, 0x3001)
+        );
+        assert_eq!(
+            Some(String::from("LOOP+3")),
+            Metadata::symbolize(&symbols, 0x3004)
+        );
+        assert_eq!(None, Metadata::symbolize(&symbols, 0x3000));
+    }
+}
@@ -0,0 +1,203 @@
+// Memory-mapped devices
+//
+// The device registers live here rather than in memory storage, and Memory
+// dispatches an access to them when its address belongs to a device. peek and
+// poke see the registers as plain words, so dumps, snapshots and loaders never
+// disturb a device. read and write are the program's accesses and behave like
+// the hardware:
+//
+//   KBSR  polls the host for a key while none is pending
+//   KBDR  hands the pending key over, clearing the ready bit of KBSR
+//   DSR   always reads ready, writes are ignored
+//   DDR   prints every word written to it
+//   MCR   stops the machine when bit 15 is cleared
+//   CLK   the clock, high word then low, when one is configured
+//
+// Reading the high clock word latches the low one, see clock.rs.
+
+use crate::{
+    clock::{Clock, ClockDevice},
+    config::Config,
+    console::Console,
+};
+
+const KBSR_READY: u16 = 1 << 15;
+const DSR_READY: u16 = 1 << 15;
+const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+
+#[derive(Clone, Debug)]
+pub struct Devices {
+    kbsr: u16,
+    kbdr: u16,
+    dsr: u16,
+    ddr: u16,
+    mcr: u16,
+    clk: u16,
+    keyboard_status: u16,
+    keyboard_data: u16,
+    display_data: u16, /* only ever set by poke, output goes to the console */
+    machine_control: u16,
+}
+
+impl Devices {
+    // No key is pending and the clock starts enabled.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            kbsr: config.kbsr,
+            kbdr: config.kbdr,
+            dsr: config.dsr,
+            ddr: config.ddr,
+            mcr: config.mcr,
+            clk: config.clock,
+            keyboard_status: 0,
+            keyboard_data: 0,
+            display_data: 0,
+            machine_control: MCR_CLOCK_ENABLE,
+        }
+    }
+
+    // The addresses of the device registers, including the clock if there is
+    // one.
+    pub fn addresses(&self, clock: bool) -> Vec<u16> {
+        let mut addresses = vec![self.kbsr, self.kbdr, self.dsr, self.ddr, self.mcr];
+        if clock {
+            addresses.extend([self.clk, self.clk.wrapping_add(1)]);
+        }
+        addresses
+    }
+
+    // Whether a register is mapped at `address`.
+    pub fn contains(&self, address: u16, clock: bool) -> bool {
+        [self.kbsr, self.kbdr, self.dsr, self.ddr, self.mcr].contains(&address)
+            || clock && (address == self.clk || address == self.clk.wrapping_add(1))
+    }
+
+    // Reads a register without side effects. Returns None if no device is
+    // mapped at `address`.
+    pub fn peek(&self, address: u16, clock: Option<(&ClockDevice, &dyn Clock)>) -> Option<u16> {
+        match address {
+            a if a == self.kbsr => Some(self.keyboard_status),
+            a if a == self.kbdr => Some(self.keyboard_data),
+            a if a == self.dsr => Some(DSR_READY),
+            a if a == self.ddr => Some(self.display_data),
+            a if a == self.mcr => Some(self.machine_control),
+            a => {
+                let (device, clock) = clock?;
+                if a == self.clk {
+                    Some((device.millis(clock) >> 16) as u16)
+                } else if a == self.clk.wrapping_add(1) {
+                    Some(device.read_low())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // Sets a register without side effects. Returns false if no device is
+    // mapped at `address`.
+    pub fn poke(&mut self, address: u16, value: u16, clock: bool) -> bool {
+        match address {
+            a if a == self.kbsr => self.keyboard_status = value,
+            a if a == self.kbdr => self.keyboard_data = value,
+            a if a == self.dsr => {}
+            a if a == self.ddr => self.display_data = value,
+            a if a == self.mcr => self.machine_control = value,
+            a => return clock && (a == self.clk || a == self.clk.wrapping_add(1)),
+        }
+        true
+    }
+
+    // A read by the program. Returns None if no device is mapped at `address`.
+    pub fn read(
+        &mut self,
+        address: u16,
+        console: &mut Console,
+        clock: Option<(&mut ClockDevice, &dyn Clock)>,
+    ) -> Option<u16> {
+        // the ready bit stays set until the program reads the latched key
+        // from KBDR, so the host is only polled while no key is pending
+        if address == self.kbsr && self.keyboard_status & KBSR_READY == 0 {
+            let key = match console.try_read_key() {
+                Some(c) => Some(c as u16),
+                None if console.is_closed() => console.end_of_input(),
+                None => None,
+            };
+            if let Some(c) = key {
+                self.keyboard_data = c;
+                self.keyboard_status |= KBSR_READY;
+            }
+        }
+        if address == self.kbdr && self.keyboard_status & KBSR_READY != 0 {
+            self.keyboard_status &= !KBSR_READY;
+            console.echo(self.keyboard_data as u8);
+        }
+        if let Some((device, clock)) = clock {
+            if address == self.clk {
+                return Some(device.read_high(clock));
+            }
+            if address == self.clk.wrapping_add(1) {
+                return Some(device.read_low());
+            }
+        }
+        self.peek(address, None)
+    }
+
+    // A write by the program. Returns false if no device is mapped at
+    // `address`.
+    pub fn write(&mut self, address: u16, value: u16, console: &mut Console, clock: bool) -> bool {
+        if address == self.ddr {
+            console.put_word(value);
+            console.flush();
+            return true;
+        }
+        /* status is owned by the display */
+        address == self.dsr || self.poke(address, value, clock)
+    }
+
+    pub fn clock_enabled(&self) -> bool {
+        self.machine_control & MCR_CLOCK_ENABLE != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::{ClockDevice, ClockMode, VirtualClock},
+        config::Config,
+        console::Console,
+        defs::MR,
+        mmio::Devices,
+    };
+
+    #[test]
+    fn peeking_leaves_the_keyboard_alone() {
+        let mut devices = Devices::new(&Config::default());
+        let mut console = Console::new(Default::default());
+        console.feed(b"k");
+
+        assert_eq!(Some(0), devices.peek(MR::KBSR as u16, None));
+        assert_eq!(
+            Some(1 << 15),
+            devices.read(MR::KBSR as u16, &mut console, None)
+        );
+        assert_eq!(Some('k' as u16), devices.peek(MR::KBDR as u16, None));
+        assert_eq!(Some(1 << 15), devices.peek(MR::KBSR as u16, None));
+        assert_eq!(None, devices.peek(0x3000, None));
+    }
+
+    #[test]
+    fn clock_words_are_only_mapped_with_a_clock() {
+        let mut devices = Devices::new(&Config::default());
+        let device = ClockDevice::new(ClockMode::Uptime);
+        let clock = VirtualClock::default();
+        assert_eq!(None, devices.peek(MR::CLK as u16, None));
+        assert_eq!(
+            Some(0),
+            devices.peek(MR::CLK as u16, Some((&device, &clock)))
+        );
+        assert!(!devices.poke(MR::CLK as u16, 1, false));
+        assert!(devices.poke(MR::CLK as u16, 1, true));
+        assert_eq!(7, devices.addresses(true).len());
+    }
+}
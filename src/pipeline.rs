@@ -0,0 +1,240 @@
+// Pipeline visualization
+//
+// Replays a window of the executed instruction stream through a classic
+// five-stage pipeline (IF ID EX MEM WB) and draws which stage each instruction
+// occupies in every cycle. The model assumes full forwarding, so the only data
+// hazard is a load followed by an instruction that uses the loaded register or
+// the condition codes it sets, which stalls in ID for one cycle. Branches and
+// jumps are predicted not taken and resolve in EX: a taken one flushes the two
+// instructions fetched behind it. LDI is treated as a single load, and service
+// routines run natively, so a TRAP costs no more than an ordinary instruction.
+
+use std::fmt::Write;
+
+use crate::{defs::OP, disasm::disassemble};
+
+const STAGES: [&str; 5] = ["IF", "ID", "EX", "MEM", "WB"];
+const CC: u16 = 8; /* the condition codes, as a pseudo-register */
+const FLUSH_PENALTY: u64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub pc: u16,
+    pub word: u16,
+    pub taken: bool, /* the next instruction was not the one at pc + 1 */
+}
+
+// Collects the instructions numbered first..first + count, counting from 1.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    first: u64,
+    count: usize,
+    pub entries: Vec<Entry>,
+}
+
+impl Recorder {
+    pub fn new(first: u64, count: usize) -> Self {
+        Self {
+            first,
+            count,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, number: u64, pc: u16, word: u16, next_pc: u16) {
+        if number >= self.first && self.entries.len() < self.count {
+            self.entries.push(Entry {
+                pc,
+                word,
+                taken: next_pc != pc.wrapping_add(1),
+            });
+        }
+    }
+}
+
+// When an instruction enters the pipeline and how long it waits in ID
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub fetch: u64,
+    pub stall: u64,
+}
+
+impl Timing {
+    // The stage occupied in `cycle`, if any.
+    fn stage(&self, cycle: u64) -> Option<&'static str> {
+        let offset = cycle.checked_sub(self.fetch)?;
+        match offset {
+            0 => Some(STAGES[0]),
+            n if n <= 1 + self.stall => Some(STAGES[1]),
+            n => STAGES.get((n - self.stall) as usize).copied(),
+        }
+    }
+
+    fn retire(&self) -> u64 {
+        self.fetch + self.stall + STAGES.len() as u64 - 1
+    }
+}
+
+pub fn schedule(entries: &[Entry]) -> Vec<Timing> {
+    let mut timings: Vec<Timing> = Vec::new();
+    let mut fetch = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let load_use = i > 0 && is_load(entries[i - 1].word) && {
+            let loaded = dest(entries[i - 1].word);
+            let sources = sources(entry.word);
+            sources.contains(&CC) || loaded.is_some_and(|r| sources.contains(&r))
+        };
+        let timing = Timing {
+            fetch,
+            stall: u64::from(load_use),
+        };
+        fetch += 1 + timing.stall;
+        if entry.taken {
+            fetch += FLUSH_PENALTY;
+        }
+        timings.push(timing);
+    }
+    timings
+}
+
+fn is_load(word: u16) -> bool {
+    matches!(OP::try_from(word >> 12), Ok(OP::LD | OP::LDI | OP::LDR))
+}
+
+// The register an instruction writes, if any.
+fn dest(word: u16) -> Option<u16> {
+    match OP::try_from(word >> 12).ok()? {
+        OP::ADD | OP::AND | OP::NOT | OP::LD | OP::LDI | OP::LDR | OP::LEA => {
+            Some((word >> 9) & 0x7)
+        }
+        OP::JSR | OP::TRAP => Some(7),
+        _ => None,
+    }
+}
+
+// The registers an instruction reads, with CC standing for the condition codes.
+fn sources(word: u16) -> Vec<u16> {
+    let (r9, r6) = ((word >> 9) & 0x7, (word >> 6) & 0x7);
+    match OP::try_from(word >> 12) {
+        Ok(OP::ADD | OP::AND) if (word >> 5) & 1 == 0 => vec![r6, word & 0x7],
+        Ok(OP::ADD | OP::AND | OP::NOT | OP::LDR | OP::JMP) => vec![r6],
+        Ok(OP::JSR) if (word >> 11) & 1 == 0 => vec![r6],
+        Ok(OP::STR) => vec![r9, r6],
+        Ok(OP::ST | OP::STI) => vec![r9],
+        Ok(OP::BR) => vec![CC],
+        Ok(OP::TRAP) => vec![0],
+        _ => Vec::new(),
+    }
+}
+
+// Draws one row per instruction and one column per cycle, then a summary.
+pub fn diagram(entries: &[Entry]) -> String {
+    let timings = schedule(entries);
+    let cycles = timings.last().map_or(0, |t| t.retire() + 1);
+
+    let mut out = format!("{:<26}", "cycle");
+    for cycle in 1..=cycles {
+        let _ = write!(out, "{:<4}", cycle);
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    for (entry, timing) in entries.iter().zip(&timings) {
+        let _ = write!(
+            out,
+            "x{:04X} {:<20}",
+            entry.pc,
+            disassemble(entry.pc, entry.word)
+        );
+        for cycle in 0..cycles {
+            let _ = write!(out, "{:<4}", timing.stage(cycle).unwrap_or(""));
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+    }
+
+    let stalls: u64 = timings.iter().map(|t| t.stall).sum();
+    let flushes = entries.iter().filter(|e| e.taken).count();
+    let _ = writeln!(
+        out,
+        "{} instructions in {} cycles, {} load-use stalls, {} taken branches ({} cycles flushed)",
+        entries.len(),
+        cycles,
+        stalls,
+        flushes,
+        flushes as u64 * FLUSH_PENALTY
+    );
+    out
+}
+
+// One line per instruction: address, word, cycle of each stage, stall cycles.
+pub fn csv(entries: &[Entry]) -> String {
+    let mut out = String::from("address,word,instruction,IF,ID,EX,MEM,WB,stall\n");
+    for (entry, timing) in entries.iter().zip(schedule(entries)) {
+        let ex = timing.fetch + 2 + timing.stall;
+        let _ = writeln!(
+            out,
+            "x{:04X},x{:04X},\"{}\",{},{},{},{},{},{}",
+            entry.pc,
+            entry.word,
+            disassemble(entry.pc, entry.word),
+            timing.fetch + 1,
+            timing.fetch + 2,
+            ex + 1,
+            ex + 2,
+            ex + 3,
+            timing.stall
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::{schedule, Entry, Recorder, Timing};
+
+    fn entry(pc: u16, word: u16, taken: bool) -> Entry {
+        Entry { pc, word, taken }
+    }
+
+    #[test]
+    fn load_use_stalls_and_taken_branches_flush() {
+        let entries = [
+            entry(0x3000, 0x6240, false), // LDR R1, R1, #0
+            entry(0x3001, 0x1261, false), // ADD R1, R1, #1 uses the load
+            entry(0x3002, 0x0FFD, true),  // BRnzp x3000
+            entry(0x3000, 0x6240, false),
+            entry(0x3001, 0x14A1, false), // ADD R2, R2, #1 is independent
+        ];
+        let timings = schedule(&entries);
+
+        assert_eq!(Timing { fetch: 0, stall: 0 }, timings[0]);
+        assert_eq!(Timing { fetch: 1, stall: 1 }, timings[1]);
+        assert_eq!(Timing { fetch: 3, stall: 0 }, timings[2]);
+        assert_eq!(Timing { fetch: 6, stall: 0 }, timings[3]);
+        assert_eq!(Timing { fetch: 7, stall: 0 }, timings[4]);
+
+        let row: Vec<_> = (0..7).map(|c| timings[1].stage(c)).collect();
+        assert_eq!(
+            vec![
+                None,
+                Some("IF"),
+                Some("ID"),
+                Some("ID"),
+                Some("EX"),
+                Some("MEM"),
+                Some("WB")
+            ],
+            row
+        );
+    }
+
+    #[test]
+    fn recorder_keeps_the_window() {
+        let mut recorder = Recorder::new(2, 2);
+        for (number, pc) in (1..=4).zip(0x3000..) {
+            recorder.record(number, pc, 0x1261, pc + 1);
+        }
+        let pcs: Vec<u16> = recorder.entries.iter().map(|e| e.pc).collect();
+        assert_eq!(vec![0x3001, 0x3002], pcs);
+    }
+}
@@ -0,0 +1,99 @@
+// Flags playground
+//
+// Shows a 16-bit value in binary, hex, unsigned and signed decimal, and the
+// condition code the machine would set after loading it into a register.
+// Values are read from the command line, or one per line from stdin.
+
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    defs::R,
+    state::{Registers, PC_START},
+};
+
+// Accepts x/0x hex, b/0b binary and decimal from -32768 to 65535.
+pub fn parse(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let error = || format!("expected a 16-bit value, got {}", text);
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix('x')) {
+        return u16::from_str_radix(hex, 16).map_err(|_| error());
+    }
+    if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix('b')) {
+        return u16::from_str_radix(&bin.replace(['_', ' '], ""), 2).map_err(|_| error());
+    }
+    match text.parse::<i32>() {
+        Ok(n) if (-0x8000..=0xFFFF).contains(&n) => Ok(n as u16),
+        _ => Err(error()),
+    }
+}
+
+pub fn describe(value: u16) -> String {
+    let mut reg = Registers::new(PC_START);
+    reg[R::R0] = value;
+    reg.update_flags(R::R0);
+    let flag = reg.cond();
+
+    let bits = format!("{:016b}", value);
+    let nibbles: Vec<&str> = (0..4).map(|i| &bits[i * 4..i * 4 + 4]).collect();
+    format!(
+        "x{:04X}  {}  {:>5}  {:>6}  {}",
+        value,
+        nibbles.join(" "),
+        value,
+        value as i16,
+        flag
+    )
+}
+
+pub fn run(values: &[String]) -> Result<(), String> {
+    if !values.is_empty() {
+        for value in values {
+            println!("{}", describe(parse(value)?));
+        }
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return Ok(());
+        }
+        match line.trim() {
+            "" => continue,
+            "q" | "quit" => return Ok(()),
+            text => match parse(text) {
+                Ok(value) => println!("{}", describe(value)),
+                Err(e) => println!("{}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playground::{describe, parse};
+
+    #[test]
+    fn parse_number_forms() {
+        assert_eq!(Ok(0x8000), parse("x8000"));
+        assert_eq!(Ok(0x8000), parse("0b1000_0000_0000_0000"));
+        assert_eq!(Ok(0xFFFF), parse("-1"));
+        assert_eq!(Ok(65535), parse("65535"));
+        assert!(parse("65536").is_err());
+        assert!(parse("-32769").is_err());
+    }
+
+    #[test]
+    fn describe_shows_every_form_and_the_flag() {
+        assert_eq!(
+            "xFFFE  1111 1111 1111 1110  65534      -2  N",
+            describe(0xFFFE)
+        );
+        assert!(describe(0).ends_with('Z'));
+        assert!(describe(0x7FFF).ends_with('P'));
+    }
+}
@@ -0,0 +1,161 @@
+// Instruction policies
+//
+// Assignments often require some instructions and forbid others. A Policy
+// names them, from --require LDI,JSR and --forbid NOT or from a policy file
+// given with --policy:
+//
+//   # lab 3: use indirect addressing, no subroutines
+//   require LDI, STI
+//   forbid JSR, JSRR
+//
+// Names are mnemonics as in opstats.rs, where JSRR and RET are told apart
+// from JSR and JMP, or trap names such as PUTS. The loaded images are checked
+// before the run: a forbidden instruction must not appear in them and a
+// required one must. During the run a forbidden instruction stops the machine
+// before it executes, and a required one that never ran fails the run at the
+// end.
+
+use crate::{defs::TRAP, opstats};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Policy {
+    pub required: Vec<&'static str>,
+    pub forbidden: Vec<&'static str>,
+    executed: Vec<&'static str>, /* required names seen so far */
+}
+
+impl Policy {
+    // Reads a policy file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut policy = Policy::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (rule, names) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let names = parse_names(names).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            match rule {
+                "require" => policy.required.extend(names),
+                "forbid" => policy.forbidden.extend(names),
+                _ => return Err(format!("line {}: unknown rule {}", i + 1, rule)),
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.required.is_empty() && self.forbidden.is_empty()
+    }
+
+    // Checks the words of the loaded images, given with their addresses.
+    // Returns a description of each violation.
+    pub fn check_image(&self, words: impl IntoIterator<Item = (u16, u16)>) -> Vec<String> {
+        let mut violations = Vec::new();
+        let mut present = Vec::new();
+        for (address, word) in words {
+            for name in names(word) {
+                if self.forbidden.contains(&name) {
+                    violations.push(format!("forbidden {} at x{:04X}", name, address));
+                }
+                present.push(name);
+            }
+        }
+        for name in &self.required {
+            if !present.contains(name) {
+                violations.push(format!("required {} does not appear in the program", name));
+            }
+        }
+        violations
+    }
+
+    // Takes note of `instr` about to execute. Returns the name it is
+    // forbidden under, if it is.
+    pub fn observe(&mut self, instr: u16) -> Option<&'static str> {
+        let mut forbidden = None;
+        for name in names(instr) {
+            if self.required.contains(&name) && !self.executed.contains(&name) {
+                self.executed.push(name);
+            }
+            if self.forbidden.contains(&name) {
+                forbidden = Some(name);
+            }
+        }
+        forbidden
+    }
+
+    // The required names that have not executed.
+    pub fn missing(&self) -> Vec<&'static str> {
+        self.required
+            .iter()
+            .filter(|name| !self.executed.contains(name))
+            .copied()
+            .collect()
+    }
+}
+
+// Reads a comma separated list of names, as given to --require and --forbid.
+pub fn parse_names(text: &str) -> Result<Vec<&'static str>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let name = name.to_uppercase();
+            opstats::ROWS
+                .iter()
+                .map(|&(mnemonic, _)| mnemonic)
+                .chain((0..=0xFF).filter_map(|v| TRAP::try_from(v).ok().map(|t| t.name())))
+                .find(|known| *known == name)
+                .ok_or(format!("unknown instruction {}", name))
+        })
+        .collect()
+}
+
+// The names `instr` goes by: its mnemonic, and the trap name for a TRAP.
+fn names(instr: u16) -> impl Iterator<Item = &'static str> {
+    let trap = (opstats::mnemonic(instr) == "TRAP")
+        .then(|| TRAP::try_from(instr & 0xFF).ok().map(|t| t.name()))
+        .flatten();
+    std::iter::once(opstats::mnemonic(instr)).chain(trap)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::{parse_names, Policy};
+
+    #[test]
+    fn policy_files_are_parsed() {
+        let policy = Policy::parse("# lab\nrequire LDI, sti\n\nforbid JSRR # no pointers\n");
+        let policy = policy.unwrap();
+        assert_eq!(vec!["LDI", "STI"], policy.required);
+        assert_eq!(vec!["JSRR"], policy.forbidden);
+        assert_eq!(
+            Err(String::from("line 1: unknown instruction MUL")),
+            Policy::parse("forbid MUL")
+        );
+        assert!(Policy::parse("allow ADD").is_err());
+    }
+
+    #[test]
+    fn images_and_runs_are_checked() {
+        let mut policy = Policy {
+            required: parse_names("LDI,PUTS").unwrap(),
+            forbidden: parse_names("RET").unwrap(),
+            ..Policy::default()
+        };
+        let violations = policy.check_image([(0x3000, 0xA001), (0x3001, 0xC1C0)]);
+        assert_eq!(
+            vec![
+                "forbidden RET at x3001",
+                "required PUTS does not appear in the program"
+            ],
+            violations
+        );
+
+        assert_eq!(None, policy.observe(0xF022));
+        assert_eq!(vec!["LDI"], policy.missing());
+        assert_eq!(Some("RET"), policy.observe(0xC1C0));
+        assert_eq!(None, policy.observe(0xA001));
+        assert!(policy.missing().is_empty());
+    }
+}
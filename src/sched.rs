@@ -0,0 +1,96 @@
+// Round-robin scheduler
+//
+// Runs every loaded image as its own process: each gets a register set with
+// PC at its origin, and the processes take turns executing `quantum`
+// instructions, as if a timer interrupt switched context between them.
+// Memory and the console are shared. A process leaves the queue when it
+// halts; a runtime error, a breakpoint or clearing the MCR stops them all.
+// TRAP SLEEP ends a process's turn early instead of sleeping.
+//
+// The switch happens in the emulator rather than through a guest interrupt
+// handler, so programs need no supervisor code to take part. It stands in for
+// a timer interrupt because the machine has no interrupt model, and lc3 help
+// says so.
+
+use std::collections::VecDeque;
+
+use crate::{
+    defs::R,
+    state::{Registers, State},
+};
+
+pub fn run_all(state: &mut State, origins: &[u16], quantum: u64, on_step: &mut dyn FnMut(&State)) {
+    let mut ready: VecDeque<Registers> = origins
+        .iter()
+        .map(|&origin| {
+            let mut reg = state.reg.clone();
+            reg[R::PC] = origin;
+            reg
+        })
+        .collect();
+
+    state.scheduled = true;
+    while let Some(reg) = ready.pop_front() {
+        state.reg = reg;
+        state.running = true;
+        state.yielded = false;
+        for _ in 0..quantum {
+            state.step_once();
+            on_step(state);
+            if !state.running || state.yielded {
+                break;
+            }
+        }
+
+        if state.error.is_some() || state.hit.is_some() || !state.mem.clock_enabled() {
+            break;
+        }
+        if state.running {
+            ready.push_back(state.reg.clone());
+        }
+    }
+    state.running = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{defs::R, sched::run_all, state::State};
+
+    #[test]
+    fn processes_take_turns() {
+        let mut state = State::new();
+        /* two counters that halt after three increments */
+        for origin in [0x3000u16, 0x4000] {
+            state.mem.poke(origin, 0x1021); // ADD R0, R0, #1
+            state.mem.poke(origin + 1, 0x1021);
+            state.mem.poke(origin + 2, 0x1021);
+            state.mem.poke(origin + 3, 0xF025); // HALT
+        }
+
+        let mut trace = Vec::new();
+        run_all(&mut state, &[0x3000, 0x4000], 2, &mut |s| {
+            trace.push(s.reg[R::PC] & 0xF000)
+        });
+
+        assert_eq!(
+            vec![0x3000, 0x3000, 0x4000, 0x4000, 0x3000, 0x3000, 0x4000, 0x4000],
+            trace
+        );
+        assert_eq!(3, state.reg[R::R0]);
+        assert!(!state.running);
+    }
+
+    #[test]
+    fn sleep_yields_the_turn() {
+        let mut state = State::new();
+        state.mem.poke(0x3000, 0xF027); // SLEEP
+        state.mem.poke(0x3001, 0xF025); // HALT
+        state.mem.poke(0x4000, 0xF025);
+
+        let mut trace = Vec::new();
+        run_all(&mut state, &[0x3000, 0x4000], 10, &mut |s| {
+            trace.push(s.reg[R::PC])
+        });
+        assert_eq!(vec![0x3001, 0x4001, 0x3002], trace);
+    }
+}
@@ -0,0 +1,219 @@
+// Virtual screen
+//
+// A model of the terminal the guest writes to, kept by the console once
+// Console::track_screen is called, so a test of a text game can check what
+// the screen ends up showing instead of the bytes that drew it:
+//
+//   state.mem.console.track_screen();
+//   ... run ...
+//   let screen = state.mem.console.screen_text();
+//   assert_eq!(Some(String::from("+--+\n|@ |\n+--+")), screen);
+//
+// The screen starts empty at the top left and grows as the cursor moves
+// down or right, so it never wraps or scrolls. It follows \n, \r, \t and
+// backspace, and the ANSI sequences CURSOR writes along with the cursor
+// moves and erases programs commonly send: ESC [ row;col H or f, A, B, C, D,
+// J and K, and ESC c to reset. Other sequences, such as colours, are dropped.
+// The cursor stays within the first 65535 rows and 1024 columns, whatever
+// the guest asks for, so writing at the edge overwrites the last cell.
+
+const TAB: usize = 8;
+const ROWS: usize = 0xFFFF;
+const COLUMNS: usize = 1024;
+
+#[derive(Clone, Debug, Default)]
+enum Escape {
+    #[default]
+    None,
+    Started,                 /* ESC seen */
+    Control(String, String), /* ESC [, then the parameters and any private prefix */
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Screen {
+    lines: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+    escape: Escape,
+}
+
+impl Screen {
+    pub fn put(&mut self, c: char) {
+        match std::mem::take(&mut self.escape) {
+            Escape::None => self.put_plain(c),
+            Escape::Started => match c {
+                '[' => self.escape = Escape::Control(String::new(), String::new()),
+                'c' => *self = Screen::default(),
+                _ => {}
+            },
+            Escape::Control(mut params, mut prefix) => match c {
+                '0'..='9' | ';' => {
+                    params.push(c);
+                    self.escape = Escape::Control(params, prefix);
+                }
+                '?' | '>' | '=' if params.is_empty() => {
+                    prefix.push(c);
+                    self.escape = Escape::Control(params, prefix);
+                }
+                '@'..='~' if prefix.is_empty() => self.control(c, &params),
+                _ => {}
+            },
+        }
+    }
+
+    // The screen as text, a line per row with trailing blanks trimmed and
+    // the empty rows at the bottom left out.
+    pub fn text(&self) -> String {
+        let mut rows: Vec<String> = (self.lines.iter())
+            .map(|line| line.iter().collect::<String>().trim_end().to_string())
+            .collect();
+        while rows.last().is_some_and(|row| row.is_empty()) {
+            rows.pop();
+        }
+        rows.join("\n")
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    fn put_plain(&mut self, c: char) {
+        self.put_unclamped(c);
+        self.clamp();
+    }
+
+    fn put_unclamped(&mut self, c: char) {
+        match c {
+            '\x1b' => self.escape = Escape::Started,
+            '\n' => {
+                self.row = self.row.saturating_add(1);
+                self.column = 0;
+            }
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => self.column = (self.column / TAB + 1) * TAB,
+            c if c.is_control() => {}
+            c => {
+                let column = self.column;
+                let line = self.line();
+                if line.len() <= column {
+                    line.resize(column + 1, ' ');
+                }
+                line[column] = c;
+                self.column += 1;
+            }
+        }
+    }
+
+    fn clamp(&mut self) {
+        self.row = self.row.min(ROWS - 1);
+        self.column = self.column.min(COLUMNS - 1);
+    }
+
+    // Carries out the control sequence ending in `command`.
+    fn control(&mut self, command: char, params: &str) {
+        self.control_unclamped(command, params);
+        self.clamp();
+    }
+
+    fn control_unclamped(&mut self, command: char, params: &str) {
+        /* the parameters are digits, so only an empty one or one too big
+        for usize fails to parse */
+        let numbers: Vec<usize> = (params.split(';'))
+            .map(|n| {
+                n.parse()
+                    .unwrap_or(if n.is_empty() { 0 } else { usize::MAX })
+            })
+            .collect();
+        let n = |i: usize| numbers.get(i).copied().unwrap_or(0);
+        let count = n(0).max(1);
+        match command {
+            'H' | 'f' => {
+                self.row = n(0).max(1) - 1;
+                self.column = n(1).max(1) - 1;
+            }
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.row = self.row.saturating_add(count),
+            'C' => self.column = self.column.saturating_add(count),
+            'D' => self.column = self.column.saturating_sub(count),
+            'J' => match n(0) {
+                0 => {
+                    self.clear_line(self.column..);
+                    self.lines.truncate(self.row + 1);
+                }
+                1 => {
+                    self.lines.iter_mut().take(self.row).for_each(Vec::clear);
+                    self.clear_line(..=self.column);
+                }
+                _ => self.lines.clear(),
+            },
+            'K' => match n(0) {
+                0 => self.clear_line(self.column..),
+                1 => self.clear_line(..=self.column),
+                _ => self.clear_line(..),
+            },
+            _ => {}
+        }
+    }
+
+    // Blanks the columns `range` of the cursor's row.
+    fn clear_line(&mut self, range: impl std::ops::RangeBounds<usize>) {
+        let Some(line) = self.lines.get_mut(self.row) else {
+            return;
+        };
+        for (i, c) in line.iter_mut().enumerate() {
+            if range.contains(&i) {
+                *c = ' ';
+            }
+        }
+    }
+
+    fn line(&mut self) -> &mut Vec<char> {
+        if self.lines.len() <= self.row {
+            self.lines.resize(self.row + 1, Vec::new());
+        }
+        &mut self.lines[self.row]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::screen::Screen;
+
+    fn render(output: &str) -> Screen {
+        let mut screen = Screen::default();
+        output.chars().for_each(|c| screen.put(c));
+        screen
+    }
+
+    #[test]
+    fn text_follows_newlines_and_overwrites() {
+        let screen = render("hello\nworld\rW\tx\n\n");
+        assert_eq!("hello\nWorld   x", screen.text());
+        assert_eq!((3, 0), screen.cursor());
+    }
+
+    #[test]
+    fn cursor_sequences_move_and_erase() {
+        let screen =
+            render("junk\x1b[2J\x1b[H+--+\x1b[2;1H|@ |\x1b[3;1H+--+\x1b[31mX\x1b[0m\x1b[D\x1b[K");
+        assert_eq!("+--+\n|@ |\n+--+", screen.text());
+
+        let screen = render("abc\ndef\x1b[1;2H\x1b[J\x1b[?25l");
+        assert_eq!("a", screen.text());
+        assert_eq!((0, 1), screen.cursor());
+    }
+
+    #[test]
+    fn huge_moves_stop_at_the_edge() {
+        let far = "\x1b[999999999C\x1b[99999999999999999999999B";
+        let mut screen = render(&format!("{}xy\n", far));
+        assert_eq!((0xFFFE, 0), screen.cursor());
+        screen.put('\n');
+        assert_eq!((0xFFFE, 0), screen.cursor());
+
+        let screen = render(&format!("{}\x1b[1;999999999Hxy", far));
+        assert_eq!((0, 1023), screen.cursor());
+        assert_eq!(format!("{}y", " ".repeat(1023)), screen.text());
+    }
+}
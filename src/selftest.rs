@@ -0,0 +1,233 @@
+// Instruction set conformance suite
+//
+// tests/isa/ holds one small object file per area of the instruction set, the
+// source it was assembled from, and a .expect file with the state the machine
+// must be left in when the program halts. The files are built into the binary,
+// so `lc3 selftest` checks an installed copy without the source tree.
+//
+// Each line of a .expect file is a key and a value, and # starts a comment:
+//
+//   R0-R7 or PC  xNNNN    the register holds the value
+//   CC           N, Z, P  the condition code that is set
+//   xADDR        xNNNN    the memory word holds the value
+//   output       "TEXT"   everything written to the console, with \n escapes
+//
+// Anything not listed is not checked. Fixtures run from their origin with
+// unknown traps as errors and stop after MAX_STEPS instructions.
+
+use crate::{
+    defs::{CondFlags, R},
+    loader::Image,
+    state::State,
+};
+
+const MAX_STEPS: u64 = 10_000;
+
+pub struct Fixture {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub object: &'static [u8],
+    pub expect: &'static str,
+}
+
+macro_rules! fixture {
+    ($name:literal) => {
+        Fixture {
+            name: $name,
+            source: include_str!(concat!("../tests/isa/", $name, ".asm")),
+            object: include_bytes!(concat!("../tests/isa/", $name, ".obj")),
+            expect: include_str!(concat!("../tests/isa/", $name, ".expect")),
+        }
+    };
+}
+
+pub const FIXTURES: [Fixture; 14] = [
+    fixture!("add"),
+    fixture!("and"),
+    fixture!("not"),
+    fixture!("br"),
+    fixture!("jmp"),
+    fixture!("jsr"),
+    fixture!("ld"),
+    fixture!("ldi"),
+    fixture!("ldr"),
+    fixture!("lea"),
+    fixture!("st"),
+    fixture!("sti"),
+    fixture!("str"),
+    fixture!("trap"),
+];
+
+#[derive(Debug, PartialEq)]
+enum Expectation {
+    Register(R, u16),
+    Cond(CondFlags),
+    Memory(u16, u16),
+    Output(String),
+}
+
+fn parse_expect(text: &'static str) -> Result<Vec<Expectation>, String> {
+    let mut expectations = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split_once('#').map_or(line, |(code, _)| code).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: String| format!("line {}: {}", number + 1, e);
+        let (key, value) = line
+            .split_once(char::is_whitespace)
+            .map(|(key, value)| (key, value.trim()))
+            .ok_or_else(|| at(format!("{} has no value", line)))?;
+
+        let expectation = match key {
+            "CC" => Expectation::Cond(match value {
+                "N" => CondFlags::N,
+                "Z" => CondFlags::Z,
+                "P" => CondFlags::P,
+                _ => return Err(at(format!("condition code {} is not N, Z or P", value))),
+            }),
+            "output" => Expectation::Output(unquote(value).map_err(at)?),
+            "PC" => Expectation::Register(R::PC, hex(value).map_err(at)?),
+            register if register.len() == 2 && register.starts_with('R') => {
+                let r = register[1..]
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|&r| r < 8)
+                    .and_then(|r| R::try_from(r).ok())
+                    .ok_or_else(|| at(format!("no register {}", register)))?;
+                Expectation::Register(r, hex(value).map_err(at)?)
+            }
+            address => Expectation::Memory(hex(address).map_err(at)?, hex(value).map_err(at)?),
+        };
+        expectations.push(expectation);
+    }
+    Ok(expectations)
+}
+
+fn hex(text: &str) -> Result<u16, String> {
+    text.strip_prefix('x')
+        .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| format!("{} is not a hex word like x3000", text))
+}
+
+fn unquote(text: &str) -> Result<String, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| format!("{} is not a quoted string", text))?;
+    Ok(inner.replace("\\n", "\n"))
+}
+
+// Runs one fixture and lists every way the final state differs from its
+// expectations.
+pub fn check(fixture: &Fixture) -> Result<(), Vec<String>> {
+    let expectations = parse_expect(fixture.expect).map_err(|e| vec![e])?;
+    let image = Image::parse(fixture.object, false).map_err(|e| vec![e.to_string()])?;
+
+    let mut state = State::new();
+    state.max_instructions = Some(MAX_STEPS);
+    state.mem.console.capture();
+    image.load(&mut state).map_err(|e| vec![e.to_string()])?;
+    state.reg[R::PC] = image.origin;
+    while state.running {
+        state.step_once();
+    }
+
+    let mut failures = Vec::new();
+    if let Some(error) = &state.error {
+        failures.push(error.to_string());
+    }
+    for expectation in expectations {
+        match expectation {
+            Expectation::Register(r, value) if state.reg[r] != value => failures.push(format!(
+                "{} is x{:04X}, expected x{:04X}",
+                r.name(),
+                state.reg[r],
+                value
+            )),
+            Expectation::Cond(flags) if state.reg.cond() != flags => {
+                failures.push(format!("CC is {}, expected {}", state.reg.cond(), flags))
+            }
+            Expectation::Memory(address, value) if state.mem.peek(address) != value => failures
+                .push(format!(
+                    "x{:04X} holds x{:04X}, expected x{:04X}",
+                    address,
+                    state.mem.peek(address),
+                    value
+                )),
+            Expectation::Output(text) if state.mem.console.captured() != Some(&text) => failures
+                .push(format!(
+                    "output was {:?}, expected {:?}",
+                    state.mem.console.captured().unwrap_or_default(),
+                    text
+                )),
+            _ => {}
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+pub fn run() -> Result<(), String> {
+    let mut failed = 0;
+    for fixture in &FIXTURES {
+        match check(fixture) {
+            Ok(()) => println!("ok    {}", fixture.name),
+            Err(failures) => {
+                failed += 1;
+                println!("FAIL  {}", fixture.name);
+                for failure in failures {
+                    println!("      {}", failure);
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} fixtures failed", failed, FIXTURES.len()));
+    }
+    println!("all {} fixtures passed", FIXTURES.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        defs::{CondFlags, R},
+        selftest::{check, parse_expect, Expectation, FIXTURES},
+    };
+
+    #[test]
+    fn every_fixture_passes() {
+        for fixture in &FIXTURES {
+            assert_eq!(Ok(()), check(fixture), "{}", fixture.name);
+        }
+    }
+
+    #[test]
+    fn objects_match_their_sources() {
+        for fixture in &FIXTURES {
+            let program = assemble(fixture.source).unwrap();
+            assert_eq!(fixture.object, program.object(None), "{}", fixture.name);
+        }
+    }
+
+    #[test]
+    fn expectations_are_parsed() {
+        assert_eq!(
+            Ok(vec![
+                Expectation::Register(R::R3, 0x0042),
+                Expectation::Cond(CondFlags::Z),
+                Expectation::Memory(0x4000, 0xBEEF),
+                Expectation::Output(String::from("hi\n")),
+            ]),
+            parse_expect("# comment\nR3 x0042\nCC Z\n\nx4000 xBEEF # stored\noutput \"hi\\n\"\n")
+        );
+        assert!(parse_expect("R8 x0000").is_err());
+        assert!(parse_expect("CC Q").is_err());
+        assert!(parse_expect("x4000 1234").is_err());
+    }
+}
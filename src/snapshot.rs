@@ -0,0 +1,225 @@
+// Machine snapshot files
+//
+// A snapshot records the registers and all of memory, as big-endian words:
+//
+//   SNAPSHOT_MAGIC  R0-R7  PC  COND  memory x0000-xFFFF
+//
+// followed by a metadata block (see meta.rs) carrying the symbols of the
+// loaded images, so two snapshots can be compared by label.
+
+use std::io::{self, ErrorKind};
+
+use crate::{
+    asm::Symbols,
+    defs::{CondFlags, R},
+    disasm::disassemble,
+    meta::Metadata,
+    state::{Registers, State, MEMORY_MAX},
+};
+
+pub const SNAPSHOT_MAGIC: u16 = 0x534E; /* "SN" */
+
+pub struct Snapshot {
+    pub reg: Registers,
+    pub memory: Vec<u16>,
+    pub symbols: Symbols,
+}
+
+impl Snapshot {
+    pub fn take(state: &State) -> Self {
+        Self {
+            reg: state.reg.clone(),
+            memory: state.mem.read_slice(..),
+            symbols: state.symbols.clone(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let metadata = Metadata {
+            symbols: self.symbols.clone(),
+            ..Metadata::default()
+        };
+        std::iter::once(SNAPSHOT_MAGIC)
+            .chain(R::ALL.map(|r| self.reg[r]))
+            .chain(self.memory.iter().copied())
+            .chain(metadata.to_words())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut words: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        if words.first() != Some(&SNAPSHOT_MAGIC) {
+            return Err(invalid("not a snapshot file"));
+        }
+        let symbols = Metadata::strip(&mut words)
+            .map(|metadata| metadata.symbols)
+            .unwrap_or_default();
+
+        let count = R::COUNT;
+        if words.len() != 1 + count + MEMORY_MAX {
+            return Err(invalid(&format!(
+                "snapshot has {} words, expected {}",
+                words.len(),
+                1 + count + MEMORY_MAX
+            )));
+        }
+        let mut reg = Registers::new(0);
+        for (i, r) in R::ALL.into_iter().enumerate() {
+            reg[r] = words[1 + i];
+        }
+        if !CondFlags::is_valid(reg[R::COND]) {
+            return Err(invalid(&format!(
+                "snapshot has invalid condition codes x{:04X}",
+                reg[R::COND]
+            )));
+        }
+        Ok(Self {
+            reg,
+            memory: words.split_off(1 + count),
+            symbols,
+        })
+    }
+}
+
+// Lists the registers and memory words that differ between `a` and `b`, one
+// per line. Memory words are named by the nearest label and disassembled.
+pub fn diff(a: &Snapshot, b: &Snapshot) -> Vec<String> {
+    let mut lines = diff_registers(a, b);
+    let symbols = if b.symbols.is_empty() {
+        &a.symbols
+    } else {
+        &b.symbols
+    };
+    for (address, (&x, &y)) in a.memory.iter().zip(&b.memory).enumerate() {
+        if x == y {
+            continue;
+        }
+        let address = address as u16;
+        let label = Metadata::symbolize(symbols, address)
+            .map(|name| format!(" <{}>", name))
+            .unwrap_or_default();
+        lines.push(format!(
+            "x{:04X}{}  x{:04X} -> x{:04X}  {} -> {}",
+            address,
+            label,
+            x,
+            y,
+            disassemble(address, x),
+            disassemble(address, y)
+        ));
+    }
+    lines
+}
+
+pub fn diff_registers(a: &Snapshot, b: &Snapshot) -> Vec<String> {
+    let mut lines = Vec::new();
+    for r in R::ALL {
+        let (x, y) = (a.reg[r], b.reg[r]);
+        if x != y {
+            lines.push(match r {
+                R::COND => format!(
+                    "CC     {} -> {}",
+                    CondFlags::from_bits(x),
+                    CondFlags::from_bits(y)
+                ),
+                _ => format!("{:<2}     x{:04X} -> x{:04X}", r.name(), x, y),
+            });
+        }
+    }
+    lines
+}
+
+// Checks that `state` matches `expected`, or only its registers with
+// `registers_only`, failing with what differs.
+pub fn assert_state(
+    expected: &Snapshot,
+    state: &State,
+    registers_only: bool,
+) -> Result<(), String> {
+    let actual = Snapshot::take(state);
+    let lines = match registers_only {
+        true => diff_registers(expected, &actual),
+        false => diff(expected, &actual),
+    };
+    match lines.is_empty() {
+        true => Ok(()),
+        false => Err(format!(
+            "state does not match the snapshot (expected -> actual):\n{}",
+            lines.join("\n")
+        )),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        defs::R,
+        snapshot::{assert_state, diff, Snapshot},
+        state::State,
+    };
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut state = State::new();
+        state.reg[R::R3] = 0xBEEF;
+        state.mem.poke(0x4000, 0x1234);
+        state.symbols.insert(String::from("DATA"), 0x4000);
+
+        let snapshot = Snapshot::parse(&Snapshot::take(&state).to_bytes()).unwrap();
+        assert_eq!(0xBEEF, snapshot.reg[R::R3]);
+        assert_eq!(0x3000, snapshot.reg[R::PC]);
+        assert_eq!(0x1234, snapshot.memory[0x4000]);
+        assert_eq!(Some(&0x4000), snapshot.symbols.get("DATA"));
+
+        assert!(Snapshot::parse(&[0x30, 0x00]).is_err());
+    }
+
+    #[test]
+    fn diff_lists_registers_and_memory() {
+        let mut state = State::new();
+        state.symbols.insert(String::from("DATA"), 0x3FFF);
+        let before = Snapshot::take(&state);
+
+        state.reg[R::R1] = 5;
+        state.reg.update_flags(R::R1);
+        state.mem.poke(0x4000, 0xF025);
+        let after = Snapshot::take(&state);
+
+        assert_eq!(
+            vec![
+                "R1     x0000 -> x0005",
+                "CC     Z -> P",
+                "x4000 <DATA+1>  x0000 -> xF025  NOP -> HALT",
+            ],
+            diff(&before, &after)
+        );
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn states_are_asserted_against_snapshots() {
+        let mut state = State::new();
+        let expected = Snapshot::take(&state);
+        assert_eq!(Ok(()), assert_state(&expected, &state, false));
+
+        state.mem.poke(0x4000, 1);
+        assert_eq!(Ok(()), assert_state(&expected, &state, true));
+        state.reg[R::R2] = 3;
+        assert_eq!(
+            Err(String::from(
+                "state does not match the snapshot (expected -> actual):\n\
+                 R2     x0000 -> x0003\n\
+                 x4000  x0000 -> x0001  NOP -> NOP"
+            )),
+            assert_state(&expected, &state, false)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+// Source line maps
+//
+// Ties addresses back to the assembly source they came from. An image built
+// with lc3 asm --metadata carries a line table, one entry per statement, and
+// a hash of its source. The debugger reads the source alongside the image
+// when its hash matches, so a source edited since the build is not shown
+// against stale addresses.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{asm, meta::Metadata};
+
+#[derive(Debug)]
+struct SourceFile {
+    path: String,
+    text: Vec<String>,
+    lines: Vec<(u16, u16)>, /* address and line number of each statement */
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+// Where a statement is in the source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location<'a> {
+    pub path: &'a str,
+    pub line: usize,
+    pub text: &'a str,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}  {}", self.path, self.line, self.text.trim())
+    }
+}
+
+impl SourceMap {
+    // Adds the source at `path` for an image carrying `metadata`. Fails when
+    // the image has no line table or was built from other source.
+    pub fn add(&mut self, path: &str, source: &str, metadata: &Metadata) -> Result<(), String> {
+        if metadata.lines.is_empty() {
+            return Err(format!("the image built from {} has no line table", path));
+        }
+        if asm::hash(source.as_bytes()) != metadata.source_hash {
+            return Err(format!("{} has changed since the image was built", path));
+        }
+        self.files.push(SourceFile {
+            path: path.to_string(),
+            text: source.lines().map(String::from).collect(),
+            lines: metadata.lines.clone(),
+        });
+        Ok(())
+    }
+
+    // Adds the source `image` was built from: the first of `candidates` that
+    // matches, or else NAME.asm beside the image. Returns whether one did.
+    pub fn find(&mut self, image: &str, metadata: &Metadata, candidates: &[String]) -> bool {
+        let sibling = Path::new(image).with_extension("asm");
+        let mut paths = candidates.iter().map(PathBuf::from).chain([sibling]);
+        paths.any(|path| match fs::read_to_string(&path) {
+            Ok(text) => self
+                .add(&path.display().to_string(), &text, metadata)
+                .is_ok(),
+            Err(_) => false,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // The statement that starts at `address`, if any source has one there.
+    pub fn statement_at(&self, address: u16) -> Option<Location<'_>> {
+        self.files.iter().find_map(|file| {
+            let &(_, line) = file.lines.iter().find(|&&(a, _)| a == address)?;
+            Some(Location {
+                path: &file.path,
+                line: line as usize,
+                text: (line as usize)
+                    .checked_sub(1)
+                    .and_then(|i| file.text.get(i))
+                    .map_or("", String::as_str),
+            })
+        })
+    }
+
+    // The address of the first statement at or after `line` of `file`,
+    // which names the source by its path or by its last components.
+    pub fn address_of(&self, file: &str, line: usize) -> Result<u16, String> {
+        let source = self
+            .files
+            .iter()
+            .find(|source| Path::new(&source.path).ends_with(file))
+            .ok_or(format!("no source {} is loaded", file))?;
+        source
+            .lines
+            .iter()
+            .filter(|&&(_, l)| l as usize >= line)
+            .min_by_key(|&&(_, l)| l)
+            .map(|&(address, _)| address)
+            .ok_or(format!("no code at or after {}:{}", file, line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm,
+        srcmap::{Location, SourceMap},
+    };
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+LOOP    ADD R1, R1, #1
+
+        BRnzp LOOP
+        .END
+";
+
+    #[test]
+    fn lines_map_both_ways() {
+        let metadata = asm::assemble(SOURCE).unwrap().metadata(SOURCE);
+        let mut map = SourceMap::default();
+        map.add("src/loop.asm", SOURCE, &metadata).unwrap();
+
+        assert_eq!(
+            Some(Location {
+                path: "src/loop.asm",
+                line: 4,
+                text: "        BRnzp LOOP"
+            }),
+            map.statement_at(0x3001)
+        );
+        assert_eq!(
+            "src/loop.asm:2  LOOP    ADD R1, R1, #1",
+            map.statement_at(0x3000).unwrap().to_string()
+        );
+        assert_eq!(None, map.statement_at(0x3002));
+
+        /* a blank line breaks on the statement after it */
+        assert_eq!(Ok(0x3001), map.address_of("loop.asm", 3));
+        assert!(map.address_of("loop.asm", 5).is_err());
+        assert!(map.address_of("other.asm", 2).is_err());
+
+        let edited = SOURCE.replace("#1", "#2");
+        assert!(map.add("src/loop.asm", &edited, &metadata).is_err());
+    }
+}
@@ -1,131 +1,1939 @@
 use std::{
-    io::Read,
-    ops::{Index, IndexMut},
+    fmt,
+    ops::{Bound, Index, IndexMut, RangeBounds},
 };
 
 use crate::{
-    defs::{FL, MR, R},
-    terminal::check_key,
+    asm::Symbols,
+    branch::BranchStats,
+    breakpoints::{Access, Breakpoint, BreakpointId, Hit, Watchpoint},
+    cache::CacheSim,
+    callstack::{CallStack, Event},
+    cfi::FlowCheck,
+    clock::{Clock, ClockDevice, RealClock, VirtualClock},
+    color,
+    config::Config,
+    console::{Console, EofPolicy, OutputEvent},
+    counters::{Counters, Counts},
+    defs::{CondFlags, OP, R},
+    diag::{AddressSet, Check, Diagnostics, Severity},
+    disasm::disassemble,
+    error::RuntimeError,
+    history::History,
+    hooks::{Halt, Hooks},
+    instr::{self, TrapTable, UnknownTrap},
+    loader::VECTOR_TABLES_END,
+    loopcheck::LoopDetector,
+    mmio::Devices,
+    pipeline::Recorder,
+    policy::Policy,
+    stats::Stats,
+    storage::Storage,
+    timeline::Timeline,
+    tracefile::TraceWriter,
+    traptrace,
 };
 
+#[derive(Clone, Debug)]
 pub struct State {
     pub reg: Registers,
     pub mem: Memory,
     pub running: bool,
+    pub error: Option<RuntimeError>, /* why the machine stopped, if abnormally */
+    pub stats: Stats,
+    pub unknown_trap: UnknownTrap,
+    pub traps: TrapTable,
+    breakpoints: Vec<(BreakpointId, Breakpoint)>,
+    next_id: u32,
+    resuming: bool,       /* skip breakpoints on the next instruction */
+    pub hit: Option<Hit>, /* the breakpoint or watchpoint that stopped the machine */
+    pub max_instructions: Option<u64>,
+    pub max_writes: Option<u64>,
+    pub exit_status: Option<u16>,       /* R0 as passed to TRAP EXIT */
+    pub deterministic: bool,            /* sleep on the clock even when scheduled */
+    pub scheduled: bool,                /* running as one of several processes */
+    pub yielded: bool,                  /* TRAP SLEEP gave up the rest of the turn */
+    pub symbols: Symbols,               /* labels from image metadata */
+    pub pipeline: Option<Recorder>,     /* instructions kept for the pipeline diagram */
+    pub trace: bool,                    /* log each instruction to stderr */
+    pub color: bool,                    /* colour the trace, see color.rs */
+    pub trace_traps: bool,              /* log each TRAP to stderr, see traptrace.rs */
+    pub trace_only: Vec<(u16, u16)>,    /* address ranges traced, all if empty */
+    previous: Option<u16>,              /* address of the latest instruction executed */
+    pub calls: CallStack,               /* subroutines entered and not yet returned from */
+    pub loops: Option<LoopDetector>,    /* stops the machine in an endless loop, see loopcheck.rs */
+    pub history: History,               /* the latest instructions run */
+    pub pc_checks: bool,                /* stop when PC wraps or strays, see State::stray_pc */
+    pub policy: Option<Policy>,         /* instructions required and forbidden */
+    pub cfi: Option<FlowCheck>,         /* stops wild jumps, see cfi.rs */
+    pub diagnostics: Diagnostics,       /* checks on the program, see diag.rs */
+    pub recording: Option<TraceWriter>, /* every change the run makes, see tracefile.rs */
+    pub timeline: Option<Timeline>,     /* calls and traps for --perfetto, see timeline.rs */
+    pub effects: Effects,               /* of the latest instruction run */
+    pub hooks: Hooks,                   /* told when the run ends, see hooks.rs */
 }
 
 impl State {
     pub fn new() -> Self {
+        Self::with_config(&Config::default())
+    }
+
+    pub fn with_config(config: &Config) -> Self {
         Self {
-            reg: Registers::new(),
-            mem: Memory::new(),
+            reg: Registers::new(config.pc_start),
+            mem: Memory::new(config),
             running: true,
+            error: None,
+            stats: Stats::default(),
+            unknown_trap: config.unknown_trap,
+            traps: config.traps.clone(),
+            breakpoints: Vec::new(),
+            next_id: 1,
+            resuming: false,
+            hit: None,
+            max_instructions: config.max_instructions,
+            max_writes: config.max_writes,
+            exit_status: None,
+            deterministic: config.deterministic,
+            scheduled: false,
+            yielded: false,
+            symbols: Symbols::new(),
+            pipeline: None,
+            trace: false,
+            color: false,
+            trace_traps: false,
+            trace_only: Vec::new(),
+            previous: None,
+            calls: CallStack::default(),
+            loops: None,
+            history: History::default(),
+            pc_checks: true,
+            policy: None,
+            cfi: None,
+            diagnostics: Diagnostics::default(),
+            recording: None,
+            timeline: None,
+            effects: Effects::default(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    // Stops the machine with a runtime error.
+    pub fn fail(&mut self, error: RuntimeError) {
+        self.error = Some(error);
+        self.running = false;
+    }
+
+    // Calls `hook` when a step stops the machine other than with an error.
+    pub fn on_halt(&mut self, hook: impl Fn(&Halt, &State) + Send + Sync + 'static) {
+        self.hooks.on_halt(hook);
+    }
+
+    // Calls `hook` when a step stops the machine with a runtime error.
+    pub fn on_exception(&mut self, hook: impl Fn(&RuntimeError, &State) + Send + Sync + 'static) {
+        self.hooks.on_exception(hook);
+    }
+
+    // Types `text` on the keyboard, for GETC, IN and KBDR to read next.
+    // Between steps this puts input at an exact point in the run.
+    pub fn queue_input(&mut self, text: &str) {
+        self.mem.console.queue(text.as_bytes());
+    }
+
+    pub fn queue_key(&mut self, key: u8) {
+        self.mem.console.queue(&[key]);
+    }
+
+    // Keeps the output as events for take_output from now on.
+    pub fn record_output(&mut self) {
+        self.mem.console.record_events();
+    }
+
+    // The output since the last call, by what wrote it and when. Empty
+    // unless record_output was called.
+    pub fn take_output(&mut self) -> Vec<OutputEvent> {
+        self.mem.console.take_events()
+    }
+
+    // Fetches the instruction at PC and executes it, and describes what it
+    // did. See step_once for runs that do not need the description.
+    pub fn step(&mut self) -> StepInfo {
+        let before = self.reg.clone();
+        let ran = self.stats.instructions;
+        self.mem.console.start_io();
+        let result = self.step_once();
+        let effects = match self.stats.instructions != ran {
+            true => Some(&self.effects),
+            false => None,
+        };
+        let (input, output) = self.mem.console.take_io();
+        let (old, new) = (before.cond(), self.reg.cond());
+        StepInfo {
+            result,
+            executed: effects.map(|e| (e.pc, e.instr)),
+            registers: R::ALL[..R::COND as usize]
+                .iter()
+                .filter(|&&r| before[r] != self.reg[r])
+                .map(|&r| (r, self.reg[r]))
+                .collect(),
+            flags: (old != new).then_some((old, new)),
+            loads: effects.map_or(Vec::new(), |e| e.loads.clone()),
+            stores: effects.map_or(Vec::new(), |e| e.stores.clone()),
+            input,
+            output,
+        }
+    }
+
+    // Fetches the instruction at PC and executes it.
+    pub fn step_once(&mut self) -> StepResult {
+        let running = self.running;
+        let result = self.advance();
+        if running && !self.running {
+            self.hooks.clone().stopped(self);
+        }
+        result
+    }
+
+    fn advance(&mut self) -> StepResult {
+        let routine = self.traps.routine_at(self.reg.pc());
+        if let Some(error) = self.stray_pc().filter(|_| routine.is_none()) {
+            self.fail(error);
+            return StepResult::Stopped;
+        }
+        if let Some(trap) = routine {
+            /* a built-in routine, run as if by the TRAP that called it */
+            self.reg[R::PC] = self.reg[R::R7];
+            self.execute_word(0xF000 | trap as u16);
+        } else {
+            let instr = self.mem.fetch(self.reg[R::PC]);
+            self.reg[R::PC] = self.reg[R::PC].wrapping_add(1);
+            self.execute_word(instr);
+        }
+        match self.hit {
+            Some(hit) => StepResult::BreakpointHit(hit),
+            None if self.running => StepResult::Running,
+            None => StepResult::Stopped,
         }
     }
+
+    // PC never wraps from xFFFF to x0000. Unless pc_checks is off, it also
+    // stays out of the vector tables, x0000 to x01FF from the trap table
+    // base, and the device page from
+    // xFE00 up, as well as any device register mapped elsewhere. They hold
+    // addresses and registers, never code, so a program that gets there has
+    // lost its way.
+    fn stray_pc(&self) -> Option<RuntimeError> {
+        let pc = self.reg.pc();
+        if pc == 0 && self.previous == Some(0xFFFF) {
+            return Some(RuntimeError::PcWrapped);
+        }
+        if !self.pc_checks {
+            return None;
+        }
+        let tables = pc.wrapping_sub(self.traps.base) <= VECTOR_TABLES_END;
+        let stray = tables || pc >= 0xFE00 || self.mem.is_device(pc);
+        stray.then_some(RuntimeError::StrayPc {
+            pc,
+            from: self.previous,
+        })
+    }
+
+    // Runs the checks of diag.rs on `instr`, which just ran at `pc` with R6
+    // at `r6` and made the call stack notice `event`.
+    fn diagnose(&mut self, pc: u16, instr: u16, r6: u16, event: Option<Event>) {
+        match event {
+            Some(Event::Clobbered(to)) => self.flag(Check::R7Clobber, pc, || {
+                format!(
+                    "R7 overwritten at x{:04X}; RET will jump to x{:04X}",
+                    pc, to
+                )
+            }),
+            Some(Event::Unbalanced { frame, sp }) => self.flag(Check::Stack, pc, || {
+                format!(
+                    "subroutine x{:04X} returns to x{:04X} with R6 at x{:04X}, x{:04X} at the call",
+                    frame.routine, frame.return_to, sp, frame.sp
+                )
+            }),
+            None => {}
+        }
+        let stack_access = matches!(OP::try_from(instr >> 12), Ok(OP::LDR | OP::STR))
+            && (instr >> 6) & 0x7 == R::R6 as u16;
+        if stack_access && r6 == 0 {
+            self.flag(Check::Stack, pc, || {
+                format!(
+                    "R6 used as a stack pointer at x{:04X} while still x0000",
+                    pc
+                )
+            });
+        }
+        if let Some(address) = self.mem.take_uninitialized_read() {
+            self.flag(Check::Uninitialized, pc, || {
+                format!(
+                    "load from x{:04X} at x{:04X}, which was never written",
+                    address, pc
+                )
+            });
+        }
+        if let Some(address) = self.mem.take_read_only_write() {
+            self.flag(Check::ReadOnly, pc, || {
+                format!(
+                    "store into read-only x{:04X} at x{:04X}, which was ignored",
+                    address, pc
+                )
+            });
+        }
+        if let Some(address) = self.mem.take_code_write() {
+            self.flag(Check::SelfModifying, pc, || {
+                format!(
+                    "store into x{:04X} at x{:04X}, which has already run as code",
+                    address, pc
+                )
+            });
+        }
+    }
+
+    // Reports `check` firing at `pc`, stopping the machine if it is an error.
+    fn flag(&mut self, check: Check, pc: u16, message: impl FnOnce() -> String) {
+        if self.diagnostics.report(check, pc, message) == Severity::Error && self.error.is_none() {
+            self.fail(RuntimeError::Diagnostic { pc, check });
+        }
+    }
+
+    // Continues after a breakpoint or watchpoint. The instruction a
+    // breakpoint stopped on runs without hitting it again.
+    pub fn resume(&mut self) {
+        self.resuming = matches!(self.hit.take(), Some(Hit::Breakpoint { .. }));
+        self.running = true;
+    }
+
+    // Puts the machine back as it was at `checkpoint`: registers, memory,
+    // counters and call stack. What the session set up stays, so breakpoints,
+    // watchpoints, tracing and the console keep running as they are.
+    pub fn restore(&mut self, checkpoint: &State) {
+        let mut mem = checkpoint.mem.clone();
+        mem.watchpoints = std::mem::take(&mut self.mem.watchpoints);
+        mem.watch_hit = None;
+        std::mem::swap(&mut mem.console, &mut self.mem.console);
+        self.mem = mem;
+        self.reg = checkpoint.reg.clone();
+        self.running = checkpoint.running;
+        self.error = checkpoint.error.clone();
+        self.stats = checkpoint.stats.clone();
+        self.exit_status = checkpoint.exit_status;
+        self.previous = checkpoint.previous;
+        self.calls = checkpoint.calls.clone();
+        self.history = checkpoint.history.clone();
+        self.hit = None;
+        self.resuming = false;
+        self.yielded = false;
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = self.next_id();
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> BreakpointId {
+        let id = self.next_id();
+        self.mem.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    // Removes a breakpoint or watchpoint. Returns whether `id` was set.
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let count = self.breakpoints.len() + self.mem.watchpoints.len();
+        self.breakpoints.retain(|&(i, _)| i != id);
+        self.mem.watchpoints.retain(|&(i, _)| i != id);
+        count != self.breakpoints.len() + self.mem.watchpoints.len()
+    }
+
+    pub fn breakpoints(&self) -> &[(BreakpointId, Breakpoint)] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[(BreakpointId, Watchpoint)] {
+        &self.mem.watchpoints
+    }
+
+    fn next_id(&mut self) -> BreakpointId {
+        self.next_id += 1;
+        BreakpointId(self.next_id - 1)
+    }
+
+    // Decodes and executes a single instruction word without fetching it.
+    // PC-relative operands are taken relative to the current PC, as if the
+    // word had just been fetched from PC - 1.
+    pub fn execute_word(&mut self, instr: u16) {
+        let pc = self.reg[R::PC].wrapping_sub(1);
+        let previous = self.previous;
+        let breakpoint = self
+            .breakpoints
+            .iter()
+            .find(|(_, b)| b.matches(pc, instr, previous));
+        if let (Some(&(id, breakpoint)), false) = (breakpoint, self.resuming) {
+            /* leave PC on the instruction so it is the next one to run */
+            self.reg[R::PC] = pc;
+            self.hit = Some(Hit::Breakpoint {
+                id,
+                address: pc,
+                breakpoint,
+            });
+            self.running = false;
+            return;
+        }
+        self.resuming = false;
+        if let Some(limit) = self.max_instructions {
+            if self.stats.instructions >= limit {
+                self.reg[R::PC] = pc;
+                self.fail(RuntimeError::InstructionLimit { pc, limit });
+                return;
+            }
+        }
+
+        if let Some(cfi) = &mut self.cfi {
+            let target = self.reg[R::field(instr >> 6)];
+            if !cfi.check(instr, target) {
+                self.reg[R::PC] = pc;
+                self.fail(RuntimeError::WildJump { pc, target });
+                return;
+            }
+        }
+        if let Some(name) = self.policy.as_mut().and_then(|p| p.observe(instr)) {
+            self.reg[R::PC] = pc;
+            self.fail(RuntimeError::Forbidden { pc, name });
+            return;
+        }
+
+        self.mem.console.instruction = self.stats.instructions;
+        self.stats.instructions += 1;
+        self.mem.clock.tick();
+        if let Some(counters) = &mut self.mem.counters {
+            counters.tick();
+        }
+        if instr >> 12 == OP::TRAP as u16 {
+            self.stats.count_trap(instr & 0xFF);
+        }
+        if let Some(opcodes) = &mut self.stats.opcodes {
+            opcodes.record(instr);
+        }
+        if let Some(branches) = &mut self.stats.branches {
+            if BranchStats::is_conditional(instr) {
+                let taken = self.reg.cond().matches((instr >> 9) & 0x7);
+                branches.record(pc, taken);
+            }
+        }
+        let traced = self.trace_only.is_empty()
+            || self
+                .trace_only
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&pc));
+        if self.trace && traced {
+            let text = disassemble(pc, instr);
+            match self.color {
+                true => eprintln!(
+                    "{}  {:04X}  {}",
+                    color::address(&format!("x{:04X}", pc)),
+                    instr,
+                    color::instruction(&text)
+                ),
+                false => eprintln!("x{:04X}  {:04X}  {}", pc, instr, text),
+            }
+        }
+        let trap = instr >> 12 == OP::TRAP as u16;
+        let call =
+            (self.trace_traps && traced && trap).then(|| traptrace::call(pc, instr & 0xFF, self));
+        self.previous = Some(pc);
+        self.history.push(pc, instr);
+        let (r6, r7) = (self.reg[R::R6], self.reg[R::R7]);
+        let before = self.recording.is_some().then(|| {
+            self.mem.take_last_write();
+            let mut reg = self.reg.clone();
+            reg.set_pc(pc);
+            reg
+        });
+        let mut effects = std::mem::take(&mut self.effects);
+        self.mem
+            .take_accesses(&mut effects.loads, &mut effects.stores);
+        instr::execute(instr, self);
+        self.mem
+            .take_accesses(&mut effects.loads, &mut effects.stores);
+        self.effects = Effects {
+            pc,
+            instr,
+            ..effects
+        };
+        if let (Some(recording), Some(before)) = (&mut self.recording, before) {
+            let write = self.mem.take_last_write();
+            let write = write.map(|address| (address, self.mem.peek(address)));
+            recording.record(pc, instr, &before, &self.reg, write);
+        }
+        let event = self.calls.follow(pc, instr, r7, &self.reg);
+        self.diagnose(pc, instr, r6, event);
+        if let Some(cfi) = &mut self.cfi {
+            cfi.follow(pc, instr, self.reg.pc());
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.follow(self.stats.instructions - 1, pc, instr, &self.reg);
+        }
+        if let Some(call) = call {
+            eprintln!("{}", traptrace::finish(call, self));
+        }
+        if let Some(cache) = &mut self.mem.cache {
+            cache.follow(instr, self.reg[R::PC]);
+        }
+        if let Some(pipeline) = &mut self.pipeline {
+            pipeline.record(self.stats.instructions, pc, instr, self.reg[R::PC]);
+        }
+
+        match self.mem.take_fault() {
+            Some(Fault::Missing(address)) => self.fail(RuntimeError::MemoryFault { pc, address }),
+            Some(Fault::Guard(address)) => self.fail(RuntimeError::GuardAccess { pc, address }),
+            None => {}
+        }
+        if let Some(limit) = self.max_writes.filter(|&limit| self.mem.writes() > limit) {
+            self.fail(RuntimeError::WriteLimit { pc, limit });
+        }
+        if let Some(limit) = self.mem.console.take_overflow() {
+            self.fail(RuntimeError::OutputLimit { pc, limit });
+        }
+        if let Some((id, watchpoint)) = self.mem.watch_hit.take() {
+            self.hit = Some(Hit::Watchpoint { id, pc, watchpoint });
+            self.running = false;
+        }
+        match self.mem.console.take_eof() {
+            Some(EofPolicy::Halt) => self.running = false,
+            Some(EofPolicy::Error) => self.fail(RuntimeError::InputClosed { pc }),
+            _ => {}
+        }
+
+        // clearing the clock enable bit of the MCR stops the machine
+        if !self.mem.clock_enabled() {
+            self.running = false;
+        }
+
+        if trap {
+            self.mem.activity += 1;
+        }
+        if let Some(loops) = &mut self.loops {
+            if self.running && loops.observe(&self.reg, self.mem.activity) {
+                let pc = self.reg.pc();
+                self.fail(RuntimeError::InfiniteLoop { pc });
+            }
+        }
+    }
+}
+
+// The memory the latest instruction loaded and stored, with the values
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Effects {
+    pub pc: u16,
+    pub instr: u16,
+    pub loads: Vec<(u16, u16)>,
+    pub stores: Vec<(u16, u16)>,
+}
+
+// What one call to State::step did. Registers, flags and memory are as the
+// instruction left them; anything it did not run leaves them empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub result: StepResult,
+    pub executed: Option<(u16, u16)>, /* address and word of the instruction, None if none ran */
+    pub registers: Vec<(R, u16)>,     /* R0-R7 and PC where they changed, and the new values */
+    pub flags: Option<(CondFlags, CondFlags)>, /* the condition codes before and after, if changed */
+    pub loads: Vec<(u16, u16)>,                /* addresses loaded and the values */
+    pub stores: Vec<(u16, u16)>,               /* addresses stored to and the values */
+    pub input: Vec<u8>,                        /* keys consumed from the console */
+    pub output: String,                        /* text written to the console */
 }
 
-const PC_START: u16 = 0x3000;
+// What a single step left the machine doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepResult {
+    Running,
+    Stopped, /* halted, failed or out of turns; see State::error */
+    BreakpointHit(Hit),
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+pub const PC_START: u16 = 0x3000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Registers {
-    reg: [u16; R::COUNT as usize],
+    reg: [u16; R::COUNT],
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new(PC_START)
+    }
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut registers = f.debug_struct("Registers");
+        for (r, value) in R::ALL.iter().zip(self.reg) {
+            match r {
+                R::COND if CondFlags::is_valid(value) => {
+                    registers.field(r.name(), &CondFlags::from_bits(value))
+                }
+                _ => registers.field(r.name(), &format_args!("x{:04X}", value)),
+            };
+        }
+        registers.finish()
+    }
 }
 
 impl Registers {
-    fn new() -> Self {
-        let mut state = Self {
-            reg: [0; R::COUNT as usize],
-        };
+    pub(crate) fn new(pc_start: u16) -> Self {
+        let mut state = Self { reg: [0; R::COUNT] };
 
         // since exactly one condition flag should be set at any given time, set the Z flag
-        state.reg[R::COND as usize] = FL::ZRO as u16;
+        state.reg[R::COND as usize] = CondFlags::Z.bits();
 
         // set the PC to starting position
         // 0x3000 is the default
-        state.reg[R::PC as usize] = PC_START;
+        state.reg[R::PC as usize] = pc_start;
         state
     }
 
-    pub fn update_flags(&mut self, r: u16) {
-        if self.reg[r as usize] == 0 {
-            self.reg[R::COND as usize] = FL::ZRO as u16;
-        } else if (self.reg[r as usize] >> 15) != 0 {
-            // a 1 in the left-most bit indicates negative
-            self.reg[R::COND as usize] = FL::NEG as u16;
-        } else {
-            self.reg[R::COND as usize] = FL::POS as u16;
-        }
+    pub fn get(&self, r: R) -> u16 {
+        self.reg[r as usize]
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        defs::R,
-        state::{Registers, PC_START},
-    };
+    pub fn set(&mut self, r: R, value: u16) {
+        self.reg[r as usize] = value;
+    }
 
-    #[test]
-    fn program_counter_init_value() {
-        let reg = Registers::new();
-        assert_eq!(PC_START, reg[R::PC]);
+    pub fn pc(&self) -> u16 {
+        self.reg[R::PC as usize]
     }
-}
 
-impl Index<R> for Registers {
-    type Output = u16;
-    fn index<'a>(&'a self, i: R) -> &'a u16 {
-        &self.reg[i as usize]
+    pub fn set_pc(&mut self, pc: u16) {
+        self.reg[R::PC as usize] = pc;
     }
-}
 
-impl IndexMut<R> for Registers {
-    fn index_mut<'a>(&'a mut self, i: R) -> &'a mut u16 {
-        &mut self.reg[i as usize]
+    pub fn cond(&self) -> CondFlags {
+        CondFlags::from_bits(self.reg[R::COND as usize])
+    }
+
+    pub fn set_cond(&mut self, flags: CondFlags) {
+        self.reg[R::COND as usize] = flags.bits();
+    }
+
+    pub fn update_flags(&mut self, r: R) {
+        self.set_cond(CondFlags::from_result(self.reg[r as usize]));
     }
 }
 
-impl Index<u16> for Registers {
+impl Index<R> for Registers {
     type Output = u16;
-    fn index<'a>(&'a self, i: u16) -> &'a u16 {
+    fn index(&self, i: R) -> &u16 {
         &self.reg[i as usize]
     }
 }
 
-impl IndexMut<u16> for Registers {
-    fn index_mut<'a>(&'a mut self, i: u16) -> &'a mut u16 {
+impl IndexMut<R> for Registers {
+    fn index_mut(&mut self, i: R) -> &mut u16 {
         &mut self.reg[i as usize]
     }
 }
 
 pub const MEMORY_MAX: usize = 1 << 16;
 
+// A memory access the machine cannot complete
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    Missing(u16), /* past the end of memory */
+    Guard(u16),   /* a guard word next to a loaded image */
+}
+
+#[derive(Clone)]
 pub struct Memory {
-    data: [u16; MEMORY_MAX],
+    storage: Storage,
+    fill_pattern: u16, /* what memory holds before anything is loaded */
+    devices: Devices,
+    read_only: Vec<(u16, u16)>,
+    size: usize,                   /* words present, device registers aside */
+    mirrors: Vec<(u16, u16, u16)>, /* start, end and the address start aliases */
+    guards: Vec<u16>,              /* words that stop the machine when accessed */
+    fault: Option<Fault>,          /* the latest access that could not complete */
+    watchpoints: Vec<(BreakpointId, Watchpoint)>,
+    watch_hit: Option<(BreakpointId, Watchpoint)>, /* the latest watched access */
+    pub console: Console,
+    pub clock: Box<dyn Clock>, /* the time the machine runs in */
+    pub clock_device: Option<ClockDevice>, /* mapped at clk and clk + 1 when enabled */
+    pub cache: Option<CacheSim>, /* simulated caches, if any are configured */
+    pub counters: Option<Counters>, /* performance counters, when mapped */
+    fetches: u64,
+    reads: u64,
+    writes: u64,
+    activity: u64,           /* device accesses and changing writes, see loopcheck.rs */
+    last_write: Option<u16>, /* address of the latest write to memory */
+    written: AddressSet,     /* words loaded or stored, see diag.rs */
+    executed: AddressSet,    /* words fetched as instructions */
+    uninitialized_read: Option<u16>, /* the latest load from a word never written */
+    code_write: Option<u16>, /* the latest store into a word that has run */
+    read_only_write: Option<u16>, /* the latest store into a read-only range */
+    loaded: Vec<(u16, u16)>, /* the program's loads since take_accesses, and their values */
+    stored: Vec<(u16, u16)>, /* likewise its stores */
 }
 
 impl Memory {
-    fn new() -> Self {
+    fn new(config: &Config) -> Self {
         Self {
-            data: [0; MEMORY_MAX],
+            storage: Storage::new(config.fill),
+            fill_pattern: config.fill,
+            devices: Devices::new(config),
+            read_only: config.read_only.clone(),
+            size: config.memory_size,
+            mirrors: config.mirrors.clone(),
+            guards: Vec::new(),
+            fault: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            console: Console::new(config.console.clone()),
+            clock: match config.deterministic {
+                true => Box::new(VirtualClock::default()),
+                false => Box::new(RealClock::default()),
+            },
+            clock_device: config.clock_mode.map(ClockDevice::new),
+            cache: (config.icache.is_some() || config.dcache.is_some())
+                .then(|| CacheSim::new(config.icache, config.dcache)),
+            counters: (config.counters_mapped)
+                .then(|| Counters::new(config.counters, config.mask_counters)),
+            fetches: 0,
+            reads: 0,
+            writes: 0,
+            activity: 0,
+            last_write: None,
+            written: AddressSet::default(),
+            executed: AddressSet::default(),
+            uninitialized_read: None,
+            code_write: None,
+            read_only_write: None,
+            loaded: Vec::new(),
+            stored: Vec::new(),
         }
     }
 
     pub fn read(&mut self, address: u16) -> u16 {
-        if address == MR::KBSR as u16 {
-            if check_key().unwrap() {
-                self.data[MR::KBSR as usize] = 1 << 15;
-
-                let mut buffer = [0u8; 1];
-                std::io::stdin().read_exact(&mut buffer).unwrap();
-                self.data[MR::KBDR as usize] = buffer[0] as u16;
-            } else {
-                self.data[MR::KBSR as usize] = 0;
-            }
+        if let Some(cache) = &mut self.cache {
+            cache.data(address);
+        }
+        self.watch(address, Access::Read);
+        if !self.written.contains(address) && !self.is_device(address) {
+            self.uninitialized_read = Some(address);
+        }
+        let value = self.access(address);
+        self.loaded.push((address, value));
+        value
+    }
+
+    // Reads an instruction word, through the instruction cache if there is one.
+    pub fn fetch(&mut self, address: u16) -> u16 {
+        if let Some(cache) = &mut self.cache {
+            cache.fetch(address);
+        }
+        self.executed.insert(address);
+        self.fetches += 1;
+        self.access(address)
+    }
+
+    fn access(&mut self, address: u16) -> u16 {
+        self.reads += 1;
+        let Some(address) = self.check(address) else {
+            return 0;
+        };
+
+        let counts = self.counts();
+        if let Some(value) = (self.counters.as_mut()).and_then(|c| c.read(address, &counts)) {
+            self.activity += 1;
+            return value;
+        }
+        let clock = (self.clock_device.as_mut()).map(|device| (device, self.clock.as_ref()));
+        let device = self.devices.read(address, &mut self.console, clock);
+        if device.is_some() {
+            self.activity += 1;
+        }
+        device.unwrap_or(self.storage[address])
+    }
+
+    // Reads a word without triggering any memory-mapped device.
+    pub fn peek(&self, address: u16) -> u16 {
+        let Some(address) = self.resolve(address) else {
+            return 0;
+        };
+        let counts = self.counts();
+        if let Some(value) = (self.counters.as_ref()).and_then(|c| c.peek(address, &counts)) {
+            return value;
+        }
+        let clock = (self.clock_device.as_ref()).map(|device| (device, self.clock.as_ref()));
+        let device = self.devices.peek(address, clock);
+        device.unwrap_or(self.storage[address])
+    }
+
+    fn counts(&self) -> Counts {
+        Counts {
+            reads: self.reads,
+            writes: self.writes,
+            fetches: self.fetches,
+        }
+    }
+
+    // Maps `address` through the mirrored regions. Returns None past the end
+    // of memory, except for device registers, which are always present.
+    fn resolve(&self, address: u16) -> Option<u16> {
+        let address = self
+            .mirrors
+            .iter()
+            .find(|&&(start, end, _)| (start..=end).contains(&address))
+            .map_or(address, |&(start, _, target)| {
+                target.wrapping_add(address - start)
+            });
+        ((address as usize) < self.size || self.devices().contains(&address)).then_some(address)
+    }
+
+    // The addresses of the device registers, including the clock and the
+    // counters when enabled.
+    pub fn devices(&self) -> Vec<u16> {
+        let mut addresses = self.devices.addresses(self.clock_device.is_some());
+        addresses.extend(self.counters.iter().flat_map(Counters::addresses));
+        addresses
+    }
+
+    pub fn is_device(&self, address: u16) -> bool {
+        self.devices.contains(address, self.clock_device.is_some())
+            || self.counters.as_ref().is_some_and(|c| c.contains(address))
+    }
+
+    // Whether the program can reach `address`: present memory, a mirror of
+    // it or a device register.
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.resolve(address).is_some()
+    }
+
+    // The number of words present, device registers aside.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Resolves an address the program accesses, recording a fault if it is
+    // missing or guarded.
+    fn check(&mut self, address: u16) -> Option<u16> {
+        if self.guards.contains(&address) {
+            self.fault = Some(Fault::Guard(address));
+            return None;
         }
-        self.data[address as usize]
+        let resolved = self.resolve(address);
+        if resolved.is_none() {
+            self.fault = Some(Fault::Missing(address));
+        }
+        resolved
+    }
+
+    // Records a program access that a watchpoint covers. Instruction fetches
+    // are not watched.
+    fn watch(&mut self, address: u16, access: Access) {
+        let watched = self
+            .watchpoints
+            .iter()
+            .find(|(_, w)| w.address == address && w.access.covers(access));
+        if let Some(&watched) = watched {
+            self.watch_hit = Some(watched);
+        }
+    }
+
+    // Returns the latest access that could not complete since the last call.
+    pub fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
+
+    // Makes any access to `address` by the program stop the machine.
+    pub fn guard(&mut self, address: u16) {
+        self.guards.push(address);
     }
 
     pub fn write(&mut self, address: u16, value: u16) {
-        self.data[address as usize] = value;
+        self.writes += 1;
+        if let Some(cache) = &mut self.cache {
+            cache.data(address);
+        }
+        self.watch(address, Access::Write);
+        self.stored.push((address, value));
+        let Some(address) = self.check(address) else {
+            return;
+        };
+
+        let protected = self
+            .read_only
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&address));
+        if protected {
+            self.read_only_write = Some(address);
+            return;
+        }
+
+        if self.counters.as_ref().is_some_and(|c| c.contains(address)) {
+            /* read-only */
+            return;
+        }
+        let clock = self.clock_device.is_some();
+        if self.devices.write(address, value, &mut self.console, clock) {
+            self.activity += 1;
+            return;
+        }
+        if self.storage[address] != value {
+            self.activity += 1;
+        }
+        self.storage[address] = value;
+        self.last_write = Some(address);
+        self.written.insert(address);
+        if self.executed.contains(address) {
+            self.code_write = Some(address);
+        }
+    }
+
+    // Whether the word at `address` was ever loaded or written, as opposed to
+    // holding the fill pattern.
+    pub fn is_written(&self, address: u16) -> bool {
+        self.written.contains(address)
+    }
+
+    // Returns the latest load from a word that was never loaded or written
+    // since the last call, if any.
+    pub fn take_uninitialized_read(&mut self) -> Option<u16> {
+        self.uninitialized_read.take()
+    }
+
+    // Returns the latest store into a word that has run as code since the
+    // last call, if any.
+    pub fn take_code_write(&mut self) -> Option<u16> {
+        self.code_write.take()
+    }
+
+    // Returns the latest store into a read-only range since the last call,
+    // if any.
+    pub fn take_read_only_write(&mut self) -> Option<u16> {
+        self.read_only_write.take()
+    }
+
+    // Moves the program's loads and stores since the last call into `loads`
+    // and `stores`, replacing what they held.
+    pub fn take_accesses(&mut self, loads: &mut Vec<(u16, u16)>, stores: &mut Vec<(u16, u16)>) {
+        loads.clear();
+        stores.clear();
+        std::mem::swap(loads, &mut self.loaded);
+        std::mem::swap(stores, &mut self.stored);
+    }
+
+    // Returns the address of the latest write since the last call, if any.
+    pub fn take_last_write(&mut self) -> Option<u16> {
+        self.last_write.take()
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    pub fn clock_enabled(&self) -> bool {
+        self.devices.clock_enabled()
+    }
+
+    pub fn fill_pattern(&self) -> u16 {
+        self.fill_pattern
+    }
+
+    // Overwrites start..=end with `value`, bypassing devices and protections,
+    // to re-poison memory the program should not rely on, as the debugger's
+    // fill command does.
+    pub fn fill(&mut self, start: u16, end: u16, value: u16) {
+        let devices = self.devices();
+        for address in start..=end {
+            if !devices.contains(&address) {
+                self.poke(address, value);
+                self.written.remove(address);
+            }
+        }
+    }
+
+    // Writes a word bypassing devices and protections, for loading images.
+    pub fn poke(&mut self, address: u16, value: u16) {
+        if let Some(address) = self.resolve(address) {
+            let counter = self.counters.as_ref().is_some_and(|c| c.contains(address));
+            if !counter
+                && !self
+                    .devices
+                    .poke(address, value, self.clock_device.is_some())
+            {
+                self.storage[address] = value;
+                self.written.insert(address);
+            }
+        }
+    }
+
+    // Writes `words` from `origin` on, bypassing devices and protections like
+    // poke. Addresses wrap past xFFFF.
+    pub fn write_slice(&mut self, origin: u16, words: &[u16]) {
+        for (offset, &word) in words.iter().enumerate() {
+            self.poke(origin.wrapping_add(offset as u16), word);
+        }
+    }
+
+    // The words in `range`, read without triggering devices like peek.
+    pub fn read_slice(&self, range: impl RangeBounds<u16>) -> Vec<u16> {
+        self.iter(range).map(|(_, word)| word).collect()
+    }
+
+    // Each address in `range` with the word it holds, as peek reads it.
+    pub fn iter(&self, range: impl RangeBounds<u16>) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as usize,
+            Bound::Excluded(&start) => start as usize + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as usize + 1,
+            Bound::Excluded(&end) => end as usize,
+            Bound::Unbounded => MEMORY_MAX,
+        };
+        (start..end).map(|address| (address as u16, self.peek(address as u16)))
+    }
+
+    // The zero-terminated string at `address`, one character per word as PUTS
+    // prints it. Words that are not characters read as U+FFFD. Like the rest
+    // of the string helpers this peeks and pokes, so devices are untouched.
+    pub fn read_string(&self, address: u16) -> String {
+        self.string_words(address)
+            .map(|word| char::from_u32(u32::from(word)).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+
+    // The string at `address` packed two bytes per word, low byte first, as
+    // PUTSP prints it. A zero byte in either half ends it.
+    pub fn read_packed_string(&self, address: u16) -> String {
+        let bytes: Vec<u8> = self
+            .string_words(address)
+            .flat_map(|word| [word as u8, (word >> 8) as u8])
+            .take_while(|&byte| byte != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn string_words(&self, address: u16) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX)
+            .map(move |i| self.peek(address.wrapping_add(i)))
+            .take_while(|&word| word != 0)
+    }
+
+    // Writes `text` one character per word with a zero terminator. Characters
+    // past U+FFFF do not fit in a word and are written as U+FFFD. Returns the
+    // number of words written.
+    pub fn write_string(&mut self, address: u16, text: &str) -> usize {
+        let words: Vec<u16> = text
+            .chars()
+            .map(|c| u16::try_from(u32::from(c)).unwrap_or(0xFFFD))
+            .chain([0])
+            .collect();
+        self.write_slice(address, &words);
+        words.len()
+    }
+
+    // Writes the UTF-8 bytes of `text` two per word, low byte first. The
+    // string ends with a zero word, or with the zero high byte of its last
+    // word when the length is odd. Returns the number of words written.
+    pub fn write_packed_string(&mut self, address: u16, text: &str) -> usize {
+        let bytes = text.as_bytes();
+        let mut words: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| pair[0] as u16 | pair.get(1).map_or(0, |&high| (high as u16) << 8))
+            .collect();
+        if bytes.len().is_multiple_of(2) {
+            words.push(0);
+        }
+        self.write_slice(address, &words);
+        words.len()
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+// Memory contents are left out, they are better read with `lc3 dump`.
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field("size", &self.size)
+            .field("mirrors", &self.mirrors)
+            .field("guards", &self.guards)
+            .field("fault", &self.fault)
+            .field("watchpoints", &self.watchpoints)
+            .field("console", &self.console)
+            .field("clock", &self.clock)
+            .field("clock_device", &self.clock_device)
+            .field("counters", &self.counters)
+            .field("cache", &self.cache)
+            .field("reads", &self.reads)
+            .field("writes", &self.writes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        breakpoints::{Access, Breakpoint, BreakpointId, Hit, Watchpoint},
+        clock::{ClockMode, VirtualClock, INSTRUCTIONS_PER_MS},
+        config::Config,
+        console::{Console, ConsoleOptions, EofPolicy, OutputKind},
+        defs::{CondFlags, MR, R, TRAP},
+        diag::{Check, Severity},
+        error::RuntimeError,
+        instr::{TrapTable, UnknownTrap},
+        state::{Memory, Registers, State, StepResult, MEMORY_MAX, PC_START},
+    };
+
+    #[test]
+    fn program_counter_init_value() {
+        let reg = Registers::new(PC_START);
+        assert_eq!(PC_START, reg[R::PC]);
+    }
+
+    #[test]
+    fn execute_word_add_immediate() {
+        let mut state = State::new();
+        state.reg[R::R2] = 5;
+
+        state.execute_word(0x12BD); // ADD R1, R2, #-3
+        assert_eq!(2, state.reg[R::R1]);
+        assert_eq!(CondFlags::P, state.reg.cond());
+        assert_eq!(PC_START, state.reg[R::PC]);
+    }
+
+    #[test]
+    fn execute_word_and_sets_zero_flag() {
+        let mut state = State::new();
+        state.reg[R::R3] = 0xFFFF;
+
+        state.execute_word(0x56E0); // AND R3, R3, #0
+        assert_eq!(0, state.reg[R::R3]);
+        assert_eq!(CondFlags::Z, state.reg.cond());
+    }
+
+    #[test]
+    fn execute_word_lea_is_relative_to_current_pc() {
+        let mut state = State::new();
+
+        state.execute_word(0xE1FE); // LEA R0, #-2
+        assert_eq!(PC_START - 2, state.reg[R::R0]);
+        assert_eq!(CondFlags::P, state.reg.cond());
+    }
+
+    #[test]
+    fn config_sets_pc_and_read_only_ranges() {
+        let config = Config {
+            pc_start: 0x4000,
+            read_only: vec![(0x0000, 0x2FFF)],
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        assert_eq!(0x4000, state.reg[R::PC]);
+
+        state.mem.write(0x1000, 0xBEEF);
+        state.mem.write(0x3000, 0xBEEF);
+        assert_eq!(0, state.mem.peek(0x1000));
+        assert_eq!(0xBEEF, state.mem.peek(0x3000));
+
+        state.mem.poke(0x1000, 0xBEEF);
+        assert_eq!(0xBEEF, state.mem.peek(0x1000));
+
+        state.diagnostics.quiet = true;
+        // STR R1, R2, #0; STR R1, R2, #1 with R2 at x1000
+        state.mem.write_slice(0x4000, &[0x7280, 0x7281]);
+        state.reg[R::R2] = 0x1000;
+        state.step_once();
+        state.step_once();
+        assert_eq!((2, 2), state.diagnostics.count(Check::ReadOnly));
+        assert_eq!(0xBEEF, state.mem.peek(0x1000));
+    }
+
+    #[test]
+    fn small_memory_faults_and_mirrors_alias() {
+        let config = Config {
+            memory_size: 0x4000,
+            mirrors: vec![(0x8000, 0x8FFF, 0x3000)],
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+
+        state.mem.poke(0x8001, 0x1234);
+        assert_eq!(0x1234, state.mem.peek(0x3001));
+        assert_eq!(1 << 15, state.mem.read(MR::DSR as u16));
+
+        state.reg[R::R1] = 0x4000;
+        state.execute_word(0x6040); // LDR R0, R1, #0
+        assert_eq!(
+            Some(RuntimeError::MemoryFault {
+                pc: PC_START - 1,
+                address: 0x4000
+            }),
+            state.error
+        );
+    }
+
+    #[test]
+    fn dumping_device_registers_consumes_no_input() {
+        let mut state = State::new();
+        state.mem.console.feed(b"k");
+        let keyboard = MR::KBSR as u16..=MR::KBDR as u16;
+        assert_eq!(vec![0, 0, 0], state.mem.read_slice(keyboard.clone()));
+        assert_eq!(vec![0, 0, 0], state.mem.read_slice(keyboard));
+        assert_eq!(1 << 15, state.mem.read(MR::KBSR as u16));
+        assert_eq!('k' as u16, state.mem.read(MR::KBDR as u16));
+    }
+
+    #[test]
+    fn typed_register_access() {
+        let mut reg = Registers::default();
+        reg.set(R::try_from(3).unwrap(), 0x1234);
+        assert_eq!(0x1234, reg.get(R::R3));
+        reg.set_pc(0x4000);
+        assert_eq!(0x4000, reg.pc());
+        assert_eq!(CondFlags::Z, reg.cond());
+        assert_eq!(Err(10), R::try_from(10));
+        assert_eq!(R::R5, R::field(0xFFFD));
+    }
+
+    #[test]
+    fn condition_codes_display_and_match() {
+        let flags = CondFlags::from_result(0x8000);
+        assert!(flags.is_n() && !flags.is_z() && !flags.is_p());
+        assert_eq!("N", flags.to_string());
+        assert!(flags.matches(0b110)); // BRnz
+        assert!(!flags.matches(0b011)); // BRzp
+        assert!(!CondFlags::is_valid(0b101));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not exactly one of N, Z and P")]
+    fn corrupted_condition_codes_are_caught() {
+        let mut state = State::new();
+        state.reg[R::COND] = 0;
+        state.execute_word(0x0E01); // BRnzp
+    }
+
+    #[test]
+    fn registers_debug_in_hex() {
+        let mut reg = Registers::default();
+        reg[R::R3] = 0xBEEF;
+        let text = format!("{:?}", reg);
+        assert!(text.starts_with("Registers { R0: x0000, R1: x0000, R2: x0000, R3: xBEEF"));
+        assert!(text.ends_with("PC: x3000, COND: Z }"));
+    }
+
+    #[test]
+    fn machine_runs_on_another_thread() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.poke(0x3000, 0x1261); // ADD R1, R1, #1
+        let state = std::thread::spawn(move || {
+            state.step_once();
+            state
+        })
+        .join()
+        .unwrap();
+        assert_eq!(1, state.reg[R::R1]);
+    }
+
+    #[test]
+    fn guard_words_stop_the_machine() {
+        let mut state = State::new();
+        state.mem.guard(0x4000);
+        state.reg[R::R1] = 0x4000;
+        state.execute_word(0x7040); // STR R0, R1, #0
+
+        assert_eq!(
+            Some(RuntimeError::GuardAccess {
+                pc: PC_START - 1,
+                address: 0x4000
+            }),
+            state.error
+        );
+        assert_eq!(0, state.mem.peek(0x4000));
+    }
+
+    #[test]
+    fn fill_pattern_spares_device_registers() {
+        let config = Config {
+            fill: 0xDEAD,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        assert_eq!(0xDEAD, state.mem.peek(0x4000));
+        assert_eq!(0, state.mem.peek(MR::KBSR as u16) & (1 << 15));
+        assert!(state.mem.clock_enabled());
+
+        state.mem.poke(0x4000, 1);
+        state.mem.fill(0x4000, 0x4001, 0xBAD0);
+        assert_eq!(0xBAD0, state.mem.peek(0x4000));
+    }
+
+    #[test]
+    fn strings_are_read_and_written() {
+        let mut mem = Memory::default();
+        assert_eq!(4, mem.write_string(0x4000, "Hé!"));
+        assert_eq!(
+            [0x48, 0xE9, 0x21, 0],
+            [0, 1, 2, 3].map(|i| mem.peek(0x4000 + i))
+        );
+        assert_eq!("Hé!", mem.read_string(0x4000));
+
+        assert_eq!(2, mem.write_packed_string(0x4010, "Hey"));
+        assert_eq!(0x6548, mem.peek(0x4010));
+        assert_eq!("Hey", mem.read_packed_string(0x4010));
+        assert_eq!(2, mem.write_packed_string(0x4020, "ab"));
+        assert_eq!("ab", mem.read_packed_string(0x4020));
+
+        mem.poke(0xFFFF, 0x41);
+        mem.poke(0x0000, 0x42);
+        assert_eq!("AB", mem.read_string(0xFFFF));
+        assert_eq!("", mem.read_string(0x5000));
+    }
+
+    #[test]
+    fn slices_bypass_devices() {
+        let mut mem = Memory::default();
+        mem.write_slice(0xFFFE, &[1, 2, 3]);
+        assert_eq!(vec![1, 2], mem.read_slice(0xFFFE..));
+        assert_eq!(vec![3, 0], mem.read_slice(0..2));
+        assert_eq!(Some((0xFFFF, 2)), mem.iter(0xFFFF..).next());
+        assert_eq!(MEMORY_MAX, mem.iter(..).count());
+
+        mem.poke(MR::KBSR as u16, 1 << 15);
+        assert_eq!(
+            vec![1 << 15, 0, 0],
+            mem.read_slice(MR::KBSR as u16..=MR::KBDR as u16)
+        );
+        assert_eq!(1 << 15, mem.peek(MR::KBSR as u16));
+    }
+
+    #[test]
+    fn clearing_mcr_clock_stops_the_machine() {
+        let mut state = State::new();
+        assert!(state.mem.clock_enabled());
+
+        state.reg[R::R1] = MR::MCR as u16;
+        state.execute_word(0x7040); // STR R0, R1, #0
+        assert!(!state.mem.clock_enabled());
+        assert!(!state.running);
+    }
+
+    #[test]
+    fn display_status_is_always_ready() {
+        let mut state = State::new();
+        state.mem.write(MR::DSR as u16, 0);
+        assert_eq!(1 << 15, state.mem.read(MR::DSR as u16));
+    }
+
+    #[test]
+    fn reading_kbdr_clears_keyboard_ready() {
+        let mut state = State::new();
+        state.mem.poke(MR::KBSR as u16, 1 << 15);
+        state.mem.poke(MR::KBDR as u16, 'a' as u16);
+
+        /* a pending key is reported without polling the host again */
+        assert_eq!(1 << 15, state.mem.read(MR::KBSR as u16));
+        assert_eq!(1 << 15, state.mem.read(MR::KBSR as u16));
+
+        assert_eq!('a' as u16, state.mem.read(MR::KBDR as u16));
+        assert_eq!(0, state.mem.peek(MR::KBSR as u16));
+    }
+
+    #[test]
+    fn stats_count_instructions_traps_and_memory_traffic() {
+        let mut state = State::new();
+        state.reg[R::R1] = 0x4000;
+
+        state.execute_word(0x7040); // STR R0, R1, #0
+        state.execute_word(0x6040); // LDR R0, R1, #0
+        state.execute_word(0xF021); // OUT
+
+        assert_eq!(3, state.stats.instructions);
+        assert_eq!(Some(&1), state.stats.traps.get(&0x21));
+        assert_eq!(1, state.mem.reads());
+        assert_eq!(1, state.mem.writes());
+    }
+
+    #[test]
+    fn breakpoint_stops_before_the_instruction() {
+        let mut state = State::new();
+        let id = state.add_breakpoint(Breakpoint::opcode("ADD").unwrap());
+        state.reg[R::PC] = PC_START + 1;
+
+        state.execute_word(0x1261); // ADD R1, R1, #1
+        assert!(!state.running);
+        assert_eq!(0, state.reg[R::R1]);
+        assert_eq!(PC_START, state.reg[R::PC]);
+        assert_eq!(
+            Some(Hit::Breakpoint {
+                id,
+                address: PC_START,
+                breakpoint: Breakpoint::Opcode(1)
+            }),
+            state.hit
+        );
+    }
+
+    #[test]
+    fn step_reports_hits_and_resume_runs_past_them() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.poke(0x3000, 0x1261); // ADD R1, R1, #1
+        state.mem.poke(0x3001, 0x3201); // ST R1, #1
+        state.mem.poke(0x3002, 0xF025); // HALT
+        let at = state.add_breakpoint(Breakpoint::Address(0x3000));
+        let watch = Watchpoint {
+            address: 0x3003,
+            access: Access::Write,
+        };
+        let watched = state.add_watchpoint(watch);
+        assert_eq!((BreakpointId(1), BreakpointId(2)), (at, watched));
+
+        let hit = Hit::Breakpoint {
+            id: at,
+            address: 0x3000,
+            breakpoint: Breakpoint::Address(0x3000),
+        };
+        assert_eq!(StepResult::BreakpointHit(hit), state.step().result);
+        state.resume();
+        assert_eq!(StepResult::Running, state.step().result);
+        assert_eq!(1, state.reg[R::R1]);
+
+        let hit = Hit::Watchpoint {
+            id: watched,
+            pc: 0x3001,
+            watchpoint: watch,
+        };
+        assert_eq!(StepResult::BreakpointHit(hit), state.step().result);
+        assert_eq!(1, state.mem.peek(0x3003));
+        assert_eq!(0x3002, state.reg[R::PC]);
+
+        assert!(state.remove_breakpoint(watched));
+        assert!(!state.remove_breakpoint(watched));
+        assert_eq!(1, state.breakpoints().len() + state.watchpoints().len());
+        state.resume();
+        assert_eq!(StepResult::Stopped, state.step().result);
+    }
+
+    #[test]
+    fn reserved_opcode_is_an_error() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.poke(0x3000, 0x1261); // ADD R1, R1, #1
+        state.mem.poke(0x3001, 0xD000);
+        state.step_once();
+        assert_eq!(StepResult::Stopped, state.step().result);
+        assert_eq!(
+            Some(RuntimeError::IllegalOpcode {
+                pc: 0x3001,
+                instr: 0xD000
+            }),
+            state.error
+        );
+        assert!(state.history.listing().starts_with("x3000  1261"));
+    }
+
+    #[test]
+    fn checks_fire_on_suspect_accesses() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        // LD R1, x3100; ST R1, x3000; LDR R1, R6, #0
+        state.mem.write_slice(0x3000, &[0x22FF, 0x33FE, 0x6380]);
+        for _ in 0..3 {
+            state.step_once();
+        }
+        /* the LDR reads x0000, which nothing wrote either */
+        assert_eq!((2, 2), state.diagnostics.count(Check::Uninitialized));
+        assert_eq!((1, 1), state.diagnostics.count(Check::SelfModifying));
+        assert_eq!((1, 1), state.diagnostics.count(Check::Stack));
+        assert_eq!(None, state.error);
+
+        let mut state = State::new();
+        state.diagnostics.set(Check::Uninitialized, Severity::Error);
+        state.mem.write_slice(0x3000, &[0x22FF]);
+        state.step_once();
+        let check = Check::Uninitialized;
+        assert_eq!(
+            Some(RuntimeError::Diagnostic { pc: 0x3000, check }),
+            state.error
+        );
+    }
+
+    #[test]
+    fn stray_pc_stops_the_machine() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.poke(0x3000, 0xC040); // JMP R1
+        state.reg[R::R1] = 0xFE02;
+        assert_eq!(StepResult::Running, state.step().result);
+        assert_eq!(StepResult::Stopped, state.step().result);
+        assert_eq!(
+            Some(RuntimeError::StrayPc {
+                pc: 0xFE02,
+                from: Some(0x3000)
+            }),
+            state.error
+        );
+        assert_eq!(1, state.history.entries().count());
+
+        let mut state = State::new();
+        state.pc_checks = false;
+        state.mem.console.feed(b"");
+        state.reg.set_pc(0xFFFF);
+        assert_eq!(StepResult::Running, state.step().result);
+        assert_eq!(StepResult::Stopped, state.step().result);
+        assert_eq!(Some(RuntimeError::PcWrapped), state.error);
+    }
+
+    #[test]
+    fn steps_describe_what_they_did() {
+        let mut state = State::new();
+        state.mem.console.feed(b"A");
+        state.mem.console.capture();
+        // LD R1, #3; ST R1, #3; GETC; OUT; .FILL xFFFF
+        state
+            .mem
+            .write_slice(0x3000, &[0x2203, 0x3203, 0xF020, 0xF021, 0xFFFF]);
+
+        let load = state.step();
+        assert_eq!(StepResult::Running, load.result);
+        assert_eq!(Some((0x3000, 0x2203)), load.executed);
+        assert_eq!(vec![(R::R1, 0xFFFF), (R::PC, 0x3001)], load.registers);
+        assert_eq!(Some((CondFlags::Z, CondFlags::N)), load.flags);
+        assert_eq!(vec![(0x3004, 0xFFFF)], load.loads);
+
+        let store = state.step();
+        assert_eq!(vec![(0x3005, 0xFFFF)], store.stores);
+        assert_eq!((None, vec![]), (store.flags, store.loads));
+
+        let getc = state.step();
+        assert_eq!((b"A".to_vec(), String::new()), (getc.input, getc.output));
+        let out = state.step();
+        assert_eq!((vec![], String::from("A")), (out.input, out.output));
+
+        state.reg.set_pc(0xFE02);
+        let stray = state.step();
+        assert_eq!((StepResult::Stopped, None), (stray.result, stray.executed));
+    }
+
+    #[test]
+    fn queued_input_is_read_where_it_was_queued() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.console.capture();
+        // GETC; LDI R1, #1; LDI R2, #1; .FILL xFE00; .FILL xFE02
+        state
+            .mem
+            .write_slice(0x3000, &[0xF020, 0xA201, 0xA401, 0xFE00, 0xFE02]);
+        state.queue_key(b'x');
+        assert_eq!(b"x".to_vec(), state.step().input);
+        assert_eq!(u16::from(b'x'), state.reg[R::R0]);
+
+        state.queue_input("yz");
+        state.step_once();
+        state.step_once();
+        assert_eq!(
+            (0x8000, u16::from(b'y')),
+            (state.reg[R::R1], state.reg[R::R2])
+        );
+        assert!(state.running && !state.mem.console.is_closed());
+    }
+
+    #[test]
+    fn output_events_say_what_wrote_them() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        state.mem.console.capture();
+        state.record_output();
+        state.queue_input("k\n");
+        state.reg[R::R0] = u16::from(b'A');
+        // OUT; LEA R0, #3; PUTS; IN; HALT; .STRINGZ "hi"
+        let program = [0xF021, 0xE003, 0xF022, 0xF023, 0xF025, 0x68, 0x69, 0];
+        state.mem.write_slice(0x3000, &program);
+        while state.running {
+            state.step_once();
+        }
+
+        let events: Vec<(OutputKind, u64, String)> = (state.take_output().into_iter())
+            .map(|event| (event.kind, event.instruction, event.text))
+            .collect();
+        assert_eq!(
+            vec![
+                (OutputKind::Out, 0, String::from("A")),
+                (OutputKind::Puts, 2, String::from("hi")),
+                (OutputKind::Message, 3, String::from("Enter a character: ")),
+                (OutputKind::Echo, 3, String::from("k")),
+                (OutputKind::Message, 3, String::from("\n")),
+                (OutputKind::Message, 4, String::from("HALT\n")),
+            ],
+            events
+        );
+        assert!(state.take_output().is_empty());
+    }
+
+    #[test]
+    fn range_breakpoints_stop_once_per_visit() {
+        let mut state = State::new();
+        state.mem.console.feed(b"");
+        // a loop at x3001..=x3002 entered from x3000
+        state.mem.write_slice(0x3000, &[0x1261, 0x1261, 0x0FFE]);
+        state.add_breakpoint(Breakpoint::Range(0x3001, 0x3002));
+
+        assert_eq!(StepResult::Running, state.step().result);
+        assert!(matches!(state.step().result, StepResult::BreakpointHit(_)));
+        state.resume();
+        for _ in 0..4 {
+            assert_eq!(StepResult::Running, state.step().result);
+        }
+        assert_eq!(3, state.reg[R::R1]);
+    }
+
+    #[test]
+    fn instruction_limit_stops_the_machine() {
+        let mut state = State::new();
+        state.max_instructions = Some(2);
+        for _ in 0..3 {
+            state.execute_word(0x1021); // ADD R0, R0, #1
+        }
+        assert_eq!(2, state.reg[R::R0]);
+        assert_eq!(
+            Some(RuntimeError::InstructionLimit {
+                pc: PC_START - 1,
+                limit: 2
+            }),
+            state.error
+        );
+    }
+
+    #[test]
+    fn output_and_write_limits_stop_the_machine() {
+        let mut config = Config::default();
+        config.console.max_output = Some(3);
+        let mut state = State::with_config(&config);
+        state.mem.console.capture();
+        state.reg[R::R0] = 0x41;
+        for _ in 0..4 {
+            state.execute_word(0xF021); // OUT
+        }
+        assert_eq!(Some("AAA"), state.mem.console.captured());
+        let limit = RuntimeError::OutputLimit {
+            pc: PC_START - 1,
+            limit: 3,
+        };
+        assert_eq!(Some(limit), state.error);
+
+        let mut state = State::new();
+        state.max_writes = Some(1);
+        state.execute_word(0x3005); // ST R0, #5
+        assert_eq!(None, state.error);
+        state.execute_word(0x3005);
+        let limit = RuntimeError::WriteLimit {
+            pc: PC_START - 1,
+            limit: 1,
+        };
+        assert_eq!(Some(limit), state.error);
+    }
+
+    #[test]
+    fn clock_device_counts_virtual_milliseconds() {
+        let config = Config {
+            clock_mode: Some(ClockMode::Uptime),
+            deterministic: true,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        for _ in 0..INSTRUCTIONS_PER_MS * 3 {
+            state.execute_word(0x0000); // NOP
+        }
+        assert_eq!(0, state.mem.read(MR::CLK as u16));
+        assert_eq!(3, state.mem.read(MR::CLK as u16 + 1));
+
+        /* a clock of the embedder's own */
+        state.mem.clock = Box::new(VirtualClock {
+            instructions: 0x10000 * INSTRUCTIONS_PER_MS,
+        });
+        assert_eq!(1, state.mem.read(MR::CLK as u16));
+
+        /* unmapped by default */
+        assert_eq!(0, State::new().mem.read(MR::CLK as u16 + 1));
+    }
+
+    #[test]
+    fn programs_read_the_performance_counters() {
+        let run = |mask_counters| {
+            let config = Config {
+                counters_mapped: true,
+                mask_counters,
+                ..Config::default()
+            };
+            let mut state = State::with_config(&config);
+            state.mem.console.feed(b"");
+            // LDI R1, #2; LDI R2, #2; STI R2, #0; .FILL xFE0E; .FILL xFE0F
+            state
+                .mem
+                .write_slice(0x3000, &[0xA202, 0xA402, 0xB400, 0xFE0E, 0xFE0F]);
+            for _ in 0..3 {
+                state.step_once();
+            }
+            (state.reg[R::R1], state.reg[R::R2], state.mem.peek(0xFE0F))
+        };
+        /* the first LDI read x3003 and xFE0E, the fetches aside */
+        assert_eq!((0, 2, 2), run(false));
+        assert_eq!((0, 0, 0), run(true));
+        assert!(!State::new().mem.is_device(MR::CNT as u16));
+    }
+
+    #[test]
+    fn deterministic_sleep_advances_the_clock() {
+        let config = Config {
+            clock_mode: Some(ClockMode::Uptime),
+            deterministic: true,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        state.reg[R::R0] = 250;
+        state.execute_word(0xF027); // TRAP x27
+
+        /* the trap itself counts as one instruction */
+        state.mem.read(MR::CLK as u16);
+        assert_eq!(250, state.mem.read(MR::CLK as u16 + 1));
+        assert!(state.yielded);
+    }
+
+    #[test]
+    fn relocated_tables_reach_built_in_routines() {
+        let config = Config {
+            traps: TrapTable {
+                base: 0x0100,
+                routines: vec![(0x0400, TRAP::OUT)],
+            },
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        state.mem.console.feed(b"");
+        state.mem.console.capture();
+        state.mem.poke(0x0130, 0x0400);
+        state.mem.write_slice(0x3000, &[0xF030, 0xF025]); // TRAP x30; HALT
+        state.reg[R::R0] = u16::from(b'!');
+        while state.running {
+            state.step_once();
+        }
+        assert_eq!(None, state.error);
+        assert_eq!(Some("!HALT\n"), state.mem.console.captured());
+
+        /* PC stays out of the relocated table */
+        state.reg.set_pc(0x0130);
+        assert!(state.stray_pc().is_some());
+    }
+
+    #[test]
+    fn set_table_entries_take_over_traps() {
+        let config = Config {
+            fill: 0xDEAD,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        state.mem.console.feed(b"");
+        state.mem.console.capture();
+        state.execute_word(0xF021); // OUT, the fill pattern is no entry
+        assert_eq!(Some("\0"), state.mem.console.captured());
+
+        state.mem.poke(0x0021, 0x0500);
+        state.execute_word(0xF021);
+        assert_eq!((0x0500, PC_START), (state.reg[R::PC], state.reg[R::R7]));
+
+        /* an entry pointing at the built-in routine is serviced at once */
+        state.traps.routines.push((0x0500, TRAP::OUT));
+        state.reg.set_pc(PC_START);
+        state.execute_word(0xF021);
+        assert_eq!(PC_START, state.reg[R::PC]);
+        assert_eq!(Some("\0\0"), state.mem.console.captured());
+    }
+
+    #[test]
+    fn putsp_stops_at_a_zero_byte_in_either_half() {
+        let putsp = |words: &[u16]| {
+            let mut state = State::new();
+            state.mem.console.capture();
+            state.mem.write_slice(0x4000, words);
+            state.reg[R::R0] = 0x4000;
+            state.execute_word(0xF024); // PUTSP
+            state.mem.console.captured().unwrap().to_string()
+        };
+        assert_eq!("Hey", putsp(&[0x6548, 0x0079, 0x0021])); // odd length
+        assert_eq!("Hi", putsp(&[0x6948, 0x0000]));
+        assert_eq!("", putsp(&[0x4100])); // a zero low byte ends it at once
+
+        let mut state = State::new();
+        state.mem.console.capture();
+        state.mem.write_packed_string(0x4000, "odd");
+        state.reg[R::R0] = 0x4000;
+        state.execute_word(0xF024);
+        assert_eq!(Some("odd"), state.mem.console.captured());
+        assert_eq!(2, state.mem.reads());
+    }
+
+    #[test]
+    fn cursor_trap_writes_escape_sequences() {
+        let mut state = State::new();
+        state.mem.console.capture();
+        for (command, row, column) in [(0, 0, 0), (1, 2, 9), (2, 0, 0), (7, 0, 0), (3, 0, 0)] {
+            state.reg[R::R0] = command;
+            state.reg[R::R1] = row;
+            state.reg[R::R2] = column;
+            state.execute_word(0xF028); // CURSOR
+        }
+        assert_eq!(
+            Some("\x1b[2J\x1b[H\x1b[3;10H\x1b[K\x1b[?25l"),
+            state.mem.console.captured()
+        );
+        assert!(state.running);
+    }
+
+    #[test]
+    fn screen_shows_where_the_cursor_put_output() {
+        let mut state = State::new();
+        state.mem.console.capture();
+        state.mem.console.track_screen();
+        state.mem.console.put_str("old text");
+        let draw = [(0, 0, 0, None), (1, 1, 2, Some('@')), (1, 0, 0, Some('#'))];
+        for (command, row, column, c) in draw {
+            state.reg[R::R0] = command;
+            state.reg[R::R1] = row;
+            state.reg[R::R2] = column;
+            state.execute_word(0xF028); // CURSOR
+            if let Some(c) = c {
+                state.reg[R::R0] = c as u16;
+                state.execute_word(0xF021); // OUT
+            }
+        }
+        assert_eq!(
+            Some(String::from("#\n  @")),
+            state.mem.console.screen_text()
+        );
+    }
+
+    #[test]
+    fn in_reads_one_key_and_echoes_it() {
+        let mut state = State::new();
+        state.mem.console.feed(&[0xE9, b'z']);
+        state.mem.console.capture();
+        state.reg[R::R0] = 0xFFFF;
+        state.execute_word(0xF023); // IN
+        assert_eq!(0x00E9, state.reg[R::R0]);
+        assert_eq!(CondFlags::P, state.reg.cond());
+        assert_eq!(
+            Some("Enter a character: \u{e9}\n"),
+            state.mem.console.captured()
+        );
+
+        /* the next key is still there, no Enter was needed */
+        state.execute_word(0xF023);
+        assert_eq!(u16::from(b'z'), state.reg[R::R0]);
+
+        state.execute_word(0xF023);
+        assert!(!state.running);
+        assert_eq!(u16::from(b'z'), state.reg[R::R0]);
+    }
+
+    #[test]
+    fn getc_and_kbdr_zero_extend_keys_and_set_flags() {
+        let getc = |input: &[u8], on_eof| {
+            let mut state = State::new();
+            state.mem.console = Console::new(ConsoleOptions {
+                on_eof,
+                ..ConsoleOptions::default()
+            });
+            state.mem.console.feed(input);
+            state.reg[R::R0] = 0xFFFF;
+            state.execute_word(0xF020); // GETC
+            (
+                state.reg[R::R0],
+                state.reg.cond(),
+                state.running,
+                state.error,
+            )
+        };
+        let halt = EofPolicy::Halt;
+        assert_eq!((0x00E9, CondFlags::P, true, None), getc(&[0xE9], halt));
+        assert_eq!((0x0000, CondFlags::Z, true, None), getc(&[0x00], halt));
+        assert_eq!((0xFFFF, CondFlags::Z, false, None), getc(&[], halt));
+        let sentinel = getc(&[], EofPolicy::Sentinel(0x0004));
+        assert_eq!((0x0004, CondFlags::P, true, None), sentinel);
+        let error = getc(&[], EofPolicy::Error);
+        assert_eq!(
+            Some(RuntimeError::InputClosed { pc: PC_START - 1 }),
+            error.3
+        );
+
+        let mut state = State::new();
+        state.mem.console.feed(&[0xE9]);
+        state.reg[R::R1] = MR::KBDR as u16;
+        for _ in 0..3 {
+            state.mem.read(MR::KBSR as u16);
+            state.execute_word(0x6040); // LDR R0, R1, #0
+            assert_eq!(0x00E9, state.reg[R::R0]);
+            assert_eq!(CondFlags::P, state.reg.cond());
+        }
+    }
+
+    #[test]
+    fn exit_trap_halts_with_r0() {
+        let mut state = State::new();
+        state.reg[R::R0] = 3;
+        state.execute_word(0xF026); // TRAP x26
+        assert!(!state.running);
+        assert_eq!(Some(3), state.exit_status);
+        assert_eq!(None, state.error);
+    }
+
+    #[test]
+    fn unknown_trap_policies() {
+        let mut state = State::new();
+        state.execute_word(0xF030); // TRAP x30
+        assert_eq!(
+            Some(RuntimeError::UnknownTrap {
+                pc: PC_START - 1,
+                vector: 0x30
+            }),
+            state.error
+        );
+
+        let mut state = State::new();
+        state.unknown_trap = UnknownTrap::Ignore;
+        state.execute_word(0xF030);
+        assert!(state.running);
+        assert_eq!(PC_START, state.reg[R::PC]);
+
+        /* a set table entry is taken whatever the policy */
+        let mut state = State::new();
+        state.unknown_trap = UnknownTrap::Ignore;
+        state.mem.poke(0x0030, 0x1000);
+        state.execute_word(0xF030);
+        assert_eq!(0x1000, state.reg[R::PC]);
+        assert_eq!(PC_START, state.reg[R::R7]);
     }
 }
@@ -0,0 +1,127 @@
+// Execution statistics
+//
+// Instruction and trap counts are kept by the executor, memory traffic by
+// Memory. Memory reads include instruction fetches. Simulated cache hits and
+// misses are reported when caches are configured (see cache.rs), and branch
+// predictor scores when branch statistics are collected (see branch.rs).
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    asm::Symbols,
+    branch::{BranchStats, PREDICTORS},
+    cache::{CacheConfig, CacheSim, Counts},
+    defs::TRAP,
+    meta::Metadata,
+    opstats::OpcodeStats,
+    state::State,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub instructions: u64,
+    pub traps: BTreeMap<u16, u64>,     /* executions per trap vector */
+    pub branches: Option<BranchStats>, /* None unless collected */
+    pub opcodes: Option<OpcodeStats>,  /* None unless collected, see opstats.rs */
+}
+
+impl Stats {
+    pub fn count_trap(&mut self, vector: u16) {
+        *self.traps.entry(vector).or_default() += 1;
+    }
+}
+
+pub fn report(state: &State, elapsed: Duration) {
+    let stats = &state.stats;
+    let seconds = elapsed.as_secs_f64();
+    let mips = if seconds > 0.0 {
+        stats.instructions as f64 / seconds / 1_000_000.0
+    } else {
+        0.0
+    };
+
+    eprintln!("instructions: {}", stats.instructions);
+    eprintln!("time: {:.3}s ({:.2} MIPS)", seconds, mips);
+    eprintln!(
+        "memory reads: {}, writes: {}",
+        state.mem.reads(),
+        state.mem.writes()
+    );
+
+    let traps: Vec<String> = stats
+        .traps
+        .iter()
+        .map(|(&vector, count)| match TRAP::try_from(vector) {
+            Ok(trap) => format!("{} {}", trap.name(), count),
+            Err(_) => format!("x{:02X} {}", vector, count),
+        })
+        .collect();
+    if !traps.is_empty() {
+        eprintln!("traps: {}", traps.join(", "));
+    }
+
+    if let Some(branches) = &stats.branches {
+        report_branches(branches);
+    }
+    if let Some(sim) = &state.mem.cache {
+        report_caches(sim, &state.symbols);
+    }
+}
+
+fn report_branches(branches: &BranchStats) {
+    let percent = |count: u64| match branches.branches {
+        0 => 0.0,
+        total => count as f64 * 100.0 / total as f64,
+    };
+    eprintln!(
+        "conditional branches: {} ({} taken, {:.1}%)",
+        branches.branches,
+        branches.taken,
+        percent(branches.taken)
+    );
+    let scores: Vec<String> = PREDICTORS
+        .iter()
+        .zip(branches.mispredicted)
+        .map(|(name, count)| format!("{} {} ({:.1}%)", name, count, percent(count)))
+        .collect();
+    eprintln!("mispredictions: {}", scores.join(", "));
+}
+
+fn report_caches(sim: &CacheSim, symbols: &Symbols) {
+    let describe = |counts: &Counts| {
+        format!(
+            "{} hits, {} misses ({:.1}% hit rate)",
+            counts.hits,
+            counts.misses,
+            counts.hit_rate()
+        )
+    };
+    for (name, cache) in [("icache", &sim.icache), ("dcache", &sim.dcache)] {
+        if let Some(cache) = cache {
+            let CacheConfig { size, ways, line } = cache.config;
+            eprintln!(
+                "{} ({} words, {}-way, {}-word lines): {}",
+                name,
+                size,
+                ways,
+                line,
+                describe(&cache.counts)
+            );
+        }
+    }
+
+    eprintln!("cache accesses by subroutine:");
+    for (&entry, counts) in &sim.routines {
+        let label = Metadata::symbolize(symbols, entry)
+            .map(|name| format!(" <{}>", name))
+            .unwrap_or_default();
+        let mut line = format!("  x{:04X}{}", entry, label);
+        if sim.icache.is_some() {
+            line += &format!("  icache {}", describe(&counts.icache));
+        }
+        if sim.dcache.is_some() {
+            line += &format!("  dcache {}", describe(&counts.dcache));
+        }
+        eprintln!("{}", line);
+    }
+}
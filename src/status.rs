@@ -0,0 +1,83 @@
+// Machine status
+//
+// A fixed layout, one item per line, so the output can be compared in tests:
+//
+// R0 x0041     65     65
+// ...
+// R7 x3001  12289  12289
+// CC N=0 Z=0 P=1
+// PC x3002: F025  HALT
+//
+// Registers are shown in hex, unsigned decimal and signed decimal. When the
+// images carried symbols, PC is also named after the nearest label, as in
+// `PC x3002 <DONE+1>: F025  HALT`.
+
+use crate::{defs::R, disasm::disassemble, meta::Metadata, state::State};
+
+pub fn status(state: &State) -> String {
+    let mut lines: Vec<String> = R::ALL[..8]
+        .iter()
+        .map(|&r| {
+            let value = state.reg[r];
+            format!(
+                "{} x{:04X} {:>6} {:>6}",
+                r.name(),
+                value,
+                value,
+                value as i16
+            )
+        })
+        .collect();
+
+    let cond = state.reg.cond();
+    lines.push(format!(
+        "CC N={} Z={} P={}",
+        u8::from(cond.is_n()),
+        u8::from(cond.is_z()),
+        u8::from(cond.is_p())
+    ));
+
+    let pc = state.reg[R::PC];
+    let next = state.mem.peek(pc);
+    let symbol = Metadata::symbolize(&state.symbols, pc)
+        .map(|name| format!(" <{}>", name))
+        .unwrap_or_default();
+    lines.push(format!(
+        "PC x{:04X}{}: {:04X}  {}",
+        pc,
+        symbol,
+        next,
+        disassemble(pc, next)
+    ));
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{defs::R, state::State, status::status};
+
+    #[test]
+    fn status_layout() {
+        let mut state = State::new();
+        state.mem.poke(0x3000, 0xF025);
+        state.reg[R::R1] = 0xFFFD;
+        state.execute_word(0x1261); // ADD R1, R1, #1
+
+        let expected = "\
+R0 x0000      0      0
+R1 xFFFE  65534     -2
+R2 x0000      0      0
+R3 x0000      0      0
+R4 x0000      0      0
+R5 x0000      0      0
+R6 x0000      0      0
+R7 x0000      0      0
+CC N=1 Z=0 P=0
+PC x3000: F025  HALT";
+        assert_eq!(expected, status(&state));
+
+        state.symbols.insert(String::from("MAIN"), 0x2FFE);
+        assert!(status(&state).ends_with("PC x3000 <MAIN+2>: F025  HALT"));
+    }
+}
@@ -0,0 +1,73 @@
+// Memory storage
+//
+// The plain words of the address space, with no devices, mirrors or
+// protections; those are decoded by Memory before an access gets here. The
+// words are kept in copy-on-write pages, so cloning a machine, for a
+// checkpoint or an embedder's snapshot, shares every page until one of the
+// copies writes it.
+
+use std::{
+    ops::{Index, IndexMut},
+    sync::Arc,
+};
+
+use crate::state::MEMORY_MAX;
+
+const PAGE_SIZE: usize = 256;
+
+#[derive(Clone)]
+pub struct Storage {
+    pages: Vec<Arc<[u16; PAGE_SIZE]>>,
+}
+
+impl Storage {
+    pub fn new(fill: u16) -> Self {
+        /* every page starts out as the same shared page */
+        let page = Arc::new([fill; PAGE_SIZE]);
+        Self {
+            pages: (0..MEMORY_MAX / PAGE_SIZE)
+                .map(|_| Arc::clone(&page))
+                .collect(),
+        }
+    }
+
+    // Whether the page holding `address` is shared with `other`.
+    pub fn shares_page(&self, other: &Storage, address: u16) -> bool {
+        let page = address as usize / PAGE_SIZE;
+        Arc::ptr_eq(&self.pages[page], &other.pages[page])
+    }
+}
+
+impl Index<u16> for Storage {
+    type Output = u16;
+    fn index(&self, address: u16) -> &u16 {
+        let address = address as usize;
+        &self.pages[address / PAGE_SIZE][address % PAGE_SIZE]
+    }
+}
+
+impl IndexMut<u16> for Storage {
+    fn index_mut(&mut self, address: u16) -> &mut u16 {
+        let address = address as usize;
+        &mut Arc::make_mut(&mut self.pages[address / PAGE_SIZE])[address % PAGE_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::Storage;
+
+    #[test]
+    fn clones_share_pages_until_written() {
+        let mut storage = Storage::new(0);
+        storage[0x4000] = 7;
+        let mut copy = storage.clone();
+        assert!(storage.shares_page(&copy, 0x4000));
+
+        copy[0x4001] = 8;
+        assert!(!storage.shares_page(&copy, 0x4000));
+        assert!(storage.shares_page(&copy, 0x4100));
+        assert_eq!((7, 0), (storage[0x4000], storage[0x4001]));
+        assert_eq!((7, 8), (copy[0x4000], copy[0x4001]));
+    }
+}
@@ -1,29 +1,28 @@
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token};
 use std::io;
-use std::os::unix::io::AsRawFd;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd};
 use termios::*;
 
 pub struct InputBuffering {
-    input_buffering_enabled: AtomicBool,
-    original_tio: Option<Termios>,
+    fd: RawFd, /* only used to restore the settings, while the console still owns it */
+    original_tio: Termios,
 }
 
 impl InputBuffering {
-    pub fn disable() -> Self {
+    // Puts the terminal behind `fd` into raw mode.
+    // Returns None, leaving `fd` untouched, when it is not a terminal.
+    pub fn disable(fd: BorrowedFd) -> Option<Self> {
         /* disable input buffering */
 
-        let fd = io::stdin().as_raw_fd();
-        let mut tio = Termios::from_fd(fd).unwrap();
+        let fd = fd.as_raw_fd();
+        let original_tio = Termios::from_fd(fd).ok()?;
+        let mut tio = original_tio;
 
         tio.c_lflag &= !(ICANON | ECHO);
         tcsetattr(fd, TCSANOW, &tio).unwrap();
 
-        Self {
-            input_buffering_enabled: AtomicBool::new(false),
-            original_tio: Some(tio),
-        }
+        Some(Self { fd, original_tio })
     }
 }
 
@@ -31,25 +30,19 @@ impl Drop for InputBuffering {
     fn drop(&mut self) {
         /* restore input buffering */
 
-        let stdin = io::stdin();
-        let fd = stdin.as_raw_fd();
-
-        if let Some(ref original_tio) = self.original_tio {
-            tcsetattr(fd, TCSANOW, original_tio).unwrap();
-            self.input_buffering_enabled.store(true, Ordering::SeqCst);
-        }
+        tcsetattr(self.fd, TCSANOW, &self.original_tio).unwrap();
     }
 }
 
 const STDIN: Token = Token(0);
 
-pub fn check_key() -> io::Result<bool> {
-    let stdin_fd = io::stdin().as_raw_fd();
-
+// Reports whether `fd` has input ready, without blocking.
+pub fn check_key(fd: BorrowedFd) -> io::Result<bool> {
     let mut poll = Poll::new().expect("Failed to create Poll instance");
     let mut events = Events::with_capacity(1024);
 
-    let mut source_fd = SourceFd(&stdin_fd);
+    let fd = fd.as_raw_fd();
+    let mut source_fd = SourceFd(&fd);
     poll.registry()
         .register(&mut source_fd, STDIN, Interest::READABLE)
         .expect("Failed to register stdin");
@@ -58,7 +51,7 @@ pub fn check_key() -> io::Result<bool> {
         .expect("Failed to poll events");
 
     for event in events.iter() {
-        if event.token() == STDIN && event.is_readable() {
+        if event.token() == STDIN && (event.is_readable() || event.is_read_closed()) {
             return Ok(true);
         }
     }
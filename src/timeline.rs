@@ -0,0 +1,230 @@
+// Timeline export
+//
+// With --perfetto FILE the run is written as JSON in the Trace Event format,
+// which chrome://tracing and ui.perfetto.dev open as a zoomable timeline:
+//
+//   {"traceEvents": [
+//   {"name": "PRINT", "cat": "subroutine", "ph": "B", "ts": 2, ...},
+//   {"name": "OUT", "cat": "trap", "ph": "X", "ts": 5, "dur": 1, ...},
+//   {"name": "PRINT", "cat": "subroutine", "ph": "E", "ts": 9, ...},
+//   ...
+//
+// Time is counted in instructions, one to the microsecond. A JSR or JSRR
+// begins a slice named for the label of the routine, or its address, and
+// the RET that lands on its return address ends it; a RET past several
+// return addresses ends all of them, as after a longjmp. A TRAP the machine
+// services is a slice one instruction long, and one that runs a service
+// routine lasts until that routine returns. Slices still open when the run
+// ends are closed there.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    asm::Symbols,
+    defs::{OP, R, TRAP},
+    state::Registers,
+};
+
+const MAX_DEPTH: usize = 1024;
+const RET: u16 = 0xC1C0;
+
+#[derive(Clone, Debug)]
+struct Event {
+    name: String,
+    category: &'static str,
+    phase: char,
+    time: u64,
+    args: Vec<(&'static str, u16)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    labels: BTreeMap<u16, String>,
+    events: Vec<Event>,
+    open: Vec<(u16, String, &'static str)>, /* return address, name, category */
+}
+
+impl Timeline {
+    pub fn new(symbols: &Symbols) -> Self {
+        let mut labels = BTreeMap::new();
+        for (name, &address) in symbols {
+            labels.entry(address).or_insert_with(|| name.clone());
+        }
+        Self {
+            labels,
+            ..Self::default()
+        }
+    }
+
+    // Takes note of `instr`, instruction number `time` of the run, which ran
+    // at `pc` and left the registers as `reg`.
+    pub fn follow(&mut self, time: u64, pc: u16, instr: u16, reg: &Registers) {
+        let next = reg.pc();
+        let returns = pc.wrapping_add(1);
+        if instr == RET {
+            if let Some(i) = self.open.iter().rposition(|&(r, _, _)| r == next) {
+                for (_, name, category) in self.open.drain(i..).rev() {
+                    self.events.push(Event {
+                        name,
+                        category,
+                        phase: 'E',
+                        time: time + 1,
+                        args: Vec::new(),
+                    });
+                }
+            }
+            return;
+        }
+        let (name, category, args) = match OP::try_from(instr >> 12) {
+            Ok(OP::JSR) => {
+                let name = match self.labels.get(&next) {
+                    Some(label) => label.clone(),
+                    None => format!("x{:04X}", next),
+                };
+                let args = vec![("call_site", pc), ("R0", reg[R::R0]), ("R1", reg[R::R1])];
+                (name, "subroutine", args)
+            }
+            Ok(OP::TRAP) => {
+                let vector = instr & 0xFF;
+                let name = match TRAP::try_from(vector) {
+                    Ok(trap) => trap.name().to_string(),
+                    Err(_) => format!("TRAP x{:02X}", vector),
+                };
+                if next == returns {
+                    self.events.push(Event {
+                        name,
+                        category: "trap",
+                        phase: 'X',
+                        time,
+                        args: vec![("call_site", pc), ("R0", reg[R::R0])],
+                    });
+                    return;
+                }
+                (name, "trap", vec![("call_site", pc)])
+            }
+            _ => return,
+        };
+        if self.open.len() == MAX_DEPTH {
+            /* runaway recursion, this call will not be matched */
+            return;
+        }
+        self.open.push((returns, name.clone(), category));
+        self.events.push(Event {
+            name,
+            category,
+            phase: 'B',
+            time,
+            args,
+        });
+    }
+
+    // The timeline as JSON, with the slices still open ended at `end`.
+    pub fn json(&self, end: u64) -> String {
+        let closing: Vec<Event> = (self.open.iter().rev())
+            .map(|(_, name, category)| Event {
+                name: name.clone(),
+                category,
+                phase: 'E',
+                time: end,
+                args: Vec::new(),
+            })
+            .collect();
+        let mut out = String::from("{\"traceEvents\": [\n");
+        out.push_str(
+            "{\"name\": \"process_name\", \"ph\": \"M\", \"pid\": 1, \"tid\": 1, \
+             \"args\": {\"name\": \"lc3\"}}",
+        );
+        for event in self.events.iter().chain(&closing) {
+            let _ = write!(
+                out,
+                ",\n{{\"name\": \"{}\", \"cat\": \"{}\", \"ph\": \"{}\", \"ts\": {}, ",
+                escape(&event.name),
+                event.category,
+                event.phase,
+                event.time
+            );
+            if event.phase == 'X' {
+                out.push_str("\"dur\": 1, ");
+            }
+            out.push_str("\"pid\": 1, \"tid\": 1");
+            if !event.args.is_empty() {
+                let args: Vec<String> = event
+                    .args
+                    .iter()
+                    .map(|(key, value)| format!("\"{}\": \"x{:04X}\"", key, value))
+                    .collect();
+                let _ = write!(out, ", \"args\": {{{}}}", args.join(", "));
+            }
+            out.push('}');
+        }
+        out.push_str("\n]}\n");
+        out
+    }
+}
+
+// `text` as the inside of a JSON string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c < ' ' => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{asm::Symbols, state::State, timeline::Timeline};
+
+    fn run(words: &[u16]) -> String {
+        let mut state = State::new();
+        state.max_instructions = Some(100);
+        state.mem.write_slice(0x3000, words);
+        state.mem.console.capture();
+        let symbols = Symbols::from([(String::from("PRINT"), 0x3003)]);
+        state.timeline = Some(Timeline::new(&symbols));
+        while state.running {
+            state.step_once();
+        }
+        state.timeline.unwrap().json(state.stats.instructions)
+    }
+
+    #[test]
+    fn calls_and_traps_become_slices() {
+        // JSR PRINT; OUT; HALT; PRINT: ADD R1, R1, #1; RET
+        let json = run(&[0x4802, 0xF021, 0xF025, 0x1261, 0xC1C0]);
+        let events: Vec<&str> = json.lines().skip(2).collect();
+        assert_eq!(
+            vec![
+                "{\"name\": \"PRINT\", \"cat\": \"subroutine\", \"ph\": \"B\", \"ts\": 0, \
+                 \"pid\": 1, \"tid\": 1, \"args\": {\"call_site\": \"x3000\", \
+                 \"R0\": \"x0000\", \"R1\": \"x0000\"}},",
+                "{\"name\": \"PRINT\", \"cat\": \"subroutine\", \"ph\": \"E\", \"ts\": 3, \
+                 \"pid\": 1, \"tid\": 1},",
+                "{\"name\": \"OUT\", \"cat\": \"trap\", \"ph\": \"X\", \"ts\": 3, \"dur\": 1, \
+                 \"pid\": 1, \"tid\": 1, \"args\": {\"call_site\": \"x3001\", \
+                 \"R0\": \"x0000\"}},",
+                "{\"name\": \"HALT\", \"cat\": \"trap\", \"ph\": \"X\", \"ts\": 4, \"dur\": 1, \
+                 \"pid\": 1, \"tid\": 1, \"args\": {\"call_site\": \"x3002\", \
+                 \"R0\": \"x0000\"}}",
+                "]}",
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn open_slices_close_at_the_end() {
+        // JSR PRINT; HALT; .FILL 0; PRINT: BRnzp PRINT
+        let json = run(&[0x4802, 0xF025, 0x0000, 0x0FFF]);
+        assert!(json.contains("\"name\": \"PRINT\", \"cat\": \"subroutine\", \"ph\": \"E\""));
+    }
+}
@@ -0,0 +1,794 @@
+// Recorded runs
+//
+// `lc3 --record FILE` writes every state change of a run to a trace file,
+// which `lc3 trace-view FILE` steps through forwards and backwards without
+// running the program again. A trace is big-endian words, with lengths and
+// offsets as two words, high first:
+//
+//   TRACE_MAGIC  VERSION  LENGTH  initial
+//   STEPS  WORDS  records...
+//   KEYFRAMES  keyframes...
+//
+// The LENGTH words of initial are the machine as the run started: its
+// registers, then memory as the word most of it holds and the runs of words
+// that differ from it, then the symbols as a metadata block (see meta.rs):
+//
+//   R0-R7  PC  COND  FILL  RUNS  (ADDRESS  COUNT  words...)...  metadata
+//
+// so a small program's trace does not carry all 64K words.
+//
+// The STEPS instructions of the run follow as WORDS words of records, one
+// MASK word each and the values it calls for:
+//
+//   bits 0-9    R::ALL[i] changed; its new value follows, in R::ALL order
+//   bit 12      PC moved on to the next word, so its value is left out
+//   bit 13      the instruction word follows, as it is not the one in memory
+//   bit 15      a word was stored; ADDRESS and VALUE follow
+//
+// The address and word of each instruction are otherwise the ones the
+// replayed machine is about to run, which keeps most records to two or three
+// words. A MASK of exactly SYNC is no instruction but the ten registers, for
+// when something other than an instruction changed them, such as the
+// scheduler switching processes.
+//
+// Every KEYFRAME_INTERVAL instructions there is a keyframe: the instruction
+// it stands before, the offset of its record, the registers, and each word
+// stored so far with its value then:
+//
+//   STEP  OFFSET  R0-R7  PC  COND  COUNT  (ADDRESS  VALUE)...
+//
+// Trace::state_at(n) starts from the last keyframe at or before n, so it
+// replays at most KEYFRAME_INTERVAL instructions however long the run was.
+// Console output, and memory changed by the devices, are not recorded.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+};
+
+use crate::{
+    defs::{CondFlags, R},
+    disasm::disassemble,
+    meta::Metadata,
+    playground,
+    snapshot::Snapshot,
+    state::{Registers, State, MEMORY_MAX},
+    status,
+};
+
+pub const TRACE_MAGIC: u16 = 0x5452; /* "TR" */
+const VERSION: u16 = 3;
+const KEYFRAME_INTERVAL: usize = 4096;
+const REGISTER_BITS: u16 = 0x03FF;
+const NEXT_BIT: u16 = 0x1000;
+const INSTR_BIT: u16 = 0x2000;
+const WRITE_BIT: u16 = 0x8000;
+const SYNC: u16 = 0x4000;
+
+// What one instruction changed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub pc: u16,
+    pub instr: u16,
+    pub registers: Vec<(R, u16)>, /* registers changed, with their new values */
+    pub write: Option<(u16, u16)>, /* the word stored and its new value */
+}
+
+fn push_long(words: &mut Vec<u16>, value: usize) {
+    words.extend([(value >> 16) as u16, value as u16]);
+}
+
+fn long(words: &[u16], at: usize) -> io::Result<usize> {
+    match words.get(at..at + 2) {
+        Some(&[high, low]) => Ok(((high as usize) << 16) | low as usize),
+        _ => Err(truncated()),
+    }
+}
+
+fn to_words(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+// A trace being recorded
+#[derive(Clone, Debug)]
+pub struct TraceWriter {
+    snapshot: Vec<u16>,
+    records: Vec<u16>,
+    steps: usize,
+    keyframes: Vec<u16>,
+    keyframe_count: usize,
+    reg: Registers,             /* as the replayed machine has them */
+    memory: Vec<u16>,           /* likewise */
+    stored: BTreeMap<u16, u16>, /* every word stored so far, for keyframes */
+}
+
+impl TraceWriter {
+    // Starts a trace of `state` as it is now.
+    pub fn new(state: &State) -> Self {
+        let initial = Snapshot::take(state);
+        Self {
+            snapshot: initial_words(&initial),
+            records: Vec::new(),
+            steps: 0,
+            keyframes: Vec::new(),
+            keyframe_count: 0,
+            reg: initial.reg,
+            memory: initial.memory,
+            stored: BTreeMap::new(),
+        }
+    }
+
+    // Records `instr`, which ran at `pc` with the registers `before` and left
+    // them as `after`, and stored `write` if it stored a word.
+    pub fn record(
+        &mut self,
+        pc: u16,
+        instr: u16,
+        before: &Registers,
+        after: &Registers,
+        write: Option<(u16, u16)>,
+    ) {
+        if *before != self.reg {
+            self.records.push(SYNC);
+            self.records.extend(R::ALL.map(|r| before[r]));
+        }
+        if self.steps > 0 && self.steps.is_multiple_of(KEYFRAME_INTERVAL) {
+            push_long(&mut self.keyframes, self.steps);
+            push_long(&mut self.keyframes, self.records.len());
+            self.keyframes.extend(R::ALL.map(|r| before[r]));
+            push_long(&mut self.keyframes, self.stored.len());
+            for (&address, &value) in &self.stored {
+                self.keyframes.extend([address, value]);
+            }
+            self.keyframe_count += 1;
+        }
+
+        let mut mask = 0;
+        let mut values = Vec::new();
+        for (i, r) in R::ALL.into_iter().enumerate() {
+            if before[r] != after[r] && !(r == R::PC && after[r] == pc.wrapping_add(1)) {
+                mask |= 1 << i;
+                values.push(after[r]);
+            }
+        }
+        if after.pc() == pc.wrapping_add(1) {
+            mask |= NEXT_BIT;
+        }
+        if self.memory[pc as usize] != instr {
+            mask |= INSTR_BIT;
+            values.push(instr);
+        }
+        if let Some((address, value)) = write {
+            mask |= WRITE_BIT;
+            values.extend([address, value]);
+            self.memory[address as usize] = value;
+            self.stored.insert(address, value);
+        }
+        self.records.push(mask);
+        self.records.extend(values);
+        self.reg = after.clone();
+        self.steps += 1;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = vec![TRACE_MAGIC, VERSION];
+        push_long(&mut words, self.snapshot.len());
+        words.extend(&self.snapshot);
+        push_long(&mut words, self.steps);
+        push_long(&mut words, self.records.len());
+        words.extend(&self.records);
+        push_long(&mut words, self.keyframe_count);
+        words.extend(&self.keyframes);
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Keyframe {
+    step: usize,
+    offset: usize, /* of the step's record */
+    reg: Registers,
+    stored: Vec<(u16, u16)>,
+}
+
+pub struct Trace {
+    pub initial: Snapshot,
+    steps: usize,
+    records: Vec<u16>,
+    keyframes: Vec<Keyframe>, /* by step */
+}
+
+impl Trace {
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(invalid("trace has an odd number of bytes"));
+        }
+        let words = to_words(bytes);
+        if words.first() != Some(&TRACE_MAGIC) {
+            return Err(invalid("not a trace file"));
+        }
+        if words.get(1) != Some(&VERSION) {
+            return Err(invalid("unsupported trace version"));
+        }
+        let length = long(&words, 2)?;
+        let initial = words.get(4..4 + length).ok_or_else(truncated)?;
+        let initial = parse_initial(initial)?;
+
+        let mut at = 4 + length;
+        let steps = long(&words, at)?;
+        let length = long(&words, at + 2)?;
+        let records = words.get(at + 4..at + 4 + length).ok_or_else(truncated)?;
+        if count_steps(records)? != steps {
+            return Err(invalid("trace has the wrong number of steps"));
+        }
+        at += 4 + length;
+
+        let count = long(&words, at)?;
+        at += 2;
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        for _ in 0..count {
+            let step = long(&words, at)?;
+            let offset = long(&words, at + 2)?;
+            let values = words.get(at + 4..at + 4 + R::COUNT).ok_or_else(truncated)?;
+            let mut reg = Registers::new(0);
+            for (r, &value) in R::ALL.into_iter().zip(values) {
+                reg[r] = value;
+            }
+            at += 4 + R::COUNT;
+            let stored = long(&words, at)?;
+            let pairs = words
+                .get(at + 2..at + 2 + 2 * stored)
+                .ok_or_else(truncated)?;
+            at += 2 + 2 * stored;
+            let previous = keyframes.last().map_or(0, |k| k.step);
+            if step <= previous || step > steps || offset > records.len() {
+                return Err(invalid("trace has a bad keyframe"));
+            }
+            keyframes.push(Keyframe {
+                step,
+                offset,
+                reg,
+                stored: pairs.chunks_exact(2).map(|p| (p[0], p[1])).collect(),
+            });
+        }
+        if at != words.len() {
+            return Err(invalid("trace has trailing words"));
+        }
+        Ok(Self {
+            initial,
+            steps,
+            records: records.to_vec(),
+            keyframes,
+        })
+    }
+
+    // The number of instructions the run took.
+    pub fn len(&self) -> usize {
+        self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps == 0
+    }
+
+    // The machine as it was before instruction `n` ran, counting from 0, so
+    // state_at(len()) is the machine at the end of the run. None past that.
+    pub fn state_at(&self, n: usize) -> Option<State> {
+        self.seek(n).map(|(state, _)| state)
+    }
+
+    // The machine before instruction `n`, and the offset of its record.
+    fn seek(&self, n: usize) -> Option<(State, usize)> {
+        if n > self.steps {
+            return None;
+        }
+        let mut state = State::new();
+        state.mem.write_slice(0, &self.initial.memory);
+        state.symbols = self.initial.symbols.clone();
+        let keyframe = self.keyframes.partition_point(|k| k.step <= n);
+        let (mut step, mut offset) = match keyframe.checked_sub(1) {
+            Some(i) => {
+                let keyframe = &self.keyframes[i];
+                state.reg = keyframe.reg.clone();
+                for &(address, value) in &keyframe.stored {
+                    state.mem.poke(address, value);
+                }
+                (keyframe.step, keyframe.offset)
+            }
+            None => {
+                state.reg = self.initial.reg.clone();
+                (0, 0)
+            }
+        };
+        while step < n {
+            offset = self.apply(&mut state, offset).1;
+            step += 1;
+        }
+        Some((state, offset))
+    }
+
+    // The instruction whose record is at `offset`, about to run on `state`,
+    // the registers a sync before it sets, and the offset of the next record.
+    fn decode(&self, state: &State, offset: usize) -> (Step, Option<Registers>, usize) {
+        let word = |at: usize| self.records.get(at).copied().unwrap_or_default();
+        let mut at = offset;
+        let mut sync = None;
+        if word(at) == SYNC {
+            let mut reg = Registers::new(0);
+            for (i, r) in R::ALL.into_iter().enumerate() {
+                reg[r] = word(at + 1 + i);
+            }
+            sync = Some(reg);
+            at += 1 + R::COUNT;
+        }
+        let pc = sync.as_ref().unwrap_or(&state.reg).pc();
+        let mask = word(at);
+        at += 1;
+
+        let mut registers = Vec::new();
+        for (i, r) in R::ALL.into_iter().enumerate() {
+            if mask & (1 << i) != 0 {
+                registers.push((r, word(at)));
+                at += 1;
+            } else if r == R::PC && mask & NEXT_BIT != 0 {
+                registers.push((r, pc.wrapping_add(1)));
+            }
+        }
+        let instr = match mask & INSTR_BIT != 0 {
+            true => {
+                at += 1;
+                word(at - 1)
+            }
+            false => state.mem.peek(pc),
+        };
+        let write = (mask & WRITE_BIT != 0).then(|| {
+            at += 2;
+            (word(at - 2), word(at - 1))
+        });
+        let step = Step {
+            pc,
+            instr,
+            registers,
+            write,
+        };
+        (step, sync, at)
+    }
+
+    // Replays the instruction whose record is at `offset` on `state`, and
+    // returns it and the offset of the next record.
+    fn apply(&self, state: &mut State, offset: usize) -> (Step, usize) {
+        let (step, sync, next) = self.decode(state, offset);
+        if let Some(reg) = sync {
+            state.reg = reg;
+        }
+        for &(r, value) in &step.registers {
+            state.reg[r] = value;
+        }
+        if let Some((address, value)) = step.write {
+            state.mem.poke(address, value);
+        }
+        (step, next)
+    }
+}
+
+// Checks that `records` holds whole records, and counts the instructions.
+// The initial section of a trace of the machine as `snapshot` has it.
+fn initial_words(snapshot: &Snapshot) -> Vec<u16> {
+    let mut counts = BTreeMap::new();
+    for &word in &snapshot.memory {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    let fill = (counts.into_iter())
+        .max_by_key(|&(_, count)| count)
+        .map_or(0, |(word, _)| word);
+
+    let mut runs = Vec::new();
+    let mut count = 0;
+    let mut address = 0;
+    while address < snapshot.memory.len() {
+        if snapshot.memory[address] == fill {
+            address += 1;
+            continue;
+        }
+        let length = (snapshot.memory[address..].iter())
+            .take_while(|&&word| word != fill)
+            .count();
+        runs.push(address as u16);
+        push_long(&mut runs, length);
+        runs.extend(&snapshot.memory[address..address + length]);
+        count += 1;
+        address += length;
+    }
+
+    let mut words: Vec<u16> = R::ALL.map(|r| snapshot.reg[r]).to_vec();
+    words.push(fill);
+    push_long(&mut words, count);
+    words.extend(runs);
+    let metadata = Metadata {
+        symbols: snapshot.symbols.clone(),
+        ..Metadata::default()
+    };
+    words.extend(metadata.to_words());
+    words
+}
+
+fn parse_initial(words: &[u16]) -> io::Result<Snapshot> {
+    let mut words = words.to_vec();
+    let symbols = Metadata::strip(&mut words)
+        .map(|metadata| metadata.symbols)
+        .unwrap_or_default();
+    let values = words.get(..R::COUNT).ok_or_else(truncated)?;
+    let mut reg = Registers::new(0);
+    for (r, &value) in R::ALL.into_iter().zip(values) {
+        reg[r] = value;
+    }
+    if !CondFlags::is_valid(reg[R::COND]) {
+        return Err(invalid("trace has invalid condition codes"));
+    }
+    let fill = *words.get(R::COUNT).ok_or_else(truncated)?;
+    let mut memory = vec![fill; MEMORY_MAX];
+    let runs = long(&words, R::COUNT + 1)?;
+    let mut at = R::COUNT + 3;
+    for _ in 0..runs {
+        let address = *words.get(at).ok_or_else(truncated)? as usize;
+        let length = long(&words, at + 1)?;
+        let run = words.get(at + 3..at + 3 + length).ok_or_else(truncated)?;
+        memory
+            .get_mut(address..address + length)
+            .ok_or_else(|| invalid("trace has a run past xFFFF"))?
+            .copy_from_slice(run);
+        at += 3 + length;
+    }
+    if at != words.len() {
+        return Err(invalid("trace has trailing words after the initial state"));
+    }
+    Ok(Snapshot {
+        reg,
+        memory,
+        symbols,
+    })
+}
+
+fn count_steps(records: &[u16]) -> io::Result<usize> {
+    let mut steps = 0;
+    let mut at = 0;
+    while let Some(&mask) = records.get(at) {
+        at += 1;
+        if mask == SYNC {
+            at += R::COUNT;
+            continue;
+        }
+        at += (mask & REGISTER_BITS).count_ones() as usize;
+        at += (mask & INSTR_BIT != 0) as usize + 2 * (mask & WRITE_BIT != 0) as usize;
+        steps += 1;
+    }
+    match at == records.len() {
+        true => Ok(steps),
+        false => Err(truncated()),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> io::Error {
+    invalid("trace is truncated")
+}
+
+pub const VIEWER_COMMANDS: [&str; 8] = [
+    "next", "back", "goto", "find", "regs", "mem", "help", "quit",
+];
+
+const VIEWER_HELP: &str = "\
+next [N]       step N instructions forward (n)
+back [N]       step N instructions back (b)
+goto INDEX     move to before instruction INDEX, counted from 0
+find ADDR      move forward to the next instruction at ADDR
+regs           show the registers (r)
+mem ADDR [N]   show N words of memory from ADDR (m)
+help           show this list
+quit           leave the viewer (q)";
+
+// What a step taken overwrote, to put it back
+type Undo = (Registers, Option<(u16, u16)>, usize);
+
+// Moves through a trace. The position is an instruction index: the machine
+// state shown is the one before that instruction ran, so position 0 is the
+// start of the run and the length of the trace its end. Short moves replay
+// steps or undo them; longer ones seek from a keyframe.
+pub struct Viewer {
+    trace: Trace,
+    state: State,
+    position: usize,
+    offset: usize, /* of the next record */
+    undo: Vec<Undo>,
+}
+
+impl Viewer {
+    pub fn new(trace: Trace) -> Self {
+        let (state, offset) = trace.seek(0).expect("a trace has a start");
+        Self {
+            trace,
+            state,
+            position: 0,
+            offset,
+            undo: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    // Moves one instruction forward. Returns false at the end of the trace.
+    pub fn forward(&mut self) -> bool {
+        if self.position == self.trace.len() {
+            return false;
+        }
+        let (step, _, _) = self.trace.decode(&self.state, self.offset);
+        let old = step
+            .write
+            .map(|(address, _)| (address, self.state.mem.peek(address)));
+        self.undo.push((self.state.reg.clone(), old, self.offset));
+        self.offset = self.trace.apply(&mut self.state, self.offset).1;
+        self.position += 1;
+        true
+    }
+
+    // Moves one instruction back. Returns false at the start of the trace.
+    pub fn backward(&mut self) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+        match self.undo.pop() {
+            Some((reg, old, offset)) => {
+                self.state.reg = reg;
+                if let Some((address, value)) = old {
+                    self.state.mem.poke(address, value);
+                }
+                self.offset = offset;
+                self.position -= 1;
+            }
+            None => self.seek(self.position - 1),
+        }
+        true
+    }
+
+    pub fn goto(&mut self, index: usize) {
+        let index = index.min(self.trace.len());
+        let near = match index >= self.position {
+            true => index - self.position <= KEYFRAME_INTERVAL,
+            false => self.position - index <= self.undo.len(),
+        };
+        if !near {
+            self.seek(index);
+        }
+        while self.position < index && self.forward() {}
+        while self.position > index && self.backward() {}
+    }
+
+    fn seek(&mut self, index: usize) {
+        let (state, offset) = self.trace.seek(index).expect("an index in the trace");
+        self.state = state;
+        self.offset = offset;
+        self.position = index;
+        self.undo.clear();
+    }
+
+    // Where the viewer is, and the instruction about to run.
+    pub fn describe(&self) -> String {
+        let len = self.trace.len();
+        if self.position == len {
+            return format!("#{} of {}  end of the run", self.position, len);
+        }
+        let (step, _, _) = self.trace.decode(&self.state, self.offset);
+        format!(
+            "#{} of {}  x{:04X}  {:04X}  {}",
+            self.position,
+            len,
+            step.pc,
+            step.instr,
+            disassemble(step.pc, step.instr)
+        )
+    }
+
+    // Runs a viewer command and returns what to show, or None to quit.
+    pub fn execute_line(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Some(String::new());
+        };
+        let value = |i: usize| -> Result<u16, String> {
+            let text = args
+                .get(i)
+                .ok_or(format!("{} expects more arguments", name))?;
+            match self.state.symbols.get(*text) {
+                Some(&address) => Ok(address),
+                None => playground::parse(text),
+            }
+        };
+        let count = |i: usize| -> Result<usize, String> {
+            args.get(i).map_or(Ok(1), |text| {
+                text.parse().map_err(|_| format!("{} is not a count", text))
+            })
+        };
+        let result = match name {
+            "next" | "n" => count(0).map(|n| self.goto(self.position.saturating_add(n))),
+            "back" | "b" => count(0).map(|n| self.goto(self.position.saturating_sub(n))),
+            "goto" | "g" => match args.first().map(|text| text.parse()) {
+                Some(Ok(index)) => {
+                    self.goto(index);
+                    Ok(())
+                }
+                _ => Err(format!("{} expects an instruction index", name)),
+            },
+            "find" | "f" => value(0).and_then(|address| {
+                let start = self.position;
+                while self.forward() && self.position < self.trace.len() {
+                    let (step, _, _) = self.trace.decode(&self.state, self.offset);
+                    if step.pc == address {
+                        return Ok(());
+                    }
+                }
+                self.goto(start);
+                Err(format!("x{:04X} does not run again", address))
+            }),
+            "regs" | "r" => return Some(status::status(&self.state)),
+            "mem" | "m" => {
+                return Some(match (value(0), count(1)) {
+                    (Ok(start), Ok(n)) => (0..n.min(0xFFFF) as u16)
+                        .map(|i| {
+                            let address = start.wrapping_add(i);
+                            let word = self.state.mem.peek(address);
+                            format!(
+                                "x{:04X}  x{:04X}  {}",
+                                address,
+                                word,
+                                disassemble(address, word)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    (Err(e), _) | (_, Err(e)) => e,
+                })
+            }
+            "help" | "h" => return Some(String::from(VIEWER_HELP)),
+            "quit" | "q" => return None,
+            _ => Err(format!("unknown command {}", name)),
+        };
+        Some(match result {
+            Ok(()) => self.describe(),
+            Err(e) => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        defs::R,
+        snapshot::Snapshot,
+        state::State,
+        tracefile::{Trace, TraceWriter, Viewer},
+    };
+
+    fn recorded() -> Vec<u8> {
+        let mut state = State::new();
+        // ADD R1, R1, #1; ST R1, x3004; ADD R1, R1, #1; HALT
+        state
+            .mem
+            .write_slice(0x3000, &[0x1261, 0x3202, 0x1261, 0xF025]);
+        state.mem.console.capture();
+        state.recording = Some(TraceWriter::new(&state));
+        while state.running {
+            state.step_once();
+        }
+        state.recording.unwrap().to_bytes()
+    }
+
+    // A loop counting in R1 and storing it, run for `steps` instructions,
+    // with R2 set from outside part way through.
+    fn counting(steps: usize, record: bool) -> State {
+        let mut state = State::new();
+        // ADD R1, R1, #1; ST R1, x3003; BRnzp x3000
+        state.mem.write_slice(0x3000, &[0x1261, 0x3201, 0x0FFD]);
+        if record {
+            state.recording = Some(TraceWriter::new(&state));
+        }
+        for i in 0..steps {
+            if i == 5000 {
+                state.reg[R::R2] = 7;
+            }
+            state.step_once();
+        }
+        state
+    }
+
+    #[test]
+    fn traces_round_trip() {
+        let trace = Trace::parse(&recorded()).unwrap();
+        assert_eq!(4, trace.len());
+        assert_eq!(0x3000, trace.initial.reg[R::PC]);
+        let state = trace.state_at(2).unwrap();
+        assert_eq!((1, 0x3002), (state.reg[R::R1], state.reg[R::PC]));
+        assert_eq!(1, state.mem.peek(0x3004));
+        assert!(trace.state_at(5).is_none());
+
+        let mut bytes = recorded();
+        bytes.truncate(bytes.len() - 2);
+        assert!(Trace::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn initial_memory_is_kept_as_runs() {
+        assert!(recorded().len() < 200, "{} bytes", recorded().len());
+
+        let config = Config {
+            fill: 0xDEAD,
+            ..Config::default()
+        };
+        let mut state = State::with_config(&config);
+        state.mem.write_slice(0x3000, &[0xF025, 0xDEAD, 0x0000]);
+        state.mem.poke(0xFFFF, 0x1234);
+        state.symbols.insert(String::from("MAIN"), 0x3000);
+        let bytes = TraceWriter::new(&state).to_bytes();
+        assert!(bytes.len() < 200, "{} bytes", bytes.len());
+
+        let initial = Trace::parse(&bytes).unwrap().initial;
+        assert_eq!(Snapshot::take(&state).memory, initial.memory);
+        assert_eq!(state.symbols, initial.symbols);
+    }
+
+    #[test]
+    fn long_traces_seek_from_keyframes() {
+        let bytes = counting(10000, true).recording.unwrap().to_bytes();
+        let empty = counting(0, true).recording.unwrap().to_bytes();
+        assert!(bytes.len() - empty.len() < 10000 * 6);
+        let trace = Trace::parse(&bytes).unwrap();
+        for n in [0, 4095, 4096, 4097, 5000, 5001, 9999, 10000] {
+            let state = trace.state_at(n).unwrap();
+            let expected = counting(n, false);
+            assert_eq!(expected.reg, state.reg, "at {}", n);
+            assert_eq!(expected.mem.peek(0x3003), state.mem.peek(0x3003));
+        }
+    }
+
+    #[test]
+    fn the_viewer_scrubs_both_ways() {
+        let mut viewer = Viewer::new(Trace::parse(&recorded()).unwrap());
+        viewer.execute_line("next 3");
+        assert_eq!((3, 2), (viewer.position(), viewer.state().reg[R::R1]));
+        assert_eq!(1, viewer.state().mem.peek(0x3004));
+
+        assert_eq!(
+            Some(String::from("#1 of 4  x3001  3202  ST R1, x3004")),
+            viewer.execute_line("back 2")
+        );
+        assert_eq!(0, viewer.state().mem.peek(0x3004));
+        viewer.execute_line("goto 99");
+        assert_eq!(4, viewer.position());
+        viewer.execute_line("goto 0");
+        viewer.execute_line("find x3002");
+        assert_eq!(2, viewer.position());
+        assert_eq!(None, viewer.execute_line("quit"));
+    }
+
+    #[test]
+    fn the_viewer_jumps_through_long_traces() {
+        let bytes = counting(10000, true).recording.unwrap().to_bytes();
+        let mut viewer = Viewer::new(Trace::parse(&bytes).unwrap());
+        viewer.execute_line("goto 9000");
+        assert_eq!(3000, viewer.state().reg[R::R1]);
+        viewer.execute_line("back 9000");
+        assert_eq!((0, 0), (viewer.position(), viewer.state().reg[R::R1]));
+    }
+}
@@ -0,0 +1,109 @@
+// Trap tracing
+//
+// With --trace-traps every TRAP is logged to stderr with its arguments as the
+// service routine sees them, rather than as raw registers:
+//
+//   x3004  PUTS x3010 "Hello\n"
+//   x3006  GETC -> 'y' (x0079)
+//   x3007  OUT 'y' (x0079)
+//   x3009  HALT
+//
+// Strings are read with peek, so tracing never touches a device register, and
+// are cut short after MAX_CHARS characters.
+
+use crate::{
+    defs::{R, TRAP},
+    state::State,
+};
+
+const MAX_CHARS: usize = 80;
+
+// A trap about to run, described from the state before it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub pc: u16,
+    pub vector: u16,
+    pub text: String,
+}
+
+pub fn call(pc: u16, vector: u16, state: &State) -> Call {
+    let r0 = state.reg[R::R0];
+    let text = match TRAP::try_from(vector) {
+        Ok(TRAP::OUT) => format!("OUT {}", character(r0)),
+        Ok(TRAP::PUTS) => format!("PUTS x{:04X} {}", r0, string(state, r0, false)),
+        Ok(TRAP::PUTSP) => format!("PUTSP x{:04X} {}", r0, string(state, r0, true)),
+        Ok(TRAP::EXIT) => format!("EXIT {}", r0),
+        Ok(TRAP::SLEEP) => format!("SLEEP {} ms", r0),
+        Ok(TRAP::CURSOR) => format!("CURSOR {} {},{}", r0, state.reg[R::R1], state.reg[R::R2]),
+        Ok(trap) => trap.name().to_string(),
+        Err(_) => format!("TRAP x{:02X}", vector),
+    };
+    Call { pc, vector, text }
+}
+
+// Completes the line for `call` with what the trap returned.
+pub fn finish(call: Call, state: &State) -> String {
+    let returned = match TRAP::try_from(call.vector) {
+        Ok(TRAP::GETC | TRAP::IN) => format!(" -> {}", character(state.reg[R::R0])),
+        _ => String::new(),
+    };
+    format!("x{:04X}  {}{}", call.pc, call.text, returned)
+}
+
+fn character(word: u16) -> String {
+    match u8::try_from(word) {
+        Ok(c) if c.is_ascii_graphic() || c == b' ' => format!("'{}' (x{:04X})", c as char, word),
+        _ => format!("x{:04X}", word),
+    }
+}
+
+// The string at `address`, quoted and escaped.
+fn string(state: &State, address: u16, packed: bool) -> String {
+    let text = if packed {
+        state.mem.read_packed_string(address)
+    } else {
+        state.mem.read_string(address)
+    };
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{:?}...", &text[..end]),
+        None => format!("{:?}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        defs::R,
+        state::State,
+        traptrace::{call, finish},
+    };
+
+    #[test]
+    fn strings_are_decoded() {
+        let mut state = State::new();
+        state.mem.write_string(0x4000, "Hi\n");
+        state.mem.write_packed_string(0x4010, "Hey");
+
+        state.reg[R::R0] = 0x4000;
+        let puts = call(0x3000, 0x22, &state);
+        assert_eq!("x3000  PUTS x4000 \"Hi\\n\"", finish(puts, &state));
+
+        state.reg[R::R0] = 0x4010;
+        let putsp = call(0x3001, 0x24, &state);
+        assert_eq!("x3001  PUTSP x4010 \"Hey\"", finish(putsp, &state));
+    }
+
+    #[test]
+    fn characters_in_and_out() {
+        let mut state = State::new();
+        let getc = call(0x3000, 0x20, &state);
+        state.reg[R::R0] = 'y' as u16;
+        assert_eq!("x3000  GETC -> 'y' (x0079)", finish(getc, &state));
+
+        state.reg[R::R0] = 10;
+        let out = call(0x3001, 0x21, &state);
+        assert_eq!("x3001  OUT x000A", finish(out, &state));
+        let unknown = call(0x3002, 0x30, &state);
+        assert_eq!("x3002  TRAP x30", finish(unknown, &state));
+    }
+}
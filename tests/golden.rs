@@ -0,0 +1,57 @@
+// Golden tests of console output
+//
+// Each program in tests/golden/ is assembled and run with its .in file fed to
+// the keyboard, and everything it writes to the console is captured. The
+// capture must match the .out file exactly. Run with GOLDEN_UPDATE=1 to
+// rewrite the .out files after an intended change in output.
+
+use std::{fs, path::Path};
+
+use lc3vm::{asm::assemble, defs::R, loader::Image, state::State};
+
+const MAX_STEPS: u64 = 100_000;
+
+fn run(source: &str, input: &[u8]) -> String {
+    let program = assemble(source).unwrap();
+    let image = Image::parse(&program.object(None), false).unwrap();
+
+    let mut state = State::new();
+    state.max_instructions = Some(MAX_STEPS);
+    state.mem.console.feed(input);
+    state.mem.console.capture();
+    image.load(&mut state).unwrap();
+    state.reg[R::PC] = image.origin;
+    while state.running {
+        state.step_once();
+    }
+
+    assert_eq!(None, state.error);
+    state.mem.console.captured().unwrap().to_string()
+}
+
+#[test]
+fn console_output_matches_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut sources: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "asm"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty());
+
+    let update = std::env::var_os("GOLDEN_UPDATE").is_some();
+    for source in sources {
+        let input = fs::read(source.with_extension("in")).unwrap_or_default();
+        let output = run(&fs::read_to_string(&source).unwrap(), &input);
+
+        let golden = source.with_extension("out");
+        if update {
+            fs::write(&golden, &output).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .unwrap_or_else(|_| panic!("{} is missing", golden.display()));
+        assert_eq!(expected, output, "{}", source.display());
+    }
+}
@@ -0,0 +1,10 @@
+; Prints a dot for each key GETC reads until the input runs out. The default
+; end-of-input policy stops the machine in the middle of the loop.
+        .ORIG x3000
+        LD  R1, DOT
+NEXT    GETC
+        ADD R0, R1, #0
+        OUT
+        BR  NEXT
+DOT     .FILL x002E
+        .END
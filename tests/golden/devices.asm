@@ -0,0 +1,19 @@
+; Copies keys to the display through the memory-mapped device registers,
+; polling KBSR and DSR, until a newline has been copied
+        .ORIG x3000
+        LD  R2, MINUS_LF
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+WAIT    LDI R1, DSR
+        BRzp WAIT
+        STI R0, DDR
+        ADD R1, R0, R2
+        BRnp POLL
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+DSR     .FILL xFE04
+DDR     .FILL xFE06
+MINUS_LF .FILL #-10
+        .END
@@ -0,0 +1,14 @@
+; Reads one character with IN and reports it back with PUTS and OUT
+        .ORIG x3000
+        IN
+        ADD R1, R0, #0
+        LEA R0, GOT
+        PUTS
+        ADD R0, R1, #0
+        OUT
+        LD  R0, NEWLINE
+        OUT
+        HALT
+GOT     .STRINGZ "\nyou typed "
+NEWLINE .FILL x000A
+        .END
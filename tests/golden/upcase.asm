@@ -0,0 +1,23 @@
+; Echoes input through GETC and OUT with lowercase letters made uppercase,
+; until a period is typed
+        .ORIG x3000
+        LD  R2, MINUS_A
+        LD  R3, MINUS_Z
+        LD  R4, CASE
+        LD  R5, MINUS_DOT
+NEXT    GETC
+        ADD R1, R0, R5
+        BRz DONE
+        ADD R1, R0, R2
+        BRn SHOW                ; below 'a'
+        ADD R1, R0, R3
+        BRp SHOW                ; above 'z'
+        ADD R0, R0, R4
+SHOW    OUT
+        BR  NEXT
+DONE    HALT
+MINUS_A   .FILL #-97
+MINUS_Z   .FILL #-122
+CASE      .FILL #-32
+MINUS_DOT .FILL #-46
+        .END
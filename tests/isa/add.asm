@@ -0,0 +1,11 @@
+; ADD in both modes, imm5 sign extension and 16-bit wraparound
+        .ORIG x3000
+        ADD R1, R0, #15         ; largest imm5
+        ADD R2, R0, #-16        ; smallest imm5, sign extended to xFFF0
+        ADD R3, R1, R2          ; register mode: 15 + -16 = -1
+        LD  R4, MAXPOS
+        ADD R4, R4, #1          ; x7FFF + 1 wraps to x8000
+        ADD R5, R3, #1          ; xFFFF + 1 wraps to zero
+        HALT
+MAXPOS  .FILL x7FFF
+        .END
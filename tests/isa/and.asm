@@ -0,0 +1,12 @@
+; AND in both modes, with imm5 sign extended to all sixteen bits
+        .ORIG x3000
+        LD  R1, PATTERN
+        LD  R2, MASK
+        AND R3, R1, R2          ; register mode
+        AND R4, R1, #15         ; no bits in common
+        AND R5, R1, #-1         ; xFFFF keeps every bit
+        AND R6, R1, #-16        ; xFFF0 clears the low nibble only
+        HALT
+PATTERN .FILL xF0F3
+MASK    .FILL x3C3C
+        .END
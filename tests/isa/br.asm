@@ -0,0 +1,46 @@
+; Every BR condition against every condition code. R6 counts the condition
+; codes checked; a branch that goes the wrong way stops with R0 still zero.
+        .ORIG x3000
+        ADD R1, R0, #-1         ; N
+        BRn   N1
+        BR    FAIL
+N1      BRnz  N2
+        BR    FAIL
+N2      BRnp  N3
+        BR    FAIL
+N3      BRnzp N4
+        BR    FAIL
+N4      BRz   FAIL
+        BRp   FAIL
+        BRzp  FAIL
+        ADD R6, R6, #1
+        ADD R1, R0, #0          ; Z
+        BRz   Z1
+        BR    FAIL
+Z1      BRnz  Z2
+        BR    FAIL
+Z2      BRzp  Z3
+        BR    FAIL
+Z3      BRnzp Z4
+        BR    FAIL
+Z4      BRn   FAIL
+        BRp   FAIL
+        BRnp  FAIL
+        ADD R6, R6, #1
+        ADD R1, R0, #1          ; P
+        BRp   P1
+        BR    FAIL
+P1      BRnp  P2
+        BR    FAIL
+P2      BRzp  P3
+        BR    FAIL
+P3      BRnzp P4
+        BR    FAIL
+P4      BRn   FAIL
+        BRz   FAIL
+        BRnz  FAIL
+        NOP                     ; BR with no conditions never branches
+        ADD R6, R6, #1
+        ADD R0, R0, #1
+FAIL    HALT
+        .END
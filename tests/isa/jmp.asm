@@ -0,0 +1,12 @@
+; JMP and RET take the whole register as the new PC
+        .ORIG x3000
+        LEA R1, THERE
+        JMP R1
+        ADD R0, R0, #1          ; skipped
+THERE   ADD R2, R2, #1
+        LEA R7, BACK
+        RET
+        ADD R0, R0, #2          ; skipped
+BACK    ADD R3, R3, #1
+        HALT
+        .END
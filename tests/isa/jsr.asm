@@ -0,0 +1,19 @@
+; JSR both ways, JSRR, and JSRR R7, which reads the base before linking
+        .ORIG x3000
+        BR   MAIN
+BEHIND  ADD R1, R1, #1
+        RET
+MAIN    JSR  BEHIND             ; backward PCoffset11
+        JSR  AHEAD              ; forward PCoffset11
+        ADD  R2, R7, #0
+        LEA  R3, AHEAD
+        JSRR R3
+        ADD  R4, R7, #0
+        LEA  R7, LINKED
+        JSRR R7
+        HALT
+AHEAD   ADD R1, R1, #1
+        RET
+LINKED  ADD R5, R7, #0
+        RET
+        .END
@@ -0,0 +1,11 @@
+; LD with backward and forward PCoffset9, setting the condition codes
+        .ORIG x3000
+        BR  MAIN
+NEG     .FILL x8001
+ZERO    .FILL x0000
+MAIN    LD  R1, NEG
+        LD  R2, POS
+        LD  R3, ZERO
+        HALT
+POS     .FILL x1234
+        .END
@@ -0,0 +1,11 @@
+; LDI loads through a pointer and sets the condition codes from the result
+        .ORIG x3000
+        LDI R1, PTR
+        LDI R2, PTRZ
+        LDI R3, PTR
+        HALT
+PTR     .FILL DATA
+PTRZ    .FILL ZEROW
+DATA    .FILL xBEEF
+ZEROW   .FILL x0000
+        .END
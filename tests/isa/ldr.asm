@@ -0,0 +1,15 @@
+; LDR with the extremes of offset6 and a base + offset that wraps
+        .ORIG x3000
+        LEA R1, MIDDLE
+        LDR R2, R1, #-32
+        LDR R3, R1, #31
+        LDR R4, R1, #0
+        ADD R5, R0, #-1
+        LDR R5, R5, #1          ; xFFFF + 1 wraps to x0000
+        HALT
+LOW     .FILL xFFFE
+        .BLKW 31
+MIDDLE  .FILL x0042
+        .BLKW 30
+HIGH    .FILL x7000
+        .END
@@ -0,0 +1,7 @@
+; LEA computes an address without reading memory and sets the condition codes
+        .ORIG x3000
+HERE    LEA R1, HERE            ; PCoffset9 of -1
+        LEA R2, THERE
+        HALT
+THERE   .FILL x0000
+        .END
@@ -0,0 +1,10 @@
+; NOT of zero, all ones and an alternating pattern
+        .ORIG x3000
+        NOT R1, R0              ; R0 starts out zero
+        NOT R2, R1
+        LD  R3, VALUE
+        NOT R4, R3
+        NOT R5, R4
+        HALT
+VALUE   .FILL x5A5A
+        .END
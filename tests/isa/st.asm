@@ -0,0 +1,12 @@
+; ST with backward and forward PCoffset9, leaving the condition codes alone
+        .ORIG x3000
+        BR  MAIN
+BEFORE  .BLKW 1
+MAIN    LD  R1, VALUE
+        ST  R1, BEFORE
+        AND R2, R2, #0
+        ST  R1, AFTER
+        HALT
+VALUE   .FILL x8421
+AFTER   .BLKW 1
+        .END
@@ -0,0 +1,8 @@
+; STI stores through a pointer and leaves the pointer itself alone
+        .ORIG x3000
+        LD  R1, VALUE
+        STI R1, PTR
+        HALT
+VALUE   .FILL x1357
+PTR     .FILL x4000
+        .END
@@ -0,0 +1,13 @@
+; STR with the extremes of offset6 and a base + offset that wraps
+        .ORIG x3000
+        LD  R1, VALUE
+        LD  R2, BASE
+        STR R1, R2, #0
+        STR R1, R2, #31
+        STR R1, R2, #-32
+        ADD R3, R0, #-1
+        STR R1, R3, #2          ; xFFFF + 2 wraps to x0001
+        HALT
+VALUE   .FILL x2468
+BASE    .FILL x4000
+        .END
@@ -0,0 +1,15 @@
+; The output service routines, and TRAP linking through R7
+        .ORIG x3000
+        LD   R0, CHAR
+        OUT
+        ADD  R1, R7, #0
+        LEA  R0, TEXT
+        PUTS
+        LEA  R0, PACKED
+        PUTSP
+        HALT
+CHAR    .FILL x0041
+TEXT    .STRINGZ "bc"
+PACKED  .FILL x6564             ; "de", low byte first
+        .FILL x0000
+        .END